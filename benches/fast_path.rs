@@ -0,0 +1,81 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! Compares small reads and writes through the fast path, taken once the ops have run out or
+//! during a run of `PartialOp::Unlimited` ops, against the general path.
+//!
+//! Run with `cargo bench --bench fast_path`. This is a plain timing loop rather than a statistical
+//! benchmark, so compare numbers from the same run.
+
+use partial_io::{PartialOp, PartialRead, PartialWrite};
+use std::{
+    io::{self, Read, Write},
+    iter,
+    time::{Duration, Instant},
+};
+
+const ITERATIONS: u32 = 2_000_000;
+const CHUNK: usize = 64;
+
+fn main() {
+    println!("{:<40} {:>10}", "benchmark", "ns/call");
+
+    bench_read("read: no ops", |reader| reader);
+    bench_read("read: all Unlimited", |mut reader| {
+        reader.set_ops(iter::repeat(PartialOp::Unlimited));
+        reader
+    });
+    bench_read("read: general path", |mut reader| {
+        reader.set_ops(iter::repeat(PartialOp::Unlimited));
+        // A cap of usize::MAX doesn't change the reads, but makes them take the general path.
+        reader.set_unlimited_cap(Some(usize::MAX));
+        reader
+    });
+
+    bench_write("write: no ops", |writer| writer);
+    bench_write("write: all Unlimited", |mut writer| {
+        writer.set_ops(iter::repeat(PartialOp::Unlimited));
+        writer
+    });
+    bench_write("write: general path", |mut writer| {
+        writer.set_ops(iter::repeat(PartialOp::Unlimited));
+        writer.set_unlimited_cap(Some(usize::MAX));
+        writer
+    });
+}
+
+fn bench_read<F>(name: &str, setup: F)
+where
+    F: FnOnce(PartialRead<io::Repeat>) -> PartialRead<io::Repeat>,
+{
+    let mut reader = setup(PartialRead::new(io::repeat(42), iter::empty()));
+    let mut buf = [0; CHUNK];
+    let start = Instant::now();
+    let mut total = 0;
+    for _ in 0..ITERATIONS {
+        total += reader.read(&mut buf).unwrap();
+    }
+    report(name, start.elapsed());
+    // Use the result so the loop isn't optimized away.
+    assert_eq!(total, CHUNK * ITERATIONS as usize);
+}
+
+fn bench_write<F>(name: &str, setup: F)
+where
+    F: FnOnce(PartialWrite<io::Sink>) -> PartialWrite<io::Sink>,
+{
+    let mut writer = setup(PartialWrite::new(io::sink(), iter::empty()));
+    let buf = [42; CHUNK];
+    let start = Instant::now();
+    let mut total = 0;
+    for _ in 0..ITERATIONS {
+        total += writer.write(&buf).unwrap();
+    }
+    report(name, start.elapsed());
+    assert_eq!(total, CHUNK * ITERATIONS as usize);
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let per_call = elapsed.as_nanos() as f64 / f64::from(ITERATIONS);
+    println!("{:<40} {:>10.2}", name, per_call);
+}
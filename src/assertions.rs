@@ -0,0 +1,537 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains assertion and driver helpers for tests driven by `PartialOp`s.
+
+#[cfg(feature = "futures03")]
+use crate::PartialAsyncRead;
+use crate::PartialOp;
+#[cfg(feature = "futures03")]
+use futures::{
+    future::poll_fn,
+    io::AsyncRead,
+    task::{noop_waker, waker, ArcWake},
+    Future,
+};
+use std::{fmt, io};
+#[cfg(feature = "futures03")]
+use std::{
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+/// How long [`assert_yielded`] waits for a pending future to wake its task.
+#[cfg(feature = "futures03")]
+const WAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Asserts that a transfer failed with an error of the expected kind.
+///
+/// # Panics
+///
+/// Panics if `result` is `Ok`, or if it is an error of a different kind.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{assert_transfer_error, PartialOp, PartialWrite};
+/// use std::io::{self, Write};
+///
+/// let ops = vec![
+///     PartialOp::Err(io::ErrorKind::Interrupted),
+///     PartialOp::Err(io::ErrorKind::BrokenPipe),
+/// ];
+/// let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+///
+/// // write_all retries Interrupted errors, but not BrokenPipe ones.
+/// assert_transfer_error(partial_writer.write_all(b"foo"), io::ErrorKind::BrokenPipe);
+/// assert_eq!(
+///     partial_writer.errors_seen(),
+///     &[io::ErrorKind::Interrupted, io::ErrorKind::BrokenPipe],
+/// );
+/// ```
+#[track_caller]
+pub fn assert_transfer_error<T>(result: io::Result<T>, expected: io::ErrorKind)
+where
+    T: fmt::Debug,
+{
+    match result {
+        Ok(value) => panic!(
+            "expected transfer to fail with {:?}, but it succeeded with {:?}",
+            expected, value
+        ),
+        Err(err) => assert_eq!(
+            err.kind(),
+            expected,
+            "expected transfer to fail with {:?}, but it failed with: {}",
+            expected,
+            err
+        ),
+    }
+}
+
+/// Asserts that every `PartialOp::Err` in `ops` uses one of the `allowed` kinds of error.
+///
+/// This is a guardrail for generated sequences of ops, to make sure that a generator stays within
+/// the intended set of faults. `PartialOp::Limited` and `PartialOp::Unlimited` ops are always
+/// allowed.
+///
+/// # Panics
+///
+/// Panics if an op uses a kind of error that isn't in `allowed`, naming the first such op and its
+/// index.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{assert_only_kinds, PartialOp};
+/// use std::io;
+///
+/// let ops = [
+///     PartialOp::Limited(4),
+///     PartialOp::Err(io::ErrorKind::Interrupted),
+///     PartialOp::Err(io::ErrorKind::WouldBlock),
+/// ];
+/// assert_only_kinds(&ops, &[io::ErrorKind::Interrupted, io::ErrorKind::WouldBlock]);
+/// ```
+#[track_caller]
+pub fn assert_only_kinds(ops: &[PartialOp], allowed: &[io::ErrorKind]) {
+    let disallowed = ops.iter().enumerate().find_map(|(index, op)| match op {
+        PartialOp::Err(kind) | PartialOp::ErrMsg(kind, _) if !allowed.contains(kind) => {
+            Some((index, kind))
+        }
+        _ => None,
+    });
+    if let Some((index, kind)) = disallowed {
+        panic!(
+            "op at index {} is Err({:?}), but only {:?} are allowed",
+            index, kind, allowed
+        );
+    }
+}
+
+/// Polls a future at most `n` times with a no-op waker, returning the last poll result along with
+/// the number of polls used.
+///
+/// Polling stops as soon as the future is ready. This makes it possible to check how much
+/// progress a future makes per poll, for example that a transfer through a wrapper with `k`
+/// `PartialOp::Err(WouldBlock)` ops takes exactly `k + 1` polls. Since the waker does nothing,
+/// this doesn't work with futures that depend on being woken up by something other than
+/// themselves, such as timers.
+///
+/// Pass in `&mut future` to keep polling the future afterwards.
+///
+/// Requires the `futures03` feature to be enabled.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[cfg(feature = "futures03")]
+/// # fn main() {
+/// use futures::io::AsyncWriteExt;
+/// use partial_io::{poll_n_times, PartialAsyncWrite, PartialOp};
+/// use std::{io, task::Poll};
+///
+/// let ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock); 2];
+/// let mut partial_writer = PartialAsyncWrite::new(Vec::new(), ops);
+///
+/// let (poll, polls) = poll_n_times(partial_writer.write(b"foo"), 10);
+/// assert!(matches!(poll, Poll::Ready(Ok(3))));
+/// assert_eq!(polls, 3);
+/// # }
+/// # #[cfg(not(feature = "futures03"))]
+/// # fn main() {}
+/// ```
+#[cfg(feature = "futures03")]
+pub fn poll_n_times<F>(mut future: F, n: usize) -> (Poll<F::Output>, usize)
+where
+    F: Future + Unpin,
+{
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    for polls in 1..=n {
+        if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+            return (Poll::Ready(output), polls);
+        }
+    }
+    (Poll::Pending, n)
+}
+
+/// A waker that counts how many times it has been woken up.
+///
+/// Pass it to [`assert_yielded`] to drive a future with it. Clones of a probe share the same
+/// count.
+///
+/// Requires the `futures03` feature to be enabled.
+#[cfg(feature = "futures03")]
+#[derive(Clone, Debug, Default)]
+pub struct WakerProbe {
+    state: Arc<ProbeState>,
+}
+
+#[cfg(feature = "futures03")]
+#[derive(Debug, Default)]
+struct ProbeState {
+    wakes: Mutex<usize>,
+    woken: Condvar,
+}
+
+#[cfg(feature = "futures03")]
+impl ArcWake for ProbeState {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        *arc_self.wakes.lock().unwrap() += 1;
+        arc_self.woken.notify_all();
+    }
+}
+
+#[cfg(feature = "futures03")]
+impl WakerProbe {
+    /// Creates a new probe that hasn't been woken up yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a `Waker` that increments this probe's count when woken up.
+    pub fn waker(&self) -> Waker {
+        waker(self.state.clone())
+    }
+
+    /// Returns the number of times this probe has been woken up.
+    pub fn wakes(&self) -> usize {
+        *self.state.wakes.lock().unwrap()
+    }
+
+    /// Waits until the count exceeds `seen`, returning false if that doesn't happen within
+    /// `timeout`.
+    fn wait_for_wake(&self, seen: usize, timeout: Duration) -> bool {
+        let wakes = self.state.wakes.lock().unwrap();
+        let (_wakes, res) = self
+            .state
+            .woken
+            .wait_timeout_while(wakes, timeout, |wakes| *wakes <= seen)
+            .unwrap();
+        !res.timed_out()
+    }
+}
+
+/// Drives a future to completion with the waker from `probe`, asserting that it yielded to the
+/// executor at least once.
+///
+/// A future yields by returning `Poll::Pending` after arranging for its task to be woken up. This
+/// checks that a future waits for I/O to become ready, for example when a `PartialAsyncRead` or
+/// `PartialAsyncWrite` applies a `PartialOp::Err(WouldBlock)` op, rather than completing in a
+/// single poll. Every time the future returns `Poll::Pending`, this blocks the current thread
+/// until the waker is woken up, which can happen during the poll itself or from another thread.
+/// The output of the future is returned once it completes.
+///
+/// Requires the `futures03` feature to be enabled.
+///
+/// # Panics
+///
+/// Panics if the future completes without ever returning `Poll::Pending`, or if it returns
+/// `Poll::Pending` and the waker isn't woken up within 5 seconds, since a real executor would
+/// never poll it again.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[cfg(feature = "futures03")]
+/// # fn main() {
+/// use futures::io::AsyncWriteExt;
+/// use partial_io::{assert_yielded, PartialAsyncWrite, PartialOp, WakerProbe};
+/// use std::io;
+///
+/// let ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock), PartialOp::Unlimited];
+/// let mut partial_writer = PartialAsyncWrite::new(Vec::new(), ops);
+///
+/// let probe = WakerProbe::new();
+/// assert_eq!(assert_yielded(partial_writer.write(b"foo"), &probe).unwrap(), 3);
+/// assert_eq!(probe.wakes(), 1);
+/// # }
+/// # #[cfg(not(feature = "futures03"))]
+/// # fn main() {}
+/// ```
+#[cfg(feature = "futures03")]
+#[track_caller]
+pub fn assert_yielded<F>(future: F, probe: &WakerProbe) -> F::Output
+where
+    F: Future,
+{
+    let waker = probe.waker();
+    let mut cx = Context::from_waker(&waker);
+    futures::pin_mut!(future);
+    let mut yielded = false;
+    loop {
+        let wakes = probe.wakes();
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => {
+                assert!(yielded, "future completed without yielding");
+                return output;
+            }
+            Poll::Pending => {
+                assert!(
+                    probe.wait_for_wake(wakes, WAKE_TIMEOUT),
+                    "future returned Poll::Pending, but its task wasn't woken up within {:?}",
+                    WAKE_TIMEOUT
+                );
+                yielded = true;
+            }
+        }
+    }
+}
+
+/// Reads exactly `buf.len()` bytes through a `PartialAsyncRead`, handling every `PartialOp` along
+/// the way.
+///
+/// This drives `poll_read` until `buf` is full. `WouldBlock` ops pend and wake the task as usual,
+/// and `Interrupted` errors, including those returned by the underlying reader, are retried. Any
+/// other error is returned, and reaching the end of the data early returns
+/// `io::ErrorKind::UnexpectedEof`. Unlike `read_exact`, this doesn't depend on an extension trait
+/// from a particular async runtime.
+///
+/// Both boxed and [unboxed](PartialAsyncRead::new_unboxed) ops are supported, as are `Delay` ops.
+///
+/// Requires the `futures03` feature to be enabled.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[cfg(feature = "futures03")]
+/// # fn main() {
+/// use partial_io::{read_exact_through, PartialAsyncRead, PartialOp};
+/// use std::io;
+///
+/// let ops = vec![
+///     PartialOp::Err(io::ErrorKind::WouldBlock),
+///     PartialOp::Limited(2),
+///     PartialOp::Err(io::ErrorKind::Interrupted),
+///     PartialOp::Limited(3),
+/// ];
+/// let mut partial_reader = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+/// let mut out = [0; 5];
+/// futures::executor::block_on(read_exact_through(&mut partial_reader, &mut out)).unwrap();
+/// assert_eq!(&out, b"Hello");
+/// # }
+/// # #[cfg(not(feature = "futures03"))]
+/// # fn main() {}
+/// ```
+#[cfg(feature = "futures03")]
+pub async fn read_exact_through<R, I>(
+    reader: &mut PartialAsyncRead<R, I>,
+    mut buf: &mut [u8],
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    I: Iterator<Item = PartialOp> + Unpin,
+{
+    while !buf.is_empty() {
+        let res = poll_fn(|cx| Pin::new(&mut *reader).poll_read(cx, buf)).await;
+        match res {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "reached end of data before filling buffer",
+                ))
+            }
+            Ok(n) => buf = &mut buf[n..],
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PartialRead;
+    use std::{io::Read, panic};
+
+    #[test]
+    fn test_assert_transfer_error() {
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::ConnectionReset),
+        ];
+        let mut partial_read = PartialRead::new(io::repeat(42), ops);
+        let mut out = [0; 4];
+
+        // read_exact retries Interrupted errors.
+        assert_transfer_error(
+            partial_read.read_exact(&mut out),
+            io::ErrorKind::ConnectionReset,
+        );
+        assert_eq!(
+            partial_read.errors_seen(),
+            &[io::ErrorKind::Interrupted, io::ErrorKind::ConnectionReset]
+        );
+
+        let res = panic::catch_unwind(|| assert_transfer_error(Ok(5), io::ErrorKind::Other));
+        assert!(res.is_err(), "success is not the expected error");
+        let res = panic::catch_unwind(|| {
+            assert_transfer_error::<()>(Err(io::ErrorKind::BrokenPipe.into()), io::ErrorKind::Other)
+        });
+        assert!(res.is_err(), "error kinds differ");
+    }
+
+    #[test]
+    fn test_assert_only_kinds() {
+        let allowed = [io::ErrorKind::Interrupted, io::ErrorKind::WouldBlock];
+        assert_only_kinds(&[], &allowed);
+        assert_only_kinds(
+            &[
+                PartialOp::Unlimited,
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+                PartialOp::Limited(1),
+                PartialOp::Err(io::ErrorKind::Interrupted),
+            ],
+            &allowed,
+        );
+        assert_only_kinds(&[PartialOp::Limited(1)], &[]);
+
+        let res = panic::catch_unwind(|| {
+            assert_only_kinds(
+                &[
+                    PartialOp::Err(io::ErrorKind::Interrupted),
+                    PartialOp::Err(io::ErrorKind::BrokenPipe),
+                ],
+                &allowed,
+            )
+        });
+        let payload = res.unwrap_err();
+        assert_eq!(
+            payload.downcast_ref::<String>().unwrap(),
+            "op at index 1 is Err(BrokenPipe), but only [Interrupted, WouldBlock] are allowed"
+        );
+    }
+
+    #[cfg(feature = "futures03")]
+    #[test]
+    fn test_poll_n_times() {
+        use crate::PartialAsyncRead;
+        use futures::io::AsyncReadExt;
+
+        for k in 0..4 {
+            let mut ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock); k];
+            ops.push(PartialOp::Limited(2));
+            let mut partial_read = PartialAsyncRead::new(&b"Hello"[..], ops);
+            let mut out = [0; 4];
+
+            let (poll, polls) = poll_n_times(partial_read.read(&mut out), 10);
+            assert!(matches!(poll, Poll::Ready(Ok(2))), "k = {}", k);
+            assert_eq!(polls, k + 1, "{} WouldBlocks take {} polls", k, k + 1);
+        }
+
+        // Running out of budget leaves the future pending, and it can be polled again.
+        let ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock); 3];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello"[..], ops);
+        let mut out = [0; 4];
+        let mut read = partial_read.read(&mut out);
+        let (poll, polls) = poll_n_times(&mut read, 2);
+        assert!(poll.is_pending());
+        assert_eq!(polls, 2);
+        let (poll, polls) = poll_n_times(&mut read, 2);
+        assert!(matches!(poll, Poll::Ready(Ok(4))));
+        assert_eq!(polls, 2);
+    }
+
+    #[cfg(feature = "futures03")]
+    #[test]
+    fn test_assert_yielded() {
+        use futures::io::AsyncReadExt;
+
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello"[..], ops);
+        let mut out = [0; 4];
+        let probe = WakerProbe::new();
+        assert_eq!(
+            assert_yielded(partial_read.read(&mut out), &probe).unwrap(),
+            2
+        );
+        assert_eq!(probe.wakes(), 2, "woken up once per WouldBlock");
+
+        let mut partial_read = PartialAsyncRead::new(&b"Hello"[..], vec![PartialOp::Unlimited]);
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            assert_yielded(partial_read.read(&mut out), &WakerProbe::new())
+        }));
+        let payload = res.unwrap_err();
+        assert_eq!(
+            *payload.downcast_ref::<&str>().unwrap(),
+            "future completed without yielding"
+        );
+    }
+
+    #[cfg(feature = "futures03")]
+    #[test]
+    fn test_read_exact_through() {
+        use futures::executor::block_on;
+
+        let data = b"Hello, world!";
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_read = PartialAsyncRead::new(&data[..], ops);
+        let mut out = [0; 8];
+        block_on(read_exact_through(&mut partial_read, &mut out)).unwrap();
+        assert_eq!(&out, b"Hello, w");
+        assert_eq!(
+            partial_read.errors_seen(),
+            &[
+                io::ErrorKind::WouldBlock,
+                io::ErrorKind::WouldBlock,
+                io::ErrorKind::Interrupted
+            ]
+        );
+
+        // Only 5 bytes are left.
+        let err = block_on(read_exact_through(&mut partial_read, &mut out)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut partial_read =
+            PartialAsyncRead::new(&data[..], vec![PartialOp::Err(io::ErrorKind::BrokenPipe)]);
+        let err = block_on(read_exact_through(&mut partial_read, &mut out)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test(start_paused = true)]
+    async fn test_read_exact_through_delays() {
+        use tokio::time::Instant;
+
+        let data = b"Hello, world!";
+        let ops = [
+            PartialOp::Delay(Duration::from_secs(5)),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Delay(Duration::from_secs(10)),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(4),
+            PartialOp::Unlimited,
+        ];
+        // An unboxed iterator works too.
+        let mut partial_read = PartialAsyncRead::new_unboxed(&data[..], ops.into_iter());
+        let mut out = [0; 8];
+        let start = Instant::now();
+        read_exact_through(&mut partial_read, &mut out)
+            .await
+            .unwrap();
+        assert_eq!(&out, b"Hello, w");
+        // Time is paused, so it only moves forward by the delays.
+        assert_eq!(start.elapsed(), Duration::from_secs(15));
+        assert_eq!(
+            partial_read.errors_seen(),
+            &[io::ErrorKind::WouldBlock, io::ErrorKind::WouldBlock]
+        );
+    }
+}
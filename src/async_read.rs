@@ -7,13 +7,19 @@
 //! This is separate from `PartialWrite` because on `WouldBlock` errors, it
 //! causes `futures` to try writing or flushing again.
 
-use crate::{futures_util::FuturesOps, PartialOp};
+use crate::{
+    futures_util::{FuturesOps, Gate, PollOutcome},
+    ops::{avoid_power_of_two, limit_io_slices_mut, BoxedOps, OpEvent, OpHistory, OpObserver},
+    schedule::chaos_ops,
+    Diagnostics, ExhaustedBehavior, OpSnapshot, PartialOp, PhasePosition, PhasedSchedule,
+};
 use futures::prelude::*;
 use pin_project::pin_project;
 use std::{
-    fmt, io,
+    cmp, fmt, io, iter,
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 /// A wrapper that breaks inner `AsyncRead` instances up according to the
@@ -71,10 +77,15 @@ use std::{
 /// # }
 /// ```
 #[pin_project]
-pub struct PartialAsyncRead<R> {
+pub struct PartialAsyncRead<R, I = BoxedOps> {
     #[pin]
     inner: R,
-    ops: FuturesOps,
+    ops: FuturesOps<I>,
+    captured: Option<Vec<u8>>,
+    avoid_power_of_two: bool,
+    observer: Option<OpObserver>,
+    /// The limit applied by the last successful `poll_fill_buf` call, until `consume` is called.
+    fill_buf_limit: Option<Option<usize>>,
 }
 
 impl<R> PartialAsyncRead<R> {
@@ -84,10 +95,36 @@ impl<R> PartialAsyncRead<R> {
         I: IntoIterator<Item = PartialOp> + 'static,
         I::IntoIter: Send,
     {
-        PartialAsyncRead {
-            inner,
-            ops: FuturesOps::new(iter),
-        }
+        Self::new_unboxed(inner, Box::new(iter.into_iter()))
+    }
+
+    /// Creates a new `PartialAsyncRead` wrapper that delivers at most one byte at a time, without
+    /// any errors.
+    ///
+    /// This is the strictest deterministic chunking possible, and is a good way to shake out
+    /// bugs around read boundaries. It is equivalent to passing in
+    /// `std::iter::repeat(PartialOp::Limited(1))` as the `PartialOp`s.
+    pub fn pinhole(inner: R) -> Self {
+        Self::new(inner, iter::repeat(PartialOp::Limited(1)))
+    }
+
+    /// Creates a new `PartialAsyncRead` wrapper that throws a deterministic mix of every kind of
+    /// `PartialOp` at the code under test, derived from `seed`.
+    ///
+    /// This is meant as a quick robustness check before writing targeted property-based tests.
+    /// The ops never run out, and each one is independently, with probability:
+    ///
+    /// * 40%: `PartialOp::Limited(n)` with `n` in `1..=32`.
+    /// * 20%: `PartialOp::Unlimited`.
+    /// * 20%: `PartialOp::Err(Interrupted)`, which is retried.
+    /// * 17%: `PartialOp::Err(WouldBlock)`, which returns `Poll::Pending` and delays the read
+    ///   until the task is polled again.
+    /// * 3%: a hard error: `PartialOp::Err` with one of `BrokenPipe`, `ConnectionReset`,
+    ///   `TimedOut` or `Other`.
+    ///
+    /// The same seed always produces the same ops.
+    pub fn chaos(inner: R, seed: u64) -> Self {
+        Self::new(inner, chaos_ops(seed))
     }
 
     /// Sets the `PartialOp`s for this reader.
@@ -111,6 +148,390 @@ impl<R> PartialAsyncRead<R> {
         this
     }
 
+    /// Computes each `PartialOp` for this reader with a callback, replacing any existing ops.
+    ///
+    /// Before each op is applied, `op_fn` is passed an [`OpHistory`] describing the ops applied
+    /// since the callback was set, the number of bytes transferred and the errors seen so far,
+    /// and returns the op to apply next. This makes it possible to express stateful fault models,
+    /// such as failing only after a run of successful transfers, that a fixed sequence of ops
+    /// can't. The callback is called whenever an op is needed, so the ops never run out.
+    ///
+    /// Setting ops any other way removes the callback.
+    pub fn set_op_fn<F>(&mut self, op_fn: F) -> &mut Self
+    where
+        F: FnMut(&OpHistory<'_>) -> PartialOp + Send + 'static,
+    {
+        self.ops.replace_with_fn(Box::new(op_fn));
+        self
+    }
+
+    /// Sets the `PartialOp`s for this reader from a [`PhasedSchedule`], and starts tracking
+    /// which phase the last consumed op belongs to.
+    ///
+    /// The current phase is reported by [`current_phase`](Self::current_phase), this reader's
+    /// `Debug` output and [`assert_op_count`](Self::assert_op_count) failure messages. Setting
+    /// ops any other way stops tracking phases.
+    pub fn set_phased_ops(&mut self, schedule: PhasedSchedule) -> &mut Self {
+        self.ops.replace_phased(schedule);
+        self
+    }
+
+    /// Returns a copy of the `PartialOp`s that this reader hasn't consumed yet.
+    ///
+    /// The copy is independent of this reader: passing it to a fresh `PartialAsyncRead` produces a
+    /// second reader that continues from the same point in the schedule, for branching tests.
+    /// Unlike [`snapshot`](Self::snapshot), only the ops are copied, not the errors seen so far. A
+    /// latched error isn't part of the schedule either, and isn't carried over.
+    ///
+    /// This collects the remaining `PartialOp`s into a buffer, so they must be finite: with an
+    /// infinite iterator, this method never returns.
+    pub fn fork_schedule(&mut self) -> Vec<PartialOp> {
+        self.ops.fork()
+    }
+
+    /// Returns a snapshot of this reader's position within its `PartialOp`s, along with the
+    /// errors seen so far.
+    ///
+    /// Pass the snapshot to [`restore`](Self::restore) to return to this position, for example to
+    /// explore several branches from the same point. The state of the underlying reader is not
+    /// part of the snapshot.
+    ///
+    /// Taking a snapshot collects the remaining `PartialOp`s into a buffer, so they must be
+    /// finite: with an infinite iterator, this method never returns.
+    pub fn snapshot(&mut self) -> OpSnapshot {
+        self.ops.snapshot()
+    }
+
+    /// Restores the position and errors seen captured by [`snapshot`](Self::snapshot).
+    pub fn restore(&mut self, snapshot: &OpSnapshot) -> &mut Self {
+        self.ops.restore(snapshot);
+        self
+    }
+}
+
+impl<R, I> PartialAsyncRead<R, I>
+where
+    I: Iterator<Item = PartialOp>,
+{
+    /// Creates a new `PartialAsyncRead` wrapper over the reader with the specified `PartialOp`s,
+    /// without boxing the iterator.
+    ///
+    /// See [`PartialRead::new_unboxed`](crate::PartialRead::new_unboxed) for more.
+    pub fn new_unboxed(inner: R, iter: I) -> Self {
+        PartialAsyncRead {
+            inner,
+            ops: FuturesOps::new_unboxed(iter),
+            captured: None,
+            avoid_power_of_two: false,
+            observer: None,
+            fill_buf_limit: None,
+        }
+    }
+
+    /// Attaches a callback that is passed an [`OpEvent`] after each `poll_read` call that returns
+    /// `Poll::Ready`.
+    ///
+    /// The event describes the `PartialOp` that applied, the number of bytes read so far, the
+    /// length of the caller's buffer and the number of bytes read by the call. Calls that return
+    /// `Poll::Pending` are not reported. Replaces any previously attached callback, and starts
+    /// counting the offset from 0 again.
+    pub fn on_op<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+    {
+        self.observer = Some(OpObserver::new(callback));
+        self
+    }
+
+    /// Like [`on_op`](Self::on_op), but timestamps events with `clock` rather than
+    /// `Instant::now()`.
+    ///
+    /// This makes it possible to correlate events with other traces using the same clock, or to
+    /// control time in tests. `clock` is called once per event, and should return quickly
+    /// without blocking, since it's called from within `poll_read`.
+    pub fn on_op_with_clock<F, C>(&mut self, callback: F, clock: C) -> &mut Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+        C: FnMut() -> Instant + Send + 'static,
+    {
+        self.observer = Some(OpObserver::with_clock(callback, clock));
+        self
+    }
+
+    /// Returns the phase of the last consumed `PartialOp`, and its index within that phase.
+    ///
+    /// Returns `None` unless ops were set with [`set_phased_ops`](Self::set_phased_ops), or if no
+    /// ops have been consumed yet. Once the schedule has run out, this keeps returning the
+    /// position of the last op.
+    pub fn current_phase(&self) -> Option<PhasePosition> {
+        self.ops.current_phase()
+    }
+
+    /// Returns the limit the next `PartialOp` will apply, if it is `PartialOp::Limited`.
+    ///
+    /// `PartialOp::Eof` counts as a limit of 0.
+    ///
+    /// Returns `None` if the next op is `PartialOp::Unlimited`, `PartialOp::Err` or
+    /// `PartialOp::Delay`, or if the ops have run out, unless
+    /// [`set_unlimited_cap`](Self::set_unlimited_cap) turns the op into a limit. This peeks at the
+    /// next op, which pulls it out of the underlying iterator early. The op is not consumed: it
+    /// still applies to the next `poll_` method call.
+    pub fn next_limit(&mut self) -> Option<usize> {
+        self.ops.next_limit()
+    }
+
+    /// Asserts that exactly `expected` `PartialOp`s have been consumed since the ops were last
+    /// set.
+    ///
+    /// Each `poll_` method call consumes one op, except that retried `Interrupted` errors consume
+    /// an op each as well. Calls made after the ops have run out aren't counted. This is useful for
+    /// pinning down golden chunking behavior: with `PartialOp::Limited` ops, a regression in how
+    /// data is chunked changes the number of ops consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics with a message including both counts if they differ.
+    #[track_caller]
+    pub fn assert_op_count(&self, expected: usize) {
+        self.ops.assert_op_count(expected);
+    }
+
+    /// Returns the number of `PartialOp`s consumed since the ops were last set.
+    ///
+    /// This counts ops the same way as [`assert_op_count`](Self::assert_op_count).
+    pub fn consumed_ops(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns the number of `PartialOp`s that haven't been consumed yet, if it is known.
+    ///
+    /// The number is known if the iterator the ops were set from reports an exact size, as
+    /// `ExactSizeIterator`s like `vec::IntoIter` do. Returns `None` for other iterators, including
+    /// infinite ones, and for ops computed by [`set_op_fn`](Self::set_op_fn).
+    pub fn ops_remaining(&self) -> Option<usize> {
+        self.ops.remaining()
+    }
+
+    /// Returns the total number of bytes read through this reader.
+    ///
+    /// This counts the bytes actually transferred by the inner reader, not the amounts requested
+    /// by callers or allowed by `PartialOp`s. Calls that return an error don't count. The
+    /// count is kept when the ops are replaced.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.ops.transferred()
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, in the order they fired.
+    ///
+    /// This includes errors that were not returned to the caller, such as retried `Interrupted`
+    /// errors and `WouldBlock` errors translated to `Poll::Pending`. Errors returned by the
+    /// underlying reader are not included.
+    pub fn errors_seen(&self) -> &[io::ErrorKind] {
+        self.ops.errors_seen()
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, along with the number of
+    /// bytes transferred through this reader before each one fired.
+    ///
+    /// This covers the same errors as [`errors_seen`](Self::errors_seen), and helps correlate
+    /// errors with positions in the stream.
+    pub fn error_offsets(&self) -> &[(io::ErrorKind, u64)] {
+        self.ops.error_offsets()
+    }
+
+    /// Sets the kind of error that is sticky once it is injected.
+    ///
+    /// Once a `PartialOp::Err` of this kind is applied, every subsequent operation on this
+    /// reader fails with the same kind of error regardless of the remaining `PartialOp`s, which
+    /// are not consumed. This models unrecoverable failures like a connection dying with
+    /// `BrokenPipe`. The latched error is preserved across [`set_ops`](Self::set_ops), and can be
+    /// reset with [`clear_latch`](Self::clear_latch).
+    ///
+    /// Pass in `None` to stop latching errors. This doesn't clear an error that has already
+    /// latched.
+    ///
+    /// Latching `Interrupted` or `WouldBlock` errors is not useful here: `Interrupted` errors are
+    /// retried forever, and `WouldBlock` errors cause the task to be woken up forever.
+    pub fn set_latch_error(&mut self, kind: Option<io::ErrorKind>) -> &mut Self {
+        self.ops.set_latch_error(kind);
+        self
+    }
+
+    /// Returns the error that has latched, if any.
+    pub fn latched_error(&self) -> Option<io::ErrorKind> {
+        self.ops.latched_error()
+    }
+
+    /// Returns a summary of everything this reader has recorded so far.
+    ///
+    /// This bundles the ops consumed, the last op, the bytes transferred and the errors injected
+    /// into a single report, for printing when a test fails.
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.ops.diagnostics()
+    }
+
+    /// Clears the error that has latched, if any, so that `PartialOp`s apply again.
+    pub fn clear_latch(&mut self) -> &mut Self {
+        self.ops.clear_latch();
+        self
+    }
+
+    /// Returns the total time this reader has spent pending due to `WouldBlock` errors and
+    /// `PartialOp::Delay` ops.
+    ///
+    /// This is the wall-clock time between returning `Poll::Pending` for a `WouldBlock` error or a
+    /// delay and the next call to a `poll_` method, including the time since the last
+    /// `Poll::Pending` if the reader hasn't been polled again yet. Use
+    /// [`set_pending_clock`](Self::set_pending_clock) to measure it with a different clock.
+    pub fn pending_duration(&self) -> Duration {
+        self.ops.pending_duration()
+    }
+
+    /// Measures [`pending_duration`](Self::pending_duration) with `clock` rather than
+    /// `Instant::now()`.
+    ///
+    /// This makes it possible to control time in tests, or to measure time spent pending on the
+    /// same clock as a runtime with paused time. `clock` is called whenever the reader starts or
+    /// stops pending, and should return quickly without blocking. Time spent pending so far
+    /// is kept.
+    pub fn set_pending_clock<C>(&mut self, clock: C) -> &mut Self
+    where
+        C: Fn() -> Instant + Send + 'static,
+    {
+        self.ops.set_pending_clock(Box::new(clock));
+        self
+    }
+
+    /// Sets whether `PartialOp::Err(WouldBlock)` ops are returned to the caller as errors.
+    ///
+    /// By default, a `WouldBlock` op is turned into `Poll::Pending`, and the task is scheduled to
+    /// be woken up right away, as the `AsyncRead` contract requires. With this set to true, the
+    /// `poll_` method instead returns `Poll::Ready(Err(e))` with `e.kind()` being `WouldBlock`,
+    /// the same way other error kinds are returned. The task is not woken up in that case.
+    ///
+    /// This is useful for testing adapters between async I/O and synchronous non-blocking I/O,
+    /// where `WouldBlock` errors are propagated up the stack.
+    pub fn set_wouldblock_as_error(&mut self, as_error: bool) -> &mut Self {
+        self.ops.set_wouldblock_as_error(as_error);
+        self
+    }
+
+    /// Randomly merges consecutive `PartialOp::Limited` ops into larger transfers.
+    ///
+    /// With `Some((p, seed))`, whenever a `PartialOp::Limited` op is applied, it is merged with
+    /// the next op with probability `p` if that op is also `PartialOp::Limited`, and so on, so a
+    /// run of `Limited(1)` ops may turn into a single `Limited(3)`. Any other op, including an
+    /// error, stops the merging. The coin flips are derived from `seed`, so they're reproducible.
+    ///
+    /// This adds some larger chunks to a fine-grained schedule without rewriting it, to exercise
+    /// code paths that only small transfers would miss. Merged ops count as consumed.
+    /// [`next_limit`](Self::next_limit) reports the limit before merging.
+    ///
+    /// Pass in `None`, the default, to turn merging off.
+    pub fn set_coalesce_prob(&mut self, prob: Option<(f64, u64)>) -> &mut Self {
+        self.ops.set_coalesce_prob(prob);
+        self
+    }
+
+    /// Attaches a [`Gate`] that holds up the next read through this reader until it's opened.
+    ///
+    /// While the gate is closed, `poll_read` returns `Poll::Pending` without consuming any
+    /// `PartialOp`s, and the task is woken up once [`GateTrigger::open`](crate::GateTrigger::open)
+    /// is called. The gate is removed once a `poll_read` call finds it open. Replaces any gate
+    /// attached earlier. See [`gate`](crate::gate) for an example.
+    pub fn set_gate(&mut self, gate: Gate) -> &mut Self {
+        self.ops.set_gate(gate);
+        self
+    }
+
+    /// Sets whether the outcome of every `poll_` method call on this reader is recorded.
+    ///
+    /// While enabled, each call to `poll_read` appends a [`PollOutcome`] to a trace, which can be
+    /// retrieved with [`take_poll_trace`](Self::take_poll_trace). This shows the exact sequence
+    /// of `Poll::Pending` and `Poll::Ready` results seen by the caller, which the `PartialOp`s
+    /// alone don't. Disabling tracing discards any outcomes recorded so far.
+    pub fn set_poll_trace(&mut self, trace: bool) -> &mut Self {
+        self.ops.set_poll_trace(trace);
+        self
+    }
+
+    /// Returns the outcomes recorded since tracing was enabled or this method was last called,
+    /// and clears them.
+    ///
+    /// Returns an empty `Vec` if tracing is disabled.
+    pub fn take_poll_trace(&mut self) -> Vec<PollOutcome> {
+        self.ops.take_poll_trace()
+    }
+
+    /// Caps the number of bytes a `PartialOp::Unlimited` op lets through in a single call.
+    ///
+    /// With a cap of `Some(cap)`, every `PartialOp::Unlimited` op is treated as
+    /// `PartialOp::Limited(cap)`, and so are calls made after the ops have run out. This bounds
+    /// the size of any single transfer, for example to keep fuzz harnesses from making huge
+    /// allocations. Only `PartialOp::Unlimited` is affected: `PartialOp::Limited(n)` is
+    /// applied as is even if `n` is larger than the cap.
+    ///
+    /// Pass in `None`, the default, to remove the cap.
+    pub fn set_unlimited_cap(&mut self, cap: Option<usize>) -> &mut Self {
+        self.ops.set_unlimited_cap(cap);
+        self
+    }
+
+    /// Sets what this reader does once its `PartialOp`s have run out.
+    ///
+    /// By default, calls are forwarded to the inner reader without limits or errors, as with
+    /// `ExhaustedBehavior::Unlimited`. With `ExhaustedBehavior::Panic`, the poll after the ops
+    /// run out panics, which catches sequences of ops that are too short for the test. With
+    /// `ExhaustedBehavior::Repeat(op)`, `op` is applied to every call from then on.
+    pub fn set_exhausted_behavior(&mut self, behavior: ExhaustedBehavior) -> &mut Self {
+        self.ops.set_exhausted_behavior(behavior);
+        self
+    }
+
+    /// Sets whether limits that are powers of two are adjusted down before being applied.
+    ///
+    /// With this set to true, whenever a `PartialOp::Limited` op would pass a buffer whose length
+    /// is a power of two greater than 1 to the inner reader, the length is reduced by one, so
+    /// for example 8 becomes 7. This is a cheap way to shake out code that assumes reads come
+    /// in power-of-two sizes, such as alignment-sensitive parsers. The limit is adjusted after
+    /// it's been truncated to the length of the caller's buffer.
+    ///
+    /// This only affects `PartialOp::Limited` ops (including `PartialOp::Unlimited` ops turned
+    /// into limits by [`set_unlimited_cap`](Self::set_unlimited_cap)). Unlimited reads, and
+    /// limits of 0 or 1, are left alone. The inner reader may still return fewer bytes than
+    /// the limit, including a power of two.
+    pub fn set_avoid_power_of_two(&mut self, avoid: bool) -> &mut Self {
+        self.avoid_power_of_two = avoid;
+        self
+    }
+
+    /// Sets whether bytes delivered by this reader are captured.
+    ///
+    /// While capturing is enabled, every byte returned to the caller by `poll_read` (after any
+    /// `PartialOp::Limited` truncation) is also appended to an internal buffer, which can be
+    /// retrieved with [`take_captured`](Self::take_captured). Disabling capturing discards any
+    /// bytes captured so far.
+    ///
+    /// Bytes observed through `poll_fill_buf` and `consume` are not captured.
+    pub fn set_capture(&mut self, capture: bool) -> &mut Self {
+        match (capture, self.captured.is_some()) {
+            (true, false) => self.captured = Some(Vec::new()),
+            (false, true) => self.captured = None,
+            _ => {}
+        }
+        self
+    }
+
+    /// Returns the bytes captured so far, leaving the capture buffer empty.
+    ///
+    /// Returns an empty `Vec` if capturing is disabled.
+    pub fn take_captured(&mut self) -> Vec<u8> {
+        self.captured
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
     /// Returns a shared reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         &self.inner
@@ -136,9 +557,10 @@ impl<R> PartialAsyncRead<R> {
 // Futures impls
 // ---
 
-impl<R> AsyncRead for PartialAsyncRead<R>
+impl<R, I> AsyncRead for PartialAsyncRead<R, I>
 where
     R: AsyncRead,
+    I: Iterator<Item = PartialOp>,
 {
     #[inline]
     fn poll_read(
@@ -147,48 +569,147 @@ where
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
         let this = self.project();
+        if this.ops.poll_gate(cx).is_pending() {
+            return Poll::Pending;
+        }
         let inner = this.inner;
         let len = buf.len();
+        let avoid = *this.avoid_power_of_two;
 
-        this.ops.poll_impl(
+        let res = this.ops.poll_impl(
             cx,
             |cx, len| match len {
+                Some(len) if avoid => inner.poll_read(cx, &mut buf[..avoid_power_of_two(len)]),
                 Some(len) => inner.poll_read(cx, &mut buf[..len]),
                 None => inner.poll_read(cx, buf),
             },
             len,
             "error during poll_read, generated by partial-io",
-        )
+        );
+        if let Poll::Ready(Ok(n)) = &res {
+            this.ops.add_transferred(*n);
+        }
+        this.ops.trace(PollOutcome::from_poll(&res, |n| *n));
+        if let (Poll::Ready(Ok(n)), Some(captured)) = (&res, this.captured) {
+            captured.extend_from_slice(&buf[..*n]);
+        }
+        if let (Poll::Ready(res), Some(observer)) = (&res, this.observer) {
+            let transferred = *res.as_ref().unwrap_or(&0);
+            observer.observe(this.ops.last_op(), len, transferred);
+        }
+        res
     }
 
-    // TODO: do we need to implement poll_read_vectored? It's a bit tricky to do.
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &mut [io::IoSliceMut],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        if this.ops.poll_gate(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let inner = this.inner;
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let avoid = *this.avoid_power_of_two;
+
+        // The op is applied to the read as a whole, so a limit covers all the slices together.
+        let res = this.ops.poll_impl(
+            cx,
+            |cx, len| {
+                let len = match len {
+                    Some(len) if avoid => avoid_power_of_two(len),
+                    Some(len) => len,
+                    None => total,
+                };
+                if len == total {
+                    inner.poll_read_vectored(cx, bufs)
+                } else {
+                    inner.poll_read_vectored(cx, &mut limit_io_slices_mut(bufs, len))
+                }
+            },
+            total,
+            "error during poll_read_vectored, generated by partial-io",
+        );
+        if let Poll::Ready(Ok(n)) = &res {
+            this.ops.add_transferred(*n);
+        }
+        this.ops.trace(PollOutcome::from_poll(&res, |n| *n));
+        if let (Poll::Ready(Ok(n)), Some(captured)) = (&res, this.captured) {
+            let mut left = *n;
+            for buf in bufs.iter() {
+                let len = cmp::min(buf.len(), left);
+                captured.extend_from_slice(&buf[..len]);
+                left -= len;
+            }
+        }
+        if let (Poll::Ready(res), Some(observer)) = (&res, this.observer) {
+            let transferred = *res.as_ref().unwrap_or(&0);
+            observer.observe(this.ops.last_op(), total, transferred);
+        }
+        res
+    }
 }
 
-impl<R> AsyncBufRead for PartialAsyncRead<R>
+/// `poll_fill_buf` consumes a `PartialOp`, and `PartialOp::Limited(n)` truncates the buffer
+/// returned by the underlying reader to `n` bytes. Until `consume` is called, later calls to
+/// `poll_fill_buf` return the same buffer without consuming another op.
+impl<R, I> AsyncBufRead for PartialAsyncRead<R, I>
 where
     R: AsyncBufRead,
+    I: Iterator<Item = PartialOp>,
 {
     fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
         let this = self.project();
         let inner = this.inner;
 
-        this.ops.poll_impl_no_limit(
+        if let Some(limit) = *this.fill_buf_limit {
+            // Callers such as futures' FillBuf rely on getting the same buffer back.
+            return match limit {
+                Some(len) => inner
+                    .poll_fill_buf(cx)
+                    .map_ok(|buf| &buf[..cmp::min(len, buf.len())]),
+                None => inner.poll_fill_buf(cx),
+            };
+        }
+
+        // PartialOp::Eof doesn't call into the closure, and returns an empty buffer.
+        let mut limit = Some(0);
+        // The length of the buffer isn't known until the underlying reader returns it.
+        let res = this.ops.poll_impl(
             cx,
-            |cx| inner.poll_fill_buf(cx),
-            "error during poll_read, generated by partial-io",
-        )
+            |cx, len| {
+                limit = len;
+                match len {
+                    Some(len) => inner
+                        .poll_fill_buf(cx)
+                        .map_ok(|buf| &buf[..cmp::min(len, buf.len())]),
+                    None => inner.poll_fill_buf(cx),
+                }
+            },
+            usize::MAX,
+            "error during poll_fill_buf, generated by partial-io",
+        );
+        if let Poll::Ready(Ok(_)) = &res {
+            *this.fill_buf_limit = Some(limit);
+        }
+        res
     }
 
     #[inline]
     fn consume(self: Pin<&mut Self>, amt: usize) {
-        self.project().inner.consume(amt)
+        let this = self.project();
+        *this.fill_buf_limit = None;
+        this.ops.add_transferred(amt);
+        this.inner.consume(amt)
     }
 }
 
 /// This is a forwarding impl to support duplex structs.
-impl<R> AsyncWrite for PartialAsyncRead<R>
+impl<R, I> AsyncWrite for PartialAsyncRead<R, I>
 where
     R: AsyncWrite,
+    I: Iterator<Item = PartialOp>,
 {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
         self.project().inner.poll_write(cx, buf)
@@ -204,9 +725,10 @@ where
 }
 
 /// This is a forwarding impl to support duplex structs.
-impl<R> AsyncSeek for PartialAsyncRead<R>
+impl<R, I> AsyncSeek for PartialAsyncRead<R, I>
 where
     R: AsyncSeek,
+    I: Iterator<Item = PartialOp>,
 {
     #[inline]
     fn poll_seek(
@@ -218,6 +740,26 @@ where
     }
 }
 
+/// This is a forwarding impl, to allow wrapping types that are both `AsyncRead` and `Stream`.
+/// `PartialOp`s aren't applied to, or consumed by, the `Stream` methods.
+impl<R, I> Stream for PartialAsyncRead<R, I>
+where
+    R: Stream,
+    I: Iterator<Item = PartialOp>,
+{
+    type Item = R::Item;
+
+    #[inline]
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.project().inner.poll_next(cx)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
 // ---
 // Tokio impls
 // ---
@@ -225,16 +767,21 @@ where
 #[cfg(feature = "tokio1")]
 pub(crate) mod tokio_impl {
     use super::PartialAsyncRead;
+    use crate::futures_util::PollOutcome;
+    use crate::ops::avoid_power_of_two;
+    use crate::PartialOp;
     use std::{
+        cmp,
         io::{self, SeekFrom},
         pin::Pin,
         task::{Context, Poll},
     };
     use tokio::io::{AsyncBufRead, AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
 
-    impl<R> AsyncRead for PartialAsyncRead<R>
+    impl<R, I> AsyncRead for PartialAsyncRead<R, I>
     where
         R: AsyncRead,
+        I: Iterator<Item = PartialOp>,
     {
         fn poll_read(
             self: Pin<&mut Self>,
@@ -242,20 +789,44 @@ pub(crate) mod tokio_impl {
             buf: &mut ReadBuf<'_>,
         ) -> Poll<io::Result<()>> {
             let this = self.project();
+            if this.ops.poll_gate(cx).is_pending() {
+                return Poll::Pending;
+            }
             let inner = this.inner;
             let capacity = buf.capacity();
+            let buf_len = buf.remaining();
+            let filled_before = buf.filled().len();
+            let avoid = *this.avoid_power_of_two;
 
-            this.ops.poll_impl(
+            let res = this.ops.poll_impl(
                 cx,
                 |cx, len| match len {
                     Some(len) => {
+                        let len = if avoid { avoid_power_of_two(len) } else { len };
                         buf.with_limited(len, |limited_buf| inner.poll_read(cx, limited_buf))
                     }
                     None => inner.poll_read(cx, buf),
                 },
                 capacity,
                 "error during poll_read, generated by partial-io",
-            )
+            );
+            if let Poll::Ready(Ok(())) = &res {
+                this.ops.add_transferred(buf.filled().len() - filled_before);
+            }
+            this.ops.trace(PollOutcome::from_poll(&res, |()| {
+                buf.filled().len() - filled_before
+            }));
+            if let (Poll::Ready(Ok(())), Some(captured)) = (&res, this.captured) {
+                captured.extend_from_slice(&buf.filled()[filled_before..]);
+            }
+            if let (Poll::Ready(res), Some(observer)) = (&res, this.observer) {
+                let transferred = match res {
+                    Ok(()) => buf.filled().len() - filled_before,
+                    Err(_) => 0,
+                };
+                observer.observe(this.ops.last_op(), buf_len, transferred);
+            }
+            res
         }
     }
 
@@ -328,30 +899,63 @@ pub(crate) mod tokio_impl {
         }
     }
 
-    impl<R> AsyncBufRead for PartialAsyncRead<R>
+    /// `poll_fill_buf` consumes a `PartialOp`, and `PartialOp::Limited(n)` truncates the buffer
+    /// returned by the underlying reader to `n` bytes. Until `consume` is called, later calls to
+    /// `poll_fill_buf` return the same buffer without consuming another op.
+    impl<R, I> AsyncBufRead for PartialAsyncRead<R, I>
     where
         R: AsyncBufRead,
+        I: Iterator<Item = PartialOp>,
     {
         fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
             let this = self.project();
             let inner = this.inner;
 
-            this.ops.poll_impl_no_limit(
+            if let Some(limit) = *this.fill_buf_limit {
+                return match limit {
+                    Some(len) => inner
+                        .poll_fill_buf(cx)
+                        .map_ok(|buf| &buf[..cmp::min(len, buf.len())]),
+                    None => inner.poll_fill_buf(cx),
+                };
+            }
+
+            // PartialOp::Eof doesn't call into the closure, and returns an empty buffer.
+            let mut limit = Some(0);
+            // The length of the buffer isn't known until the underlying reader returns it.
+            let res = this.ops.poll_impl(
                 cx,
-                |cx| inner.poll_fill_buf(cx),
+                |cx, len| {
+                    limit = len;
+                    match len {
+                        Some(len) => inner
+                            .poll_fill_buf(cx)
+                            .map_ok(|buf| &buf[..cmp::min(len, buf.len())]),
+                        None => inner.poll_fill_buf(cx),
+                    }
+                },
+                usize::MAX,
                 "error during poll_fill_buf, generated by partial-io",
-            )
+            );
+            if let Poll::Ready(Ok(_)) = &res {
+                *this.fill_buf_limit = Some(limit);
+            }
+            res
         }
 
         fn consume(self: Pin<&mut Self>, amt: usize) {
-            self.project().inner.consume(amt)
+            let this = self.project();
+            *this.fill_buf_limit = None;
+            this.ops.add_transferred(amt);
+            this.inner.consume(amt)
         }
     }
 
     /// This is a forwarding impl to support duplex structs.
-    impl<R> AsyncWrite for PartialAsyncRead<R>
+    impl<R, I> AsyncWrite for PartialAsyncRead<R, I>
     where
         R: AsyncWrite,
+        I: Iterator<Item = PartialOp>,
     {
         #[inline]
         fn poll_write(
@@ -374,9 +978,10 @@ pub(crate) mod tokio_impl {
     }
 
     /// This is a forwarding impl to support duplex structs.
-    impl<R> AsyncSeek for PartialAsyncRead<R>
+    impl<R, I> AsyncSeek for PartialAsyncRead<R, I>
     where
         R: AsyncSeek,
+        I: Iterator<Item = PartialOp>,
     {
         #[inline]
         fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
@@ -583,14 +1188,18 @@ pub(crate) mod tokio_impl {
     }
 }
 
-impl<R> fmt::Debug for PartialAsyncRead<R>
+impl<R, I> fmt::Debug for PartialAsyncRead<R, I>
 where
     R: fmt::Debug,
+    I: Iterator<Item = PartialOp>,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("PartialAsyncRead")
-            .field("inner", &self.inner)
-            .finish()
+        let mut f = f.debug_struct("PartialAsyncRead");
+        f.field("inner", &self.inner);
+        if let Some(phase) = self.ops.current_phase() {
+            f.field("phase", &format_args!("{}", phase));
+        }
+        f.finish()
     }
 }
 
@@ -606,4 +1215,477 @@ mod tests {
     fn test_sendable() {
         assert_send::<PartialAsyncRead<File>>();
     }
+
+    #[test]
+    fn test_chaos() {
+        use futures::io::AsyncRead;
+
+        let data: Vec<u8> = (0..=255).collect();
+        let run = |seed| {
+            let waker = futures::task::noop_waker();
+            let mut cx = Context::from_waker(&waker);
+            let mut partial_read = PartialAsyncRead::chaos(&data[..], seed);
+            let mut buf = [0; 64];
+            let mut results = Vec::new();
+            for _ in 0..200 {
+                let res = match Pin::new(&mut partial_read).poll_read(&mut cx, &mut buf) {
+                    Poll::Ready(Ok(n)) => Ok(buf[..n].to_vec()),
+                    Poll::Ready(Err(err)) => Err(Some(err.kind())),
+                    Poll::Pending => Err(None),
+                };
+                results.push(res);
+            }
+            (results, partial_read.errors_seen().to_vec())
+        };
+
+        let (results, errors_seen) = run(1);
+        assert_eq!(
+            run(1),
+            (results.clone(), errors_seen.clone()),
+            "reproducible"
+        );
+        assert_ne!(run(2).0, results);
+
+        assert!(results.iter().any(|res| res.is_ok()));
+        assert!(results.contains(&Err(None)), "pending polls");
+        for kind in [io::ErrorKind::Interrupted, io::ErrorKind::WouldBlock] {
+            assert!(errors_seen.contains(&kind), "{:?} seen", kind);
+        }
+        assert!(
+            errors_seen.iter().any(|kind| !matches!(
+                kind,
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+            )),
+            "hard errors seen"
+        );
+    }
+
+    #[test]
+    fn test_poll_trace() {
+        use futures::io::AsyncRead;
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        partial_read.set_poll_trace(true);
+        let mut buf = [0; 16];
+        for _ in 0..4 {
+            let _ = Pin::new(&mut partial_read).poll_read(&mut cx, &mut buf);
+        }
+        assert_eq!(
+            partial_read.take_poll_trace(),
+            vec![
+                PollOutcome::Pending,
+                PollOutcome::Ready(3),
+                // Interrupted errors are retried within the same poll.
+                PollOutcome::Err(io::ErrorKind::BrokenPipe),
+                PollOutcome::Ready(10),
+            ]
+        );
+        assert!(
+            partial_read.take_poll_trace().is_empty(),
+            "trace was cleared"
+        );
+
+        partial_read.set_poll_trace(false);
+        let _ = Pin::new(&mut partial_read).poll_read(&mut cx, &mut buf);
+        assert!(partial_read.take_poll_trace().is_empty(), "tracing is off");
+    }
+
+    #[test]
+    fn test_eof() {
+        use futures::io::AsyncRead;
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let ops = vec![PartialOp::Limited(5), PartialOp::Eof];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        let mut buf = [0; 16];
+        let mut reads = Vec::new();
+        for _ in 0..3 {
+            match Pin::new(&mut partial_read).poll_read(&mut cx, &mut buf) {
+                Poll::Ready(Ok(n)) => reads.push(buf[..n].to_vec()),
+                other => panic!("expected a successful read, got {:?}", other),
+            }
+        }
+        assert_eq!(reads, [&b"Hello"[..], b"", b", world!"]);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        use futures::{executor::block_on, io::AsyncReadExt};
+
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        let mut buf = [0; 8];
+        block_on(async {
+            assert_eq!(partial_read.read(&mut buf).await.unwrap(), 2);
+            assert_eq!(partial_read.read(&mut buf).await.unwrap(), 3);
+        });
+
+        let diagnostics = partial_read.diagnostics();
+        assert_eq!(diagnostics.ops_consumed, 3);
+        assert_eq!(diagnostics.last_op, Some(PartialOp::Limited(3)));
+        assert_eq!(diagnostics.transferred, 5);
+        assert_eq!(diagnostics.error_offsets, [(io::ErrorKind::Interrupted, 2)]);
+        assert_eq!(diagnostics.flushes, None);
+        assert_eq!(diagnostics.total_limit_remaining, None);
+    }
+
+    #[test]
+    fn test_buf_read() {
+        use futures::{executor::block_on, io::AsyncBufReadExt};
+
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(3),
+            PartialOp::Eof,
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(4),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!\n"[..], ops);
+        block_on(async {
+            let mut chunks = Vec::new();
+            for _ in 0..3 {
+                let buf = partial_read.fill_buf().await.unwrap();
+                let len = buf.len();
+                chunks.push(buf.to_vec());
+                partial_read.consume_unpin(len);
+            }
+            assert_eq!(chunks, [&b"He"[..], b"llo", b""]);
+
+            // read_until has to stitch the line together from limited fills.
+            let mut line = Vec::new();
+            partial_read.read_until(b'\n', &mut line).await.unwrap();
+            assert_eq!(line, b", world!\n");
+        });
+        assert_eq!(partial_read.consumed_ops(), 6);
+        assert_eq!(partial_read.bytes_transferred(), 14);
+    }
+
+    #[test]
+    fn test_pending_once() {
+        use crate::WakerProbe;
+        use futures::io::AsyncRead;
+
+        let probe = WakerProbe::new();
+        let waker = probe.waker();
+        let mut cx = Context::from_waker(&waker);
+        let ops = vec![
+            PartialOp::PendingOnce,
+            PartialOp::Limited(5),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        let mut buf = [0; 16];
+
+        assert!(Pin::new(&mut partial_read)
+            .poll_read(&mut cx, &mut buf)
+            .is_pending());
+        assert_eq!(probe.wakes(), 0, "PendingOnce doesn't wake the task");
+        match Pin::new(&mut partial_read).poll_read(&mut cx, &mut buf) {
+            Poll::Ready(Ok(n)) => assert_eq!(&buf[..n], b"Hello"),
+            other => panic!("expected a successful read, got {:?}", other),
+        }
+
+        // By contrast, WouldBlock wakes the task right away.
+        assert!(Pin::new(&mut partial_read)
+            .poll_read(&mut cx, &mut buf)
+            .is_pending());
+        assert_eq!(probe.wakes(), 1);
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test]
+    async fn test_eof_tokio() {
+        use tokio::io::AsyncReadExt;
+
+        let ops = vec![PartialOp::Eof, PartialOp::Limited(5)];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        let mut out = Vec::new();
+        let n = AsyncReadExt::read_to_end(&mut partial_read, &mut out).await;
+        assert_eq!(n.unwrap(), 0);
+        let n = AsyncReadExt::read_to_end(&mut partial_read, &mut out).await;
+        assert_eq!(n.unwrap(), 13);
+        assert_eq!(out, b"Hello, world!");
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test]
+    async fn test_buf_read_tokio() {
+        use tokio::io::AsyncBufReadExt;
+
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(4),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello\nworld\n"[..], ops);
+        let mut line = Vec::new();
+        AsyncBufReadExt::read_until(&mut partial_read, b'\n', &mut line)
+            .await
+            .unwrap();
+        assert_eq!(line, b"Hello\n");
+        assert_eq!(partial_read.consumed_ops(), 3);
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test(start_paused = true)]
+    async fn test_delay() {
+        use crate::WakerProbe;
+        use futures::{io::AsyncReadExt, FutureExt};
+        use std::time::Duration;
+
+        let ops = vec![
+            PartialOp::Delay(Duration::from_millis(50)),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        partial_read
+            .set_poll_trace(true)
+            .set_pending_clock(|| tokio::time::Instant::now().into_std());
+        let mut buf = [0; 16];
+
+        let probe = WakerProbe::new();
+        let waker = probe.waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut read = partial_read.read(&mut buf);
+        assert!(read.poll_unpin(&mut cx).is_pending());
+
+        // The task must only be woken up once the delay has elapsed.
+        tokio::time::advance(Duration::from_millis(49)).await;
+        assert_eq!(probe.wakes(), 0);
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert_eq!(probe.wakes(), 1);
+        match read.poll_unpin(&mut cx) {
+            Poll::Ready(res) => assert_eq!(res.unwrap(), 3),
+            Poll::Pending => panic!("delay elapsed, so the read must be ready"),
+        }
+        assert_eq!(&buf[..3], b"Hel");
+        assert_eq!(
+            partial_read.take_poll_trace(),
+            [PollOutcome::Pending, PollOutcome::Ready(3)]
+        );
+        assert_eq!(partial_read.pending_duration(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_delay_timer_thread() {
+        use crate::{assert_yielded, WakerProbe};
+        use futures::{io::AsyncReadExt, FutureExt};
+        use std::time::{Duration, Instant};
+
+        // Outside a Tokio runtime, delays are tracked by the shared timer thread. Start a long
+        // delay first, to check that a shorter one registered later isn't held up behind it.
+        let ops = vec![PartialOp::Delay(Duration::from_secs(3600))];
+        let mut long_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        let mut long_buf = [0; 16];
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut long_future = long_read.read(&mut long_buf);
+        assert!(long_future.poll_unpin(&mut cx).is_pending());
+
+        let ops = vec![
+            PartialOp::Delay(Duration::from_millis(5)),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        let mut buf = [0; 16];
+        let probe = WakerProbe::new();
+        let start = Instant::now();
+        assert_eq!(
+            assert_yielded(partial_read.read(&mut buf), &probe).unwrap(),
+            3
+        );
+        assert!(start.elapsed() >= Duration::from_millis(5));
+        assert_eq!(probe.wakes(), 1);
+    }
+
+    #[test]
+    fn test_read_vectored() {
+        use futures::io::AsyncRead;
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(6),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello, world!"[..], ops);
+        partial_read.set_capture(true);
+        let mut first = [0; 4];
+        let mut second = [0; 4];
+
+        let mut bufs = [
+            io::IoSliceMut::new(&mut first),
+            io::IoSliceMut::new(&mut second),
+        ];
+        let res = Pin::new(&mut partial_read).poll_read_vectored(&mut cx, &mut bufs);
+        assert!(matches!(res, Poll::Ready(Ok(3))));
+        assert_eq!(&first, b"Hel\0", "the first slice is partially filled");
+        assert_eq!(&second, &[0; 4], "the second slice is untouched");
+        assert_eq!(partial_read.consumed_ops(), 1, "one op per vectored read");
+
+        let mut bufs = [
+            io::IoSliceMut::new(&mut first),
+            io::IoSliceMut::new(&mut second),
+        ];
+        let res = Pin::new(&mut partial_read).poll_read_vectored(&mut cx, &mut bufs);
+        assert!(matches!(res, Poll::Ready(Ok(6))));
+        assert_eq!(&first, b"lo, ");
+        assert_eq!(&second, b"wo\0\0");
+        assert_eq!(partial_read.take_captured(), b"Hello, wo");
+    }
+
+    #[test]
+    fn test_exhausted_behavior() {
+        use crate::{poll_n_times, ExhaustedBehavior};
+        use futures::io::AsyncReadExt;
+
+        let mut partial_read = PartialAsyncRead::new(&b"Hello"[..], vec![PartialOp::Limited(2)]);
+        partial_read.set_exhausted_behavior(ExhaustedBehavior::Repeat(PartialOp::Err(
+            io::ErrorKind::WouldBlock,
+        )));
+        let mut buf = [0; 8];
+        let (res, _) = poll_n_times(partial_read.read(&mut buf), 1);
+        assert!(matches!(res, Poll::Ready(Ok(2))));
+        let (res, polls) = poll_n_times(partial_read.read(&mut buf), 100);
+        assert!(res.is_pending(), "the reader stays pending");
+        assert_eq!(polls, 100);
+        assert_eq!(partial_read.consumed_ops(), 101);
+    }
+
+    #[test]
+    fn test_stream() {
+        use futures::{executor::block_on, stream::StreamExt};
+
+        let ops = vec![PartialOp::Err(io::ErrorKind::BrokenPipe); 2];
+        let mut partial_read = PartialAsyncRead::new(stream::iter(vec![1, 2, 3]), ops);
+        assert_eq!(partial_read.size_hint(), (3, Some(3)));
+        let items: Vec<_> = block_on(partial_read.by_ref().collect());
+        assert_eq!(items, vec![1, 2, 3]);
+        assert_eq!(
+            partial_read.consumed_ops(),
+            0,
+            "stream methods don't consume ops"
+        );
+    }
+
+    #[test]
+    fn test_wouldblock_as_error() {
+        use futures::io::AsyncRead;
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+        ];
+        let mut partial_read = PartialAsyncRead::new(&b"Hello"[..], ops);
+        let mut buf = [0; 8];
+
+        assert!(Pin::new(&mut partial_read)
+            .poll_read(&mut cx, &mut buf)
+            .is_pending());
+
+        partial_read.set_wouldblock_as_error(true);
+        match Pin::new(&mut partial_read).poll_read(&mut cx, &mut buf) {
+            Poll::Ready(Err(err)) => assert_eq!(err.kind(), io::ErrorKind::WouldBlock),
+            other => panic!("expected a WouldBlock error, got {:?}", other),
+        }
+        assert_eq!(
+            partial_read.errors_seen(),
+            &[io::ErrorKind::WouldBlock, io::ErrorKind::WouldBlock]
+        );
+
+        match Pin::new(&mut partial_read).poll_read(&mut cx, &mut buf) {
+            Poll::Ready(Ok(n)) => assert_eq!(&buf[..n], b"Hello"),
+            other => panic!("expected a successful read, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test]
+    async fn test_capture() {
+        use tokio::io::AsyncReadExt;
+
+        let data = b"Hello, world!".to_vec();
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(1),
+            PartialOp::Limited(5),
+        ];
+        let mut partial_read = PartialAsyncRead::new(std::io::Cursor::new(data.clone()), ops);
+        partial_read.set_capture(true);
+
+        let mut out = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            let n = partial_read.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, data);
+        assert_eq!(
+            partial_read.take_captured(),
+            data,
+            "captured bytes match source"
+        );
+    }
+
+    #[test]
+    fn test_unlimited_fast_path() {
+        use futures::io::AsyncRead;
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let data = b"Hello, world! Goodbye, world!";
+        let ops = vec![
+            PartialOp::Unlimited,
+            PartialOp::Unlimited,
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+            PartialOp::Eof,
+            PartialOp::Unlimited,
+        ];
+        // Runs of Unlimited ops take the fast path.
+        let mut fast = PartialAsyncRead::new(&data[..], ops.clone());
+        // A cap of usize::MAX turns Unlimited ops into limits that don't change anything, but
+        // makes them take the general path.
+        let mut general = PartialAsyncRead::new(&data[..], ops);
+        general.set_unlimited_cap(Some(usize::MAX));
+
+        for i in 0..12 {
+            let mut fast_buf = [0; 4];
+            let mut general_buf = [0; 4];
+            let fast_poll = Pin::new(&mut fast).poll_read(&mut cx, &mut fast_buf);
+            let general_poll = Pin::new(&mut general).poll_read(&mut cx, &mut general_buf);
+            assert_eq!(
+                fast_poll.map_err(|err| err.kind()),
+                general_poll.map_err(|err| err.kind()),
+                "poll_read call {}",
+                i
+            );
+            assert_eq!(fast_buf, general_buf, "poll_read call {}", i);
+            assert_eq!(fast.consumed_ops(), general.consumed_ops(), "call {}", i);
+            assert_eq!(fast.bytes_transferred(), general.bytes_transferred());
+        }
+    }
 }
@@ -7,13 +7,21 @@
 //! This is separate from `PartialWrite` because on `WouldBlock` errors, it
 //! causes `futures` to try writing or flushing again.
 
-use crate::{futures_util::FuturesOps, PartialOp};
+use crate::{
+    futures_util::{runtime_now, FuturesOps, Gate, PollOutcome, QueueModel},
+    ops::{
+        inspect_write, limit_io_slices, limited_zero_error, BoxedOps, Inspector, OpEvent,
+        OpHistory, OpObserver,
+    },
+    Diagnostics, ExhaustedBehavior, OpSnapshot, PartialOp, PhasePosition, PhasedSchedule,
+};
 use futures::{io, prelude::*};
 use pin_project::pin_project;
 use std::{
-    fmt,
+    fmt, iter,
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 /// A wrapper that breaks inner `AsyncWrite` instances up according to the
@@ -75,10 +83,16 @@ use std::{
 /// # }
 /// ```
 #[pin_project]
-pub struct PartialAsyncWrite<W> {
+pub struct PartialAsyncWrite<W, I = BoxedOps> {
     #[pin]
     inner: W,
-    ops: FuturesOps,
+    ops: FuturesOps<I>,
+    inspector: Option<Inspector>,
+    observer: Option<OpObserver>,
+    flush_interrupts: usize,
+    flush_ops: Option<FuturesOps>,
+    close_ops: Option<FuturesOps>,
+    queue: Option<QueueModel>,
 }
 
 impl<W> PartialAsyncWrite<W> {
@@ -88,10 +102,17 @@ impl<W> PartialAsyncWrite<W> {
         I: IntoIterator<Item = PartialOp> + 'static,
         I::IntoIter: Send,
     {
-        PartialAsyncWrite {
-            inner,
-            ops: FuturesOps::new(iter),
-        }
+        Self::new_unboxed(inner, Box::new(iter.into_iter()))
+    }
+
+    /// Creates a new `PartialAsyncWrite` wrapper that accepts at most one byte at a time, without
+    /// any errors.
+    ///
+    /// This is the strictest deterministic chunking possible, and is a good way to shake out
+    /// bugs around write boundaries. It is equivalent to passing in
+    /// `std::iter::repeat(PartialOp::Limited(1))` as the `PartialOp`s.
+    pub fn pinhole(inner: W) -> Self {
+        Self::new(inner, iter::repeat(PartialOp::Limited(1)))
     }
 
     /// Sets the `PartialOp`s for this writer.
@@ -115,6 +136,497 @@ impl<W> PartialAsyncWrite<W> {
         this
     }
 
+    /// Computes each `PartialOp` for this writer with a callback, replacing any existing ops.
+    ///
+    /// Before each op is applied, `op_fn` is passed an [`OpHistory`] describing the ops applied
+    /// since the callback was set, the number of bytes transferred and the errors seen so far,
+    /// and returns the op to apply next. This makes it possible to express stateful fault models,
+    /// such as failing only after a run of successful transfers, that a fixed sequence of ops
+    /// can't. The callback is called whenever an op is needed, so the ops never run out.
+    ///
+    /// Setting ops any other way removes the callback.
+    pub fn set_op_fn<F>(&mut self, op_fn: F) -> &mut Self
+    where
+        F: FnMut(&OpHistory<'_>) -> PartialOp + Send + 'static,
+    {
+        self.ops.replace_with_fn(Box::new(op_fn));
+        self
+    }
+
+    /// Sets the `PartialOp`s for this writer from a [`PhasedSchedule`], and starts tracking
+    /// which phase the last consumed op belongs to.
+    ///
+    /// The current phase is reported by [`current_phase`](Self::current_phase), this writer's
+    /// `Debug` output and [`assert_op_count`](Self::assert_op_count) failure messages. Setting
+    /// ops any other way stops tracking phases.
+    pub fn set_phased_ops(&mut self, schedule: PhasedSchedule) -> &mut Self {
+        self.ops.replace_phased(schedule);
+        self
+    }
+
+    /// Returns a copy of the `PartialOp`s that this writer hasn't consumed yet.
+    ///
+    /// The copy is independent of this writer: passing it to a fresh `PartialAsyncWrite` produces a
+    /// second writer that continues from the same point in the schedule, for branching tests.
+    /// Unlike [`snapshot`](Self::snapshot), only the ops are copied, not the errors seen so far. A
+    /// latched error isn't part of the schedule either, and isn't carried over.
+    ///
+    /// This collects the remaining `PartialOp`s into a buffer, so they must be finite: with an
+    /// infinite iterator, this method never returns.
+    pub fn fork_schedule(&mut self) -> Vec<PartialOp> {
+        self.ops.fork()
+    }
+
+    /// Returns a snapshot of this writer's position within its `PartialOp`s, along with the
+    /// errors seen so far.
+    ///
+    /// Pass the snapshot to [`restore`](Self::restore) to return to this position, for example to
+    /// explore several branches from the same point. The state of the underlying writer is not
+    /// part of the snapshot.
+    ///
+    /// Taking a snapshot collects the remaining `PartialOp`s into a buffer, so they must be
+    /// finite: with an infinite iterator, this method never returns.
+    pub fn snapshot(&mut self) -> OpSnapshot {
+        self.ops.snapshot()
+    }
+
+    /// Restores the position and errors seen captured by [`snapshot`](Self::snapshot).
+    pub fn restore(&mut self, snapshot: &OpSnapshot) -> &mut Self {
+        self.ops.restore(snapshot);
+        self
+    }
+}
+
+impl<W, I> PartialAsyncWrite<W, I>
+where
+    I: Iterator<Item = PartialOp>,
+{
+    /// Creates a new `PartialAsyncWrite` wrapper over the writer with the specified `PartialOp`s,
+    /// without boxing the iterator.
+    ///
+    /// See [`PartialRead::new_unboxed`](crate::PartialRead::new_unboxed) for more.
+    pub fn new_unboxed(inner: W, iter: I) -> Self {
+        PartialAsyncWrite {
+            inner,
+            ops: FuturesOps::new_unboxed(iter),
+            inspector: None,
+            observer: None,
+            flush_interrupts: 0,
+            flush_ops: None,
+            close_ops: None,
+            queue: None,
+        }
+    }
+
+    /// Attaches a callback that observes every `poll_write` call that returns `Poll::Ready`.
+    ///
+    /// `inspector` is called with the `PartialOp` that applied to the call and the bytes the
+    /// underlying writer accepted, which is empty if the call failed. Once the ops have run out,
+    /// writes are reported as `PartialOp::Unlimited`. Calls that return `Poll::Pending` are not
+    /// reported.
+    ///
+    /// This makes it possible to observe the data passing through write-only writers, such as
+    /// sockets, that can't be read back afterwards.
+    pub fn with_inspector<F>(mut self, inspector: F) -> Self
+    where
+        F: FnMut(&PartialOp, &[u8]) + Send + 'static,
+    {
+        self.inspector = Some(Box::new(inspector));
+        self
+    }
+
+    /// Attaches a callback that is passed an [`OpEvent`] after each `poll_write` call that returns
+    /// `Poll::Ready`.
+    ///
+    /// The event describes the `PartialOp` that applied, the number of bytes written so far, the
+    /// length of the caller's buffer and the number of bytes written by the call. Calls that return
+    /// `Poll::Pending`, and calls to the flush and close methods, are not reported. Replaces any
+    /// previously attached callback, and starts counting the offset from 0 again.
+    pub fn on_op<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+    {
+        self.observer = Some(OpObserver::new(callback));
+        self
+    }
+
+    /// Like [`on_op`](Self::on_op), but timestamps events with `clock` rather than
+    /// `Instant::now()`.
+    ///
+    /// This makes it possible to correlate events with other traces using the same clock, or to
+    /// control time in tests. `clock` is called once per event, and should return quickly
+    /// without blocking, since it's called from within `poll_write`.
+    pub fn on_op_with_clock<F, C>(&mut self, callback: F, clock: C) -> &mut Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+        C: FnMut() -> Instant + Send + 'static,
+    {
+        self.observer = Some(OpObserver::with_clock(callback, clock));
+        self
+    }
+
+    /// Simulates a bounded internal queue that applies backpressure as it fills up.
+    ///
+    /// The queue holds up to `capacity` bytes, and drains at `drain_rate` bytes per second. Each
+    /// `poll_write` call is limited to the space left in the queue, and the bytes written are
+    /// added to it. If the queue is full, `poll_write` returns `Poll::Pending` without consuming a
+    /// `PartialOp`, and schedules the task to be woken up once the next byte has drained, the
+    /// same way as for a `PartialOp::Delay`. A queue with a `drain_rate` of 0 never drains, so
+    /// once it's full, the task is never woken up. This models a bounded async sink more
+    /// realistically than fixed `PartialOp::Err(WouldBlock)` ops, which can still be used on top
+    /// of it.
+    ///
+    /// Time is measured with Tokio's clock if the `tokio1` feature is enabled, so the queue
+    /// drains according to paused time in tests, and with `Instant::now()` otherwise. The queue
+    /// starts out empty.
+    pub fn set_queue_model(&mut self, capacity: usize, drain_rate: u64) -> &mut Self {
+        self.set_queue_model_with_clock(capacity, drain_rate, runtime_now)
+    }
+
+    /// Like [`set_queue_model`](Self::set_queue_model), but measures time with `clock` rather
+    /// than the default clock.
+    ///
+    /// This makes it possible to control how fast the queue drains in tests.
+    pub fn set_queue_model_with_clock<C>(
+        &mut self,
+        capacity: usize,
+        drain_rate: u64,
+        clock: C,
+    ) -> &mut Self
+    where
+        C: FnMut() -> Instant + Send + 'static,
+    {
+        self.queue = Some(QueueModel::new(capacity, drain_rate, clock));
+        self
+    }
+
+    /// Returns the phase of the last consumed `PartialOp`, and its index within that phase.
+    ///
+    /// Returns `None` unless ops were set with [`set_phased_ops`](Self::set_phased_ops), or if no
+    /// ops have been consumed yet. Once the schedule has run out, this keeps returning the
+    /// position of the last op.
+    pub fn current_phase(&self) -> Option<PhasePosition> {
+        self.ops.current_phase()
+    }
+
+    /// Returns the limit the next `PartialOp` will apply, if it is `PartialOp::Limited`.
+    ///
+    /// `PartialOp::Eof` counts as a limit of 0.
+    ///
+    /// Returns `None` if the next op is `PartialOp::Unlimited`, `PartialOp::Err` or
+    /// `PartialOp::Delay`, or if the ops have run out, unless
+    /// [`set_unlimited_cap`](Self::set_unlimited_cap) turns the op into a limit. This peeks at the
+    /// next op, which pulls it out of the underlying iterator early. The op is not consumed: it
+    /// still applies to the next `poll_` method call.
+    pub fn next_limit(&mut self) -> Option<usize> {
+        self.ops.next_limit()
+    }
+
+    /// Asserts that exactly `expected` `PartialOp`s have been consumed since the ops were last
+    /// set.
+    ///
+    /// Each `poll_` method call consumes one op, except that retried `Interrupted` errors consume
+    /// an op each as well. Calls made after the ops have run out aren't counted. This is useful for
+    /// pinning down golden chunking behavior: with `PartialOp::Limited` ops, a regression in how
+    /// data is chunked changes the number of ops consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics with a message including both counts if they differ.
+    #[track_caller]
+    pub fn assert_op_count(&self, expected: usize) {
+        self.ops.assert_op_count(expected);
+    }
+
+    /// Returns the number of `PartialOp`s consumed since the ops were last set.
+    ///
+    /// This counts ops the same way as [`assert_op_count`](Self::assert_op_count).
+    pub fn consumed_ops(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns the number of `PartialOp`s that haven't been consumed yet, if it is known.
+    ///
+    /// The number is known if the iterator the ops were set from reports an exact size, as
+    /// `ExactSizeIterator`s like `vec::IntoIter` do. Returns `None` for other iterators, including
+    /// infinite ones, and for ops computed by [`set_op_fn`](Self::set_op_fn).
+    pub fn ops_remaining(&self) -> Option<usize> {
+        self.ops.remaining()
+    }
+
+    /// Returns the total number of bytes written through this writer.
+    ///
+    /// This counts the bytes actually transferred by the inner writer, not the amounts requested
+    /// by callers or allowed by `PartialOp`s. Calls that return an error don't count, and neither
+    /// do flushes or closes. The count is kept when the ops are replaced.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.ops.transferred()
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, in the order they fired.
+    ///
+    /// This includes errors that were not returned to the caller, such as retried `Interrupted`
+    /// errors and `WouldBlock` errors translated to `Poll::Pending`. Errors returned by the
+    /// underlying writer are not included.
+    pub fn errors_seen(&self) -> &[io::ErrorKind] {
+        self.ops.errors_seen()
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, along with the number of
+    /// bytes transferred through this writer before each one fired.
+    ///
+    /// This covers the same errors as [`errors_seen`](Self::errors_seen), and helps correlate
+    /// errors with positions in the stream.
+    pub fn error_offsets(&self) -> &[(io::ErrorKind, u64)] {
+        self.ops.error_offsets()
+    }
+
+    /// Sets the kind of error that is sticky once it is injected.
+    ///
+    /// Once a `PartialOp::Err` of this kind is applied, every subsequent operation on this
+    /// writer fails with the same kind of error regardless of the remaining `PartialOp`s, which
+    /// are not consumed. This models unrecoverable failures like a connection dying with
+    /// `BrokenPipe`. The latched error is preserved across [`set_ops`](Self::set_ops), and can be
+    /// reset with [`clear_latch`](Self::clear_latch).
+    ///
+    /// Pass in `None` to stop latching errors. This doesn't clear an error that has already
+    /// latched.
+    ///
+    /// Latching `Interrupted` or `WouldBlock` errors is not useful here: `Interrupted` errors are
+    /// retried forever, and `WouldBlock` errors cause the task to be woken up forever.
+    pub fn set_latch_error(&mut self, kind: Option<io::ErrorKind>) -> &mut Self {
+        self.ops.set_latch_error(kind);
+        self
+    }
+
+    /// Returns the error that has latched, if any.
+    pub fn latched_error(&self) -> Option<io::ErrorKind> {
+        self.ops.latched_error()
+    }
+
+    /// Returns a summary of everything this writer has recorded so far.
+    ///
+    /// This bundles the ops consumed, the last op, the bytes transferred and the errors injected
+    /// into a single report, for printing when a test fails.
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.ops.diagnostics()
+    }
+
+    /// Clears the error that has latched, if any, so that `PartialOp`s apply again.
+    pub fn clear_latch(&mut self) -> &mut Self {
+        self.ops.clear_latch();
+        self
+    }
+
+    /// Returns the total time this writer has spent pending due to `WouldBlock` errors and
+    /// `PartialOp::Delay` ops.
+    ///
+    /// This is the wall-clock time between returning `Poll::Pending` for a `WouldBlock` error or a
+    /// delay and the next call to a `poll_` method, including the time since the last
+    /// `Poll::Pending` if the writer hasn't been polled again yet. Use
+    /// [`set_pending_clock`](Self::set_pending_clock) to measure it with a different clock.
+    pub fn pending_duration(&self) -> Duration {
+        self.ops.pending_duration()
+    }
+
+    /// Measures [`pending_duration`](Self::pending_duration) with `clock` rather than
+    /// `Instant::now()`.
+    ///
+    /// This makes it possible to control time in tests, or to measure time spent pending on the
+    /// same clock as a runtime with paused time. `clock` is called whenever the writer starts or
+    /// stops pending, and should return quickly without blocking. Time spent pending so far
+    /// is kept.
+    pub fn set_pending_clock<C>(&mut self, clock: C) -> &mut Self
+    where
+        C: Fn() -> Instant + Send + 'static,
+    {
+        self.ops.set_pending_clock(Box::new(clock));
+        self
+    }
+
+    /// Sets whether `PartialOp::Err(WouldBlock)` ops are returned to the caller as errors.
+    ///
+    /// By default, a `WouldBlock` op is turned into `Poll::Pending`, and the task is scheduled to
+    /// be woken up right away, as the `AsyncWrite` contract requires. With this set to true, the
+    /// `poll_` method instead returns `Poll::Ready(Err(e))` with `e.kind()` being `WouldBlock`,
+    /// the same way other error kinds are returned. The task is not woken up in that case.
+    ///
+    /// This is useful for testing adapters between async I/O and synchronous non-blocking I/O,
+    /// where `WouldBlock` errors are propagated up the stack.
+    pub fn set_wouldblock_as_error(&mut self, as_error: bool) -> &mut Self {
+        self.ops.set_wouldblock_as_error(as_error);
+        for ops in [&mut self.flush_ops, &mut self.close_ops]
+            .into_iter()
+            .flatten()
+        {
+            ops.set_wouldblock_as_error(as_error);
+        }
+        self
+    }
+
+    /// Randomly merges consecutive `PartialOp::Limited` ops into larger transfers.
+    ///
+    /// With `Some((p, seed))`, whenever a `PartialOp::Limited` op is applied, it is merged with
+    /// the next op with probability `p` if that op is also `PartialOp::Limited`, and so on, so a
+    /// run of `Limited(1)` ops may turn into a single `Limited(3)`. Any other op, including an
+    /// error, stops the merging. The coin flips are derived from `seed`, so they're reproducible.
+    ///
+    /// This adds some larger chunks to a fine-grained schedule without rewriting it, to exercise
+    /// code paths that only small transfers would miss. Merged ops count as consumed.
+    /// [`next_limit`](Self::next_limit) reports the limit before merging.
+    ///
+    /// Pass in `None`, the default, to turn merging off.
+    pub fn set_coalesce_prob(&mut self, prob: Option<(f64, u64)>) -> &mut Self {
+        self.ops.set_coalesce_prob(prob);
+        self
+    }
+
+    /// Attaches a [`Gate`] that holds up the next write through this writer until it's opened.
+    ///
+    /// While the gate is closed, `poll_write` returns `Poll::Pending` without consuming any
+    /// `PartialOp`s, and the task is woken up once [`GateTrigger::open`](crate::GateTrigger::open)
+    /// is called. The gate is removed once a `poll_write` call finds it open. Replaces any gate
+    /// attached earlier. See [`gate`](crate::gate) for an example.
+    pub fn set_gate(&mut self, gate: Gate) -> &mut Self {
+        self.ops.set_gate(gate);
+        self
+    }
+
+    /// Sets whether the outcome of every `poll_` method call on this writer is recorded.
+    ///
+    /// While enabled, each call to `poll_write`, `poll_flush` and `poll_close` (or
+    /// `poll_shutdown`) appends a [`PollOutcome`] to a trace, which can be retrieved with
+    /// [`take_poll_trace`](Self::take_poll_trace). This shows the exact sequence of
+    /// `Poll::Pending` and `Poll::Ready` results seen by the caller, which the `PartialOp`s alone
+    /// don't. Disabling tracing discards any outcomes recorded so far.
+    pub fn set_poll_trace(&mut self, trace: bool) -> &mut Self {
+        self.ops.set_poll_trace(trace);
+        self
+    }
+
+    /// Returns the outcomes recorded since tracing was enabled or this method was last called,
+    /// and clears them.
+    ///
+    /// Returns an empty `Vec` if tracing is disabled.
+    pub fn take_poll_trace(&mut self) -> Vec<PollOutcome> {
+        self.ops.take_poll_trace()
+    }
+
+    /// Caps the number of bytes a `PartialOp::Unlimited` op lets through in a single call.
+    ///
+    /// With a cap of `Some(cap)`, every `PartialOp::Unlimited` op is treated as
+    /// `PartialOp::Limited(cap)`, and so are calls made after the ops have run out. This bounds
+    /// the size of any single transfer, for example to keep fuzz harnesses from making huge
+    /// allocations. Only `PartialOp::Unlimited` is affected: `PartialOp::Limited(n)` is
+    /// applied as is even if `n` is larger than the cap.
+    ///
+    /// Pass in `None`, the default, to remove the cap.
+    pub fn set_unlimited_cap(&mut self, cap: Option<usize>) -> &mut Self {
+        self.ops.set_unlimited_cap(cap);
+        self
+    }
+
+    /// Sets what this writer does once its `PartialOp`s have run out.
+    ///
+    /// By default, calls are forwarded to the inner writer without limits or errors, as with
+    /// `ExhaustedBehavior::Unlimited`. With `ExhaustedBehavior::Panic`, the poll after the ops
+    /// run out panics, which catches sequences of ops that are too short for the test. With
+    /// `ExhaustedBehavior::Repeat(op)`, `op` is applied to every call from then on.
+    pub fn set_exhausted_behavior(&mut self, behavior: ExhaustedBehavior) -> &mut Self {
+        self.ops.set_exhausted_behavior(behavior);
+        self
+    }
+
+    /// Makes the next `n` calls to `poll_flush` fail with `io::ErrorKind::Interrupted`.
+    ///
+    /// These calls don't consume any `PartialOp`s or call into the underlying writer. Once they
+    /// have been used up, `poll_flush` goes back to applying `PartialOp`s as usual. This is a
+    /// targeted way to check that code retries flushes that are interrupted: unlike
+    /// `PartialOp::Err(Interrupted)`, which this wrapper retries internally, these errors are
+    /// returned to the caller.
+    ///
+    /// Interrupted flushes aren't included in [`errors_seen`](Self::errors_seen).
+    pub fn set_flush_interrupts(&mut self, n: usize) -> &mut Self {
+        self.flush_interrupts = n;
+        self
+    }
+
+    /// Returns the number of calls to `poll_flush` that will still fail with
+    /// `io::ErrorKind::Interrupted`.
+    pub fn flush_interrupts_remaining(&self) -> usize {
+        self.flush_interrupts
+    }
+
+    /// Sets separate `PartialOp`s for `poll_flush` and `poll_close` (or `poll_shutdown`).
+    ///
+    /// By default, flushes and closes draw from the same `PartialOp`s as writes. Once this is
+    /// called, they draw from `iter` instead (closes only until
+    /// [`set_close_ops`](Self::set_close_ops) is called), so errors can be injected into flushes
+    /// without disturbing the write schedule. This is useful for wrappers that flush on their own
+    /// schedule, such as `tokio::io::BufWriter`: its buffered writes go through while the flush
+    /// it issues to this writer fails. `PartialOp::Err` ops in `iter` are applied to flushes and
+    /// closes as usual, while `PartialOp::Limited` and `PartialOp::Unlimited` let the call through
+    /// to the underlying writer, as do calls made after `iter` runs out.
+    ///
+    /// Errors injected by these ops aren't included in [`errors_seen`](Self::errors_seen).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "tokio1")]
+    /// use partial_io::{PartialAsyncWrite, PartialOp};
+    /// # #[cfg(feature = "tokio1")]
+    /// use std::io;
+    /// # #[cfg(feature = "tokio1")]
+    /// use tokio::io::{AsyncWriteExt, BufWriter};
+    ///
+    /// # #[cfg(feature = "tokio1")]
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut partial_writer = PartialAsyncWrite::new(Vec::new(), vec![]);
+    ///     partial_writer.set_flush_ops(vec![PartialOp::Err(io::ErrorKind::BrokenPipe)]);
+    ///     let mut buf_writer = BufWriter::new(partial_writer);
+    ///
+    ///     buf_writer.write_all(b"Hello").await.unwrap();
+    ///     let err = buf_writer.flush().await.unwrap_err();
+    ///     assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    /// }
+    ///
+    /// # #[cfg(not(feature = "tokio1"))]
+    /// # fn main() {
+    /// #     assert!(true, "dummy test");
+    /// # }
+    /// ```
+    pub fn set_flush_ops<J>(&mut self, iter: J) -> &mut Self
+    where
+        J: IntoIterator<Item = PartialOp> + 'static,
+        J::IntoIter: Send,
+    {
+        let mut flush_ops = FuturesOps::new(iter);
+        flush_ops.set_wouldblock_as_error(self.ops.wouldblock_as_error());
+        self.flush_ops = Some(flush_ops);
+        self
+    }
+
+    /// Sets separate `PartialOp`s for `poll_close` (or `poll_shutdown`).
+    ///
+    /// Once this is called, closes draw from `iter`, leaving the write ops, and the flush ops set
+    /// by [`set_flush_ops`](Self::set_flush_ops) if any, to writes and flushes. This makes it
+    /// possible to inject errors into the close at the end of a stream alone. The ops in `iter`
+    /// behave the same as the ones passed to `set_flush_ops`.
+    ///
+    /// Errors injected by these ops aren't included in [`errors_seen`](Self::errors_seen).
+    pub fn set_close_ops<J>(&mut self, iter: J) -> &mut Self
+    where
+        J: IntoIterator<Item = PartialOp> + 'static,
+        J::IntoIter: Send,
+    {
+        let mut close_ops = FuturesOps::new(iter);
+        close_ops.set_wouldblock_as_error(self.ops.wouldblock_as_error());
+        self.close_ops = Some(close_ops);
+        self
+    }
+
     /// Returns a shared reference to the underlying writer.
     pub fn get_ref(&self) -> &W {
         &self.inner
@@ -140,52 +652,151 @@ impl<W> PartialAsyncWrite<W> {
 // Futures impls
 // ---
 
-impl<W> AsyncWrite for PartialAsyncWrite<W>
+impl<W, I> AsyncWrite for PartialAsyncWrite<W, I>
 where
     W: AsyncWrite,
+    I: Iterator<Item = PartialOp>,
 {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
         let this = self.project();
+        if this.ops.poll_gate(cx).is_pending() {
+            return Poll::Pending;
+        }
         let inner = this.inner;
+        let queue_buf = match this.queue.as_mut() {
+            Some(queue) => match queue.poll_limit(cx, buf) {
+                Poll::Ready(queue_buf) => queue_buf,
+                Poll::Pending => {
+                    this.ops.trace(PollOutcome::Pending);
+                    return Poll::Pending;
+                }
+            },
+            None => buf,
+        };
 
-        this.ops.poll_impl(
+        let poll = this.ops.poll_impl(
             cx,
             |cx, len| match len {
-                Some(len) => inner.poll_write(cx, &buf[..len]),
-                None => inner.poll_write(cx, buf),
+                Some(0) if !queue_buf.is_empty() => Poll::Ready(Err(limited_zero_error())),
+                Some(len) => inner.poll_write(cx, &queue_buf[..len]),
+                None => inner.poll_write(cx, queue_buf),
             },
-            buf.len(),
+            queue_buf.len(),
             "error during poll_write, generated by partial-io",
-        )
+        );
+        if let Poll::Ready(Ok(n)) = &poll {
+            this.ops.add_transferred(*n);
+        }
+        this.ops.trace(PollOutcome::from_poll(&poll, |n| *n));
+        if let (Some(queue), Poll::Ready(Ok(n))) = (this.queue, &poll) {
+            queue.push(*n);
+        }
+        if let (Some(inspector), Poll::Ready(res)) = (this.inspector, &poll) {
+            inspect_write(inspector, this.ops.last_op(), buf, res);
+        }
+        if let (Some(observer), Poll::Ready(res)) = (this.observer, &poll) {
+            let transferred = *res.as_ref().unwrap_or(&0);
+            observer.observe(this.ops.last_op(), buf.len(), transferred);
+        }
+        poll
     }
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[io::IoSlice],
+    ) -> Poll<io::Result<usize>> {
         let this = self.project();
+        if this.ops.poll_gate(cx).is_pending() {
+            return Poll::Pending;
+        }
         let inner = this.inner;
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let space = match this.queue.as_mut() {
+            Some(queue) => match queue.poll_space(cx, total) {
+                Poll::Ready(space) => space,
+                Poll::Pending => {
+                    this.ops.trace(PollOutcome::Pending);
+                    return Poll::Pending;
+                }
+            },
+            None => total,
+        };
 
-        this.ops.poll_impl_no_limit(
+        // The op is applied to the write as a whole, so a limit covers all the slices together.
+        let poll = this.ops.poll_impl(
             cx,
-            |cx| inner.poll_flush(cx),
-            "error during poll_flush, generated by partial-io",
-        )
+            |cx, len| match len.unwrap_or(space) {
+                0 if total > 0 => Poll::Ready(Err(limited_zero_error())),
+                len if len == total => inner.poll_write_vectored(cx, bufs),
+                len => inner.poll_write_vectored(cx, &limit_io_slices(bufs, len)),
+            },
+            space,
+            "error during poll_write_vectored, generated by partial-io",
+        );
+        if let Poll::Ready(Ok(n)) = &poll {
+            this.ops.add_transferred(*n);
+        }
+        this.ops.trace(PollOutcome::from_poll(&poll, |n| *n));
+        if let (Some(queue), Poll::Ready(Ok(n))) = (this.queue, &poll) {
+            queue.push(*n);
+        }
+        if let (Some(inspector), Poll::Ready(res)) = (this.inspector, &poll) {
+            let buf: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+            inspect_write(inspector, this.ops.last_op(), &buf, res);
+        }
+        if let (Some(observer), Poll::Ready(res)) = (this.observer, &poll) {
+            let transferred = *res.as_ref().unwrap_or(&0);
+            observer.observe(this.ops.last_op(), total, transferred);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let inner = this.inner;
+
+        if *this.flush_interrupts > 0 {
+            *this.flush_interrupts -= 1;
+            this.ops.trace(PollOutcome::Err(io::ErrorKind::Interrupted));
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "poll_flush interrupted, generated by partial-io",
+            )));
+        }
+
+        let err_str = "error during poll_flush, generated by partial-io";
+        let poll = match this.flush_ops.as_mut() {
+            Some(ops) => ops.poll_impl_no_limit(cx, |cx| inner.poll_flush(cx), err_str),
+            None => this
+                .ops
+                .poll_impl_no_limit(cx, |cx| inner.poll_flush(cx), err_str),
+        };
+        this.ops.trace(PollOutcome::from_poll(&poll, |()| 0));
+        poll
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
         let this = self.project();
         let inner = this.inner;
 
-        this.ops.poll_impl_no_limit(
-            cx,
-            |cx| inner.poll_close(cx),
-            "error during poll_close, generated by partial-io",
-        )
+        let err_str = "error during poll_close, generated by partial-io";
+        let poll = match this.close_ops.as_mut().or(this.flush_ops.as_mut()) {
+            Some(ops) => ops.poll_impl_no_limit(cx, |cx| inner.poll_close(cx), err_str),
+            None => this
+                .ops
+                .poll_impl_no_limit(cx, |cx| inner.poll_close(cx), err_str),
+        };
+        this.ops.trace(PollOutcome::from_poll(&poll, |()| 0));
+        poll
     }
 }
 
 /// This is a forwarding impl to support duplex structs.
-impl<W> AsyncRead for PartialAsyncWrite<W>
+impl<W, I> AsyncRead for PartialAsyncWrite<W, I>
 where
     W: AsyncRead,
+    I: Iterator<Item = PartialOp>,
 {
     #[inline]
     fn poll_read(
@@ -207,9 +818,10 @@ where
 }
 
 /// This is a forwarding impl to support duplex structs.
-impl<W> AsyncBufRead for PartialAsyncWrite<W>
+impl<W, I> AsyncBufRead for PartialAsyncWrite<W, I>
 where
     W: AsyncBufRead,
+    I: Iterator<Item = PartialOp>,
 {
     #[inline]
     fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
@@ -223,9 +835,10 @@ where
 }
 
 /// This is a forwarding impl to support duplex structs.
-impl<W> AsyncSeek for PartialAsyncWrite<W>
+impl<W, I> AsyncSeek for PartialAsyncWrite<W, I>
 where
     W: AsyncSeek,
+    I: Iterator<Item = PartialOp>,
 {
     #[inline]
     fn poll_seek(
@@ -237,6 +850,36 @@ where
     }
 }
 
+/// This is a forwarding impl, to allow wrapping types that are both `AsyncWrite` and `Sink`.
+/// `PartialOp`s aren't applied to, or consumed by, the `Sink` methods.
+impl<W, I, Item> Sink<Item> for PartialAsyncWrite<W, I>
+where
+    W: Sink<Item>,
+    I: Iterator<Item = PartialOp>,
+{
+    type Error = W::Error;
+
+    #[inline]
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    #[inline]
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        self.project().inner.start_send(item)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
 // ---
 // Tokio impls
 // ---
@@ -244,6 +887,9 @@ where
 #[cfg(feature = "tokio1")]
 mod tokio_impl {
     use super::PartialAsyncWrite;
+    use crate::futures_util::PollOutcome;
+    use crate::ops::{inspect_write, limit_io_slices, limited_zero_error};
+    use crate::PartialOp;
     use std::{
         io::{self, SeekFrom},
         pin::Pin,
@@ -251,9 +897,26 @@ mod tokio_impl {
     };
     use tokio::io::{AsyncBufRead, AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
 
-    impl<W> AsyncWrite for PartialAsyncWrite<W>
+    impl<W, I> PartialAsyncWrite<W, I>
+    where
+        W: AsyncWrite,
+        I: Iterator<Item = PartialOp>,
+    {
+        /// Returns true if the underlying writer reports that it has an efficient
+        /// `poll_write_vectored` implementation.
+        ///
+        /// This forwards to `tokio::io::AsyncWrite::is_write_vectored` on the underlying writer.
+        ///
+        /// Requires the `tokio1` feature to be enabled.
+        pub fn is_inner_write_vectored(&self) -> bool {
+            self.inner.is_write_vectored()
+        }
+    }
+
+    impl<W, I> AsyncWrite for PartialAsyncWrite<W, I>
     where
         W: AsyncWrite,
+        I: Iterator<Item = PartialOp>,
     {
         fn poll_write(
             self: Pin<&mut Self>,
@@ -261,46 +924,150 @@ mod tokio_impl {
             buf: &[u8],
         ) -> Poll<io::Result<usize>> {
             let this = self.project();
+            if this.ops.poll_gate(cx).is_pending() {
+                return Poll::Pending;
+            }
             let inner = this.inner;
+            let queue_buf = match this.queue.as_mut() {
+                Some(queue) => match queue.poll_limit(cx, buf) {
+                    Poll::Ready(queue_buf) => queue_buf,
+                    Poll::Pending => {
+                        this.ops.trace(PollOutcome::Pending);
+                        return Poll::Pending;
+                    }
+                },
+                None => buf,
+            };
 
-            this.ops.poll_impl(
+            let poll = this.ops.poll_impl(
                 cx,
                 |cx, len| match len {
-                    Some(len) => inner.poll_write(cx, &buf[..len]),
-                    None => inner.poll_write(cx, buf),
+                    Some(0) if !queue_buf.is_empty() => Poll::Ready(Err(limited_zero_error())),
+                    Some(len) => inner.poll_write(cx, &queue_buf[..len]),
+                    None => inner.poll_write(cx, queue_buf),
                 },
-                buf.len(),
+                queue_buf.len(),
                 "error during poll_write, generated by partial-io",
-            )
+            );
+            if let Poll::Ready(Ok(n)) = &poll {
+                this.ops.add_transferred(*n);
+            }
+            this.ops.trace(PollOutcome::from_poll(&poll, |n| *n));
+            if let (Some(queue), Poll::Ready(Ok(n))) = (this.queue, &poll) {
+                queue.push(*n);
+            }
+            if let (Some(inspector), Poll::Ready(res)) = (this.inspector, &poll) {
+                inspect_write(inspector, this.ops.last_op(), buf, res);
+            }
+            if let (Some(observer), Poll::Ready(res)) = (this.observer, &poll) {
+                let transferred = *res.as_ref().unwrap_or(&0);
+                observer.observe(this.ops.last_op(), buf.len(), transferred);
+            }
+            poll
         }
 
-        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            bufs: &[io::IoSlice],
+        ) -> Poll<io::Result<usize>> {
             let this = self.project();
+            if this.ops.poll_gate(cx).is_pending() {
+                return Poll::Pending;
+            }
             let inner = this.inner;
+            let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+            let space = match this.queue.as_mut() {
+                Some(queue) => match queue.poll_space(cx, total) {
+                    Poll::Ready(space) => space,
+                    Poll::Pending => {
+                        this.ops.trace(PollOutcome::Pending);
+                        return Poll::Pending;
+                    }
+                },
+                None => total,
+            };
 
-            this.ops.poll_impl_no_limit(
+            // The op is applied to the write as a whole, so a limit covers all the slices together.
+            let poll = this.ops.poll_impl(
                 cx,
-                |cx| inner.poll_flush(cx),
-                "error during poll_flush, generated by partial-io",
-            )
+                |cx, len| match len.unwrap_or(space) {
+                    0 if total > 0 => Poll::Ready(Err(limited_zero_error())),
+                    len if len == total => inner.poll_write_vectored(cx, bufs),
+                    len => inner.poll_write_vectored(cx, &limit_io_slices(bufs, len)),
+                },
+                space,
+                "error during poll_write_vectored, generated by partial-io",
+            );
+            if let Poll::Ready(Ok(n)) = &poll {
+                this.ops.add_transferred(*n);
+            }
+            this.ops.trace(PollOutcome::from_poll(&poll, |n| *n));
+            if let (Some(queue), Poll::Ready(Ok(n))) = (this.queue, &poll) {
+                queue.push(*n);
+            }
+            if let (Some(inspector), Poll::Ready(res)) = (this.inspector, &poll) {
+                let buf: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+                inspect_write(inspector, this.ops.last_op(), &buf, res);
+            }
+            if let (Some(observer), Poll::Ready(res)) = (this.observer, &poll) {
+                let transferred = *res.as_ref().unwrap_or(&0);
+                observer.observe(this.ops.last_op(), total, transferred);
+            }
+            poll
+        }
+
+        /// This forwards to the underlying writer, since limits apply to vectored writes too.
+        #[inline]
+        fn is_write_vectored(&self) -> bool {
+            self.inner.is_write_vectored()
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            let this = self.project();
+            let inner = this.inner;
+
+            if *this.flush_interrupts > 0 {
+                *this.flush_interrupts -= 1;
+                this.ops.trace(PollOutcome::Err(io::ErrorKind::Interrupted));
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "poll_flush interrupted, generated by partial-io",
+                )));
+            }
+
+            let err_str = "error during poll_flush, generated by partial-io";
+            let poll = match this.flush_ops.as_mut() {
+                Some(ops) => ops.poll_impl_no_limit(cx, |cx| inner.poll_flush(cx), err_str),
+                None => this
+                    .ops
+                    .poll_impl_no_limit(cx, |cx| inner.poll_flush(cx), err_str),
+            };
+            this.ops.trace(PollOutcome::from_poll(&poll, |()| 0));
+            poll
         }
 
         fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
             let this = self.project();
             let inner = this.inner;
 
-            this.ops.poll_impl_no_limit(
-                cx,
-                |cx| inner.poll_shutdown(cx),
-                "error during poll_shutdown, generated by partial-io",
-            )
+            let err_str = "error during poll_shutdown, generated by partial-io";
+            let poll = match this.close_ops.as_mut().or(this.flush_ops.as_mut()) {
+                Some(ops) => ops.poll_impl_no_limit(cx, |cx| inner.poll_shutdown(cx), err_str),
+                None => this
+                    .ops
+                    .poll_impl_no_limit(cx, |cx| inner.poll_shutdown(cx), err_str),
+            };
+            this.ops.trace(PollOutcome::from_poll(&poll, |()| 0));
+            poll
         }
     }
 
     /// This is a forwarding impl to support duplex structs.
-    impl<W> AsyncRead for PartialAsyncWrite<W>
+    impl<W, I> AsyncRead for PartialAsyncWrite<W, I>
     where
         W: AsyncRead,
+        I: Iterator<Item = PartialOp>,
     {
         #[inline]
         fn poll_read(
@@ -313,9 +1080,10 @@ mod tokio_impl {
     }
 
     /// This is a forwarding impl to support duplex structs.
-    impl<W> AsyncBufRead for PartialAsyncWrite<W>
+    impl<W, I> AsyncBufRead for PartialAsyncWrite<W, I>
     where
         W: AsyncBufRead,
+        I: Iterator<Item = PartialOp>,
     {
         #[inline]
         fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
@@ -329,9 +1097,10 @@ mod tokio_impl {
     }
 
     /// This is a forwarding impl to support duplex structs.
-    impl<W> AsyncSeek for PartialAsyncWrite<W>
+    impl<W, I> AsyncSeek for PartialAsyncWrite<W, I>
     where
         W: AsyncSeek,
+        I: Iterator<Item = PartialOp>,
     {
         #[inline]
         fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
@@ -343,16 +1112,106 @@ mod tokio_impl {
             self.project().inner.poll_complete(cx)
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// A writer that doesn't override `is_write_vectored`.
+        struct NonVectored;
+
+        impl AsyncWrite for NonVectored {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                _cx: &mut Context,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Poll::Ready(Ok(buf.len()))
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        #[test]
+        fn test_is_inner_write_vectored() {
+            // Vec<u8> has an efficient vectored write implementation.
+            let partial_write = PartialAsyncWrite::new(Vec::new(), vec![]);
+            assert!(partial_write.is_inner_write_vectored());
+
+            let partial_write = PartialAsyncWrite::new(NonVectored, vec![]);
+            assert!(!partial_write.is_inner_write_vectored());
+        }
+
+        #[tokio::test]
+        async fn test_write_vectored() {
+            use crate::PartialOp;
+            use std::io::IoSlice;
+            use tokio::io::AsyncWriteExt;
+
+            let ops = vec![PartialOp::Limited(4), PartialOp::Limited(100)];
+            let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+            assert!(partial_write.is_write_vectored());
+            let bufs = [IoSlice::new(b"abc"), IoSlice::new(b"def")];
+            assert_eq!(partial_write.write_vectored(&bufs).await.unwrap(), 4);
+            assert_eq!(partial_write.write_vectored(&bufs).await.unwrap(), 6);
+            assert_eq!(partial_write.get_ref(), b"abcdabcdef");
+        }
+
+        #[tokio::test]
+        async fn test_flush_ops_buf_writer() {
+            use crate::PartialOp;
+            use tokio::io::{AsyncWriteExt, BufWriter};
+
+            let mut partial_write =
+                PartialAsyncWrite::new(Vec::new(), vec![PartialOp::Limited(2); 8]);
+            partial_write.set_flush_ops(vec![
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+                PartialOp::Err(io::ErrorKind::BrokenPipe),
+                PartialOp::Unlimited,
+                PartialOp::Err(io::ErrorKind::ConnectionReset),
+            ]);
+            let mut buf_writer = BufWriter::new(partial_write);
+
+            // Buffered writes go through, even though the flush ops start with errors.
+            buf_writer.write_all(b"Hello, world!").await.unwrap();
+            assert!(buf_writer.get_ref().get_ref().is_empty());
+
+            // The flush issued by BufWriter first writes out its buffer using the write ops,
+            // then fails on the inner flush.
+            let err = buf_writer.flush().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            assert_eq!(&buf_writer.get_ref().get_ref()[..], b"Hello, world!");
+            assert!(
+                buf_writer.get_ref().errors_seen().is_empty(),
+                "flush errors aren't recorded with write errors"
+            );
+
+            buf_writer.flush().await.unwrap();
+            let err = buf_writer.shutdown().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+            buf_writer.shutdown().await.unwrap();
+        }
+    }
 }
 
-impl<W> fmt::Debug for PartialAsyncWrite<W>
+impl<W, I> fmt::Debug for PartialAsyncWrite<W, I>
 where
     W: fmt::Debug,
+    I: Iterator<Item = PartialOp>,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("PartialAsyncWrite")
-            .field("inner", &self.inner)
-            .finish()
+        let mut f = f.debug_struct("PartialAsyncWrite");
+        f.field("inner", &self.inner);
+        if let Some(phase) = self.ops.current_phase() {
+            f.field("phase", &format_args!("{}", phase));
+        }
+        f.finish()
     }
 }
 
@@ -360,6 +1219,7 @@ where
 mod tests {
     use super::*;
 
+    use futures::io::AsyncWriteExt;
     use std::fs::File;
 
     use crate::tests::assert_send;
@@ -368,4 +1228,534 @@ mod tests {
     fn test_sendable() {
         assert_send::<PartialAsyncWrite<File>>();
     }
+
+    #[test]
+    fn test_pending_duration() {
+        use std::{
+            sync::{Arc, Mutex},
+            time::Instant,
+        };
+
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+        ];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock_now = now.clone();
+        partial_write.set_pending_clock(move || *clock_now.lock().unwrap());
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert_eq!(partial_write.pending_duration(), Duration::ZERO);
+
+        let poll = Pin::new(&mut partial_write).poll_write(&mut cx, b"abc");
+        assert!(poll.is_pending());
+        *now.lock().unwrap() += Duration::from_millis(20);
+        assert_eq!(
+            partial_write.pending_duration(),
+            Duration::from_millis(20),
+            "time is counted before the writer is polled again"
+        );
+        *now.lock().unwrap() += Duration::from_millis(5);
+        let poll = Pin::new(&mut partial_write).poll_write(&mut cx, b"abc");
+        assert!(matches!(poll, Poll::Ready(Ok(2))));
+        assert_eq!(partial_write.pending_duration(), Duration::from_millis(25));
+
+        *now.lock().unwrap() += Duration::from_millis(20);
+        assert_eq!(
+            partial_write.pending_duration(),
+            Duration::from_millis(25),
+            "time isn't counted once the writer is ready"
+        );
+    }
+
+    #[test]
+    fn test_write_vectored() {
+        let ops = vec![
+            PartialOp::Limited(5),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(1),
+        ];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let bufs = [
+            io::IoSlice::new(b"Hel"),
+            io::IoSlice::new(b"lo, "),
+            io::IoSlice::new(b"world!"),
+        ];
+
+        let poll = Pin::new(&mut partial_write).poll_write_vectored(&mut cx, &bufs);
+        assert!(matches!(poll, Poll::Ready(Ok(5))));
+        assert_eq!(partial_write.get_ref(), b"Hello");
+        assert_eq!(partial_write.consumed_ops(), 1, "one op per vectored write");
+
+        let bufs = [io::IoSlice::new(b", "), io::IoSlice::new(b"world!")];
+        let poll = Pin::new(&mut partial_write).poll_write_vectored(&mut cx, &bufs);
+        assert!(poll.is_pending());
+        assert_eq!(
+            partial_write.get_ref(),
+            b"Hello",
+            "nothing written while pending"
+        );
+        let poll = Pin::new(&mut partial_write).poll_write_vectored(&mut cx, &bufs);
+        assert!(matches!(poll, Poll::Ready(Ok(1))));
+
+        let bufs = [io::IoSlice::new(b" "), io::IoSlice::new(b"world!")];
+        let poll = Pin::new(&mut partial_write).poll_write_vectored(&mut cx, &bufs);
+        assert!(
+            matches!(poll, Poll::Ready(Ok(7))),
+            "unlimited once ops run out"
+        );
+        assert_eq!(partial_write.get_ref(), b"Hello, world!");
+    }
+
+    #[test]
+    fn test_sink() {
+        use futures::{executor::block_on, sink::SinkExt};
+
+        let ops = vec![PartialOp::Err(io::ErrorKind::BrokenPipe); 2];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+        block_on(async {
+            partial_write.send(1u8).await.unwrap();
+            partial_write.feed(2u8).await.unwrap();
+            SinkExt::<u8>::close(&mut partial_write).await.unwrap();
+        });
+        assert_eq!(partial_write.get_ref(), &[1, 2]);
+        assert_eq!(
+            partial_write.consumed_ops(),
+            0,
+            "sink methods don't consume ops"
+        );
+    }
+
+    #[test]
+    fn test_close_ops() {
+        use futures::executor::block_on;
+
+        let mut partial_write =
+            PartialAsyncWrite::new(Vec::new(), vec![PartialOp::Err(io::ErrorKind::Other)]);
+        partial_write
+            .set_close_ops(vec![PartialOp::Err(io::ErrorKind::BrokenPipe)])
+            .set_flush_ops(vec![PartialOp::Err(io::ErrorKind::ConnectionReset)]);
+
+        block_on(async {
+            // Writes only draw from the write ops...
+            let err = partial_write.write(b"Hello").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Other);
+            partial_write.write_all(b"Hello").await.unwrap();
+            // ... flushes only from the flush ops...
+            let err = AsyncWriteExt::flush(&mut partial_write).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+            AsyncWriteExt::flush(&mut partial_write).await.unwrap();
+            // ... and closes only from the close ops.
+            let err = AsyncWriteExt::close(&mut partial_write).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            AsyncWriteExt::close(&mut partial_write).await.unwrap();
+        });
+        assert_eq!(partial_write.get_ref(), b"Hello");
+    }
+
+    #[test]
+    fn test_new_unboxed() {
+        use futures::executor::block_on;
+
+        let ops = [PartialOp::Limited(2), PartialOp::Err(io::ErrorKind::Other)];
+        let mut partial_write = PartialAsyncWrite::new_unboxed(Vec::new(), ops.iter().cloned());
+        // Flush ops are boxed separately from the write ops.
+        partial_write.set_flush_ops(vec![PartialOp::Err(io::ErrorKind::BrokenPipe)]);
+
+        block_on(async {
+            assert_eq!(partial_write.write(b"Hello").await.unwrap(), 2);
+            let err = AsyncWriteExt::flush(&mut partial_write).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            // Closes fall back to the flush ops, which have run out.
+            AsyncWriteExt::close(&mut partial_write).await.unwrap();
+            let err = partial_write.write(b"llo").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Other);
+        });
+        assert_eq!(partial_write.get_ref(), b"He");
+        assert_eq!(partial_write.consumed_ops(), 2);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        use futures::executor::block_on;
+
+        let ops = vec![
+            PartialOp::Limited(4),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+        block_on(async {
+            partial_write.write_all(b"Hello, world!").await.unwrap();
+        });
+
+        let diagnostics = partial_write.diagnostics();
+        assert_eq!(diagnostics.ops_consumed, 3);
+        assert_eq!(diagnostics.last_op, Some(PartialOp::Unlimited));
+        assert_eq!(diagnostics.transferred, 13);
+        assert_eq!(diagnostics.error_offsets, [(io::ErrorKind::WouldBlock, 4)]);
+        assert_eq!(diagnostics.flushes, None);
+        assert_eq!(diagnostics.shrinking_capacity, None);
+    }
+
+    #[test]
+    fn test_limited_zero() {
+        use futures::executor::block_on;
+
+        let ops = vec![PartialOp::Limited(0), PartialOp::Limited(0)];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+
+        block_on(async {
+            let err = partial_write.write_all(b"Hello").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+            assert!(err.to_string().contains("use PartialOp::Eof"), "{}", err);
+            let err = partial_write
+                .write_vectored(&[io::IoSlice::new(b"Hello")])
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+            partial_write.write_all(b"Hello").await.unwrap();
+        });
+        assert_eq!(partial_write.get_ref(), b"Hello");
+    }
+
+    #[tokio::test]
+    async fn test_bytes_transferred() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(4),
+            PartialOp::Limited(1),
+        ];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+        partial_write.write_all(b"Hello, world!").await.unwrap();
+        AsyncWriteExt::close(&mut partial_write).await.unwrap();
+        assert_eq!(partial_write.bytes_transferred(), 13);
+    }
+
+    #[tokio::test]
+    async fn test_inspector() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+        ];
+        let mut partial_write =
+            PartialAsyncWrite::new(io::sink(), ops).with_inspector(move |op, bytes| {
+                events2.lock().unwrap().push((op.clone(), bytes.to_vec()));
+            });
+
+        assert_eq!(partial_write.write(b"abcd").await.unwrap(), 2);
+        partial_write.write(b"cd").await.unwrap_err();
+        assert_eq!(partial_write.write(b"cd").await.unwrap(), 2);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (PartialOp::Limited(2), b"ab".to_vec()),
+                (PartialOp::Err(io::ErrorKind::BrokenPipe), vec![]),
+                (PartialOp::Unlimited, b"cd".to_vec()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_on_op() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        let ops = vec![
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+        partial_write.on_op(move |event| events2.lock().unwrap().push(event));
+
+        partial_write.write_all(b"abcde").await.unwrap();
+
+        let events = events.lock().unwrap();
+        let fields: Vec<_> = events
+            .iter()
+            .map(|e| (e.op.clone(), e.offset, e.buf_len, e.transferred))
+            .collect();
+        // WouldBlock and Interrupted errors are handled internally, and aren't reported.
+        assert_eq!(
+            fields,
+            vec![
+                (PartialOp::Limited(2), 0, 5, 2),
+                (PartialOp::Unlimited, 2, 3, 3),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ops_remaining() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+        assert_eq!(partial_write.ops_remaining(), Some(4));
+
+        partial_write.write_all(b"Hello").await.unwrap();
+        assert_eq!(partial_write.ops_remaining(), Some(0));
+        assert_eq!(partial_write.consumed_ops(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_err_msg() {
+        let ops = vec![
+            PartialOp::ErrMsg(io::ErrorKind::WouldBlock, "not yet".into()),
+            PartialOp::ErrMsg(io::ErrorKind::Interrupted, "try again".into()),
+            PartialOp::Limited(2),
+            PartialOp::ErrMsg(io::ErrorKind::Other, "disk full".into()),
+        ];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+
+        // WouldBlock and Interrupted errors with messages are still handled internally.
+        let err = partial_write.write_all(b"Hello").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "disk full");
+        assert_eq!(partial_write.get_ref(), b"He");
+    }
+
+    #[tokio::test]
+    async fn test_flush_interrupts() {
+        let mut partial_write = PartialAsyncWrite::new(io::BufWriter::new(Vec::new()), vec![]);
+        partial_write.set_flush_interrupts(2);
+        partial_write.write_all(b"Hello").await.unwrap();
+
+        for _ in 0..2 {
+            let err = partial_write.flush().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+            assert!(partial_write.get_ref().get_ref().is_empty());
+        }
+        partial_write.flush().await.unwrap();
+        assert_eq!(&partial_write.get_ref().get_ref()[..], b"Hello");
+    }
+
+    #[tokio::test]
+    async fn test_poll_trace() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), ops);
+        partial_write.set_poll_trace(true).set_flush_interrupts(1);
+        partial_write.write_all(b"Hello").await.unwrap();
+        AsyncWriteExt::flush(&mut partial_write).await.unwrap_err();
+        AsyncWriteExt::flush(&mut partial_write).await.unwrap();
+        AsyncWriteExt::close(&mut partial_write).await.unwrap();
+        assert_eq!(
+            partial_write.take_poll_trace(),
+            vec![
+                PollOutcome::Ready(2),
+                PollOutcome::Pending,
+                PollOutcome::Ready(3),
+                PollOutcome::Err(io::ErrorKind::Interrupted),
+                PollOutcome::Ready(0),
+                PollOutcome::Ready(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_queue_model() {
+        use std::sync::{Arc, Mutex};
+
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let now2 = now.clone();
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), vec![]);
+        // 8 bytes, draining at 100 bytes per second, or 1 byte every 10ms.
+        partial_write.set_queue_model_with_clock(8, 100, move || *now2.lock().unwrap());
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut poll_write =
+            |partial_write: &mut PartialAsyncWrite<Vec<u8>>, buf: &[u8]| match Pin::new(
+                partial_write,
+            )
+            .poll_write(&mut cx, buf)
+            {
+                Poll::Ready(res) => Some(res.unwrap()),
+                Poll::Pending => None,
+            };
+
+        assert_eq!(poll_write(&mut partial_write, b"Hello"), Some(5));
+        assert_eq!(
+            poll_write(&mut partial_write, b", world!"),
+            Some(3),
+            "limited to the space left"
+        );
+        assert_eq!(
+            poll_write(&mut partial_write, b"orld!"),
+            None,
+            "pending once full"
+        );
+
+        *now.lock().unwrap() += Duration::from_millis(5);
+        assert_eq!(poll_write(&mut partial_write, b"orld!"), None);
+        *now.lock().unwrap() += Duration::from_millis(25);
+        assert_eq!(
+            poll_write(&mut partial_write, b"orld!"),
+            Some(3),
+            "3 bytes drained in 30ms"
+        );
+        assert_eq!(poll_write(&mut partial_write, b"d!"), None);
+
+        // Draining the whole queue, and then some, doesn't bank any extra space.
+        *now.lock().unwrap() += Duration::from_secs(1);
+        assert_eq!(poll_write(&mut partial_write, b"d! Goodbye"), Some(8));
+        assert_eq!(&partial_write.get_ref()[..], b"Hello, world! Goodb");
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_model_paused() {
+        use crate::WakerProbe;
+        use tokio::time::Instant;
+
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), vec![]);
+        // 8 bytes, draining at 100 bytes per second, or 1 byte every 10ms.
+        partial_write.set_queue_model(8, 100);
+        partial_write.write_all(b"Hello, w").await.unwrap();
+
+        // The queue is full, so the task waits for a byte to drain rather than spinning.
+        let probe = WakerProbe::new();
+        let waker = probe.waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut partial_write)
+            .poll_write(&mut cx, b"orld!")
+            .is_pending());
+        assert_eq!(probe.wakes(), 0);
+        tokio::time::advance(Duration::from_millis(9)).await;
+        assert_eq!(probe.wakes(), 0);
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert_eq!(probe.wakes(), 1);
+        assert!(matches!(
+            Pin::new(&mut partial_write).poll_write(&mut cx, b"orld!"),
+            Poll::Ready(Ok(1))
+        ));
+
+        // Writing the rest takes as long as draining it, with time advancing on its own while the
+        // task is idle.
+        let start = Instant::now();
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            partial_write.write_all(b"rld! Goodbye!"),
+        )
+        .await
+        .expect("write finishes before the timeout")
+        .unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(130));
+        assert_eq!(&partial_write.get_ref()[..], b"Hello, world! Goodbye!");
+    }
+
+    #[tokio::test]
+    async fn test_gate() {
+        use crate::{gate, WakerProbe};
+
+        let (gate, trigger) = gate();
+        let mut partial_write = PartialAsyncWrite::new(Vec::new(), vec![PartialOp::Limited(3)]);
+        partial_write.set_gate(gate).set_poll_trace(true);
+
+        // Polling by hand shows that the write is held up, and that opening the gate wakes the
+        // task.
+        let probe = WakerProbe::new();
+        let waker = probe.waker();
+        let mut cx = Context::from_waker(&waker);
+        for _ in 0..2 {
+            assert!(Pin::new(&mut partial_write)
+                .poll_write(&mut cx, b"Hello")
+                .is_pending());
+        }
+        assert_eq!(probe.wakes(), 0);
+        assert_eq!(
+            partial_write.snapshot().remaining().len(),
+            1,
+            "no ops consumed"
+        );
+        trigger.open();
+        assert_eq!(probe.wakes(), 1, "opening the gate wakes the task");
+        trigger.open();
+        assert_eq!(probe.wakes(), 1);
+
+        // With a spawned task, the write only completes after the gate is opened.
+        let (gate, trigger) = crate::gate();
+        partial_write.set_gate(gate);
+        let write = tokio::spawn(async move {
+            let n = partial_write.write(b"Hello").await.unwrap();
+            (n, partial_write)
+        });
+        tokio::task::yield_now().await;
+        trigger.open();
+        let (n, mut partial_write) = write.await.unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(&partial_write.get_ref()[..], b"Hel");
+        assert_eq!(
+            partial_write.take_poll_trace()[..2],
+            [PollOutcome::Pending, PollOutcome::Pending]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pinhole() {
+        let data = b"Hello, world!";
+        let mut partial_write = PartialAsyncWrite::pinhole(Vec::new());
+        for i in 0..data.len() {
+            assert_eq!(partial_write.write(&data[i..]).await.unwrap(), 1);
+        }
+        AsyncWriteExt::flush(&mut partial_write).await.unwrap();
+        assert_eq!(&partial_write.get_ref()[..], data);
+    }
+
+    #[test]
+    fn test_unlimited_fast_path() {
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let ops = vec![
+            PartialOp::Unlimited,
+            PartialOp::Unlimited,
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Unlimited,
+        ];
+        // Runs of Unlimited ops take the fast path.
+        let mut fast = PartialAsyncWrite::new(Vec::new(), ops.clone());
+        // A cap of usize::MAX turns Unlimited ops into limits that don't change anything, but
+        // makes them take the general path.
+        let mut general = PartialAsyncWrite::new(Vec::new(), ops);
+        general.set_unlimited_cap(Some(usize::MAX));
+
+        for (i, chunk) in b"Hello, world! Goodbye, world!".chunks(3).enumerate() {
+            let fast_poll = Pin::new(&mut fast).poll_write(&mut cx, chunk);
+            let general_poll = Pin::new(&mut general).poll_write(&mut cx, chunk);
+            assert_eq!(
+                fast_poll.map_err(|err| err.kind()),
+                general_poll.map_err(|err| err.kind()),
+                "poll_write call {}",
+                i
+            );
+            assert_eq!(fast.consumed_ops(), general.consumed_ops(), "call {}", i);
+            assert_eq!(fast.bytes_transferred(), general.bytes_transferred());
+        }
+        assert_eq!(fast.get_ref(), general.get_ref());
+    }
 }
@@ -0,0 +1,205 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! A builder for random, reproducible sequences of `PartialOp`s.
+
+use crate::{rng::SplitMix64, PartialOp, PartialRead, PartialWrite};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    ops::RangeInclusive,
+};
+
+/// A builder for random sequences of `PartialOp`s, weighted by kind.
+///
+/// This is a lightweight alternative to the `quickcheck1` and `proptest1` features, for tests
+/// that just need a varied, reproducible sequence of ops. Each op is independently:
+///
+/// * with probability [`error_rate`](Self::error_rate), `PartialOp::Err` with the given kind;
+/// * otherwise, with probability [`unlimited_rate`](Self::unlimited_rate),
+///   `PartialOp::Unlimited`;
+/// * otherwise, `PartialOp::Limited(n)`, with `n` picked uniformly from
+///   [`limited_range`](Self::limited_range).
+///
+/// Sequences are derived from a seed, so the same seed always produces the same ops. If no seed
+/// is set with [`seed`](Self::seed), one is picked at random when the builder is created; it can
+/// be retrieved with [`get_seed`](Self::get_seed) so that a failure can be replayed.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{PartialOp, PartialOpBuilder};
+/// use std::io::{self, Read};
+///
+/// let builder = PartialOpBuilder::new()
+///     .limited_range(1..=64)
+///     .error_rate(0.2, io::ErrorKind::WouldBlock)
+///     .unlimited_rate(0.1)
+///     .len(100)
+///     .seed(42);
+///
+/// let ops = builder.build_vec();
+/// assert_eq!(ops.len(), 100);
+/// assert_eq!(ops, builder.build_vec(), "sequences are reproducible");
+///
+/// let mut partial_read = builder.build_read(&b"Hello, world!"[..]);
+/// let mut out = Vec::new();
+/// loop {
+///     match partial_read.read_to_end(&mut out) {
+///         Ok(_) => break,
+///         Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+///         Err(err) => panic!("unexpected error: {}", err),
+///     }
+/// }
+/// assert_eq!(out, b"Hello, world!");
+/// ```
+#[derive(Clone, Debug)]
+pub struct PartialOpBuilder {
+    limited_range: RangeInclusive<usize>,
+    error_rate: f64,
+    error_kind: io::ErrorKind,
+    unlimited_rate: f64,
+    len: usize,
+    seed: u64,
+}
+
+impl PartialOpBuilder {
+    /// Creates a new `PartialOpBuilder` with a random seed.
+    ///
+    /// By default, the builder produces 100 `PartialOp::Limited` ops with limits in `1..=64`, and
+    /// no errors or `PartialOp::Unlimited` ops.
+    pub fn new() -> Self {
+        Self {
+            limited_range: 1..=64,
+            error_rate: 0.0,
+            error_kind: io::ErrorKind::Interrupted,
+            unlimited_rate: 0.0,
+            len: 100,
+            seed: RandomState::new().build_hasher().finish(),
+        }
+    }
+
+    /// Sets the range that `PartialOp::Limited` limits are picked from. Defaults to `1..=64`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty.
+    pub fn limited_range(mut self, range: RangeInclusive<usize>) -> Self {
+        assert!(!range.is_empty(), "limited range {:?} is empty", range);
+        self.limited_range = range;
+        self
+    }
+
+    /// Sets the probability that an op is `PartialOp::Err(kind)`. Defaults to 0.
+    ///
+    /// `rate` is clamped to `0.0..=1.0`.
+    pub fn error_rate(mut self, rate: f64, kind: io::ErrorKind) -> Self {
+        self.error_rate = rate;
+        self.error_kind = kind;
+        self
+    }
+
+    /// Sets the probability that an op that isn't an error is `PartialOp::Unlimited`. Defaults
+    /// to 0.
+    ///
+    /// `rate` is clamped to `0.0..=1.0`.
+    pub fn unlimited_rate(mut self, rate: f64) -> Self {
+        self.unlimited_rate = rate;
+        self
+    }
+
+    /// Sets the number of ops to produce. Defaults to 100.
+    pub fn len(mut self, len: usize) -> Self {
+        self.len = len;
+        self
+    }
+
+    /// Sets the seed that ops are derived from.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the seed that ops are derived from.
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Builds a sequence of ops.
+    pub fn build_vec(&self) -> Vec<PartialOp> {
+        let mut rng = SplitMix64::new(self.seed);
+        (0..self.len)
+            .map(|_| {
+                if rng.chance(self.error_rate) {
+                    PartialOp::Err(self.error_kind)
+                } else if rng.chance(self.unlimited_rate) {
+                    PartialOp::Unlimited
+                } else {
+                    PartialOp::Limited(rng.in_range(&self.limited_range))
+                }
+            })
+            .collect()
+    }
+
+    /// Builds a `PartialRead` wrapper over `inner` with a sequence of ops.
+    pub fn build_read<R: io::Read>(&self, inner: R) -> PartialRead<R> {
+        PartialRead::new(inner, self.build_vec())
+    }
+
+    /// Builds a `PartialWrite` wrapper over `inner` with a sequence of ops.
+    pub fn build_write<W: io::Write>(&self, inner: W) -> PartialWrite<W> {
+        PartialWrite::new(inner, self.build_vec())
+    }
+}
+
+impl Default for PartialOpBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weights() {
+        let ops = PartialOpBuilder::new()
+            .limited_range(3..=5)
+            .error_rate(0.2, io::ErrorKind::WouldBlock)
+            .unlimited_rate(0.5)
+            .len(1000)
+            .seed(7)
+            .build_vec();
+        assert_eq!(ops.len(), 1000);
+
+        let (mut errors, mut unlimited, mut limited) = (0, 0, 0);
+        for op in &ops {
+            match op {
+                PartialOp::Err(io::ErrorKind::WouldBlock) => errors += 1,
+                PartialOp::Unlimited => unlimited += 1,
+                PartialOp::Limited(n) => {
+                    assert!((3..=5).contains(n), "limit {} in range", n);
+                    limited += 1;
+                }
+                other => panic!("unexpected op {:?}", other),
+            }
+        }
+        // Expected: 200 errors, 400 unlimited, 400 limited.
+        assert!((150..250).contains(&errors), "errors: {}", errors);
+        assert!((330..470).contains(&unlimited), "unlimited: {}", unlimited);
+        assert!((330..470).contains(&limited), "limited: {}", limited);
+    }
+
+    #[test]
+    fn test_seed() {
+        let builder = PartialOpBuilder::new().error_rate(0.3, io::ErrorKind::Interrupted);
+        let seeded = builder.clone().seed(builder.get_seed());
+        assert_eq!(builder.build_vec(), seeded.build_vec());
+        assert_ne!(
+            builder.clone().seed(1).build_vec(),
+            builder.seed(2).build_vec()
+        );
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains a harness for differential testing of transfers.
+
+use crate::PartialOp;
+use std::fmt;
+
+/// Runs two transfers under the same sequence of `PartialOp`s, and reports the first point at
+/// which they diverge.
+///
+/// `run_a` and `run_b` are each passed a copy of `ops`, and should perform a transfer through a
+/// wrapper driven by them. Each returns the bytes transferred, along with a split log: the number
+/// of bytes moved by each individual call, in order.
+///
+/// Split logs are compared first, since a difference in chunking is usually the root cause of a
+/// difference in output. Returns `None` if both the split logs and the outputs are identical.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{diff_transfers, PartialOp, PartialWrite, TransferDiff};
+/// use std::io::Write;
+///
+/// // Writes `data` through a PartialWrite, recording the size of each write.
+/// fn run(ops: Vec<PartialOp>, data: &[u8]) -> (Vec<u8>, Vec<usize>) {
+///     let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+///     let mut splits = Vec::new();
+///     let mut remaining = data;
+///     while !remaining.is_empty() {
+///         let n = partial_writer.write(remaining).unwrap();
+///         splits.push(n);
+///         remaining = &remaining[n..];
+///     }
+///     (partial_writer.into_inner(), splits)
+/// }
+///
+/// let ops = vec![PartialOp::Limited(2), PartialOp::Limited(1)];
+/// assert_eq!(
+///     diff_transfers(&ops, |ops| run(ops, b"hello"), |ops| run(ops, b"hello")),
+///     None,
+/// );
+/// assert_eq!(
+///     diff_transfers(&ops, |ops| run(ops, b"hello"), |ops| run(ops, b"help!")),
+///     Some(TransferDiff::Output { offset: 3, a: Some(b'l'), b: Some(b'p') }),
+/// );
+/// ```
+pub fn diff_transfers<A, B>(ops: &[PartialOp], run_a: A, run_b: B) -> Option<TransferDiff>
+where
+    A: FnOnce(Vec<PartialOp>) -> (Vec<u8>, Vec<usize>),
+    B: FnOnce(Vec<PartialOp>) -> (Vec<u8>, Vec<usize>),
+{
+    let (output_a, splits_a) = run_a(ops.to_vec());
+    let (output_b, splits_b) = run_b(ops.to_vec());
+
+    if let Some(index) = first_difference(&splits_a, &splits_b) {
+        return Some(TransferDiff::Split {
+            index,
+            a: splits_a.get(index).copied(),
+            b: splits_b.get(index).copied(),
+        });
+    }
+    first_difference(&output_a, &output_b).map(|offset| TransferDiff::Output {
+        offset,
+        a: output_a.get(offset).copied(),
+        b: output_b.get(offset).copied(),
+    })
+}
+
+/// Returns the first index at which `a` and `b` differ, including the end of the shorter one.
+pub(crate) fn first_difference<T: PartialEq>(a: &[T], b: &[T]) -> Option<usize> {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(index) => Some(index),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+/// The first divergence between two transfers, as reported by [`diff_transfers`].
+///
+/// `None` for `a` or `b` means that the corresponding transfer ended before that point.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TransferDiff {
+    /// The split logs differ at the call at `index`.
+    Split {
+        /// The index of the first call that moved a different number of bytes.
+        index: usize,
+        /// The number of bytes moved by that call in the first transfer.
+        a: Option<usize>,
+        /// The number of bytes moved by that call in the second transfer.
+        b: Option<usize>,
+    },
+    /// The split logs are identical, but the outputs differ at byte `offset`.
+    Output {
+        /// The offset of the first byte that differs.
+        offset: usize,
+        /// The byte at that offset in the first transfer's output.
+        a: Option<u8>,
+        /// The byte at that offset in the second transfer's output.
+        b: Option<u8>,
+    },
+}
+
+impl fmt::Display for TransferDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferDiff::Split { index, a, b } => {
+                write!(f, "split logs differ at call {}: {:?} vs {:?}", index, a, b)
+            }
+            TransferDiff::Output { offset, a, b } => {
+                write!(f, "outputs differ at offset {}: {:?} vs {:?}", offset, a, b)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PartialRead;
+    use std::io::{self, Read};
+
+    /// Reads `data` through a `PartialRead` with a buffer of `buf_len`.
+    fn read_all(ops: Vec<PartialOp>, data: &[u8], buf_len: usize) -> (Vec<u8>, Vec<usize>) {
+        let mut partial_read = PartialRead::new(data, ops);
+        let mut output = Vec::new();
+        let mut splits = Vec::new();
+        let mut buf = vec![0; buf_len];
+        loop {
+            match partial_read.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    output.extend_from_slice(&buf[..n]);
+                    splits.push(n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }
+        (output, splits)
+    }
+
+    #[test]
+    fn test_diff_transfers() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        let data = b"Hello, world!";
+
+        assert_eq!(
+            diff_transfers(
+                &ops,
+                |ops| read_all(ops, data, 8),
+                |ops| read_all(ops, data, 8)
+            ),
+            None
+        );
+
+        // A smaller buffer chunks the unlimited read differently.
+        let diff = diff_transfers(
+            &ops,
+            |ops| read_all(ops, data, 8),
+            |ops| read_all(ops, data, 4),
+        )
+        .unwrap();
+        assert_eq!(
+            diff,
+            TransferDiff::Split {
+                index: 1,
+                a: Some(8),
+                b: Some(4)
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "split logs differ at call 1: Some(8) vs Some(4)"
+        );
+
+        // Identical chunking, but one transfer is truncated.
+        let diff = diff_transfers(
+            &ops,
+            |ops| read_all(ops, data, 16),
+            |ops| {
+                let (mut output, splits) = read_all(ops, data, 16);
+                output.pop();
+                (output, splits)
+            },
+        );
+        assert_eq!(
+            diff,
+            Some(TransferDiff::Output {
+                offset: data.len() - 1,
+                a: Some(b'!'),
+                b: None
+            })
+        );
+    }
+}
@@ -0,0 +1,889 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains a compact text format for sequences of `PartialOp`s.
+
+use crate::PartialOp;
+use std::{env, error, fmt, io, str::FromStr, time::Duration};
+
+/// The `io::ErrorKind`s supported by the text format, along with their names.
+///
+/// This table is frozen: names are never derived from `Debug` output, and existing entries must
+/// never be renamed or removed, so that ops serialized with one version of Rust and partial-io
+/// can be parsed with any later one. New entries may be appended as `io::ErrorKind` gains stable
+/// variants, as long as they are available on the minimum supported Rust version. Kinds not in
+/// this table are written out as `ERR:Other`.
+const ERROR_KINDS: &[(&str, io::ErrorKind)] = &[
+    ("NotFound", io::ErrorKind::NotFound),
+    ("PermissionDenied", io::ErrorKind::PermissionDenied),
+    ("ConnectionRefused", io::ErrorKind::ConnectionRefused),
+    ("ConnectionReset", io::ErrorKind::ConnectionReset),
+    ("ConnectionAborted", io::ErrorKind::ConnectionAborted),
+    ("NotConnected", io::ErrorKind::NotConnected),
+    ("AddrInUse", io::ErrorKind::AddrInUse),
+    ("AddrNotAvailable", io::ErrorKind::AddrNotAvailable),
+    ("BrokenPipe", io::ErrorKind::BrokenPipe),
+    ("AlreadyExists", io::ErrorKind::AlreadyExists),
+    ("WouldBlock", io::ErrorKind::WouldBlock),
+    ("InvalidInput", io::ErrorKind::InvalidInput),
+    ("InvalidData", io::ErrorKind::InvalidData),
+    ("TimedOut", io::ErrorKind::TimedOut),
+    ("WriteZero", io::ErrorKind::WriteZero),
+    ("Interrupted", io::ErrorKind::Interrupted),
+    ("Unsupported", io::ErrorKind::Unsupported),
+    ("UnexpectedEof", io::ErrorKind::UnexpectedEof),
+    ("OutOfMemory", io::ErrorKind::OutOfMemory),
+    ("Other", io::ErrorKind::Other),
+];
+
+fn kind_from_name(name: &str) -> Option<io::ErrorKind> {
+    ERROR_KINDS
+        .iter()
+        .find(|(kind_name, _)| *kind_name == name)
+        .map(|(_, kind)| *kind)
+}
+
+fn name_from_kind(kind: io::ErrorKind) -> &'static str {
+    ERROR_KINDS
+        .iter()
+        .find(|(_, table_kind)| *table_kind == kind)
+        .map_or("Other", |(name, _)| name)
+}
+
+/// The units accepted for delays, along with the number of nanoseconds in each.
+const DELAY_UNITS: &[(&str, u128)] = &[("ms", 1_000_000), ("us", 1_000), ("ns", 1)];
+
+fn parse_delay(s: &str) -> Option<Duration> {
+    let (count, unit_nanos) = DELAY_UNITS
+        .iter()
+        .find_map(|(unit, nanos)| Some((s.strip_suffix(unit)?, *nanos)))?;
+    let nanos = count.parse::<u128>().ok()?.checked_mul(unit_nanos)?;
+    let secs = u64::try_from(nanos / 1_000_000_000).ok()?;
+    Some(Duration::new(secs, (nanos % 1_000_000_000) as u32))
+}
+
+/// Returns true if `kind` can be written out in the text format accepted by [`parse_ops`].
+///
+/// Errors of unsupported kinds are written out as `ERR:Other`, so they turn into
+/// `io::ErrorKind::Other` when parsed back. Programs that build sequences of ops to save or share
+/// as text can use this to reject such kinds up front. The set of supported kinds only ever
+/// grows.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::is_supported_kind;
+/// use std::io;
+///
+/// assert!(is_supported_kind(io::ErrorKind::BrokenPipe));
+/// assert!(is_supported_kind(io::ErrorKind::Other));
+/// ```
+pub fn is_supported_kind(kind: io::ErrorKind) -> bool {
+    ERROR_KINDS
+        .iter()
+        .any(|(_, table_kind)| *table_kind == kind)
+}
+
+/// Parses a sequence of `PartialOp`s from a compact text format.
+///
+/// Ops are separated by commas, whitespace, or both. Each op is one of:
+///
+/// * `U`: `PartialOp::Unlimited`.
+/// * `L<n>`, e.g. `L16`: `PartialOp::Limited(n)`.
+/// * `WB`: `PartialOp::Err(io::ErrorKind::WouldBlock)`.
+/// * `INT`: `PartialOp::Err(io::ErrorKind::Interrupted)`.
+/// * `ERR:<kind>`, e.g. `ERR:BrokenPipe`: `PartialOp::Err` with the `io::ErrorKind` variant of
+///   that name.
+/// * `D<n><unit>`, e.g. `D10ms`: `PartialOp::Delay`, with the unit being one of `ms`, `us` or
+///   `ns`.
+/// * `EOF`: `PartialOp::Eof`.
+/// * `PEND`: `PartialOp::PendingOnce`.
+///
+/// Ops can be grouped with parentheses, and an op or group can be followed by `{n}` to repeat it
+/// exactly `n` times, e.g. `(L1 WB){3}`. Repetitions can be nested. The open-ended repetition
+/// operators `+` and `*` are only accepted by [`parse_ops_with_cap`], which takes an explicit
+/// repetition count for them, so that the result is always finite. Parsing fails with a
+/// "repetition too large" error if the expanded sequence would be longer than 1,048,576 ops.
+///
+/// The `Display` impl for `PartialOp` writes ops out in this format, so
+/// `op.to_string().parse()` returns the original op, except that the message of a
+/// `PartialOp::ErrMsg` is dropped. The names of supported `io::ErrorKind`s are fixed and don't
+/// depend on the Rust version, so text written out today can be parsed by later versions. Error
+/// kinds without a stable name, including ones added in later Rust versions, are written out as
+/// `ERR:Other`.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{parse_ops, PartialOp};
+/// use std::io;
+///
+/// let ops = parse_ops("L1, WB, L2 ERR:BrokenPipe U").unwrap();
+/// assert_eq!(
+///     ops,
+///     vec![
+///         PartialOp::Limited(1),
+///         PartialOp::Err(io::ErrorKind::WouldBlock),
+///         PartialOp::Limited(2),
+///         PartialOp::Err(io::ErrorKind::BrokenPipe),
+///         PartialOp::Unlimited,
+///     ],
+/// );
+/// ```
+pub fn parse_ops(s: &str) -> Result<Vec<PartialOp>, ParseOpsError> {
+    Parser::new(s, None).parse()
+}
+
+/// Parses a sequence of `PartialOp`s like [`parse_ops`], additionally accepting the `+` and `*`
+/// repetition operators.
+///
+/// An op or group followed by `+` or `*` is repeated `cap` times. The difference between the two
+/// is that `+` means "at least once", so it is an error to use it with a `cap` of 0, while `*`
+/// with a `cap` of 0 removes the op or group entirely. Together with `{n}`, this makes it easy to
+/// write schedules whose overall length is controlled by a single parameter.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{parse_ops_with_cap, PartialOp};
+/// use std::io;
+///
+/// let ops = parse_ops_with_cap("(L1 WB){2} U+ ERR:BrokenPipe", 3).unwrap();
+/// assert_eq!(
+///     ops,
+///     vec![
+///         PartialOp::Limited(1),
+///         PartialOp::Err(io::ErrorKind::WouldBlock),
+///         PartialOp::Limited(1),
+///         PartialOp::Err(io::ErrorKind::WouldBlock),
+///         PartialOp::Unlimited,
+///         PartialOp::Unlimited,
+///         PartialOp::Unlimited,
+///         PartialOp::Err(io::ErrorKind::BrokenPipe),
+///     ],
+/// );
+/// ```
+pub fn parse_ops_with_cap(s: &str, cap: usize) -> Result<Vec<PartialOp>, ParseOpsError> {
+    Parser::new(s, Some(cap)).parse()
+}
+
+/// The maximum number of ops that parsing can produce, once repetitions have been expanded.
+///
+/// Repetition counts come from the input, which may be untrusted (see `PartialOp::from_env`), so
+/// this keeps a short input from allocating an unbounded amount of memory.
+const MAX_OPS: usize = 1 << 20;
+
+/// A recursive descent parser for the text format.
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+    // The index of the next op token, as written (before any repetition).
+    op_index: usize,
+    cap: Option<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str, cap: Option<usize>) -> Self {
+        Self {
+            s,
+            pos: 0,
+            op_index: 0,
+            cap,
+        }
+    }
+
+    fn parse(mut self) -> Result<Vec<PartialOp>, ParseOpsError> {
+        self.parse_seq(false)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+
+    fn skip_separators(&mut self) {
+        while let Some(c) = self.peek() {
+            if c != ',' && !c.is_whitespace() {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn error(token: &str, message: &'static str) -> ParseOpsError {
+        ParseOpsError {
+            token: token.to_owned(),
+            index: None,
+            message,
+        }
+    }
+
+    /// Parses a sequence of items, up to the end of the input or, within a group, the closing
+    /// parenthesis.
+    fn parse_seq(&mut self, in_group: bool) -> Result<Vec<PartialOp>, ParseOpsError> {
+        let mut ops = Vec::new();
+        loop {
+            self.skip_separators();
+            let start = self.pos;
+            let item = match self.peek() {
+                None if in_group => return Err(Self::error("(", "unclosed `(`")),
+                None => return Ok(ops),
+                Some(')') if in_group => {
+                    self.pos += 1;
+                    return Ok(ops);
+                }
+                Some(')') => return Err(Self::error(")", "unmatched `)`")),
+                Some(c @ ('{' | '}' | '+' | '*')) => {
+                    return Err(Self::error(
+                        &c.to_string(),
+                        "repetition without an op or group to repeat",
+                    ))
+                }
+                Some('(') => {
+                    self.pos += 1;
+                    self.parse_seq(true)?
+                }
+                Some(_) => vec![self.parse_op()?],
+            };
+            let item = self.parse_repetitions(item)?;
+            if ops.len() + item.len() > MAX_OPS {
+                return Err(Self::error(
+                    &self.s[start..self.pos],
+                    "repetition too large",
+                ));
+            }
+            ops.extend(item);
+        }
+    }
+
+    fn parse_op(&mut self) -> Result<PartialOp, ParseOpsError> {
+        let rest = &self.s[self.pos..];
+        let len = rest
+            .find(|c: char| c == ',' || c.is_whitespace() || "(){}+*".contains(c))
+            .unwrap_or(rest.len());
+        let token = &rest[..len];
+        self.pos += len;
+        let index = self.op_index;
+        self.op_index += 1;
+        token.parse().map_err(|err: ParseOpsError| ParseOpsError {
+            index: Some(index),
+            ..err
+        })
+    }
+
+    /// Applies any repetition operators directly following an op or group.
+    fn parse_repetitions(
+        &mut self,
+        mut item: Vec<PartialOp>,
+    ) -> Result<Vec<PartialOp>, ParseOpsError> {
+        loop {
+            let start = self.pos;
+            let count = match self.peek() {
+                Some('{') => {
+                    let rest = &self.s[self.pos..];
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| Self::error(rest, "unclosed `{`"))?;
+                    let count = rest[1..end]
+                        .trim()
+                        .parse()
+                        .map_err(|_| Self::error(&rest[..=end], "expected a count in `{n}`"))?;
+                    self.pos += end + 1;
+                    count
+                }
+                Some(c @ ('+' | '*')) => {
+                    let token = c.to_string();
+                    let cap = self.cap.ok_or_else(|| {
+                        Self::error(&token, "`+` and `*` require a cap, see parse_ops_with_cap")
+                    })?;
+                    if c == '+' && cap == 0 {
+                        return Err(Self::error(&token, "`+` requires a cap of at least 1"));
+                    }
+                    self.pos += 1;
+                    cap
+                }
+                _ => return Ok(item),
+            };
+            item = repeat_ops(&item, count)
+                .ok_or_else(|| Self::error(&self.s[start..self.pos], "repetition too large"))?;
+        }
+    }
+}
+
+/// Repeats `ops` `count` times, or returns `None` if the result would have more than `MAX_OPS`
+/// ops.
+fn repeat_ops(ops: &[PartialOp], count: usize) -> Option<Vec<PartialOp>> {
+    let len = ops.len().checked_mul(count).filter(|len| *len <= MAX_OPS)?;
+    let mut out = Vec::with_capacity(len);
+    for _ in 0..count {
+        out.extend_from_slice(ops);
+    }
+    Some(out)
+}
+
+/// Writes out a sequence of `PartialOp`s as a Rust expression that evaluates to the same ops.
+///
+/// This is meant for turning a failing case, e.g. one generated by `proptest`, into a regression
+/// test: print `let ops = {};` with this function's output, and paste it in. The expression is a
+/// `vec![...]` of `PartialOp` values, with error kinds written as `io::ErrorKind::` paths, so
+/// `partial_io::PartialOp` and `std::io` must be in scope where it's pasted.
+///
+/// Error kinds are written out using the same fixed set of names as the text format accepted by
+/// [`parse_ops`]. Kinds without a stable name are written out as `io::ErrorKind::Other`.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{to_rust_literal, PartialOp};
+/// use std::io;
+///
+/// let ops = vec![
+///     PartialOp::Limited(3),
+///     PartialOp::Err(io::ErrorKind::BrokenPipe),
+///     PartialOp::Unlimited,
+/// ];
+/// assert_eq!(
+///     to_rust_literal(&ops),
+///     "vec![PartialOp::Limited(3), PartialOp::Err(io::ErrorKind::BrokenPipe), \
+///      PartialOp::Unlimited]",
+/// );
+/// ```
+pub fn to_rust_literal(ops: &[PartialOp]) -> String {
+    let ops: Vec<_> = ops
+        .iter()
+        .map(|op| match op {
+            PartialOp::Limited(n) => format!("PartialOp::Limited({})", n),
+            PartialOp::Unlimited => "PartialOp::Unlimited".to_owned(),
+            PartialOp::Eof => "PartialOp::Eof".to_owned(),
+            PartialOp::PendingOnce => "PartialOp::PendingOnce".to_owned(),
+            PartialOp::Err(kind) => {
+                format!("PartialOp::Err(io::ErrorKind::{})", name_from_kind(*kind))
+            }
+            PartialOp::ErrMsg(kind, msg) => format!(
+                "PartialOp::ErrMsg(io::ErrorKind::{}, {:?}.into())",
+                name_from_kind(*kind),
+                msg
+            ),
+            PartialOp::Delay(duration) if duration.subsec_nanos() % 1_000_000 == 0 => {
+                format!(
+                    "PartialOp::Delay(Duration::from_millis({}))",
+                    duration.as_millis()
+                )
+            }
+            PartialOp::Delay(duration) => format!(
+                "PartialOp::Delay(Duration::new({}, {}))",
+                duration.as_secs(),
+                duration.subsec_nanos()
+            ),
+        })
+        .collect();
+    format!("vec![{}]", ops.join(", "))
+}
+
+impl PartialOp {
+    /// Reads a sequence of `PartialOp`s from the environment variable `var`, in the format
+    /// accepted by [`parse_ops`].
+    ///
+    /// Returns `Ok(None)` if the environment variable isn't set, and an error if it is set but
+    /// can't be parsed. This makes it possible to rerun a test under different sequences of ops
+    /// without recompiling it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialRead};
+    ///
+    /// let ops = PartialOp::from_env("PARTIAL_IO_OPS")
+    ///     .expect("PARTIAL_IO_OPS is valid")
+    ///     .unwrap_or_else(|| vec![PartialOp::Limited(1)]);
+    /// let partial_reader = PartialRead::new(std::io::repeat(42), ops);
+    /// ```
+    pub fn from_env(var: &str) -> Result<Option<Vec<PartialOp>>, ParseOpsError> {
+        match env::var(var) {
+            Ok(value) => parse_ops(&value).map(Some),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(env::VarError::NotUnicode(value)) => Err(ParseOpsError {
+                token: value.to_string_lossy().into_owned(),
+                index: None,
+                message: "environment variable is not valid Unicode",
+            }),
+        }
+    }
+}
+
+impl FromStr for PartialOp {
+    type Err = ParseOpsError;
+
+    /// Parses a single `PartialOp` in the format accepted by [`parse_ops`].
+    fn from_str(s: &str) -> Result<Self, ParseOpsError> {
+        let error = |message| ParseOpsError {
+            token: s.to_owned(),
+            index: None,
+            message,
+        };
+
+        match s {
+            "U" => Ok(PartialOp::Unlimited),
+            "WB" => Ok(PartialOp::Err(io::ErrorKind::WouldBlock)),
+            "INT" => Ok(PartialOp::Err(io::ErrorKind::Interrupted)),
+            "EOF" => Ok(PartialOp::Eof),
+            "PEND" => Ok(PartialOp::PendingOnce),
+            _ => {
+                if let Some(limit) = s.strip_prefix('L') {
+                    limit
+                        .parse()
+                        .map(PartialOp::Limited)
+                        .map_err(|_| error("expected a byte count after `L`"))
+                } else if let Some(name) = s.strip_prefix("ERR:") {
+                    kind_from_name(name)
+                        .map(PartialOp::Err)
+                        .ok_or_else(|| error("unknown io::ErrorKind"))
+                } else if let Some(delay) = s.strip_prefix('D') {
+                    parse_delay(delay)
+                        .map(PartialOp::Delay)
+                        .ok_or_else(|| error("expected a duration like `10ms` after `D`"))
+                } else {
+                    Err(error(concat!(
+                        "expected one of `U`, `L<n>`, `WB`, `INT`, `ERR:<kind>`, `D<duration>`, ",
+                        "`EOF` or `PEND`",
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for PartialOp {
+    /// Writes out this op in the format accepted by [`parse_ops`].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialOp::Limited(n) => write!(f, "L{}", n),
+            PartialOp::Unlimited => f.write_str("U"),
+            PartialOp::Eof => f.write_str("EOF"),
+            PartialOp::PendingOnce => f.write_str("PEND"),
+            // The text format has no room for messages, so they're dropped.
+            PartialOp::Err(io::ErrorKind::WouldBlock)
+            | PartialOp::ErrMsg(io::ErrorKind::WouldBlock, _) => f.write_str("WB"),
+            PartialOp::Err(io::ErrorKind::Interrupted)
+            | PartialOp::ErrMsg(io::ErrorKind::Interrupted, _) => f.write_str("INT"),
+            PartialOp::Err(kind) | PartialOp::ErrMsg(kind, _) => {
+                write!(f, "ERR:{}", name_from_kind(*kind))
+            }
+            PartialOp::Delay(duration) => {
+                let nanos = duration.as_nanos();
+                let (unit, unit_nanos) = DELAY_UNITS
+                    .iter()
+                    .find(|(_, unit_nanos)| nanos % unit_nanos == 0)
+                    .expect("every duration is a whole number of nanoseconds");
+                write!(f, "D{}{}", nanos / unit_nanos, unit)
+            }
+        }
+    }
+}
+
+/// An error that occurred while parsing `PartialOp`s from text.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseOpsError {
+    token: String,
+    index: Option<usize>,
+    message: &'static str,
+}
+
+impl ParseOpsError {
+    /// Returns the text that failed to parse.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Returns the index of the op that failed to parse, if known.
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+}
+
+impl fmt::Display for ParseOpsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(
+                f,
+                "invalid op `{}` at index {}: {}",
+                self.token, index, self.message
+            ),
+            None => write!(f, "invalid op `{}`: {}", self.token, self.message),
+        }
+    }
+}
+
+impl error::Error for ParseOpsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ops() {
+        assert_eq!(parse_ops("").unwrap(), vec![]);
+        assert_eq!(
+            parse_ops(" L1,WB ,,INT\tERR:TimedOut\nU ").unwrap(),
+            vec![
+                PartialOp::Limited(1),
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+                PartialOp::Err(io::ErrorKind::Interrupted),
+                PartialOp::Err(io::ErrorKind::TimedOut),
+                PartialOp::Unlimited,
+            ]
+        );
+
+        let err = parse_ops("L1 Lx").unwrap_err();
+        assert_eq!(err.token(), "Lx");
+        assert_eq!(err.index(), Some(1));
+        assert_eq!(
+            err.to_string(),
+            "invalid op `Lx` at index 1: expected a byte count after `L`"
+        );
+
+        let err = parse_ops("ERR:Bogus").unwrap_err();
+        assert_eq!(err.index(), Some(0));
+        assert!(parse_ops("X").is_err());
+        assert!(parse_ops("L-1").is_err());
+
+        assert_eq!(
+            parse_ops("D10ms D250us D7ns D0ms").unwrap(),
+            vec![
+                PartialOp::Delay(Duration::from_millis(10)),
+                PartialOp::Delay(Duration::from_micros(250)),
+                PartialOp::Delay(Duration::from_nanos(7)),
+                PartialOp::Delay(Duration::ZERO),
+            ]
+        );
+        for bad in ["D", "D10", "D10s", "Dms", "D-1ms"] {
+            assert_eq!(
+                bad.parse::<PartialOp>().unwrap_err().to_string(),
+                format!(
+                    "invalid op `{}`: expected a duration like `10ms` after `D`",
+                    bad
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_repetitions() {
+        use PartialOp::*;
+        const WB: PartialOp = Err(io::ErrorKind::WouldBlock);
+
+        assert_eq!(parse_ops("L1{3}").unwrap(), vec![Limited(1); 3]);
+        assert_eq!(
+            parse_ops("(L1 WB){2} U").unwrap(),
+            vec![Limited(1), WB, Limited(1), WB, Unlimited]
+        );
+        assert_eq!(
+            parse_ops("((L1){2} WB){2}").unwrap(),
+            vec![Limited(1), Limited(1), WB, Limited(1), Limited(1), WB]
+        );
+        assert_eq!(parse_ops("(L1, L2){0} U").unwrap(), vec![Unlimited]);
+        assert_eq!(parse_ops("L1{2}{3}").unwrap(), vec![Limited(1); 6]);
+        assert_eq!(parse_ops("()").unwrap(), vec![]);
+
+        assert_eq!(
+            parse_ops_with_cap("(L1 WB){3} U+ ERR:BrokenPipe", 2).unwrap(),
+            vec![
+                Limited(1),
+                WB,
+                Limited(1),
+                WB,
+                Limited(1),
+                WB,
+                Unlimited,
+                Unlimited,
+                Err(io::ErrorKind::BrokenPipe),
+            ]
+        );
+        assert_eq!(parse_ops_with_cap("L1* U", 0).unwrap(), vec![Unlimited]);
+        assert_eq!(
+            parse_ops_with_cap("(L1 L2)*", 2).unwrap(),
+            vec![Limited(1), Limited(2), Limited(1), Limited(2)]
+        );
+
+        // Errors.
+        let err = parse_ops("U+").unwrap_err();
+        assert_eq!(err.token(), "+");
+        assert_eq!(
+            err.to_string(),
+            "invalid op `+`: `+` and `*` require a cap, see parse_ops_with_cap"
+        );
+        assert!(parse_ops_with_cap("U+", 0).is_err());
+        assert_eq!(parse_ops("(L1 U").unwrap_err().token(), "(");
+        assert_eq!(parse_ops("L1)").unwrap_err().token(), ")");
+        assert_eq!(parse_ops("{3}").unwrap_err().token(), "{");
+        assert_eq!(parse_ops("L1{x}").unwrap_err().token(), "{x}");
+        assert_eq!(parse_ops("L1{3").unwrap_err().token(), "{3");
+
+        // Repetitions that would expand too far are rejected without allocating.
+        let err = parse_ops("L1{18446744073709551615}").unwrap_err();
+        assert_eq!(err.token(), "{18446744073709551615}");
+        assert_eq!(
+            err.to_string(),
+            "invalid op `{18446744073709551615}`: repetition too large"
+        );
+        let err = parse_ops("((L1){100000}){100000}").unwrap_err();
+        assert_eq!(err.token(), "{100000}");
+        assert_eq!(
+            err.to_string(),
+            "invalid op `{100000}`: repetition too large"
+        );
+        let err = parse_ops_with_cap("(L1 U)*", usize::MAX).unwrap_err();
+        assert_eq!(err.token(), "*");
+        let err = parse_ops("L1{1048576} U").unwrap_err();
+        assert_eq!(err.token(), "U", "the total length is bounded too");
+        assert_eq!(parse_ops("L1{1048576}").unwrap().len(), 1 << 20);
+
+        // Op indexes count ops as written.
+        let err = parse_ops("(L1 U){5} Lx").unwrap_err();
+        assert_eq!((err.token(), err.index()), ("Lx", Some(2)));
+    }
+
+    #[test]
+    fn test_is_supported_kind() {
+        for (name, kind) in ERROR_KINDS {
+            assert!(is_supported_kind(*kind), "{} is supported", name);
+        }
+
+        // Supported kinds, and only those, survive a round trip through the text format. Error
+        // codes from the OS map to a wide range of kinds, some of which may be unsupported
+        // depending on the Rust version.
+        for code in 0..256 {
+            let kind = io::Error::from_raw_os_error(code).kind();
+            let op = PartialOp::Err(kind);
+            let round_trip = op.to_string().parse::<PartialOp>().unwrap() == op;
+            assert_eq!(is_supported_kind(kind), round_trip, "{:?}", kind);
+            if !round_trip {
+                assert_eq!(op.to_string(), "ERR:Other");
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_rust_literal() {
+        // Parses the output of to_rust_literal back, by hand.
+        fn parse_literal(s: &str) -> Vec<PartialOp> {
+            let inner = s.strip_prefix("vec![").unwrap().strip_suffix(']').unwrap();
+            if inner.is_empty() {
+                return Vec::new();
+            }
+            // Durations can contain ", " too, so split on the start of each op instead.
+            inner
+                .split("PartialOp::")
+                .skip(1)
+                .map(|op| {
+                    let op = format!("PartialOp::{}", op.trim_end_matches(", "));
+                    let op = op.as_str();
+                    if op == "PartialOp::Unlimited" {
+                        PartialOp::Unlimited
+                    } else if op == "PartialOp::Eof" {
+                        PartialOp::Eof
+                    } else if op == "PartialOp::PendingOnce" {
+                        PartialOp::PendingOnce
+                    } else if let Some(n) = op.strip_prefix("PartialOp::Limited(") {
+                        PartialOp::Limited(n.strip_suffix(')').unwrap().parse().unwrap())
+                    } else if let Some(ms) =
+                        op.strip_prefix("PartialOp::Delay(Duration::from_millis(")
+                    {
+                        let ms = ms.strip_suffix("))").unwrap().parse().unwrap();
+                        PartialOp::Delay(Duration::from_millis(ms))
+                    } else if let Some(args) = op.strip_prefix("PartialOp::Delay(Duration::new(") {
+                        let (secs, nanos) =
+                            args.strip_suffix("))").unwrap().split_once(',').unwrap();
+                        PartialOp::Delay(Duration::new(
+                            secs.parse().unwrap(),
+                            nanos.trim().parse().unwrap(),
+                        ))
+                    } else {
+                        let name = op
+                            .strip_prefix("PartialOp::Err(io::ErrorKind::")
+                            .unwrap()
+                            .strip_suffix(')')
+                            .unwrap();
+                        PartialOp::Err(kind_from_name(name).unwrap())
+                    }
+                })
+                .collect()
+        }
+
+        let mut ops = vec![
+            PartialOp::Limited(0),
+            PartialOp::Limited(usize::MAX),
+            PartialOp::Unlimited,
+            PartialOp::Eof,
+            PartialOp::PendingOnce,
+            PartialOp::Delay(Duration::from_millis(1500)),
+            PartialOp::Delay(Duration::new(2, 1)),
+        ];
+        ops.extend(ERROR_KINDS.iter().map(|(_, kind)| PartialOp::Err(*kind)));
+        let literal = to_rust_literal(&ops);
+        assert_eq!(parse_literal(&literal), ops);
+        // The text format is a different spelling of the same ops.
+        assert_eq!(
+            parse_ops(
+                &ops.iter()
+                    .map(|op| op.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+            .unwrap(),
+            parse_literal(&literal)
+        );
+
+        assert_eq!(to_rust_literal(&[]), "vec![]");
+        assert_eq!(
+            to_rust_literal(&[PartialOp::Err(io::ErrorKind::Interrupted)]),
+            "vec![PartialOp::Err(io::ErrorKind::Interrupted)]"
+        );
+        assert_eq!(
+            to_rust_literal(&[PartialOp::ErrMsg(
+                io::ErrorKind::Other,
+                "disk \"full\"".into()
+            )]),
+            r#"vec![PartialOp::ErrMsg(io::ErrorKind::Other, "disk \"full\"".into())]"#
+        );
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let mut ops = vec![
+            PartialOp::Unlimited,
+            PartialOp::Limited(0),
+            PartialOp::Limited(usize::MAX),
+            PartialOp::Eof,
+            PartialOp::PendingOnce,
+            PartialOp::Delay(Duration::ZERO),
+            PartialOp::Delay(Duration::from_millis(10)),
+            PartialOp::Delay(Duration::from_micros(1001)),
+            PartialOp::Delay(Duration::new(2, 1)),
+            PartialOp::Delay(Duration::MAX),
+        ];
+        ops.extend(ERROR_KINDS.iter().map(|(_, kind)| PartialOp::Err(*kind)));
+        for op in ops {
+            let s = op.to_string();
+            assert_eq!(
+                s.parse::<PartialOp>(),
+                Ok(op.clone()),
+                "round trip for {}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_stable_tokens() {
+        // These tokens may appear in serialized corpora, and must never change.
+        let ops = [
+            PartialOp::Unlimited,
+            PartialOp::Limited(16),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Delay(Duration::from_millis(10)),
+            PartialOp::Eof,
+            PartialOp::PendingOnce,
+            PartialOp::ErrMsg(io::ErrorKind::BrokenPipe, "closed".into()),
+        ];
+        let tokens: Vec<_> = ops.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            tokens,
+            [
+                "U",
+                "L16",
+                "WB",
+                "INT",
+                "ERR:BrokenPipe",
+                "D10ms",
+                "EOF",
+                "PEND",
+                "ERR:BrokenPipe"
+            ]
+        );
+
+        let names: Vec<_> = ERROR_KINDS.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names.join(","),
+            "NotFound,PermissionDenied,ConnectionRefused,ConnectionReset,ConnectionAborted,\
+             NotConnected,AddrInUse,AddrNotAvailable,BrokenPipe,AlreadyExists,WouldBlock,\
+             InvalidInput,InvalidData,TimedOut,WriteZero,Interrupted,Unsupported,UnexpectedEof,\
+             OutOfMemory,Other",
+            "existing names must never be renamed, removed or reordered; only append new ones"
+        );
+    }
+
+    #[test]
+    fn test_error_kinds_table() {
+        // Every kind the crate intends to support. If this fails, a kind is missing from
+        // ERROR_KINDS, and will be written out as ERR:Other.
+        let supported = [
+            io::ErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied,
+            io::ErrorKind::ConnectionRefused,
+            io::ErrorKind::ConnectionReset,
+            io::ErrorKind::ConnectionAborted,
+            io::ErrorKind::NotConnected,
+            io::ErrorKind::AddrInUse,
+            io::ErrorKind::AddrNotAvailable,
+            io::ErrorKind::BrokenPipe,
+            io::ErrorKind::AlreadyExists,
+            io::ErrorKind::WouldBlock,
+            io::ErrorKind::InvalidInput,
+            io::ErrorKind::InvalidData,
+            io::ErrorKind::TimedOut,
+            io::ErrorKind::WriteZero,
+            io::ErrorKind::Interrupted,
+            io::ErrorKind::Unsupported,
+            io::ErrorKind::UnexpectedEof,
+            io::ErrorKind::OutOfMemory,
+            io::ErrorKind::Other,
+        ];
+        for kind in supported {
+            assert!(
+                ERROR_KINDS
+                    .iter()
+                    .any(|(_, table_kind)| *table_kind == kind),
+                "{:?} is missing from ERROR_KINDS",
+                kind
+            );
+        }
+
+        for (i, (name, kind)) in ERROR_KINDS.iter().enumerate() {
+            // The names happen to match today's Debug output, but are never derived from it.
+            assert_eq!(*name, format!("{:?}", kind), "name for {:?}", kind);
+            assert!(
+                ERROR_KINDS[..i]
+                    .iter()
+                    .all(|(other_name, other_kind)| other_name != name && other_kind != kind),
+                "{} is duplicated in ERROR_KINDS",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_env() {
+        const VAR: &str = "PARTIAL_IO_TEST_FROM_ENV_OPS";
+
+        env::remove_var(VAR);
+        assert_eq!(PartialOp::from_env(VAR), Ok(None));
+
+        env::set_var(VAR, "L1,WB,L2");
+        assert_eq!(
+            PartialOp::from_env(VAR),
+            Ok(Some(vec![
+                PartialOp::Limited(1),
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+                PartialOp::Limited(2),
+            ]))
+        );
+
+        env::set_var(VAR, "L1,bogus");
+        let err = PartialOp::from_env(VAR).unwrap_err();
+        assert_eq!(err.index(), Some(1));
+
+        env::remove_var(VAR);
+    }
+}
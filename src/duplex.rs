@@ -0,0 +1,686 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains a wrapper that breaks up both reads and writes on a duplex stream,
+//! according to independent iterators.
+
+use crate::{diff::first_difference, futures_util::FuturesOps, ops::limited_zero_error, PartialOp};
+use futures::prelude::*;
+use pin_project::pin_project;
+use std::{
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A wrapper that breaks up both directions of an inner duplex stream, according to independent
+/// iterators.
+///
+/// `PartialAsyncRead` and `PartialAsyncWrite` forward the other direction to the inner instance
+/// untouched. `PartialDuplex` instead applies partial behavior to both directions at once:
+///
+/// * `read_ops` are consulted by `poll_read`.
+/// * `write_ops` are consulted by `poll_write`, `poll_flush` and `poll_close` (or
+///   `poll_shutdown` for `tokio`).
+///
+/// Each sequence of ops is consumed independently of the other, so reads and writes can be made
+/// flaky at the same time without having to interleave the ops by hand. The ops behave the same
+/// as with `PartialAsyncRead` and `PartialAsyncWrite`.
+///
+/// Available with the `futures03` feature for `futures` traits, and with the `tokio1` feature for
+/// `tokio` traits.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[cfg(feature = "tokio1")]
+/// use partial_io::{PartialDuplex, PartialOp};
+/// # #[cfg(feature = "tokio1")]
+/// use std::io;
+/// # #[cfg(feature = "tokio1")]
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+///
+/// # #[cfg(feature = "tokio1")]
+/// #[tokio::main]
+/// async fn main() -> io::Result<()> {
+///     let (client, mut server) = tokio::io::duplex(64);
+///     let read_ops = vec![PartialOp::Limited(2), PartialOp::Err(io::ErrorKind::Interrupted)];
+///     let write_ops = vec![PartialOp::Err(io::ErrorKind::WouldBlock), PartialOp::Limited(3)];
+///     let mut partial_duplex = PartialDuplex::new(client, read_ops, write_ops);
+///
+///     // The write side is limited by write_ops.
+///     assert_eq!(partial_duplex.write(b"Hello").await?, 3);
+///     let mut out = [0; 8];
+///     assert_eq!(server.read(&mut out).await?, 3);
+///     assert_eq!(&out[..3], b"Hel");
+///
+///     // The read side is limited by read_ops.
+///     server.write_all(b"world").await?;
+///     assert_eq!(partial_duplex.read(&mut out).await?, 2);
+///     assert_eq!(&out[..2], b"wo");
+///
+///     Ok(())
+/// }
+///
+/// # #[cfg(not(feature = "tokio1"))]
+/// # fn main() {
+/// #     assert!(true, "dummy test");
+/// # }
+/// ```
+#[pin_project]
+pub struct PartialDuplex<T> {
+    #[pin]
+    inner: T,
+    read_ops: FuturesOps,
+    write_ops: FuturesOps,
+    captured: Option<Captured>,
+}
+
+/// The bytes read and written through a `PartialDuplex`, while capturing is enabled.
+#[derive(Debug, Default)]
+struct Captured {
+    read: Vec<u8>,
+    written: Vec<u8>,
+}
+
+impl Captured {
+    fn capture_read(captured: &mut Option<Self>, bytes: &[u8]) {
+        if let Some(captured) = captured {
+            captured.read.extend_from_slice(bytes);
+        }
+    }
+
+    fn capture_written(captured: &mut Option<Self>, bytes: &[u8]) {
+        if let Some(captured) = captured {
+            captured.written.extend_from_slice(bytes);
+        }
+    }
+}
+
+impl<T> PartialDuplex<T> {
+    /// Creates a new `PartialDuplex` wrapper over the duplex stream with the specified
+    /// `PartialOp`s for each direction.
+    pub fn new<R, W>(inner: T, read_ops: R, write_ops: W) -> Self
+    where
+        R: IntoIterator<Item = PartialOp> + 'static,
+        R::IntoIter: Send,
+        W: IntoIterator<Item = PartialOp> + 'static,
+        W::IntoIter: Send,
+    {
+        PartialDuplex {
+            inner,
+            read_ops: FuturesOps::new(read_ops),
+            write_ops: FuturesOps::new(write_ops),
+            captured: None,
+        }
+    }
+
+    /// Sets the `PartialOp`s consulted by reads.
+    pub fn set_read_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        self.read_ops.replace(iter);
+        self
+    }
+
+    /// Sets the `PartialOp`s consulted by writes, flushes and closes.
+    pub fn set_write_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        self.write_ops.replace(iter);
+        self
+    }
+
+    /// Returns the number of read ops consumed so far.
+    pub fn consumed_read_ops(&self) -> usize {
+        self.read_ops.consumed()
+    }
+
+    /// Returns the number of write ops consumed so far.
+    pub fn consumed_write_ops(&self) -> usize {
+        self.write_ops.consumed()
+    }
+
+    /// Returns the total number of bytes read through this wrapper.
+    pub fn bytes_read(&self) -> u64 {
+        self.read_ops.transferred()
+    }
+
+    /// Returns the total number of bytes written through this wrapper.
+    pub fn bytes_written(&self) -> u64 {
+        self.write_ops.transferred()
+    }
+
+    /// Sets whether bytes read and written through this wrapper are captured.
+    ///
+    /// While capturing is enabled, every byte returned to the caller by `poll_read` and every byte
+    /// accepted by `poll_write` is appended to an internal buffer for its direction. The buffers
+    /// can be retrieved with [`take_captured`](Self::take_captured), and compared with
+    /// [`assert_echo_lossless`](Self::assert_echo_lossless). Disabling capturing discards any
+    /// bytes captured so far.
+    pub fn set_capture(&mut self, capture: bool) -> &mut Self {
+        match (capture, self.captured.is_some()) {
+            (true, false) => self.captured = Some(Captured::default()),
+            (false, true) => self.captured = None,
+            _ => {}
+        }
+        self
+    }
+
+    /// Returns the bytes captured so far, as a pair of the bytes read and the bytes written, and
+    /// leaves the capture buffers empty.
+    ///
+    /// Returns a pair of empty buffers if capturing isn't enabled.
+    pub fn take_captured(&mut self) -> (Vec<u8>, Vec<u8>) {
+        self.captured
+            .as_mut()
+            .map_or_else(Default::default, |captured| {
+                (
+                    std::mem::take(&mut captured.read),
+                    std::mem::take(&mut captured.written),
+                )
+            })
+    }
+
+    /// Asserts that every byte written through this wrapper was read back unchanged.
+    ///
+    /// This is the correctness check for loopback tests, where the inner stream echoes whatever is
+    /// written to it: the read and write `PartialOp`s may split up the transfer differently in
+    /// each direction, but once everything written has been read back, the bytes captured in
+    /// both directions must be the same. Call this after draining the read side.
+    ///
+    /// # Panics
+    ///
+    /// Panics if capturing isn't enabled with [`set_capture`](Self::set_capture), or if the bytes
+    /// read differ from the bytes written. The panic message shows the offset of the first
+    /// difference, along with the bytes in each direction from that point on.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "tokio1")]
+    /// use partial_io::{PartialDuplex, PartialOp};
+    /// # #[cfg(feature = "tokio1")]
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    ///
+    /// # #[cfg(feature = "tokio1")]
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let (client, mut server) = tokio::io::duplex(64);
+    ///     let read_ops = vec![PartialOp::Limited(2); 10];
+    ///     let write_ops = vec![PartialOp::Limited(3); 10];
+    ///     let mut partial_duplex = PartialDuplex::new(client, read_ops, write_ops);
+    ///     partial_duplex.set_capture(true);
+    ///
+    ///     // The server echoes everything back.
+    ///     partial_duplex.write_all(b"Hello, world!").await.unwrap();
+    ///     let mut echo = [0; 13];
+    ///     server.read_exact(&mut echo).await.unwrap();
+    ///     server.write_all(&echo).await.unwrap();
+    ///
+    ///     // Drain the read side.
+    ///     let mut out = Vec::new();
+    ///     while out.len() < 13 {
+    ///         let mut chunk = [0; 8];
+    ///         let n = partial_duplex.read(&mut chunk).await.unwrap();
+    ///         out.extend_from_slice(&chunk[..n]);
+    ///     }
+    ///     partial_duplex.assert_echo_lossless();
+    /// }
+    ///
+    /// # #[cfg(not(feature = "tokio1"))]
+    /// # fn main() {}
+    /// ```
+    #[track_caller]
+    pub fn assert_echo_lossless(&self) {
+        let captured = self
+            .captured
+            .as_ref()
+            .expect("assert_echo_lossless requires capturing, enable it with set_capture");
+        if let Some(offset) = first_difference(&captured.written, &captured.read) {
+            panic!(
+                "echo lost data: {} bytes written, {} bytes read, first difference at offset {}\n\
+                 written from there: b\"{}\"\n   \
+                 read from there: b\"{}\"",
+                captured.written.len(),
+                captured.read.len(),
+                offset,
+                escape_tail(&captured.written, offset),
+                escape_tail(&captured.read, offset),
+            );
+        }
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns a pinned mutable reference to the underlying stream.
+    pub fn pin_get_mut(self: Pin<&mut Self>) -> Pin<&mut T> {
+        self.project().inner
+    }
+
+    /// Consumes this wrapper, returning the underlying stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// Escapes the bytes of `bytes` from `offset` on, up to a limit, for a panic message.
+fn escape_tail(bytes: &[u8], offset: usize) -> String {
+    const MAX_LEN: usize = 32;
+    let tail = &bytes[offset.min(bytes.len())..];
+    let mut escaped: String = tail
+        .iter()
+        .take(MAX_LEN)
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect();
+    if tail.len() > MAX_LEN {
+        escaped.push_str("...");
+    }
+    escaped
+}
+
+// ---
+// Futures impls
+// ---
+
+impl<T> AsyncRead for PartialDuplex<T>
+where
+    T: AsyncRead,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let inner = this.inner;
+        let len = buf.len();
+
+        let res = this.read_ops.poll_impl(
+            cx,
+            |cx, len| match len {
+                Some(len) => inner.poll_read(cx, &mut buf[..len]),
+                None => inner.poll_read(cx, buf),
+            },
+            len,
+            "error during poll_read, generated by partial-io",
+        );
+        if let Poll::Ready(Ok(n)) = &res {
+            this.read_ops.add_transferred(*n);
+            Captured::capture_read(this.captured, &buf[..*n]);
+        }
+        res
+    }
+}
+
+impl<T> AsyncWrite for PartialDuplex<T>
+where
+    T: AsyncWrite,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let inner = this.inner;
+
+        let res = this.write_ops.poll_impl(
+            cx,
+            |cx, len| match len {
+                Some(0) if !buf.is_empty() => Poll::Ready(Err(limited_zero_error())),
+                Some(len) => inner.poll_write(cx, &buf[..len]),
+                None => inner.poll_write(cx, buf),
+            },
+            buf.len(),
+            "error during poll_write, generated by partial-io",
+        );
+        if let Poll::Ready(Ok(n)) = &res {
+            this.write_ops.add_transferred(*n);
+            Captured::capture_written(this.captured, &buf[..*n]);
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let inner = this.inner;
+
+        this.write_ops.poll_impl_no_limit(
+            cx,
+            |cx| inner.poll_flush(cx),
+            "error during poll_flush, generated by partial-io",
+        )
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let inner = this.inner;
+
+        this.write_ops.poll_impl_no_limit(
+            cx,
+            |cx| inner.poll_close(cx),
+            "error during poll_close, generated by partial-io",
+        )
+    }
+}
+
+// ---
+// Tokio impls
+// ---
+
+#[cfg(feature = "tokio1")]
+mod tokio_impl {
+    use super::{Captured, PartialDuplex};
+    use crate::{ops::limited_zero_error, ReadBufExt};
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    impl<T> AsyncRead for PartialDuplex<T>
+    where
+        T: AsyncRead,
+    {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let this = self.project();
+            let inner = this.inner;
+            let capacity = buf.capacity();
+            let filled_before = buf.filled().len();
+
+            let res = this.read_ops.poll_impl(
+                cx,
+                |cx, len| match len {
+                    Some(len) => {
+                        buf.with_limited(len, |limited_buf| inner.poll_read(cx, limited_buf))
+                    }
+                    None => inner.poll_read(cx, buf),
+                },
+                capacity,
+                "error during poll_read, generated by partial-io",
+            );
+            let transferred = buf.filled().len() - filled_before;
+            if let Poll::Ready(Ok(())) = &res {
+                this.read_ops.add_transferred(transferred);
+                Captured::capture_read(this.captured, &buf.filled()[filled_before..]);
+            }
+            res
+        }
+    }
+
+    impl<T> AsyncWrite for PartialDuplex<T>
+    where
+        T: AsyncWrite,
+    {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.project();
+            let inner = this.inner;
+
+            let res = this.write_ops.poll_impl(
+                cx,
+                |cx, len| match len {
+                    Some(0) if !buf.is_empty() => Poll::Ready(Err(limited_zero_error())),
+                    Some(len) => inner.poll_write(cx, &buf[..len]),
+                    None => inner.poll_write(cx, buf),
+                },
+                buf.len(),
+                "error during poll_write, generated by partial-io",
+            );
+            if let Poll::Ready(Ok(n)) = &res {
+                this.write_ops.add_transferred(*n);
+                Captured::capture_written(this.captured, &buf[..*n]);
+            }
+            res
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            let this = self.project();
+            let inner = this.inner;
+
+            this.write_ops.poll_impl_no_limit(
+                cx,
+                |cx| inner.poll_flush(cx),
+                "error during poll_flush, generated by partial-io",
+            )
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+            let this = self.project();
+            let inner = this.inner;
+
+            this.write_ops.poll_impl_no_limit(
+                cx,
+                |cx| inner.poll_shutdown(cx),
+                "error during poll_shutdown, generated by partial-io",
+            )
+        }
+    }
+}
+
+impl<T> fmt::Debug for PartialDuplex<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartialDuplex")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{executor::block_on, io::Cursor};
+    use std::fs::File;
+
+    use crate::tests::assert_send;
+
+    #[test]
+    fn test_sendable() {
+        assert_send::<PartialDuplex<File>>();
+    }
+
+    #[test]
+    fn test_independent_ops() {
+        let read_ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(3),
+        ];
+        let write_ops = vec![
+            PartialOp::Limited(4),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+        ];
+        let inner = Cursor::new(b"Hello, world!".to_vec());
+        let mut partial_duplex = PartialDuplex::new(inner, read_ops, write_ops);
+
+        block_on(async {
+            let mut buf = [0; 8];
+            assert_eq!(partial_duplex.read(&mut buf).await.unwrap(), 2);
+            assert_eq!(&buf[..2], b"He");
+            // The cursor is shared, so this write overwrites "llo, ".
+            assert_eq!(partial_duplex.write(b"LLO, W").await.unwrap(), 4);
+            assert_eq!(partial_duplex.write(b" W").await.unwrap(), 1);
+            assert_eq!(partial_duplex.read(&mut buf).await.unwrap(), 3);
+            assert_eq!(&buf[..3], b"wor");
+            assert_eq!(
+                partial_duplex.flush().await.unwrap_err().kind(),
+                io::ErrorKind::BrokenPipe
+            );
+        });
+
+        assert_eq!(partial_duplex.consumed_read_ops(), 3);
+        assert_eq!(partial_duplex.consumed_write_ops(), 4);
+        assert_eq!(partial_duplex.bytes_read(), 5);
+        assert_eq!(partial_duplex.bytes_written(), 5);
+        assert_eq!(partial_duplex.into_inner().into_inner(), b"HeLLO, world!");
+    }
+
+    #[test]
+    fn test_limited_zero() {
+        let write_ops = vec![PartialOp::Limited(0)];
+        let mut partial_duplex = PartialDuplex::new(Cursor::new(Vec::new()), vec![], write_ops);
+
+        block_on(async {
+            let err = partial_duplex.write_all(b"Hello").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+            assert!(err.to_string().contains("use PartialOp::Eof"), "{}", err);
+            partial_duplex.write_all(b"Hello").await.unwrap();
+        });
+        assert_eq!(partial_duplex.into_inner().into_inner(), b"Hello");
+    }
+
+    /// An in-memory stream that echoes back whatever is written to it, dropping every
+    /// `drop_every`th byte if set.
+    #[derive(Debug, Default)]
+    struct Loopback {
+        buf: std::collections::VecDeque<u8>,
+        drop_every: Option<usize>,
+        written: usize,
+    }
+
+    impl AsyncRead for Loopback {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let n = buf.len().min(self.buf.len());
+            for (dst, src) in buf.iter_mut().zip(self.buf.drain(..n)) {
+                *dst = src;
+            }
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncWrite for Loopback {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            for &b in buf {
+                self.written += 1;
+                if self.drop_every.map_or(true, |n| self.written % n != 0) {
+                    self.buf.push_back(b);
+                }
+            }
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn echo(inner: Loopback) -> PartialDuplex<Loopback> {
+        let read_ops = vec![PartialOp::Limited(3), PartialOp::Limited(5)];
+        let write_ops = vec![PartialOp::Limited(4), PartialOp::Limited(2)];
+        let mut partial_duplex = PartialDuplex::new(inner, read_ops, write_ops);
+        partial_duplex.set_capture(true);
+
+        block_on(async {
+            partial_duplex
+                .write_all(b"Hello, world! Hello again!")
+                .await
+                .unwrap();
+            let mut out = Vec::new();
+            partial_duplex.read_to_end(&mut out).await.unwrap();
+        });
+        partial_duplex
+    }
+
+    #[test]
+    fn test_echo_lossless() {
+        let mut partial_duplex = echo(Loopback::default());
+        partial_duplex.assert_echo_lossless();
+
+        let (read, written) = partial_duplex.take_captured();
+        assert_eq!(read, b"Hello, world! Hello again!");
+        assert_eq!(written, b"Hello, world! Hello again!");
+        assert_eq!(partial_duplex.take_captured(), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_echo_lossy() {
+        let partial_duplex = echo(Loopback {
+            drop_every: Some(5),
+            ..Loopback::default()
+        });
+
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            partial_duplex.assert_echo_lossless()
+        }))
+        .unwrap_err();
+        let message = err
+            .downcast_ref::<String>()
+            .expect("panic message is a String");
+        assert!(
+            message.contains("26 bytes written, 21 bytes read, first difference at offset 4"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains(r#"written from there: b"o, world!"#),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains(r#"read from there: b", wold! "#),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "requires capturing")]
+    fn test_echo_without_capture() {
+        let partial_duplex = PartialDuplex::new(Loopback::default(), vec![], vec![]);
+        partial_duplex.assert_echo_lossless();
+    }
+
+    #[cfg(feature = "tokio1")]
+    #[tokio::test]
+    async fn test_limited_zero_tokio() {
+        use tokio::io::AsyncWriteExt;
+
+        let write_ops = vec![PartialOp::Limited(0)];
+        let mut partial_duplex = PartialDuplex::new(Vec::new(), vec![], write_ops);
+
+        let err = AsyncWriteExt::write_all(&mut partial_duplex, b"Hello")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        AsyncWriteExt::write_all(&mut partial_duplex, b"Hello")
+            .await
+            .unwrap();
+        assert_eq!(partial_duplex.into_inner(), b"Hello");
+    }
+}
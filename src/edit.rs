@@ -0,0 +1,184 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! Helpers for editing sequences of `PartialOp`s.
+//!
+//! These are meant for probing the boundaries of a failing case, e.g. one found by
+//! [`minimize`](crate::minimize) or by a property-based test: start from the failing sequence,
+//! tweak one op at a time, and see whether the failure still reproduces.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use partial_io::{edit, PartialOp};
+//! use std::io;
+//!
+//! let mut ops = vec![PartialOp::Limited(3), PartialOp::Unlimited];
+//! edit::bump_limit_at(&mut ops, 0, 2);
+//! edit::insert_error_at(&mut ops, 1, io::ErrorKind::Interrupted);
+//! edit::remove_at(&mut ops, 2);
+//! assert_eq!(
+//!     ops,
+//!     vec![PartialOp::Limited(5), PartialOp::Err(io::ErrorKind::Interrupted)],
+//! );
+//! ```
+
+use crate::PartialOp;
+use std::io;
+
+/// Adds `delta` to the limit of the `PartialOp::Limited` op at `index`, and returns the old op.
+///
+/// The new limit saturates at 0 and `usize::MAX`.
+///
+/// # Panics
+///
+/// Panics if `index` is out of bounds, or if the op at `index` isn't `PartialOp::Limited`.
+pub fn bump_limit_at(ops: &mut [PartialOp], index: usize, delta: isize) -> PartialOp {
+    let op = &mut ops[index];
+    let old = op.clone();
+    match op {
+        PartialOp::Limited(n) => {
+            *n = if delta >= 0 {
+                n.saturating_add(delta as usize)
+            } else {
+                n.saturating_sub(delta.unsigned_abs())
+            };
+        }
+        other => panic!(
+            "op at index {} is {:?}, but only Limited ops can be bumped",
+            index, other
+        ),
+    }
+    old
+}
+
+/// Clamps the limits of `PartialOp::Limited` ops to at most `max`, and returns the number of ops
+/// that were changed.
+///
+/// This is a sanitizer for sequences of ops that come from outside the test, e.g. from
+/// [`PartialOp::from_env`] or a file, so that a malformed or hostile sequence can't make a
+/// wrapper ask for huge transfers. Other ops are left alone. Note that `PartialOp::Unlimited`
+/// ops aren't affected either: use `set_unlimited_cap` on the wrapper to bound those.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{edit, parse_ops, PartialOp};
+///
+/// let mut ops = parse_ops("L4 L100000000 U").unwrap();
+/// assert_eq!(edit::clamp_limits(&mut ops, 4096), 1);
+/// assert_eq!(
+///     ops,
+///     vec![PartialOp::Limited(4), PartialOp::Limited(4096), PartialOp::Unlimited],
+/// );
+/// ```
+pub fn clamp_limits(ops: &mut [PartialOp], max: usize) -> usize {
+    let mut clamped = 0;
+    for op in ops {
+        if let PartialOp::Limited(n) = op {
+            if *n > max {
+                *n = max;
+                clamped += 1;
+            }
+        }
+    }
+    clamped
+}
+
+/// Inserts a `PartialOp::Err(kind)` op at `index`, shifting all ops after it to the right.
+///
+/// # Panics
+///
+/// Panics if `index > ops.len()`.
+pub fn insert_error_at(ops: &mut Vec<PartialOp>, index: usize, kind: io::ErrorKind) {
+    ops.insert(index, PartialOp::Err(kind));
+}
+
+/// Removes and returns the op at `index`, shifting all ops after it to the left.
+///
+/// # Panics
+///
+/// Panics if `index` is out of bounds.
+pub fn remove_at(ops: &mut Vec<PartialOp>, index: usize) -> PartialOp {
+    ops.remove(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bump_limit_at() {
+        let mut ops = vec![PartialOp::Unlimited, PartialOp::Limited(5)];
+        assert_eq!(bump_limit_at(&mut ops, 1, 3), PartialOp::Limited(5));
+        assert_eq!(ops[1], PartialOp::Limited(8));
+        assert_eq!(bump_limit_at(&mut ops, 1, -2), PartialOp::Limited(8));
+        assert_eq!(ops[1], PartialOp::Limited(6));
+        bump_limit_at(&mut ops, 1, -100);
+        assert_eq!(ops[1], PartialOp::Limited(0), "saturates at 0");
+        bump_limit_at(&mut ops, 1, isize::MIN);
+        assert_eq!(ops[1], PartialOp::Limited(0));
+        assert_eq!(ops[0], PartialOp::Unlimited, "other ops are untouched");
+    }
+
+    #[test]
+    #[should_panic(expected = "op at index 0 is Unlimited, but only Limited ops can be bumped")]
+    fn test_bump_limit_at_not_limited() {
+        bump_limit_at(&mut [PartialOp::Unlimited], 0, 1);
+    }
+
+    #[test]
+    fn test_clamp_limits() {
+        let mut ops = vec![
+            PartialOp::Limited(usize::MAX),
+            PartialOp::Limited(8),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(9),
+            PartialOp::Unlimited,
+            PartialOp::Limited(0),
+        ];
+        assert_eq!(clamp_limits(&mut ops, 8), 2);
+        assert_eq!(
+            ops,
+            vec![
+                PartialOp::Limited(8),
+                PartialOp::Limited(8),
+                PartialOp::Err(io::ErrorKind::Interrupted),
+                PartialOp::Limited(8),
+                PartialOp::Unlimited,
+                PartialOp::Limited(0),
+            ]
+        );
+        assert_eq!(clamp_limits(&mut ops, 8), 0, "clamping is idempotent");
+    }
+
+    #[test]
+    fn test_insert_error_at() {
+        let mut ops = vec![PartialOp::Limited(1), PartialOp::Limited(2)];
+        insert_error_at(&mut ops, 1, io::ErrorKind::WouldBlock);
+        insert_error_at(&mut ops, 3, io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            ops,
+            vec![
+                PartialOp::Limited(1),
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+                PartialOp::Limited(2),
+                PartialOp::Err(io::ErrorKind::BrokenPipe),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remove_at() {
+        let mut ops = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        assert_eq!(
+            remove_at(&mut ops, 1),
+            PartialOp::Err(io::ErrorKind::Interrupted)
+        );
+        assert_eq!(ops, vec![PartialOp::Limited(1), PartialOp::Unlimited]);
+    }
+}
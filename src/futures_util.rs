@@ -1,96 +1,941 @@
 // Copyright (c) The partial-io Contributors
 // SPDX-License-Identifier: MIT
 
-use crate::{make_ops, PartialOp};
+use crate::{
+    ops::{trace_op, BoxedOps, ExhaustedBehavior, OpFn, OpSnapshot, Ops},
+    Diagnostics, PartialOp, PhasePosition, PhasedSchedule,
+};
+use once_cell::sync::Lazy;
 use std::{
-    cmp, io,
-    task::{Context, Poll},
+    cmp,
+    collections::BinaryHeap,
+    fmt, io,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
 };
+#[cfg(feature = "tokio1")]
+use std::{future::Future, pin::Pin};
+
+/// The outcome of a single `poll_` method call on an async wrapper, as recorded in its poll trace.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PollOutcome {
+    /// The call returned `Poll::Ready(Ok(_))`, transferring this many bytes.
+    ///
+    /// This is always 0 for methods that don't transfer data, such as `poll_flush`.
+    Ready(usize),
+
+    /// The call returned `Poll::Ready(Err(e))`, with `e` being of this kind.
+    Err(io::ErrorKind),
+
+    /// The call returned `Poll::Pending`.
+    Pending,
+}
+
+impl PollOutcome {
+    /// Converts the result of a `poll_` method call into an outcome, using `len` to get the number
+    /// of bytes transferred by a successful call.
+    pub(crate) fn from_poll<T>(poll: &Poll<io::Result<T>>, len: impl FnOnce(&T) -> usize) -> Self {
+        match poll {
+            Poll::Ready(Ok(val)) => PollOutcome::Ready(len(val)),
+            Poll::Ready(Err(err)) => PollOutcome::Err(err.kind()),
+            Poll::Pending => PollOutcome::Pending,
+        }
+    }
+}
+
+/// Summarizes the result of a `poll_` method call for tracing, using `val` to pick out what to show
+/// of a successful call.
+#[inline]
+fn poll_outcome<'a, T, U>(
+    poll: &'a Poll<io::Result<T>>,
+    val: impl FnOnce(&'a T) -> &'a U,
+) -> Poll<Result<&'a U, io::ErrorKind>>
+where
+    U: ?Sized,
+{
+    match poll {
+        Poll::Ready(Ok(t)) => Poll::Ready(Ok(val(t))),
+        Poll::Ready(Err(err)) => Poll::Ready(Err(err.kind())),
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+/// Creates a one-shot gate for an async wrapper, along with the trigger that opens it.
+///
+/// Attach the [`Gate`] to a `PartialAsyncRead` or `PartialAsyncWrite` with its `set_gate`
+/// method. The next read or write through the wrapper then returns `Poll::Pending` until
+/// [`GateTrigger::open`] is called, for example from another task, which wakes the waiting task
+/// up. This makes it possible to interleave I/O with other events in a precise order.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[cfg(feature = "tokio1")]
+/// use partial_io::{gate, PartialAsyncWrite};
+/// # #[cfg(feature = "tokio1")]
+/// use tokio::io::AsyncWriteExt;
+///
+/// # #[cfg(feature = "tokio1")]
+/// #[tokio::main]
+/// async fn main() {
+///     let (gate, trigger) = gate();
+///     let mut partial_writer = PartialAsyncWrite::new(Vec::new(), vec![]);
+///     partial_writer.set_gate(gate);
+///
+///     let write = tokio::spawn(async move {
+///         partial_writer.write_all(b"Hello").await.unwrap();
+///         partial_writer
+///     });
+///     // ... do something else while the write is held up ...
+///     trigger.open();
+///     let partial_writer = write.await.unwrap();
+///     assert_eq!(partial_writer.get_ref(), b"Hello");
+/// }
+///
+/// # #[cfg(not(feature = "tokio1"))]
+/// # fn main() {
+/// #     assert!(true, "dummy test");
+/// # }
+/// ```
+pub fn gate() -> (Gate, GateTrigger) {
+    let state = Arc::new(Mutex::new(GateState {
+        open: false,
+        waker: None,
+    }));
+    (
+        Gate {
+            state: state.clone(),
+        },
+        GateTrigger { state },
+    )
+}
+
+/// A gate that holds up the next read or write through an async wrapper until it's opened.
+///
+/// Created by [`gate`].
+#[derive(Debug)]
+pub struct Gate {
+    state: Arc<Mutex<GateState>>,
+}
+
+/// Opens the [`Gate`] it was created with.
+///
+/// Created by [`gate`]. If every trigger is dropped without opening the gate, the gate stays
+/// closed forever.
+#[derive(Clone, Debug)]
+pub struct GateTrigger {
+    state: Arc<Mutex<GateState>>,
+}
+
+#[derive(Debug)]
+struct GateState {
+    open: bool,
+    waker: Option<Waker>,
+}
+
+impl Gate {
+    /// Returns `Poll::Ready` if the gate is open, and otherwise registers the task to be woken up
+    /// once it opens.
+    fn poll_open(&self, cx: &mut Context) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.open {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl GateTrigger {
+    /// Opens the gate, waking up the task waiting on it if there is one.
+    ///
+    /// Opening a gate more than once has no further effect.
+    pub fn open(&self) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.open = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Creates the error for a `PartialOp::Err` or `PartialOp::ErrMsg` of this kind, using the op's
+/// message if it has one and `err_str` otherwise.
+fn injected_error(op: Option<&PartialOp>, kind: io::ErrorKind, err_str: &'static str) -> io::Error {
+    match op {
+        Some(PartialOp::ErrMsg(_, msg)) => io::Error::new(kind, msg.clone()),
+        _ => io::Error::new(kind, err_str),
+    }
+}
+
+/// A timer for a `PartialOp::Delay`, which wakes the waiting task up once the delay has elapsed.
+///
+/// Within a Tokio runtime (with the `tokio1` feature), the runtime's own timer is used, so delays
+/// respect paused time in tests. Otherwise, delays are tracked by a single timer thread shared by
+/// all wrappers, so that they work with any executor.
+enum DelayTimer {
+    #[cfg(feature = "tokio1")]
+    Tokio(Pin<Box<tokio::time::Sleep>>),
+    Shared(SharedDelay),
+}
+
+impl DelayTimer {
+    fn start(duration: Duration) -> Self {
+        #[cfg(feature = "tokio1")]
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return DelayTimer::Tokio(Box::pin(tokio::time::sleep(duration)));
+        }
+        DelayTimer::Shared(SharedDelay::start(duration))
+    }
+
+    /// Returns `Poll::Ready` if the delay has elapsed, and otherwise registers the task to be
+    /// woken up once it does.
+    fn poll_elapsed(&mut self, cx: &mut Context) -> Poll<()> {
+        match self {
+            #[cfg(feature = "tokio1")]
+            DelayTimer::Tokio(sleep) => sleep.as_mut().poll(cx),
+            DelayTimer::Shared(delay) => delay.poll_elapsed(cx),
+        }
+    }
+}
+
+/// A delay tracked by the shared timer thread.
+struct SharedDelay {
+    deadline: Instant,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl SharedDelay {
+    fn start(duration: Duration) -> Self {
+        let now = Instant::now();
+        // Saturate rather than overflow for very long delays, such as Duration::MAX.
+        let deadline = now.checked_add(duration).unwrap_or_else(far_future);
+        let waker = Arc::new(Mutex::new(None));
+        TIMER_THREAD.register(TimerEntry {
+            deadline,
+            waker: waker.clone(),
+        });
+        Self { deadline, waker }
+    }
+
+    fn poll_elapsed(&self, cx: &mut Context) -> Poll<()> {
+        let mut waker = self.waker.lock().unwrap();
+        // The timer thread only takes the waker once the deadline has passed, so checking the
+        // deadline with the lock held means that a wakeup can't be missed.
+        if Instant::now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            *waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+fn far_future() -> Instant {
+    // About 30 years, like tokio::time::Instant::far_future.
+    Instant::now() + Duration::from_secs(86400 * 365 * 30)
+}
+
+static TIMER_THREAD: Lazy<TimerThread> = Lazy::new(TimerThread::spawn);
+
+/// A thread that wakes up tasks once their delays have elapsed.
+struct TimerThread {
+    shared: Arc<(Mutex<BinaryHeap<TimerEntry>>, Condvar)>,
+}
+
+impl TimerThread {
+    fn spawn() -> Self {
+        let shared: Arc<(Mutex<BinaryHeap<TimerEntry>>, Condvar)> = Arc::default();
+        let thread_shared = shared.clone();
+        thread::Builder::new()
+            .name("partial-io-timer".to_owned())
+            .spawn(move || Self::run(&thread_shared))
+            .expect("partial-io: failed to spawn timer thread");
+        Self { shared }
+    }
 
-pub(crate) struct FuturesOps {
-    ops: Box<dyn Iterator<Item = PartialOp> + Send>,
+    fn register(&self, entry: TimerEntry) {
+        let (entries, condvar) = &*self.shared;
+        entries.lock().unwrap().push(entry);
+        condvar.notify_one();
+    }
+
+    fn run(shared: &(Mutex<BinaryHeap<TimerEntry>>, Condvar)) {
+        let (entries, condvar) = shared;
+        let mut entries = entries.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            while entries.peek().map_or(false, |entry| entry.deadline <= now) {
+                let entry = entries.pop().expect("just peeked");
+                let waker = entry.waker.lock().unwrap().take();
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+            let next_deadline = entries.peek().map(|entry| entry.deadline);
+            entries = match next_deadline {
+                Some(deadline) => condvar.wait_timeout(entries, deadline - now).unwrap().0,
+                None => condvar.wait(entries).unwrap(),
+            };
+        }
+    }
+}
+
+/// A delay registered with the timer thread, ordered so that the earliest deadline is at the top
+/// of the heap.
+struct TimerEntry {
+    deadline: Instant,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+/// A simulated bounded queue that fills up as data is written, and drains over time.
+pub(crate) struct QueueModel {
+    capacity: usize,
+    drain_rate: u64,
+    clock: Box<dyn FnMut() -> Instant + Send>,
+    level: usize,
+    last_drained: Instant,
+    delay: Option<DelayTimer>,
+}
+
+/// Returns the current time, according to Tokio's clock with the `tokio1` feature, so that paused
+/// time is respected in tests.
+pub(crate) fn runtime_now() -> Instant {
+    #[cfg(feature = "tokio1")]
+    return tokio::time::Instant::now().into_std();
+    #[cfg(not(feature = "tokio1"))]
+    Instant::now()
+}
+
+impl QueueModel {
+    /// Creates a new, empty queue that drains `drain_rate` bytes per second.
+    pub(crate) fn new<C>(capacity: usize, drain_rate: u64, mut clock: C) -> Self
+    where
+        C: FnMut() -> Instant + Send + 'static,
+    {
+        let last_drained = clock();
+        Self {
+            capacity,
+            drain_rate,
+            clock: Box::new(clock),
+            level: 0,
+            last_drained,
+            delay: None,
+        }
+    }
+
+    /// Limits `buf` to the space left in the queue, or returns `Poll::Pending` and schedules the
+    /// task to be woken up if the queue is full.
+    pub(crate) fn poll_limit<'a>(&mut self, cx: &mut Context, buf: &'a [u8]) -> Poll<&'a [u8]> {
+        self.poll_space(cx, buf.len()).map(|len| &buf[..len])
+    }
+
+    /// Limits a write of `len` bytes to the space left in the queue, or returns `Poll::Pending`
+    /// and schedules the task to be woken up if the queue is full.
+    ///
+    /// The task is woken up through a [`DelayTimer`] once the next byte is due to drain, so that
+    /// it stays idle while it waits.
+    pub(crate) fn poll_space(&mut self, cx: &mut Context, len: usize) -> Poll<usize> {
+        let now = (self.clock)();
+        self.drain(now);
+        let space = self.capacity - self.level;
+        if space > 0 || len == 0 {
+            self.delay = None;
+            return Poll::Ready(cmp::min(space, len));
+        }
+
+        if let Some(delay) = &mut self.delay {
+            if delay.poll_elapsed(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        // Either no timer is running, or it has elapsed without the clock passed in catching up:
+        // wait for the next byte to drain.
+        self.delay = None;
+        if let Some(wait) = self.time_to_drain_one(now) {
+            let mut delay = DelayTimer::start(wait);
+            if delay.poll_elapsed(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            } else {
+                self.delay = Some(delay);
+            }
+        }
+        // Otherwise, the queue never drains, so the task is never woken up.
+        Poll::Pending
+    }
+
+    /// Adds `n` written bytes to the queue.
+    pub(crate) fn push(&mut self, n: usize) {
+        self.level += n;
+    }
+
+    fn drain(&mut self, now: Instant) {
+        if self.level == 0 {
+            // An empty queue doesn't bank time to drain later writes with.
+            self.last_drained = now;
+            return;
+        }
+        let elapsed = now.saturating_duration_since(self.last_drained);
+        let drained = elapsed.as_nanos() * u128::from(self.drain_rate) / 1_000_000_000;
+        // Only move the clock forward once whole bytes have drained, so that frequent polls
+        // still make progress.
+        if drained > 0 {
+            self.level -= cmp::min(drained, self.level as u128) as usize;
+            self.last_drained = now;
+        }
+    }
+
+    /// Returns how long after `now` the next byte drains, or `None` if the queue doesn't drain.
+    fn time_to_drain_one(&self, now: Instant) -> Option<Duration> {
+        if self.drain_rate == 0 {
+            return None;
+        }
+        // The smallest elapsed time for which `drain` counts a whole byte.
+        let per_byte = (1_000_000_000 + self.drain_rate - 1) / self.drain_rate;
+        let elapsed = now.saturating_duration_since(self.last_drained);
+        Some(Duration::from_nanos(per_byte).saturating_sub(elapsed))
+    }
+}
+
+/// The state behind the async wrappers in this crate, for building custom wrappers.
+///
+/// `FuturesOps` holds a sequence of `PartialOp`s, and applies them to `poll_` methods through
+/// [`poll_impl`](Self::poll_impl) and [`poll_impl_no_limit`](Self::poll_impl_no_limit). This
+/// makes it possible to inject partial-io behavior directly into a type's own `AsyncRead`,
+/// `AsyncWrite` or similar implementations, rather than wrapping it with `PartialAsyncRead` or
+/// `PartialAsyncWrite`.
+///
+/// Like the wrappers, `FuturesOps` is generic over the iterator of ops, and defaults to the boxed
+/// [`BoxedOps`]. Use [`new_unboxed`](Self::new_unboxed) to avoid boxing the iterator.
+///
+/// Requires the `futures03` feature to be enabled.
+pub struct FuturesOps<I = BoxedOps> {
+    ops: Ops<I>,
+    pending_since: Option<Instant>,
+    pending_duration: Duration,
+    pending_clock: Box<dyn Fn() -> Instant + Send>,
+    last_op: Option<PartialOp>,
+    wouldblock_as_error: bool,
+    poll_trace: Option<Vec<PollOutcome>>,
+    gate: Option<Gate>,
+    delay: Option<DelayTimer>,
 }
 
 impl FuturesOps {
-    /// Creates a new instance of `TokioOps`.
-    pub(crate) fn new<I>(iter: I) -> Self
+    /// Creates a new `FuturesOps` with the specified `PartialOp`s.
+    pub fn new<I>(iter: I) -> Self
     where
         I: IntoIterator<Item = PartialOp> + 'static,
         I::IntoIter: Send,
     {
-        Self {
-            ops: make_ops(iter),
-        }
+        Self::from_ops(Ops::new(iter))
     }
 
-    /// Replaces ops with a new iterator.
-    pub(crate) fn replace<I>(&mut self, iter: I)
+    /// Replaces the remaining ops with the specified `PartialOp`s.
+    pub fn replace<I>(&mut self, iter: I)
     where
         I: IntoIterator<Item = PartialOp> + 'static,
         I::IntoIter: Send,
     {
-        self.ops = make_ops(iter)
+        self.ops.replace(iter)
     }
 
-    /// Helper for poll methods.
+    /// Replaces ops with the ops in a phased schedule, and starts tracking its phases.
+    pub(crate) fn replace_phased(&mut self, schedule: PhasedSchedule) {
+        self.ops.replace_phased(schedule)
+    }
+
+    /// Materializes the remaining ops and returns a copy of them.
+    pub(crate) fn fork(&mut self) -> Vec<PartialOp> {
+        self.ops.fork()
+    }
+
+    /// Materializes the remaining ops and returns a snapshot of the current state.
+    pub(crate) fn snapshot(&mut self) -> OpSnapshot {
+        self.ops.snapshot()
+    }
+
+    /// Restores the state captured in a snapshot.
+    pub(crate) fn restore(&mut self, snapshot: &OpSnapshot) {
+        self.ops.restore(snapshot)
+    }
+
+    /// Replaces ops with a callback that computes each op from the history of earlier ops.
+    pub(crate) fn replace_with_fn(&mut self, op_fn: OpFn) {
+        self.ops.replace_with_fn(op_fn)
+    }
+}
+
+impl<I> FuturesOps<I>
+where
+    I: Iterator<Item = PartialOp>,
+{
+    /// Creates a new `FuturesOps` with the specified `PartialOp`s, without boxing the iterator.
     ///
-    /// `cb` is the callback that implements the actual logic. The second argument is `Some(n)` to
-    /// limit the number of bytes being written, or `None` for unlimited.
-    pub(crate) fn poll_impl<T>(
+    /// Unlike with [`new`](FuturesOps::new), the iterator doesn't need to be `'static` or
+    /// `Send`, and the ops can't be replaced afterwards.
+    pub fn new_unboxed(iter: I) -> Self {
+        Self::from_ops(Ops::new_unboxed(iter))
+    }
+
+    fn from_ops(mut ops: Ops<I>) -> Self {
+        // Delays are turned into Poll::Pending by poll_impl, rather than blocking the thread.
+        ops.set_sleep_on_delay(false);
+        Self {
+            ops,
+            pending_since: None,
+            pending_duration: Duration::ZERO,
+            pending_clock: Box::new(Instant::now),
+            last_op: None,
+            wouldblock_as_error: false,
+            poll_trace: None,
+            gate: None,
+            delay: None,
+        }
+    }
+
+    /// Returns the phase that the last consumed op belongs to, if phases are being tracked.
+    pub(crate) fn current_phase(&self) -> Option<PhasePosition> {
+        self.ops.current_phase()
+    }
+
+    /// Returns the limit of the next op if it is `PartialOp::Limited`, without consuming it.
+    pub(crate) fn next_limit(&mut self) -> Option<usize> {
+        self.ops.next_limit()
+    }
+
+    /// Returns a summary of the state recorded so far. Wrappers fill in their own fields.
+    pub(crate) fn diagnostics(&self) -> Diagnostics {
+        self.ops.diagnostics()
+    }
+
+    /// Returns the kinds of errors injected so far, in order.
+    pub(crate) fn errors_seen(&self) -> &[io::ErrorKind] {
+        self.ops.errors_seen()
+    }
+
+    /// Returns the kinds of errors injected so far, along with the number of bytes transferred
+    /// before each one.
+    pub(crate) fn error_offsets(&self) -> &[(io::ErrorKind, u64)] {
+        self.ops.error_offsets()
+    }
+
+    /// Records that `n` more bytes were transferred.
+    #[inline]
+    pub(crate) fn add_transferred(&mut self, n: usize) {
+        self.ops.add_transferred(n)
+    }
+
+    /// Returns the total number of bytes transferred so far.
+    pub(crate) fn transferred(&self) -> u64 {
+        self.ops.transferred()
+    }
+
+    /// Panics if the number of ops consumed since the ops were last replaced isn't `expected`.
+    #[track_caller]
+    pub(crate) fn assert_op_count(&self, expected: usize) {
+        self.ops.assert_op_count(expected)
+    }
+
+    /// Returns the number of ops consumed since the ops were last replaced.
+    pub(crate) fn consumed(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns the number of ops left, if the iterator reports an exact size.
+    pub(crate) fn remaining(&self) -> Option<usize> {
+        self.ops.remaining()
+    }
+
+    /// Sets the kind of error that latches once it is injected.
+    pub(crate) fn set_latch_error(&mut self, kind: Option<io::ErrorKind>) {
+        self.ops.set_latch_error(kind)
+    }
+
+    /// Sets the probability of merging each `PartialOp::Limited` op with the one after it.
+    pub(crate) fn set_coalesce_prob(&mut self, prob: Option<(f64, u64)>) {
+        self.ops.set_coalesce_prob(prob)
+    }
+
+    /// Sets the limit that `PartialOp::Unlimited` ops are turned into.
+    pub(crate) fn set_unlimited_cap(&mut self, cap: Option<usize>) {
+        self.ops.set_unlimited_cap(cap)
+    }
+
+    /// Sets what happens once the ops have run out.
+    pub(crate) fn set_exhausted_behavior(&mut self, behavior: ExhaustedBehavior) {
+        self.ops.set_exhausted_behavior(behavior)
+    }
+
+    /// Attaches a gate that wrappers check with [`poll_gate`](Self::poll_gate) before consuming
+    /// any ops, replacing any gate attached earlier.
+    pub(crate) fn set_gate(&mut self, gate: Gate) {
+        self.gate = Some(gate);
+    }
+
+    /// Returns `Poll::Pending` while a gate is attached and closed, and removes the gate once it
+    /// has opened.
+    pub(crate) fn poll_gate(&mut self, cx: &mut Context) -> Poll<()> {
+        if let Some(gate) = &self.gate {
+            if gate.poll_open(cx).is_pending() {
+                self.trace(PollOutcome::Pending);
+                return Poll::Pending;
+            }
+            self.gate = None;
+        }
+        Poll::Ready(())
+    }
+
+    /// Returns whether `WouldBlock` errors are returned to the caller rather than turned into
+    /// `Poll::Pending`.
+    pub(crate) fn wouldblock_as_error(&self) -> bool {
+        self.wouldblock_as_error
+    }
+
+    /// Sets whether `WouldBlock` errors are returned to the caller rather than turned into
+    /// `Poll::Pending`.
+    pub(crate) fn set_wouldblock_as_error(&mut self, as_error: bool) {
+        self.wouldblock_as_error = as_error;
+    }
+
+    /// Sets whether the outcomes of `poll_` method calls are recorded.
+    pub(crate) fn set_poll_trace(&mut self, trace: bool) {
+        self.poll_trace = if trace { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns the outcomes recorded so far, and clears them.
+    pub(crate) fn take_poll_trace(&mut self) -> Vec<PollOutcome> {
+        self.poll_trace
+            .as_mut()
+            .map_or_else(Vec::new, std::mem::take)
+    }
+
+    /// Records the outcome of a `poll_` method call, if tracing is enabled.
+    #[inline]
+    pub(crate) fn trace(&mut self, outcome: PollOutcome) {
+        if let Some(trace) = &mut self.poll_trace {
+            trace.push(outcome);
+        }
+    }
+
+    /// Returns the latched error, if any.
+    pub(crate) fn latched_error(&self) -> Option<io::ErrorKind> {
+        self.ops.latched_error()
+    }
+
+    /// Clears the latched error, if any.
+    pub(crate) fn clear_latch(&mut self) {
+        self.ops.clear_latch()
+    }
+
+    /// Returns the op applied by the last call to `poll_impl` that returned `Poll::Ready`, or
+    /// `None` if the ops had run out.
+    pub(crate) fn last_op(&self) -> Option<&PartialOp> {
+        self.last_op.as_ref()
+    }
+
+    /// Returns the total time spent pending due to `WouldBlock` errors and delays.
+    ///
+    /// This includes the time since the last `Poll::Pending` if the wrapper hasn't been polled
+    /// again yet.
+    pub(crate) fn pending_duration(&self) -> Duration {
+        let now = (self.pending_clock)();
+        self.pending_duration
+            + self
+                .pending_since
+                .map_or(Duration::ZERO, |since| now.saturating_duration_since(since))
+    }
+
+    /// Sets the clock that time spent pending is measured with.
+    ///
+    /// A pending period in progress is measured up to now with the old clock, and continues with
+    /// the new one.
+    pub(crate) fn set_pending_clock(&mut self, clock: Box<dyn Fn() -> Instant + Send>) {
+        let was_pending = self.pending_since.is_some();
+        self.end_pending();
+        self.pending_clock = clock;
+        if was_pending {
+            self.start_pending();
+        }
+    }
+
+    /// Starts a timer for a `PartialOp::Delay`, and polls it once.
+    fn start_delay(&mut self, cx: &mut Context, duration: Duration) -> Poll<()> {
+        self.delay = Some(DelayTimer::start(duration));
+        self.poll_delay(cx)
+    }
+
+    /// Returns `Poll::Pending` while a delay is running, and clears the delay once it has
+    /// elapsed.
+    fn poll_delay(&mut self, cx: &mut Context) -> Poll<()> {
+        if let Some(delay) = &mut self.delay {
+            if delay.poll_elapsed(cx).is_pending() {
+                self.start_pending();
+                return Poll::Pending;
+            }
+            self.delay = None;
+        }
+        Poll::Ready(())
+    }
+
+    /// Marks the start of a period spent pending.
+    fn start_pending(&mut self) {
+        self.pending_since = Some((self.pending_clock)());
+    }
+
+    /// Marks the end of a period spent pending, if there is one.
+    #[inline]
+    fn end_pending(&mut self) {
+        if let Some(since) = self.pending_since.take() {
+            self.pending_duration += (self.pending_clock)().saturating_duration_since(since);
+        }
+    }
+
+    /// Applies the next `PartialOp` to a `poll_` method that transfers bytes, such as `poll_read`
+    /// or `poll_write`.
+    ///
+    /// `len` is the number of bytes that the caller asked to transfer, typically the length of
+    /// the buffer passed in. `cb` implements the actual transfer: it is passed the context, and
+    /// `Some(n)` with `n <= len` if the transfer must be limited to `n` bytes, or `None` if it
+    /// is unlimited. `cb` is called at most once, and only if the op lets the call through to the
+    /// underlying instance.
+    ///
+    /// The next op is consumed, and:
+    ///
+    /// * `PartialOp::Limited(n)` calls `cb` with `Some(min(n, len))`.
+    /// * `PartialOp::Unlimited`, or running out of ops, calls `cb` with `None`.
+    /// * `PartialOp::Err(WouldBlock)` returns `Poll::Pending` and schedules the task to be woken
+    ///   up.
+    /// * `PartialOp::Err(Interrupted)` is retried with the op after it.
+    /// * Other errors are returned as `Poll::Ready(Err(e))`, with `err_str` as the message for
+    ///   `PartialOp::Err` ops.
+    /// * `PartialOp::Delay` returns `Poll::Pending` until the delay has elapsed, then applies the
+    ///   op after it.
+    /// * `PartialOp::Eof` returns `Poll::Ready(Ok(T::default()))` without calling `cb`.
+    /// * `PartialOp::PendingOnce` returns `Poll::Pending` without scheduling the task to be woken
+    ///   up.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use futures::{executor::block_on, io::{AsyncRead, AsyncReadExt}};
+    /// use partial_io::{FuturesOps, PartialOp};
+    /// use std::{
+    ///     io,
+    ///     pin::Pin,
+    ///     task::{Context, Poll},
+    /// };
+    ///
+    /// // A custom reader with partial-io behavior built in.
+    /// struct MyReader<R> {
+    ///     inner: R,
+    ///     ops: FuturesOps,
+    /// }
+    ///
+    /// impl<R: AsyncRead + Unpin> AsyncRead for MyReader<R> {
+    ///     fn poll_read(
+    ///         self: Pin<&mut Self>,
+    ///         cx: &mut Context<'_>,
+    ///         buf: &mut [u8],
+    ///     ) -> Poll<io::Result<usize>> {
+    ///         let this = self.get_mut();
+    ///         let inner = &mut this.inner;
+    ///         let len = buf.len();
+    ///         this.ops.poll_impl(
+    ///             cx,
+    ///             |cx, limit| match limit {
+    ///                 Some(n) => Pin::new(inner).poll_read(cx, &mut buf[..n]),
+    ///                 None => Pin::new(inner).poll_read(cx, buf),
+    ///             },
+    ///             len,
+    ///             "error during poll_read, generated by MyReader",
+    ///         )
+    ///     }
+    /// }
+    ///
+    /// let ops = vec![
+    ///     PartialOp::Limited(3),
+    ///     PartialOp::Err(io::ErrorKind::WouldBlock),
+    ///     PartialOp::Err(io::ErrorKind::BrokenPipe),
+    /// ];
+    /// let mut reader = MyReader {
+    ///     inner: &b"Hello, world!"[..],
+    ///     ops: FuturesOps::new(ops),
+    /// };
+    /// let mut buf = [0; 16];
+    /// block_on(async {
+    ///     assert_eq!(reader.read(&mut buf).await.unwrap(), 3);
+    ///     let err = reader.read(&mut buf).await.unwrap_err();
+    ///     assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    ///     assert_eq!(err.to_string(), "error during poll_read, generated by MyReader");
+    ///     assert_eq!(reader.read(&mut buf).await.unwrap(), 10);
+    /// });
+    /// ```
+    pub fn poll_impl<T>(
         &mut self,
         cx: &mut Context,
         cb: impl FnOnce(&mut Context, Option<usize>) -> Poll<io::Result<T>>,
-        remaining: usize,
+        len: usize,
         err_str: &'static str,
-    ) -> Poll<io::Result<T>> {
+    ) -> Poll<io::Result<T>>
+    where
+        T: Default + fmt::Debug,
+    {
+        self.end_pending();
+        if self.poll_delay(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if self.ops.is_exhausted() {
+            // Fast path: nothing left to do but call into the inner instance.
+            self.last_op = None;
+            return cb(cx, None);
+        }
+        if self.ops.take_unlimited() {
+            // Also call straight into the inner instance during a run of Unlimited ops.
+            let poll = cb(cx, None);
+            if poll.is_ready() {
+                self.last_op = Some(PartialOp::Unlimited);
+            }
+            return poll;
+        }
+
         loop {
-            match self.ops.next() {
-                Some(PartialOp::Limited(n)) => {
-                    let len = cmp::min(n, remaining);
-                    break cb(cx, Some(len));
-                }
-                Some(PartialOp::Err(kind)) => {
-                    if kind == io::ErrorKind::WouldBlock {
+            let op = self.ops.next();
+            let poll = match op {
+                Some(PartialOp::Limited(n)) => cb(cx, Some(cmp::min(n, len))),
+                Some(PartialOp::Err(kind) | PartialOp::ErrMsg(kind, _)) => {
+                    if kind == io::ErrorKind::WouldBlock && !self.wouldblock_as_error {
                         // Async* instances must convert WouldBlock errors to Poll::Pending and
                         // reschedule the task.
                         cx.waker().wake_by_ref();
-                        break Poll::Pending;
+                        self.start_pending();
+                        Poll::Pending
                     } else if kind == io::ErrorKind::Interrupted {
                         // Async* instances must retry on Interrupted errors.
+                        trace_op(op.as_ref(), Some(len), "retrying");
                         continue;
                     } else {
-                        break Poll::Ready(Err(io::Error::new(kind, err_str)));
+                        Poll::Ready(Err(injected_error(op.as_ref(), kind, err_str)))
+                    }
+                }
+                Some(PartialOp::Unlimited) | None => cb(cx, None),
+                Some(PartialOp::Delay(duration)) => {
+                    let poll = self.start_delay(cx, duration);
+                    trace_op(op.as_ref(), Some(len), poll);
+                    match poll {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(()) => continue,
                     }
                 }
-                Some(PartialOp::Unlimited) | None => break cb(cx, None),
+                // For reads, a successful call that transfers nothing signals end-of-stream.
+                Some(PartialOp::Eof) => Poll::Ready(Ok(T::default())),
+                Some(PartialOp::PendingOnce) => {
+                    // Deliberately don't wake the task: it's up to the caller to poll again.
+                    self.start_pending();
+                    Poll::Pending
+                }
+            };
+            trace_op(op.as_ref(), Some(len), poll_outcome(&poll, |val| val));
+            if poll.is_ready() {
+                self.last_op = op;
             }
+            return poll;
         }
     }
 
-    /// Helper for poll methods that ignore the length specified in `PartialOp::Limited`.
-    pub(crate) fn poll_impl_no_limit<T>(
+    /// Applies the next `PartialOp` to a `poll_` method that doesn't transfer bytes, such as
+    /// `poll_flush` or `poll_close`.
+    ///
+    /// `cb` implements the actual operation, and is called at most once, only if the op lets the
+    /// call through to the underlying instance. Ops behave the same as with
+    /// [`poll_impl`](Self::poll_impl), except that `PartialOp::Limited` and `PartialOp::Eof`
+    /// behave the same as `PartialOp::Unlimited`.
+    pub fn poll_impl_no_limit<T>(
         &mut self,
         cx: &mut Context,
         cb: impl FnOnce(&mut Context) -> Poll<io::Result<T>>,
         err_str: &'static str,
     ) -> Poll<io::Result<T>> {
+        self.end_pending();
+        if self.poll_delay(cx).is_pending() {
+            return Poll::Pending;
+        }
         loop {
-            match self.ops.next() {
-                Some(PartialOp::Err(kind)) => {
-                    if kind == io::ErrorKind::WouldBlock {
+            let op = self.ops.next();
+            let poll = match op {
+                Some(PartialOp::Err(kind) | PartialOp::ErrMsg(kind, _)) => {
+                    if kind == io::ErrorKind::WouldBlock && !self.wouldblock_as_error {
                         // Async* instances must convert WouldBlock errors to Poll::Pending and
                         // reschedule the task.
                         cx.waker().wake_by_ref();
-                        break Poll::Pending;
+                        self.start_pending();
+                        Poll::Pending
                     } else if kind == io::ErrorKind::Interrupted {
                         // Async* instances must retry on interrupted errors.
+                        trace_op(op.as_ref(), None, "retrying");
                         continue;
                     } else {
-                        break Poll::Ready(Err(io::Error::new(kind, err_str)));
+                        Poll::Ready(Err(injected_error(op.as_ref(), kind, err_str)))
                     }
                 }
-                _ => break cb(cx),
-            }
+                Some(PartialOp::Delay(duration)) => {
+                    let poll = self.start_delay(cx, duration);
+                    trace_op(op.as_ref(), None, poll);
+                    match poll {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(()) => continue,
+                    }
+                }
+                Some(PartialOp::PendingOnce) => {
+                    // Deliberately don't wake the task: it's up to the caller to poll again.
+                    self.start_pending();
+                    Poll::Pending
+                }
+                _ => cb(cx),
+            };
+            trace_op(op.as_ref(), None, poll_outcome(&poll, |_| &()));
+            return poll;
+        }
+    }
+}
+
+impl<I> fmt::Debug for FuturesOps<I>
+where
+    I: Iterator<Item = PartialOp>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("FuturesOps");
+        f.field("consumed", &self.ops.consumed());
+        f.field("last_op", &self.last_op);
+        if let Some(phase) = self.ops.current_phase() {
+            f.field("phase", &format_args!("{}", phase));
         }
+        f.finish()
     }
 }
@@ -0,0 +1,195 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! Deterministic generation of random sequences of `PartialOp`s.
+//!
+//! This is a dependency-light alternative to the `quickcheck1` and `proptest1` features: a
+//! sequence is derived entirely from a `u64` seed, so a failure in CI can be replayed locally by
+//! printing the seed and passing it back in. For a builder-style interface over the same idea, see
+//! [`PartialOpBuilder`](crate::PartialOpBuilder).
+//!
+//! # Examples
+//!
+//! ```rust
+//! use partial_io::{gen::{random_ops, OpConfig}, PartialRead};
+//! use std::io::{self, Read};
+//!
+//! let seed = 0x5eed;
+//! let ops = random_ops(seed, 50, OpConfig::default());
+//! assert_eq!(ops, random_ops(seed, 50, OpConfig::default()), "sequences are reproducible");
+//!
+//! let mut partial_read = PartialRead::new(&b"Hello, world!"[..], ops);
+//! let mut out = Vec::new();
+//! loop {
+//!     match partial_read.read_to_end(&mut out) {
+//!         Ok(_) => break,
+//!         Err(err) if matches!(
+//!             err.kind(),
+//!             io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock,
+//!         ) => continue,
+//!         Err(err) => panic!("seed {:#x}: unexpected error: {}", seed, err),
+//!     }
+//! }
+//! assert_eq!(out, b"Hello, world!");
+//! ```
+
+use crate::{rng::SplitMix64, PartialOp};
+use std::{io, ops::RangeInclusive};
+
+/// The relative weights of each kind of op produced by [`random_ops`], along with the range of
+/// byte limits.
+///
+/// Weights are relative to each other, so they don't need to add up to 1. A weight of 0 (or
+/// less) means that kind of op is never produced.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpConfig {
+    /// The weight of `PartialOp::Limited` ops.
+    pub limited: f64,
+
+    /// The weight of `PartialOp::Unlimited` ops.
+    pub unlimited: f64,
+
+    /// The weight of `PartialOp::Eof` ops.
+    pub eof: f64,
+
+    /// The weights of `PartialOp::Err` ops, by error kind.
+    pub errors: Vec<(io::ErrorKind, f64)>,
+
+    /// The range that `PartialOp::Limited` limits are picked from, uniformly.
+    pub limit_range: RangeInclusive<usize>,
+}
+
+impl Default for OpConfig {
+    /// The default configuration produces, with probability:
+    ///
+    /// * 60%: `PartialOp::Limited(n)` with `n` in `1..=64`.
+    /// * 20%: `PartialOp::Unlimited`.
+    /// * 10%: `PartialOp::Err(Interrupted)`.
+    /// * 10%: `PartialOp::Err(WouldBlock)`.
+    fn default() -> Self {
+        Self {
+            limited: 0.6,
+            unlimited: 0.2,
+            eof: 0.0,
+            errors: vec![
+                (io::ErrorKind::Interrupted, 0.1),
+                (io::ErrorKind::WouldBlock, 0.1),
+            ],
+            limit_range: 1..=64,
+        }
+    }
+}
+
+/// Returns a random sequence of `len` ops derived from `seed`, according to `config`.
+///
+/// The same seed and config always produce the same sequence, on every platform.
+///
+/// # Panics
+///
+/// Panics if `len` is non-zero and either no op has a positive weight, or `limit_range` is empty
+/// while `limited` has a positive weight.
+pub fn random_ops(seed: u64, len: usize, config: OpConfig) -> Vec<PartialOp> {
+    let weight = |w: f64| if w > 0.0 { w } else { 0.0 };
+    let mut choices: Vec<(Option<PartialOp>, f64)> = vec![
+        (None, weight(config.limited)),
+        (Some(PartialOp::Unlimited), weight(config.unlimited)),
+        (Some(PartialOp::Eof), weight(config.eof)),
+    ];
+    choices.extend(
+        config
+            .errors
+            .iter()
+            .map(|&(kind, w)| (Some(PartialOp::Err(kind)), weight(w))),
+    );
+    choices.retain(|(_, w)| *w > 0.0);
+    let total: f64 = choices.iter().map(|(_, w)| w).sum();
+
+    if len > 0 {
+        assert!(!choices.is_empty(), "no op has a positive weight");
+        assert!(
+            config.limited <= 0.0 || !config.limit_range.is_empty(),
+            "limit range {:?} is empty",
+            config.limit_range
+        );
+    }
+
+    let mut rng = SplitMix64::new(seed);
+    (0..len)
+        .map(|_| {
+            let mut x = rng.next_f64() * total;
+            // Fall back to the last choice in case of floating-point rounding.
+            let mut choice = &choices[choices.len() - 1].0;
+            for (op, w) in &choices {
+                if x < *w {
+                    choice = op;
+                    break;
+                }
+                x -= w;
+            }
+            match choice {
+                Some(op) => op.clone(),
+                None => PartialOp::Limited(rng.in_range(&config.limit_range)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reproducible() {
+        let config = OpConfig {
+            eof: 0.1,
+            ..OpConfig::default()
+        };
+        let ops = random_ops(42, 200, config.clone());
+        assert_eq!(ops.len(), 200);
+        assert_eq!(ops, random_ops(42, 200, config.clone()));
+        assert_ne!(ops, random_ops(43, 200, config));
+        assert!(ops.contains(&PartialOp::Eof));
+    }
+
+    #[test]
+    fn test_weights() {
+        let config = OpConfig {
+            limited: 2.0,
+            unlimited: 1.0,
+            eof: 0.0,
+            errors: vec![(io::ErrorKind::BrokenPipe, 1.0)],
+            limit_range: 5..=6,
+        };
+        let ops = random_ops(7, 4000, config);
+
+        let (mut limited, mut unlimited, mut errors) = (0, 0, 0);
+        for op in &ops {
+            match op {
+                PartialOp::Limited(n) => {
+                    assert!((5..=6).contains(n), "limit {} in range", n);
+                    limited += 1;
+                }
+                PartialOp::Unlimited => unlimited += 1,
+                PartialOp::Err(io::ErrorKind::BrokenPipe) => errors += 1,
+                other => panic!("unexpected op {:?}", other),
+            }
+        }
+        // Expected: 2000 limited, 1000 unlimited, 1000 errors.
+        assert!((1800..2200).contains(&limited), "limited: {}", limited);
+        assert!((850..1150).contains(&unlimited), "unlimited: {}", unlimited);
+        assert!((850..1150).contains(&errors), "errors: {}", errors);
+    }
+
+    #[test]
+    #[should_panic(expected = "no op has a positive weight")]
+    fn test_no_weights() {
+        let config = OpConfig {
+            limited: 0.0,
+            unlimited: 0.0,
+            eof: 0.0,
+            errors: Vec::new(),
+            limit_range: 1..=1,
+        };
+        random_ops(0, 1, config);
+    }
+}
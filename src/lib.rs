@@ -10,13 +10,28 @@
 //! * `PartialRead` and `PartialWrite`, which wrap existing `Read` and
 //!   `Write` implementations and allow specifying arbitrary behavior on the
 //!   next `read`, `write` or `flush` call.
+//! * `PartialSeek`, which wraps existing `Seek` implementations and allows injecting errors
+//!   into `seek` calls.
 //! * With the optional `futures03` and `tokio1` features, `PartialAsyncRead` and
 //!   `PartialAsyncWrite` to wrap existing `AsyncRead` and `AsyncWrite`
 //!   implementations. These implementations are task-aware, so they will know
-//!   how to pause and unpause tasks if they return a `WouldBlock` error.
+//!   how to pause and unpause tasks if they return a `WouldBlock` error. `PartialDuplex` wraps
+//!   duplex streams, with independent operations for reads and writes.
+//!   To build partial behavior into a custom async type instead, see `FuturesOps`.
+//! * With the optional `futures03` feature, `PartialSink` to wrap existing `Sink`
+//!   implementations, with independent control over readiness, sending and flushing.
 //! * With the optional `proptest1` ([proptest]) and `quickcheck1` ([quickcheck]) features,
 //!   generation of random sequences of operations for property-based testing. See the
 //!   `proptest_types` and `quickcheck_types` documentation for more.
+//! * `PartialOpBuilder`, a lightweight way to generate random but reproducible sequences of
+//!   operations without the property-based testing features. For a lower-level interface, see
+//!   the [`gen`] module.
+//! * Helpers for building, minimizing and editing sequences of operations, to narrow down failing
+//!   cases. See [`minimize`] and the [`edit`] module.
+//! * With the optional `tracing01` feature, a `trace`-level [tracing] event for every
+//!   `PartialOp` applied, along with the number of bytes requested and the outcome. Run tests with
+//!   a subscriber and `RUST_LOG=partial_io=trace` to see the exact sequence of ops applied to a
+//!   stream.
 //!
 //! # Motivation
 //!
@@ -75,39 +90,81 @@
 //!
 //! [proptest]: https://altsysrq.github.io/proptest-book/intro.html
 //! [quickcheck]: https://docs.rs/quickcheck
+//! [tracing]: https://docs.rs/tracing
 //! [1]: https://github.com/gyscos/zstd-rs/commit/3123e418595f6badd5b06db2a14c4ff4555e7705
 //! [2]: https://github.com/gyscos/zstd-rs/commit/02dc9d9a3419618fc729542b45c96c32b0f178bb
 //! [tests in `zstd-rs`]: https://github.com/gyscos/zstd-rs/blob/master/src/stream/mod.rs
 
+mod assertions;
 #[cfg(feature = "futures03")]
 mod async_read;
 #[cfg(feature = "futures03")]
 mod async_write;
+mod builder;
+mod diff;
+mod dsl;
+#[cfg(feature = "futures03")]
+mod duplex;
+pub mod edit;
 #[cfg(feature = "futures03")]
 mod futures_util;
+pub mod gen;
+mod minimize;
+mod ops;
 #[cfg(feature = "proptest1")]
 pub mod proptest_types;
 #[cfg(feature = "quickcheck1")]
 pub mod quickcheck_types;
 mod read;
+mod rng;
+mod schedule;
+mod seek;
+#[cfg(feature = "futures03")]
+mod sink;
 mod write;
 
-use std::io;
+use std::{borrow::Cow, io, time::Duration};
 
+#[cfg(feature = "futures03")]
+pub use crate::assertions::{assert_yielded, poll_n_times, read_exact_through, WakerProbe};
 #[cfg(feature = "tokio1")]
 pub use crate::async_read::tokio_impl::ReadBufExt;
 #[cfg(feature = "futures03")]
 pub use crate::async_read::PartialAsyncRead;
 #[cfg(feature = "futures03")]
 pub use crate::async_write::PartialAsyncWrite;
-pub use crate::{read::PartialRead, write::PartialWrite};
+#[cfg(feature = "futures03")]
+pub use crate::duplex::PartialDuplex;
+#[cfg(feature = "futures03")]
+pub use crate::futures_util::{gate, FuturesOps, Gate, GateTrigger, PollOutcome};
+#[cfg(feature = "futures03")]
+pub use crate::sink::PartialSink;
+pub use crate::{
+    assertions::{assert_only_kinds, assert_transfer_error},
+    builder::PartialOpBuilder,
+    diff::{diff_transfers, TransferDiff},
+    dsl::{is_supported_kind, parse_ops, parse_ops_with_cap, to_rust_literal, ParseOpsError},
+    minimize::minimize,
+    ops::{BoxedOps, Diagnostics, ExhaustedBehavior, OpEvent, OpHistory, OpSnapshot, ReplayOps},
+    read::PartialRead,
+    schedule::{
+        repeat_schedule, schedule_covers, CoverageReport, IndexedSchedule, PhasePosition,
+        PhasedSchedule, RangeSchedule, RangeScheduleIter,
+    },
+    seek::PartialSeek,
+    write::PartialWrite,
+};
 
 /// What to do the next time an IO operation is performed.
 ///
 /// This is not the same as `io::Result<Option<usize>>` because it contains
 /// `io::ErrorKind` instances, not `io::Error` instances. This allows it to be
 /// clonable.
-#[derive(Clone, Debug)]
+///
+/// New kinds of operations may be added in the future, so matches on `PartialOp` outside this
+/// crate must include a wildcard arm.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum PartialOp {
     /// Limit the next IO operation to a certain number of bytes.
     ///
@@ -118,6 +175,10 @@ pub enum PartialOp {
     /// Some methods like `Write::flush` and `AsyncWrite::poll_flush` don't
     /// have a limit. For these methods, `Limited(n)` behaves the same as
     /// `Unlimited`.
+    ///
+    /// Writes of a nonempty buffer fail with `io::ErrorKind::WriteZero` under `Limited(0)`,
+    /// without calling into the underlying operation, so that a stray `Limited(0)` can't make a
+    /// write loop spin. To return `Ok(0)` from a write on purpose, use `Eof`.
     Limited(usize),
 
     /// Do not limit the next IO operation.
@@ -134,15 +195,53 @@ pub enum PartialOp {
     ///   is scheduled to be woken up in the future.
     /// * `ErrorKind::Interrupted` causes a retry.
     Err(io::ErrorKind),
-}
 
-#[inline]
-fn make_ops<I>(iter: I) -> Box<dyn Iterator<Item = PartialOp> + Send>
-where
-    I: IntoIterator<Item = PartialOp> + 'static,
-    I::IntoIter: Send,
-{
-    Box::new(iter.into_iter().fuse())
+    /// Return an error with a custom message instead of calling into the underlying operation.
+    ///
+    /// This behaves the same as `Err`, except that the error is created with
+    /// `io::Error::new(kind, message)`, so tests can tell injected errors apart by their message.
+    /// If the error latches, later errors have the default message.
+    ErrMsg(io::ErrorKind, Cow<'static, str>),
+
+    /// Wait for this long, then apply the op after this one.
+    ///
+    /// Synchronous wrappers block the current thread with `std::thread::sleep`. Async wrappers
+    /// return `Poll::Pending` instead, and schedule the task to be woken up once the delay has
+    /// elapsed. This simulates slow I/O, for testing timeouts.
+    ///
+    /// Within a Tokio runtime, with the `tokio1` feature enabled, async wrappers use the runtime's
+    /// timer, so the runtime must have its time driver enabled, and delays respect
+    /// `tokio::time::pause`. Otherwise, they use a timer thread shared by all wrappers.
+    ///
+    /// A delay doesn't limit the number of bytes transferred: that's up to the op after it.
+    Delay(Duration),
+
+    /// Signal end-of-stream once, without calling into the underlying operation.
+    ///
+    /// Reads return `Ok(0)`, which callers treat as the end of the stream. The stream isn't
+    /// actually over, though: later reads carry on where the stream left off. This is useful
+    /// for testing code that must stop at a zero-length read.
+    ///
+    /// Writes also return `Ok(0)`, to exercise write loops that must not spin when no progress is
+    /// made.
+    ///
+    /// Methods without a limit, like `Write::flush` and `AsyncWrite::poll_flush`, treat `Eof` the
+    /// same as `Unlimited`.
+    Eof,
+
+    /// Return `Poll::Pending` once *without* scheduling the task to be woken up, then apply the
+    /// op after this one the next time the operation is polled.
+    ///
+    /// This differs from `Err(io::ErrorKind::WouldBlock)`, which also returns `Poll::Pending` but
+    /// wakes the task right away so that it is polled again. With `PendingOnce`, nothing wakes the
+    /// task: the test has to poll it again, or wake it, itself. This is meant for catching futures
+    /// that mishandle spurious or missing wakeups, and for testing manual wake logic. A future
+    /// that is simply awaited on an executor after a `PendingOnce` op hangs, which is the point.
+    ///
+    /// This is only meaningful for async wrappers. Synchronous wrappers can't return
+    /// `Poll::Pending`, so they treat `PendingOnce` the same as `Err(io::ErrorKind::Interrupted)`,
+    /// which well-behaved callers retry.
+    PendingOnce,
 }
 
 #[cfg(test)]
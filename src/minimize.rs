@@ -0,0 +1,142 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains a minimizer for sequences of `PartialOp`s.
+
+use crate::PartialOp;
+
+/// Greedily shrinks a sequence of `PartialOp`s that reproduces a failure.
+///
+/// `still_fails` is called with candidate sequences, and should return true if the failure still
+/// reproduces with that sequence. The minimizer repeatedly tries removing runs of ops and
+/// shrinking `PartialOp::Limited` values toward 1, keeping every change for which `still_fails`
+/// returns true, until no further change helps. The result is a locally minimal sequence: removing
+/// any single op or reducing any single limit makes the failure go away.
+///
+/// If `ops` doesn't reproduce the failure to begin with, it is returned unchanged.
+///
+/// This is a lightweight form of delta debugging that doesn't depend on `proptest` or
+/// `quickcheck`.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{minimize, PartialOp};
+/// use std::io;
+///
+/// // A bug that's triggered by an error right after a short read.
+/// let still_fails = |ops: &[PartialOp]| {
+///     ops.windows(2).any(|w| {
+///         matches!(w[0], PartialOp::Limited(n) if n < 4)
+///             && w[1] == PartialOp::Err(io::ErrorKind::BrokenPipe)
+///     })
+/// };
+/// let ops = vec![
+///     PartialOp::Limited(10),
+///     PartialOp::Unlimited,
+///     PartialOp::Limited(3),
+///     PartialOp::Err(io::ErrorKind::BrokenPipe),
+///     PartialOp::Limited(7),
+/// ];
+/// assert_eq!(
+///     minimize(ops, still_fails),
+///     vec![PartialOp::Limited(1), PartialOp::Err(io::ErrorKind::BrokenPipe)],
+/// );
+/// ```
+pub fn minimize<F>(ops: Vec<PartialOp>, mut still_fails: F) -> Vec<PartialOp>
+where
+    F: FnMut(&[PartialOp]) -> bool,
+{
+    let mut ops = ops;
+    if !still_fails(&ops) {
+        return ops;
+    }
+
+    loop {
+        let mut progress = false;
+
+        // Try removing runs of ops, starting with large runs.
+        let mut run_len = (ops.len() / 2).max(1);
+        while run_len > 0 && !ops.is_empty() {
+            let mut start = 0;
+            while start < ops.len() {
+                let end = (start + run_len).min(ops.len());
+                let mut candidate = Vec::with_capacity(ops.len() - (end - start));
+                candidate.extend_from_slice(&ops[..start]);
+                candidate.extend_from_slice(&ops[end..]);
+                if still_fails(&candidate) {
+                    ops = candidate;
+                    progress = true;
+                } else {
+                    start += run_len;
+                }
+            }
+            run_len /= 2;
+        }
+
+        // Try shrinking limits. Don't go below 1, since for writers 0 can mean that writes are no
+        // longer accepted.
+        for i in 0..ops.len() {
+            let n = match ops[i] {
+                PartialOp::Limited(n) => n,
+                _ => continue,
+            };
+            for k in [1, n / 2, n - 1] {
+                if k == 0 || k >= n {
+                    continue;
+                }
+                let old = std::mem::replace(&mut ops[i], PartialOp::Limited(k));
+                if still_fails(&ops) {
+                    progress = true;
+                    break;
+                }
+                ops[i] = old;
+            }
+        }
+
+        if !progress {
+            break ops;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_minimize() {
+        // Fails if at least 10 bytes are allowed through before an Other error.
+        let still_fails = |ops: &[PartialOp]| {
+            let mut total = 0;
+            for op in ops {
+                match op {
+                    PartialOp::Limited(n) => total += n,
+                    PartialOp::Err(io::ErrorKind::Other) => return total >= 10,
+                    _ => {}
+                }
+            }
+            false
+        };
+        let ops = vec![
+            PartialOp::Limited(8),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(30),
+            PartialOp::Unlimited,
+            PartialOp::Limited(5),
+            PartialOp::Err(io::ErrorKind::Other),
+            PartialOp::Limited(9),
+        ];
+        let minimized = minimize(ops, still_fails);
+        assert!(still_fails(&minimized));
+        assert_eq!(
+            minimized,
+            vec![PartialOp::Limited(10), PartialOp::Err(io::ErrorKind::Other)]
+        );
+
+        // A passing sequence is returned unchanged.
+        let ops = vec![PartialOp::Limited(3), PartialOp::Unlimited];
+        assert_eq!(minimize(ops.clone(), still_fails), ops);
+    }
+}
@@ -0,0 +1,1233 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains the sequence of `PartialOp`s shared by all the wrappers.
+
+use crate::{
+    rng::SplitMix64,
+    schedule::{locate_phase, PhasePosition, PhasedSchedule},
+    PartialOp,
+};
+use std::{
+    cmp, fmt,
+    io::{self, IoSlice, IoSliceMut},
+    thread,
+    time::Instant,
+};
+
+/// A callback that observes the op applied to each transfer, along with the bytes transferred.
+pub(crate) type Inspector = Box<dyn FnMut(&PartialOp, &[u8]) + Send>;
+
+/// A callback that transforms the result of each transfer, given the bytes transferred.
+pub(crate) type ResultMap = Box<dyn FnMut(io::Result<usize>, &[u8]) -> io::Result<usize> + Send>;
+
+/// A callback that computes each op from the history of earlier ops.
+pub(crate) type OpFn = Box<dyn FnMut(&OpHistory<'_>) -> PartialOp + Send>;
+
+/// The boxed iterator that wrappers use for their `PartialOp`s by default.
+///
+/// Wrappers are generic over the iterator that drives them, and default to this type. Methods
+/// that swap out the iterator after construction, such as `set_ops`, are only available with the
+/// default, since they need to accept iterators of any type. To avoid the allocation and dynamic
+/// dispatch, construct a wrapper with `new_unboxed` instead.
+pub type BoxedOps = Box<dyn Iterator<Item = PartialOp> + Send>;
+
+/// The number of ops kept in an [`OpHistory`].
+const HISTORY_LEN: usize = 64;
+
+/// Adjusts `len` down to a length that isn't a power of two, unless it's 0 or 1.
+#[inline]
+pub(crate) fn avoid_power_of_two(len: usize) -> usize {
+    if len > 1 && len.is_power_of_two() {
+        len - 1
+    } else {
+        len
+    }
+}
+
+/// Truncates `bufs` so that they add up to at most `limit` bytes, slicing the last one as needed.
+pub(crate) fn limit_io_slices<'a>(bufs: &'a [IoSlice<'_>], mut limit: usize) -> Vec<IoSlice<'a>> {
+    let mut limited = Vec::new();
+    for buf in bufs {
+        if limit == 0 {
+            break;
+        }
+        let len = cmp::min(buf.len(), limit);
+        limited.push(IoSlice::new(&buf[..len]));
+        limit -= len;
+    }
+    limited
+}
+
+/// Truncates `bufs` so that they add up to at most `limit` bytes, slicing the last one as needed.
+pub(crate) fn limit_io_slices_mut<'a>(
+    bufs: &'a mut [IoSliceMut<'_>],
+    mut limit: usize,
+) -> Vec<IoSliceMut<'a>> {
+    let mut limited = Vec::new();
+    for buf in bufs {
+        if limit == 0 {
+            break;
+        }
+        let len = cmp::min(buf.len(), limit);
+        limited.push(IoSliceMut::new(&mut buf[..len]));
+        limit -= len;
+    }
+    limited
+}
+
+/// Emits a `trace` event for a call that consumed `op`, if the `tracing01` feature is enabled.
+///
+/// `None` for `op` means that the ops have run out. `requested` is the number of bytes the caller
+/// asked to transfer, for calls that transfer data.
+#[inline]
+pub(crate) fn trace_op(op: Option<&PartialOp>, requested: Option<usize>, outcome: impl fmt::Debug) {
+    #[cfg(feature = "tracing01")]
+    tracing::trace!(op = ?op, requested = ?requested, outcome = ?outcome, "applied PartialOp");
+    #[cfg(not(feature = "tracing01"))]
+    let _ = (op, requested, outcome);
+}
+
+/// Returns true if [`trace_op`] events are being recorded, so ops must go through the general path
+/// to be traced.
+#[inline]
+fn tracing_ops() -> bool {
+    #[cfg(feature = "tracing01")]
+    return tracing::enabled!(tracing::Level::TRACE);
+    #[cfg(not(feature = "tracing01"))]
+    false
+}
+
+/// Panics if `res` reports more than `limit` bytes transferred.
+///
+/// Used by wrappers with strict limits enabled.
+#[track_caller]
+pub(crate) fn check_limit(res: &io::Result<usize>, limit: usize, what: &str) {
+    if let Ok(n) = res {
+        assert!(
+            *n <= limit,
+            "inner {} transferred {} bytes, exceeding the limit of {}",
+            what,
+            n,
+            limit
+        );
+    }
+}
+
+/// Returns the error for a `PartialOp::Limited(0)` op applied to a nonempty write.
+///
+/// A write that returns `Ok(0)` without an end in sight makes retry loops spin forever, so writers
+/// reject `Limited(0)` outright. `PartialOp::Eof` is the way to ask for `Ok(0)` on purpose.
+pub(crate) fn limited_zero_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::WriteZero,
+        "partial-io: Limited(0) would cause write_all to loop; use PartialOp::Eof to \
+         intentionally return 0",
+    )
+}
+
+/// Calls `inspector` for a completed write of `buf` under `op`.
+///
+/// `None` for `op` means that the ops have run out, which is reported as `PartialOp::Unlimited`.
+pub(crate) fn inspect_write(
+    inspector: &mut Inspector,
+    op: Option<&PartialOp>,
+    buf: &[u8],
+    res: &io::Result<usize>,
+) {
+    let written = match res {
+        Ok(n) => &buf[..*n],
+        Err(_) => &[],
+    };
+    inspector(op.unwrap_or(&PartialOp::Unlimited), written);
+}
+
+/// Metadata about a single read or write, passed to `on_op` callbacks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct OpEvent {
+    /// The `PartialOp` that applied to the call, or `PartialOp::Unlimited` if the ops had run
+    /// out.
+    pub op: PartialOp,
+    /// The total number of bytes transferred by earlier calls, counted from when the callback
+    /// was attached.
+    pub offset: u64,
+    /// The length of the buffer passed in by the caller.
+    pub buf_len: usize,
+    /// The number of bytes transferred by this call, or 0 if it failed.
+    pub transferred: usize,
+    /// When the call completed, according to the observer's clock.
+    ///
+    /// This is `Instant::now()` unless a different clock was passed in to `on_op_with_clock`.
+    pub timestamp: Instant,
+}
+
+/// Reports an `OpEvent` for each completed read or write, and keeps track of the offset.
+pub(crate) struct OpObserver {
+    callback: Box<dyn FnMut(OpEvent) + Send>,
+    clock: Box<dyn FnMut() -> Instant + Send>,
+    offset: u64,
+}
+
+impl OpObserver {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+    {
+        Self::with_clock(callback, Instant::now)
+    }
+
+    pub(crate) fn with_clock<F, C>(callback: F, clock: C) -> Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+        C: FnMut() -> Instant + Send + 'static,
+    {
+        Self {
+            callback: Box::new(callback),
+            clock: Box::new(clock),
+            offset: 0,
+        }
+    }
+
+    /// Reports a completed call. `None` for `op` means that the ops have run out.
+    pub(crate) fn observe(&mut self, op: Option<&PartialOp>, buf_len: usize, transferred: usize) {
+        (self.callback)(OpEvent {
+            op: op.cloned().unwrap_or(PartialOp::Unlimited),
+            offset: self.offset,
+            buf_len,
+            transferred,
+            timestamp: (self.clock)(),
+        });
+        self.offset += transferred as u64;
+    }
+}
+
+/// What a wrapper does once its `PartialOp`s have run out.
+///
+/// Set with the `set_exhausted_behavior` method on wrappers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExhaustedBehavior {
+    /// Forward calls to the inner instance without limits or errors. This is the default.
+    Unlimited,
+
+    /// Panic, to catch sequences of ops that are shorter than the test needs.
+    Panic,
+
+    /// Keep applying the given op forever. For example, `Repeat(PartialOp::Err(WouldBlock))`
+    /// makes an async wrapper stay pending once the ops have run out.
+    Repeat(PartialOp),
+}
+
+impl Default for ExhaustedBehavior {
+    fn default() -> Self {
+        ExhaustedBehavior::Unlimited
+    }
+}
+
+/// The history of a wrapper's ops, passed to the callback set with `set_op_fn`.
+#[derive(Clone, Copy, Debug)]
+pub struct OpHistory<'a> {
+    recent_ops: &'a [PartialOp],
+    op_count: usize,
+    transferred: u64,
+    errors_seen: &'a [io::ErrorKind],
+}
+
+impl<'a> OpHistory<'a> {
+    /// Returns the most recent ops applied since the callback was set, oldest first.
+    ///
+    /// Up to the last 64 ops are kept. Ops are recorded as they were applied, i.e. after any
+    /// coalescing or capping of unlimited ops.
+    pub fn recent_ops(&self) -> &'a [PartialOp] {
+        self.recent_ops
+    }
+
+    /// Returns the total number of ops applied since the callback was set.
+    pub fn op_count(&self) -> usize {
+        self.op_count
+    }
+
+    /// Returns the total number of bytes transferred by the wrapper so far.
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    /// Returns the kinds of errors injected by the wrapper so far, in order.
+    pub fn errors_seen(&self) -> &'a [io::ErrorKind] {
+        self.errors_seen
+    }
+}
+
+/// An iterator over a repeatable source of `PartialOp`s, which starts over from the beginning
+/// when cloned.
+///
+/// Wrappers created with `new_repeatable` are driven by this iterator, and implement `Clone`: the
+/// clone starts from the first op in `source`, no matter how far the original has got. This
+/// makes it possible to run the same ops against several inner instances, for example to compare
+/// two implementations under identical partial conditions.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{PartialOp, PartialRead};
+/// use std::io::Read;
+///
+/// let ops = vec![PartialOp::Limited(2), PartialOp::Limited(3)];
+/// let mut first = PartialRead::new_repeatable(&b"Hello"[..], ops);
+/// let mut buf = [0; 8];
+/// assert_eq!(first.read(&mut buf).unwrap(), 2);
+///
+/// // The clone starts over from the first op. The reader is cloned as it is, so point the clone
+/// // at a different one.
+/// let mut second = first.clone();
+/// *second.get_mut() = &b"World"[..];
+/// assert_eq!(second.consumed_ops(), 0);
+/// assert_eq!(second.read(&mut buf).unwrap(), 2);
+/// assert_eq!(&buf[..2], b"Wo");
+/// ```
+pub struct ReplayOps<V: IntoIterator> {
+    source: V,
+    iter: V::IntoIter,
+}
+
+impl<V> ReplayOps<V>
+where
+    V: IntoIterator<Item = PartialOp> + Clone,
+{
+    /// Creates a new `ReplayOps` over the ops in `source`.
+    pub fn new(source: V) -> Self {
+        Self {
+            iter: source.clone().into_iter(),
+            source,
+        }
+    }
+}
+
+impl<V> Iterator for ReplayOps<V>
+where
+    V: IntoIterator<Item = PartialOp>,
+{
+    type Item = PartialOp;
+
+    #[inline]
+    fn next(&mut self) -> Option<PartialOp> {
+        self.iter.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<V> Clone for ReplayOps<V>
+where
+    V: IntoIterator<Item = PartialOp> + Clone,
+{
+    /// Returns a new `ReplayOps` that starts over from the beginning of the source.
+    fn clone(&self) -> Self {
+        Self::new(self.source.clone())
+    }
+}
+
+impl<V> fmt::Debug for ReplayOps<V>
+where
+    V: IntoIterator + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayOps")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+/// The sequence of `PartialOp`s driving a wrapper.
+pub(crate) struct Ops<I = BoxedOps> {
+    iter: I,
+    peeked: Option<PartialOp>,
+    exhausted: bool,
+    errors_seen: Vec<io::ErrorKind>,
+    error_offsets: Vec<(io::ErrorKind, u64)>,
+    transferred: u64,
+    latch_error: Option<io::ErrorKind>,
+    latched: Option<io::ErrorKind>,
+    unlimited_cap: Option<usize>,
+    coalesce: Option<(f64, SplitMix64)>,
+    consumed: usize,
+    last_op: Option<PartialOp>,
+    phases: Option<Vec<(String, usize)>>,
+    op_fn: Option<OpFn>,
+    recent_ops: Vec<PartialOp>,
+    op_fn_count: usize,
+    sleep_on_delay: bool,
+    exhausted_behavior: ExhaustedBehavior,
+}
+
+impl Ops {
+    /// Creates a new instance of `Ops`, boxing the iterator.
+    pub(crate) fn new<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        Self::new_unboxed(Box::new(iter.into_iter()))
+    }
+
+    /// Replaces ops with a new iterator.
+    ///
+    /// Settings and the errors seen so far are preserved, while the count of consumed ops, the
+    /// last op, any phases and any op callback are reset.
+    pub(crate) fn replace<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        let new = Self::new(iter);
+        self.iter = new.iter;
+        self.peeked = new.peeked;
+        self.exhausted = new.exhausted;
+        self.consumed = 0;
+        self.last_op = None;
+        self.phases = None;
+        self.op_fn = None;
+    }
+
+    /// Replaces ops with a callback that computes each op from the history of earlier ops.
+    pub(crate) fn replace_with_fn(&mut self, op_fn: OpFn) {
+        self.replace(std::iter::empty());
+        self.op_fn = Some(op_fn);
+        self.recent_ops.clear();
+        self.op_fn_count = 0;
+    }
+
+    /// Replaces ops with the ops in a phased schedule, and starts tracking its phases.
+    pub(crate) fn replace_phased(&mut self, schedule: PhasedSchedule) {
+        let (phases, ops) = schedule.into_parts();
+        self.replace(ops);
+        self.phases = Some(phases);
+    }
+
+    /// Materializes the remaining ops and returns a copy of them.
+    ///
+    /// The remaining ops must be finite.
+    pub(crate) fn fork(&mut self) -> Vec<PartialOp> {
+        let mut remaining: Vec<_> = self.peeked.take().into_iter().collect();
+        if !self.exhausted {
+            remaining.extend(&mut self.iter);
+        }
+        self.iter = Box::new(remaining.clone().into_iter());
+        self.exhausted = remaining.is_empty();
+        remaining
+    }
+
+    /// Materializes the remaining ops and returns a snapshot of the current state.
+    ///
+    /// The remaining ops must be finite.
+    pub(crate) fn snapshot(&mut self) -> OpSnapshot {
+        OpSnapshot {
+            remaining: self.fork(),
+            errors_seen: self.errors_seen.clone(),
+            error_offsets: self.error_offsets.clone(),
+            transferred: self.transferred,
+            latched: self.latched,
+            consumed: self.consumed,
+        }
+    }
+
+    /// Restores the state captured in a snapshot.
+    pub(crate) fn restore(&mut self, snapshot: &OpSnapshot) {
+        self.iter = Box::new(snapshot.remaining.clone().into_iter());
+        self.peeked = None;
+        self.exhausted = snapshot.remaining.is_empty();
+        self.errors_seen = snapshot.errors_seen.clone();
+        self.error_offsets = snapshot.error_offsets.clone();
+        self.transferred = snapshot.transferred;
+        self.latched = snapshot.latched;
+        self.consumed = snapshot.consumed;
+    }
+}
+
+impl<I> Ops<I>
+where
+    I: Iterator<Item = PartialOp>,
+{
+    /// Creates a new instance of `Ops` over an iterator, without boxing it.
+    pub(crate) fn new_unboxed(iter: I) -> Self {
+        // An iterator that reports that it's empty can take the fast path right away.
+        let exhausted = iter.size_hint().1 == Some(0);
+        Self {
+            iter,
+            peeked: None,
+            exhausted,
+            errors_seen: Vec::new(),
+            error_offsets: Vec::new(),
+            transferred: 0,
+            latch_error: None,
+            latched: None,
+            unlimited_cap: None,
+            coalesce: None,
+            consumed: 0,
+            last_op: None,
+            phases: None,
+            op_fn: None,
+            recent_ops: Vec::new(),
+            op_fn_count: 0,
+            sleep_on_delay: true,
+            exhausted_behavior: ExhaustedBehavior::Unlimited,
+        }
+    }
+
+    /// Returns a copy of these ops with the same settings, but none of the progress: no ops
+    /// consumed, no bytes transferred and no errors seen.
+    ///
+    /// The iterator is cloned as it is, so this only starts over from the beginning of the ops if
+    /// cloning the iterator does, as with [`ReplayOps`].
+    pub(crate) fn restarted(&self) -> Self
+    where
+        I: Clone,
+    {
+        let mut ops = Self::new_unboxed(self.iter.clone());
+        ops.latch_error = self.latch_error;
+        ops.unlimited_cap = self.unlimited_cap;
+        ops.coalesce = self.coalesce.clone();
+        ops.phases = self.phases.clone();
+        ops.sleep_on_delay = self.sleep_on_delay;
+        ops.exhausted_behavior = self.exhausted_behavior.clone();
+        ops
+    }
+
+    /// Returns the phase that the last consumed op belongs to, if phases are being tracked.
+    ///
+    /// Returns `None` if no ops have been consumed yet.
+    pub(crate) fn current_phase(&self) -> Option<PhasePosition> {
+        let phases = self.phases.as_ref()?;
+        let index = self.consumed.checked_sub(1)?;
+        locate_phase(
+            phases.iter().map(|(name, len)| (name.as_str(), *len)),
+            index,
+        )
+    }
+
+    /// Returns true if the ops have run out.
+    ///
+    /// Once this returns true, wrappers forward calls directly to the inner instance.
+    #[inline]
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.exhausted
+            && self.peeked.is_none()
+            && self.latched.is_none()
+            && self.unlimited_cap.is_none()
+            && self.op_fn.is_none()
+            && matches!(self.exhausted_behavior, ExhaustedBehavior::Unlimited)
+    }
+
+    /// Consumes the next op if it's `PartialOp::Unlimited` and no setting changes how it applies,
+    /// and returns true if it did.
+    ///
+    /// This lets wrappers forward calls directly to the inner instance during a run of `Unlimited`
+    /// ops, as they do once the ops have run out. The op is counted as consumed the same as with
+    /// [`next`](Self::next). Any other op is left to be returned by `next`.
+    #[inline]
+    pub(crate) fn take_unlimited(&mut self) -> bool {
+        if self.latched.is_some()
+            || self.unlimited_cap.is_some()
+            || self.op_fn.is_some()
+            || tracing_ops()
+        {
+            return false;
+        }
+        match self.peek() {
+            Some(PartialOp::Unlimited) => {
+                self.peeked = None;
+                self.consumed += 1;
+                self.last_op = Some(PartialOp::Unlimited);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sets what happens once the ops have run out.
+    pub(crate) fn set_exhausted_behavior(&mut self, behavior: ExhaustedBehavior) {
+        self.exhausted_behavior = behavior;
+    }
+
+    /// Sets whether `next` handles `PartialOp::Delay` ops itself by sleeping. Defaults to true.
+    ///
+    /// Async wrappers turn this off, since they must not block the thread.
+    #[cfg(feature = "futures03")]
+    pub(crate) fn set_sleep_on_delay(&mut self, sleep: bool) {
+        self.sleep_on_delay = sleep;
+    }
+
+    /// Returns the next op, or `None` if the ops have run out.
+    ///
+    /// Unless turned off with `set_sleep_on_delay`, `PartialOp::Delay` ops are handled here by
+    /// sleeping for the duration, then moving on to the op after the delay.
+    #[inline]
+    pub(crate) fn next(&mut self) -> Option<PartialOp> {
+        loop {
+            match self.next_op() {
+                Some(PartialOp::Delay(duration)) if self.sleep_on_delay => thread::sleep(duration),
+                op => return op,
+            }
+        }
+    }
+
+    #[inline]
+    fn next_op(&mut self) -> Option<PartialOp> {
+        if let Some(kind) = self.latched {
+            // A latched error overrides the iterator, which isn't advanced.
+            self.record_error(kind);
+            self.consumed += 1;
+            self.last_op = Some(PartialOp::Err(kind));
+            return Some(PartialOp::Err(kind));
+        }
+
+        let op = match self.peeked.take() {
+            Some(op) => Some(op),
+            None => self.pull(),
+        };
+        if op.is_some() {
+            self.consumed += 1;
+        } else if self.exhausted_behavior == ExhaustedBehavior::Panic {
+            panic!(
+                "ran out of PartialOps after {} were consumed (exhausted behavior is Panic)",
+                self.consumed
+            );
+        }
+        let op = match op {
+            Some(PartialOp::Limited(n)) => Some(PartialOp::Limited(self.coalesce(n))),
+            op => op,
+        };
+        if let Some(PartialOp::Err(kind) | PartialOp::ErrMsg(kind, _)) = op {
+            self.record_error(kind);
+            if self.latch_error == Some(kind) {
+                self.latched = Some(kind);
+            }
+        }
+        let op = match (op, self.unlimited_cap) {
+            // Once the ops have run out, transfers are unlimited, so the cap applies to them too.
+            (Some(PartialOp::Unlimited) | None, Some(cap)) => Some(PartialOp::Limited(cap)),
+            (op, _) => op,
+        };
+        if let (Some(_), Some(op)) = (&self.op_fn, &op) {
+            if self.recent_ops.len() == HISTORY_LEN {
+                self.recent_ops.remove(0);
+            }
+            self.recent_ops.push(op.clone());
+            self.op_fn_count += 1;
+        }
+        if op.is_some() {
+            self.last_op = op.clone();
+        }
+        op
+    }
+
+    /// Returns a reference to the next op without consuming it.
+    ///
+    /// This pulls the op out of the underlying iterator early.
+    pub(crate) fn peek(&mut self) -> Option<&PartialOp> {
+        if self.peeked.is_none() {
+            self.peeked = self.pull();
+        }
+        self.peeked.as_ref()
+    }
+
+    /// Returns the limit of the next op if it is `PartialOp::Limited`, without consuming it.
+    pub(crate) fn next_limit(&mut self) -> Option<usize> {
+        if self.latched.is_some() {
+            return None;
+        }
+        let cap = self.unlimited_cap;
+        match self.peek() {
+            Some(PartialOp::Limited(n)) => Some(*n),
+            Some(PartialOp::Eof) => Some(0),
+            Some(PartialOp::Unlimited) | None => cap,
+            Some(
+                PartialOp::Err(_)
+                | PartialOp::ErrMsg(..)
+                | PartialOp::Delay(_)
+                | PartialOp::PendingOnce,
+            ) => None,
+        }
+    }
+
+    /// Sets the kind of error that latches once it is injected.
+    pub(crate) fn set_latch_error(&mut self, kind: Option<io::ErrorKind>) {
+        self.latch_error = kind;
+    }
+
+    /// Sets the probability of merging each `PartialOp::Limited` op with the one after it.
+    pub(crate) fn set_coalesce_prob(&mut self, prob: Option<(f64, u64)>) {
+        self.coalesce = prob.map(|(p, seed)| (p, SplitMix64::new(seed)));
+    }
+
+    /// Merges a `PartialOp::Limited(n)` op that was just consumed with the following `Limited`
+    /// ops, one at a time, as long as the coin flips come up heads. Returns the merged limit.
+    fn coalesce(&mut self, mut n: usize) -> usize {
+        let (p, mut rng) = match self.coalesce.take() {
+            Some(coalesce) => coalesce,
+            None => return n,
+        };
+        while rng.chance(p) {
+            match self.peek() {
+                Some(PartialOp::Limited(m)) => {
+                    n = n.saturating_add(*m);
+                    self.peeked = None;
+                    self.consumed += 1;
+                }
+                // Anything else, including errors, breaks coalescing.
+                _ => break,
+            }
+        }
+        self.coalesce = Some((p, rng));
+        n
+    }
+
+    /// Sets the limit that `PartialOp::Unlimited` ops are turned into.
+    pub(crate) fn set_unlimited_cap(&mut self, cap: Option<usize>) {
+        self.unlimited_cap = cap;
+    }
+
+    /// Returns the latched error, if any.
+    pub(crate) fn latched_error(&self) -> Option<io::ErrorKind> {
+        self.latched
+    }
+
+    /// Clears the latched error, if any.
+    pub(crate) fn clear_latch(&mut self) {
+        self.latched = None;
+    }
+
+    /// Returns the kinds of errors injected so far, in order.
+    pub(crate) fn errors_seen(&self) -> &[io::ErrorKind] {
+        &self.errors_seen
+    }
+
+    /// Returns the kinds of errors injected so far, along with the number of bytes transferred
+    /// before each one.
+    pub(crate) fn error_offsets(&self) -> &[(io::ErrorKind, u64)] {
+        &self.error_offsets
+    }
+
+    /// Returns the total number of bytes transferred so far.
+    pub(crate) fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    /// Records that `n` more bytes were transferred.
+    #[inline]
+    pub(crate) fn add_transferred(&mut self, n: usize) {
+        self.transferred += n as u64;
+    }
+
+    /// Records the bytes transferred by `res` if it is successful, then returns it.
+    #[inline]
+    pub(crate) fn track_transfer(&mut self, res: io::Result<usize>) -> io::Result<usize> {
+        if let Ok(n) = res {
+            self.add_transferred(n);
+        }
+        res
+    }
+
+    fn record_error(&mut self, kind: io::ErrorKind) {
+        self.errors_seen.push(kind);
+        self.error_offsets.push((kind, self.transferred));
+    }
+
+    /// Returns the number of ops consumed since the ops were last replaced.
+    pub(crate) fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Returns the number of ops left, if the iterator reports an exact size.
+    pub(crate) fn remaining(&self) -> Option<usize> {
+        if self.op_fn.is_some() || matches!(self.exhausted_behavior, ExhaustedBehavior::Repeat(_)) {
+            return None;
+        }
+        let peeked = usize::from(self.peeked.is_some());
+        if self.exhausted {
+            return Some(peeked);
+        }
+        match self.iter.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower + peeked),
+            _ => None,
+        }
+    }
+
+    /// Panics if the number of ops consumed isn't `expected`.
+    #[track_caller]
+    pub(crate) fn assert_op_count(&self, expected: usize) {
+        if self.consumed != expected {
+            match self.current_phase() {
+                Some(phase) => panic!(
+                    "expected {} PartialOps to be consumed, but {} were (last consumed: {})",
+                    expected, self.consumed, phase
+                ),
+                None => panic!(
+                    "expected {} PartialOps to be consumed, but {} were",
+                    expected, self.consumed
+                ),
+            }
+        }
+    }
+
+    /// Returns a summary of the state recorded so far. Wrappers fill in their own fields.
+    pub(crate) fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            ops_consumed: self.consumed,
+            last_op: self.last_op.clone(),
+            transferred: self.transferred,
+            error_offsets: self.error_offsets.clone(),
+            latched_error: self.latched,
+            phase: self.current_phase(),
+            flushes: None,
+            total_limit_remaining: None,
+            shrinking_capacity: None,
+        }
+    }
+
+    #[inline]
+    fn pull(&mut self) -> Option<PartialOp> {
+        if let Some(op_fn) = &mut self.op_fn {
+            return Some(op_fn(&OpHistory {
+                recent_ops: &self.recent_ops,
+                op_count: self.op_fn_count,
+                transferred: self.transferred,
+                errors_seen: &self.errors_seen,
+            }));
+        }
+        if !self.exhausted {
+            // Don't keep calling the inner iterator once it's returned None.
+            let op = self.iter.next();
+            self.exhausted = op.is_none();
+            if op.is_some() {
+                return op;
+            }
+        }
+        match &self.exhausted_behavior {
+            ExhaustedBehavior::Repeat(op) => Some(op.clone()),
+            ExhaustedBehavior::Unlimited | ExhaustedBehavior::Panic => None,
+        }
+    }
+}
+
+/// A summary of everything a wrapper has recorded, returned by its `diagnostics` method.
+///
+/// This bundles the individual accessors into a single report, which is handy for printing with
+/// `{:#?}` when a test fails. Fields that only apply to some wrappers, or to features that
+/// weren't turned on, are `None`.
+///
+/// `PartialRead`, `PartialWrite`, `PartialAsyncRead` and `PartialAsyncWrite` provide a
+/// `diagnostics` method.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Diagnostics {
+    /// The number of `PartialOp`s consumed since the ops were last set.
+    pub ops_consumed: usize,
+    /// The last `PartialOp` consumed, or `None` if none have been consumed since the ops were
+    /// last set.
+    pub last_op: Option<PartialOp>,
+    /// The total number of bytes transferred through the wrapper.
+    pub transferred: u64,
+    /// The kinds of errors injected by `PartialOp::Err`, along with the number of bytes
+    /// transferred before each one fired.
+    pub error_offsets: Vec<(io::ErrorKind, u64)>,
+    /// The error that has latched, if any.
+    pub latched_error: Option<io::ErrorKind>,
+    /// The phase of the last consumed op, if the ops were set with a phased schedule.
+    pub phase: Option<PhasePosition>,
+    /// The number of calls to `flush` that succeeded, or `None` for readers and async writers.
+    pub flushes: Option<usize>,
+    /// The number of bytes the writer can still accept before reaching its total limit, or
+    /// `None` if no total limit was set with `PartialWrite::with_total_limit`.
+    pub total_limit_remaining: Option<u64>,
+    /// The current cap on the length of each write, or `None` if no shrinking capacity was set
+    /// with `PartialWrite::set_shrinking_capacity`.
+    pub shrinking_capacity: Option<usize>,
+}
+
+/// A snapshot of a wrapper's position within its `PartialOp`s, along with the errors seen and
+/// latched so far.
+///
+/// Created by the `snapshot` method on wrappers, and passed to `restore` to return the wrapper to
+/// the same position.
+#[derive(Clone, Debug)]
+pub struct OpSnapshot {
+    remaining: Vec<PartialOp>,
+    errors_seen: Vec<io::ErrorKind>,
+    error_offsets: Vec<(io::ErrorKind, u64)>,
+    transferred: u64,
+    latched: Option<io::ErrorKind>,
+    consumed: usize,
+}
+
+impl OpSnapshot {
+    /// Returns the `PartialOp`s that remained when this snapshot was taken.
+    pub fn remaining(&self) -> &[PartialOp] {
+        &self.remaining
+    }
+
+    /// Returns the total number of bytes that the remaining `PartialOp::Limited` ops permit.
+    ///
+    /// This is useful for tests that adapt how much data they feed through a wrapper to its
+    /// schedule. Errors, delays, `PartialOp::Eof` and `PartialOp::PendingOnce` don't permit any
+    /// bytes. Returns `None` if any remaining op is `PartialOp::Unlimited`, since the budget is
+    /// then unbounded.
+    ///
+    /// Taking a snapshot materializes the remaining ops, so they must be finite.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialRead};
+    /// use std::io::{self, Read};
+    ///
+    /// let ops = vec![
+    ///     PartialOp::Limited(2),
+    ///     PartialOp::Err(io::ErrorKind::Interrupted),
+    ///     PartialOp::Limited(3),
+    /// ];
+    /// let mut partial_reader = PartialRead::new(&b"Hello"[..], ops);
+    /// assert_eq!(partial_reader.snapshot().remaining_limited_budget(), Some(5));
+    ///
+    /// let mut buf = [0; 8];
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    /// assert_eq!(partial_reader.snapshot().remaining_limited_budget(), Some(3));
+    /// ```
+    pub fn remaining_limited_budget(&self) -> Option<usize> {
+        self.remaining.iter().try_fold(0, |budget, op| match op {
+            PartialOp::Limited(n) => Some(budget + n),
+            PartialOp::Unlimited => None,
+            PartialOp::Err(_)
+            | PartialOp::ErrMsg(..)
+            | PartialOp::Delay(_)
+            | PartialOp::Eof
+            | PartialOp::PendingOnce => Some(budget),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exhausted() {
+        let mut ops = Ops::new(vec![PartialOp::Unlimited]);
+        assert!(!ops.is_exhausted());
+        assert_eq!(ops.next(), Some(PartialOp::Unlimited));
+        assert!(
+            !ops.is_exhausted(),
+            "exhaustion is only known after next() returns None"
+        );
+        assert_eq!(ops.next(), None);
+        assert!(ops.is_exhausted());
+
+        ops.replace(Vec::new());
+        assert!(
+            ops.is_exhausted(),
+            "empty iterators are exhausted right away"
+        );
+        ops.replace(std::iter::repeat(PartialOp::Unlimited));
+        assert!(!ops.is_exhausted());
+    }
+
+    #[test]
+    fn test_take_unlimited() {
+        let mut ops = Ops::new(vec![
+            PartialOp::Unlimited,
+            PartialOp::Unlimited,
+            PartialOp::Limited(2),
+            PartialOp::Unlimited,
+        ]);
+        assert!(ops.take_unlimited());
+        assert!(ops.take_unlimited());
+        assert_eq!(ops.consumed(), 2);
+        assert!(!ops.take_unlimited(), "other ops are left in place");
+        assert_eq!(ops.consumed(), 2);
+        assert_eq!(ops.next(), Some(PartialOp::Limited(2)));
+
+        ops.set_unlimited_cap(Some(16));
+        assert!(
+            !ops.take_unlimited(),
+            "the cap turns Unlimited into a limit"
+        );
+        ops.set_unlimited_cap(None);
+        assert!(ops.take_unlimited());
+        assert_eq!(ops.consumed(), 4);
+        assert!(!ops.take_unlimited());
+        assert!(ops.is_exhausted());
+
+        let mut ops = Ops::new(vec![PartialOp::Err(io::ErrorKind::BrokenPipe)]);
+        ops.set_latch_error(Some(io::ErrorKind::BrokenPipe));
+        ops.set_exhausted_behavior(ExhaustedBehavior::Repeat(PartialOp::Unlimited));
+        ops.next();
+        assert!(!ops.take_unlimited(), "latched errors apply instead");
+        ops.clear_latch();
+        assert!(ops.take_unlimited(), "repeated Unlimited ops are taken too");
+    }
+
+    #[test]
+    fn test_limit_io_slices() {
+        let bufs = [
+            IoSlice::new(b"foo"),
+            IoSlice::new(b""),
+            IoSlice::new(b"bar"),
+        ];
+        let limited = |limit| -> Vec<Vec<u8>> {
+            limit_io_slices(&bufs, limit)
+                .iter()
+                .map(|buf| buf.to_vec())
+                .collect()
+        };
+        assert!(limited(0).is_empty());
+        assert_eq!(limited(2), [&b"fo"[..]]);
+        assert_eq!(limited(3), [&b"foo"[..]]);
+        assert_eq!(limited(4), [&b"foo"[..], b"", b"b"]);
+        assert_eq!(limited(100), [&b"foo"[..], b"", b"bar"]);
+    }
+
+    #[test]
+    fn test_exhausted_behavior() {
+        let mut ops = Ops::new(vec![PartialOp::Limited(1)]);
+        ops.set_exhausted_behavior(ExhaustedBehavior::Repeat(PartialOp::Err(
+            io::ErrorKind::WouldBlock,
+        )));
+        assert!(!ops.is_exhausted());
+        assert_eq!(ops.next(), Some(PartialOp::Limited(1)));
+        for _ in 0..3 {
+            assert_eq!(ops.next(), Some(PartialOp::Err(io::ErrorKind::WouldBlock)));
+        }
+        assert!(!ops.is_exhausted(), "the fast path isn't taken");
+        assert_eq!(ops.remaining(), None);
+        assert_eq!(ops.consumed(), 4, "repeated ops count as consumed");
+
+        ops.set_exhausted_behavior(ExhaustedBehavior::Panic);
+        assert_eq!(ops.next_limit(), None, "peeking past the end doesn't panic");
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| ops.next()));
+        assert!(res.is_err(), "running out panics");
+
+        ops.set_exhausted_behavior(ExhaustedBehavior::default());
+        assert_eq!(ops.next(), None);
+        assert!(ops.is_exhausted());
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut ops = Ops::new(vec![PartialOp::Limited(3), PartialOp::Unlimited]);
+        assert_eq!(ops.next_limit(), Some(3));
+        assert_eq!(
+            ops.peek(),
+            Some(&PartialOp::Limited(3)),
+            "peeking is idempotent"
+        );
+        assert_eq!(ops.next(), Some(PartialOp::Limited(3)));
+        assert_eq!(ops.next_limit(), None);
+        assert_eq!(ops.next(), Some(PartialOp::Unlimited));
+        assert_eq!(ops.peek(), None);
+        assert!(ops.is_exhausted());
+    }
+
+    #[test]
+    fn test_unlimited_cap() {
+        let mut ops = Ops::new(vec![PartialOp::Unlimited, PartialOp::Limited(1000)]);
+        ops.set_unlimited_cap(Some(16));
+        assert!(!ops.is_exhausted());
+        assert_eq!(ops.next_limit(), Some(16));
+        assert_eq!(ops.next(), Some(PartialOp::Limited(16)));
+        assert_eq!(
+            ops.next(),
+            Some(PartialOp::Limited(1000)),
+            "Limited ops above the cap are untouched"
+        );
+        assert_eq!(
+            ops.next(),
+            Some(PartialOp::Limited(16)),
+            "the cap applies once the ops have run out"
+        );
+        assert!(!ops.is_exhausted(), "the fast path isn't taken");
+
+        ops.set_unlimited_cap(None);
+        assert_eq!(ops.next(), None);
+        assert!(ops.is_exhausted());
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let mut ops = Ops::new(vec![PartialOp::Limited(1); 200]);
+        ops.set_coalesce_prob(Some((0.5, 7)));
+        let mut limits = Vec::new();
+        while let Some(op) = ops.next() {
+            match op {
+                PartialOp::Limited(n) => limits.push(n),
+                other => panic!("unexpected op {:?}", other),
+            }
+        }
+        assert_eq!(
+            limits.iter().sum::<usize>(),
+            200,
+            "total bytes are preserved"
+        );
+        assert!(limits.iter().any(|&n| n > 1), "some ops were coalesced");
+        assert!(limits.contains(&1), "some ops were not");
+        assert_eq!(ops.consumed, 200, "merged ops count as consumed");
+
+        // Errors and unlimited ops break coalescing.
+        let mut ops = Ops::new(vec![
+            PartialOp::Limited(1),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(3),
+            PartialOp::Limited(4),
+            PartialOp::Unlimited,
+            PartialOp::Limited(5),
+        ]);
+        ops.set_coalesce_prob(Some((1.0, 0)));
+        let all: Vec<_> = std::iter::from_fn(|| ops.next()).collect();
+        assert_eq!(
+            all,
+            vec![
+                PartialOp::Limited(3),
+                PartialOp::Err(io::ErrorKind::Interrupted),
+                PartialOp::Limited(7),
+                PartialOp::Unlimited,
+                PartialOp::Limited(5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_observer() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        let mut observer = OpObserver::new(move |event| events2.lock().unwrap().push(event));
+        observer.observe(Some(&PartialOp::Limited(2)), 8, 2);
+        observer.observe(Some(&PartialOp::Err(io::ErrorKind::Interrupted)), 6, 0);
+        observer.observe(None, 6, 6);
+        observer.observe(None, 4, 0);
+
+        let offsets: Vec<_> = events.lock().unwrap().iter().map(|e| e.offset).collect();
+        assert_eq!(offsets, [0, 2, 2, 8]);
+        assert_eq!(events.lock().unwrap()[2].op, PartialOp::Unlimited);
+    }
+
+    #[test]
+    fn test_consumed() {
+        let mut ops = Ops::new(vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Unlimited,
+        ]);
+        ops.set_latch_error(Some(io::ErrorKind::BrokenPipe));
+        assert_eq!(ops.next_limit(), Some(1));
+        assert_eq!(ops.consumed, 0, "peeking doesn't consume");
+        ops.next();
+        ops.next();
+        ops.next();
+        assert_eq!(ops.consumed, 3, "latched errors are consumed");
+
+        ops.clear_latch();
+        ops.next();
+        ops.next();
+        assert_eq!(ops.consumed, 4, "running out isn't counted");
+        ops.assert_op_count(4);
+
+        ops.replace(vec![PartialOp::Unlimited]);
+        assert_eq!(ops.consumed, 0);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut ops = Ops::new(vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(2),
+        ]);
+        assert_eq!(ops.next_limit(), Some(1));
+        let snapshot = ops.snapshot();
+        assert_eq!(snapshot.remaining().len(), 3, "peeked op is included");
+
+        assert_eq!(ops.next(), Some(PartialOp::Limited(1)));
+        assert_eq!(ops.next(), Some(PartialOp::Err(io::ErrorKind::Interrupted)));
+        assert_eq!(ops.errors_seen(), &[io::ErrorKind::Interrupted]);
+
+        ops.restore(&snapshot);
+        assert!(ops.errors_seen().is_empty());
+        let all: Vec<_> = std::iter::from_fn(|| ops.next()).collect();
+        assert_eq!(all, snapshot.remaining());
+        assert!(ops.is_exhausted());
+    }
+
+    #[test]
+    fn test_op_fn() {
+        let mut ops = Ops::new(vec![PartialOp::Unlimited]);
+        // Alternate between 1 and 2 byte transfers, failing once 4 bytes have been transferred.
+        ops.replace_with_fn(Box::new(|history: &OpHistory<'_>| {
+            assert_eq!(history.recent_ops().len(), history.op_count());
+            if history.transferred() >= 4 && history.errors_seen().is_empty() {
+                PartialOp::Err(io::ErrorKind::BrokenPipe)
+            } else {
+                PartialOp::Limited(history.op_count() % 2 + 1)
+            }
+        }));
+        assert!(!ops.is_exhausted());
+
+        let mut applied = Vec::new();
+        for _ in 0..6 {
+            let op = ops.next().unwrap();
+            if let PartialOp::Limited(n) = op {
+                ops.add_transferred(n);
+            }
+            applied.push(op);
+        }
+        assert_eq!(
+            applied,
+            [
+                PartialOp::Limited(1),
+                PartialOp::Limited(2),
+                PartialOp::Limited(1),
+                PartialOp::Err(io::ErrorKind::BrokenPipe),
+                PartialOp::Limited(1),
+                PartialOp::Limited(2),
+            ]
+        );
+        assert!(ops.fork().is_empty());
+        assert!(
+            !ops.is_exhausted(),
+            "the callback keeps going after forking"
+        );
+
+        ops.replace(vec![]);
+        assert!(ops.is_exhausted(), "replacing ops removes the callback");
+    }
+
+    #[test]
+    fn test_remaining_limited_budget() {
+        let mut ops = Ops::new(vec![
+            PartialOp::Limited(4),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(2),
+            PartialOp::Limited(1),
+        ]);
+        let mut budgets = vec![ops.snapshot().remaining_limited_budget()];
+        while ops.next().is_some() {
+            budgets.push(ops.snapshot().remaining_limited_budget());
+        }
+        assert_eq!(
+            budgets,
+            [Some(7), Some(3), Some(3), Some(1), Some(0)],
+            "budget decreases as ops are consumed"
+        );
+
+        ops.replace(vec![PartialOp::Limited(4), PartialOp::Unlimited]);
+        assert_eq!(ops.snapshot().remaining_limited_budget(), None);
+        ops.next();
+        assert_eq!(ops.snapshot().remaining_limited_budget(), None);
+        ops.next();
+        assert_eq!(ops.snapshot().remaining_limited_budget(), Some(0));
+    }
+}
@@ -36,9 +36,14 @@
 //!
 //! For a detailed example, see `examples/buggy_write.rs` in this repository.
 
-use crate::PartialOp;
-use proptest::{option::weighted, prelude::*};
-use std::io;
+use crate::{minimize, PartialOp};
+use proptest::{
+    collection::{vec, SizeRange},
+    option::weighted,
+    prelude::*,
+    test_runner::{Config, Reason, TestError, TestRunner},
+};
+use std::{fmt, io, time::Duration};
 
 /// Returns a strategy that generates `PartialOp` instances given a way to generate errors.
 ///
@@ -54,6 +59,141 @@ pub fn partial_op_strategy(
     })
 }
 
+/// Returns a strategy that generates `PartialOp` instances given a way to generate errors, along
+/// with `PartialOp::Unlimited` some of the time.
+///
+/// This is the same as [`partial_op_strategy`], except that ops that aren't errors are
+/// `PartialOp::Unlimited` with probability `unlimited_prob`. Mixing large, unbounded transfers in
+/// with limited ones exercises the fast paths that `partial_op_strategy` never reaches. Shrinking
+/// removes errors first, then turns `Unlimited` ops into `Limited` ones and reduces limits toward
+/// 1.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{
+///     proptest_types::{interrupted_strategy, partial_op_strategy_with_unlimited},
+///     PartialWrite,
+/// };
+/// use proptest::{collection::vec, test_runner::TestRunner};
+/// use std::io::Write;
+///
+/// let data = b"Hello, world!";
+/// let strategy = partial_op_strategy_with_unlimited(interrupted_strategy(), 8, 0.1);
+/// let mut runner = TestRunner::default();
+/// runner
+///     .run(&vec(strategy, 0..32), |ops| {
+///         let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+///         partial_writer.write_all(data).unwrap();
+///         assert_eq!(&partial_writer.get_ref()[..], &data[..]);
+///         Ok(())
+///     })
+///     .unwrap();
+/// ```
+pub fn partial_op_strategy_with_unlimited(
+    error_strategy: impl Strategy<Value = Option<io::ErrorKind>>,
+    limit_bytes: usize,
+    unlimited_prob: f64,
+) -> impl Strategy<Value = PartialOp> {
+    (
+        error_strategy,
+        weighted(unlimited_prob, Just(())),
+        1..=limit_bytes,
+    )
+        .prop_map(
+            |(error_kind, unlimited, limit)| match (error_kind, unlimited) {
+                (Some(kind), _) => PartialOp::Err(kind),
+                (None, Some(())) => PartialOp::Unlimited,
+                (None, None) => PartialOp::Limited(limit),
+            },
+        )
+}
+
+/// Returns a strategy that generates `PartialOp::Limited` and `PartialOp::Unlimited` instances,
+/// and never any errors.
+///
+/// `PartialOp::Unlimited` is generated with probability `unlimited_prob`, and
+/// `PartialOp::Limited` with a limit of between 1 and `limit_bytes` bytes otherwise. This isolates
+/// chunking behavior from error handling, so tests can check that the final output is the same
+/// regardless of how it was chunked. Shrinking turns `Unlimited` ops into `Limited` ones and
+/// reduces limits toward 1.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{proptest_types::chunking_only_strategy, PartialWrite};
+/// use proptest::{collection::vec, prelude::*, test_runner::TestRunner};
+/// use std::io::Write;
+///
+/// let data = b"Hello, world!";
+/// let mut runner = TestRunner::default();
+/// runner
+///     .run(&vec(chunking_only_strategy(8, 0.1), 0..32), |ops| {
+///         let mut partial_writer = PartialWrite::new(Vec::new(), ops);
+///         partial_writer.write_all(data).unwrap();
+///         prop_assert_eq!(&partial_writer.get_ref()[..], &data[..]);
+///         Ok(())
+///     })
+///     .unwrap();
+/// ```
+pub fn chunking_only_strategy(
+    limit_bytes: usize,
+    unlimited_prob: f64,
+) -> impl Strategy<Value = PartialOp> {
+    (weighted(unlimited_prob, Just(())), 1..=limit_bytes).prop_map(|(unlimited, limit)| {
+        match unlimited {
+            Some(()) => PartialOp::Unlimited,
+            None => PartialOp::Limited(limit),
+        }
+    })
+}
+
+/// Returns a strategy that generates `PartialOp::Limited` instances whose limits cluster around
+/// the boundary of a buffer of `buf_size` bytes.
+///
+/// Code built on `std::io::BufReader` and `std::io::BufWriter` (whose default buffer size is
+/// 8192 bytes) or similar types often has bugs around refilling or flushing the internal buffer.
+/// Limits of exactly `buf_size - 1`, `buf_size` and `buf_size + 1` bytes land transfers right
+/// around the buffer boundary, while small limits of between 1 and 8 bytes leave the buffer
+/// partially filled. Each of these four kinds of limits is generated a quarter of the time.
+///
+/// Shrinking only moves between these values, simplifying toward `buf_size - 1`, so shrunk
+/// schedules still exercise the boundary. Generate sequences of ops with
+/// [`proptest::collection::vec`].
+///
+/// # Panics
+///
+/// Panics if `buf_size` is less than 2.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{proptest_types::buffer_boundary_strategy, PartialRead};
+/// use proptest::{collection::vec, test_runner::TestRunner};
+/// use std::io::{BufRead, BufReader};
+///
+/// let data = vec![b'a'; 100];
+/// let mut runner = TestRunner::default();
+/// runner
+///     .run(&vec(buffer_boundary_strategy(16), 0..32), |ops| {
+///         let partial_reader = PartialRead::new(&data[..], ops);
+///         let reader = BufReader::with_capacity(16, partial_reader);
+///         assert_eq!(reader.split(b'\n').count(), 1);
+///         Ok(())
+///     })
+///     .unwrap();
+/// ```
+pub fn buffer_boundary_strategy(buf_size: usize) -> impl Strategy<Value = PartialOp> {
+    assert!(buf_size >= 2, "buf_size must be at least 2");
+    prop_oneof![
+        Just(buf_size - 1),
+        Just(buf_size),
+        Just(buf_size + 1),
+        1..=8usize,
+    ]
+    .prop_map(PartialOp::Limited)
+}
+
 /// Returns a strategy that generates `Interrupted` errors 20% of the time.
 pub fn interrupted_strategy() -> impl Strategy<Value = Option<io::ErrorKind>> {
     weighted(0.2, Just(io::ErrorKind::Interrupted))
@@ -75,3 +215,624 @@ pub fn interrupted_would_block_strategy() -> impl Strategy<Value = Option<io::Er
         ],
     )
 }
+
+/// Returns a strategy that generates sequences of `PartialOp`s modeling packet loss followed by
+/// retransmission.
+///
+/// Each chunk is delivered with a `PartialOp::Limited` of between 1 and `limit_bytes` bytes. With
+/// probability `loss_rate`, the chunk is lost before it is delivered: the loss is modeled as a
+/// `PartialOp::Eof` (a zero-length transfer), followed by a `PartialOp::Delay(retransmit_after)`
+/// standing in for the retransmission timeout, followed by the chunk itself.
+///
+/// `size` is the number of chunks to generate. Shrinking reduces the number of loss events.
+pub fn lossy_strategy(
+    loss_rate: f64,
+    retransmit_after: Duration,
+    limit_bytes: usize,
+    size: impl Into<SizeRange>,
+) -> impl Strategy<Value = Vec<PartialOp>> {
+    vec((proptest::bool::weighted(loss_rate), 1..=limit_bytes), size).prop_map(move |chunks| {
+        let mut ops = Vec::with_capacity(chunks.len());
+        for (lost, limit) in chunks {
+            if lost {
+                ops.push(PartialOp::Eof);
+                ops.push(PartialOp::Delay(retransmit_after));
+            }
+            ops.push(PartialOp::Limited(limit));
+        }
+        ops
+    })
+}
+
+/// Returns a strategy that generates sequences of `PartialOp`s driving `read_exact` into its
+/// short-read error path.
+///
+/// The sequences are meant for readers with exactly `total_available` bytes, of which
+/// `read_exact` requests `request_size`. They break those bytes up into short reads of between 1
+/// and `request_size` bytes, interleaved with `Interrupted` errors that `read_exact` retries, so
+/// that `read_exact` has to make partial progress over several reads before it reaches the real
+/// end of the data and returns `io::ErrorKind::UnexpectedEof`. Since no other errors are
+/// generated, this is guaranteed for every sequence.
+///
+/// # Panics
+///
+/// Panics if `total_available` is not less than `request_size`.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{proptest_types::short_read_strategy, PartialRead};
+/// use proptest::test_runner::TestRunner;
+/// use std::io::{self, Read};
+///
+/// let data = b"Hello";
+/// let mut runner = TestRunner::default();
+/// runner
+///     .run(&short_read_strategy(data.len(), 8), |ops| {
+///         let mut partial_reader = PartialRead::new(&data[..], ops);
+///         let mut out = [0; 8];
+///         let err = partial_reader.read_exact(&mut out).unwrap_err();
+///         assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+///         Ok(())
+///     })
+///     .unwrap();
+/// ```
+pub fn short_read_strategy(
+    total_available: usize,
+    request_size: usize,
+) -> impl Strategy<Value = Vec<PartialOp>> {
+    assert!(
+        total_available < request_size,
+        "total_available ({}) must be less than request_size ({})",
+        total_available,
+        request_size
+    );
+    // Enough ops to split every available byte into its own read.
+    vec(
+        partial_op_strategy(interrupted_strategy(), request_size),
+        0..=total_available + 1,
+    )
+}
+
+/// Returns a strategy that generates pairs of `(write_ops, read_ops)` sequences for testing echo
+/// or loopback components, such that every byte written can be read back.
+///
+/// Each sequence splits exactly `total` bytes into `PartialOp::Limited` chunks of between 1 and
+/// `limit_bytes` bytes, interleaved with `Interrupted` errors. Since there are no zero-length
+/// chunks or other errors, `write_all` through a writer driven by `write_ops` never drops data,
+/// and `read_exact` through a reader driven by `read_ops` reads back exactly `total` bytes,
+/// although the two sequences chunk the data differently. Shrinking preserves this: shrunk pairs
+/// still account for exactly `total` bytes each.
+///
+/// # Panics
+///
+/// Panics if `limit_bytes` is 0.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{proptest_types::echo_schedules_strategy, PartialRead, PartialWrite};
+/// use proptest::test_runner::TestRunner;
+/// use std::io::{Read, Write};
+///
+/// let data = b"Hello, world!";
+/// let mut runner = TestRunner::default();
+/// runner
+///     .run(&echo_schedules_strategy(data.len(), 4), |(write_ops, read_ops)| {
+///         let mut partial_writer = PartialWrite::new(Vec::new(), write_ops);
+///         partial_writer.write_all(data).unwrap();
+///         let echoed = partial_writer.into_inner();
+///
+///         let mut partial_reader = PartialRead::new(&echoed[..], read_ops);
+///         let mut out = vec![0; data.len()];
+///         partial_reader.read_exact(&mut out).unwrap();
+///         assert_eq!(&out[..], &data[..]);
+///         Ok(())
+///     })
+///     .unwrap();
+/// ```
+pub fn echo_schedules_strategy(
+    total: usize,
+    limit_bytes: usize,
+) -> impl Strategy<Value = (Vec<PartialOp>, Vec<PartialOp>)> {
+    assert!(limit_bytes > 0, "limit_bytes must be greater than 0");
+    let schedule = move || {
+        vec((proptest::bool::weighted(0.2), 1..=limit_bytes), 0..=total)
+            .prop_map(move |chunks| conserving_schedule(chunks, total, limit_bytes))
+    };
+    (schedule(), schedule())
+}
+
+/// Returns a strategy that generates sequences of `PartialOp`s by mutating `seed_pattern`.
+///
+/// This is useful when a failure is suspected to follow a known pattern (e.g. an error right
+/// after a 1-byte read): writing that pattern down as the seed focuses generation on nearby
+/// sequences, which finds related failures faster than generating sequences from scratch.
+///
+/// Every generated sequence has the same length as `seed_pattern`. Each op is independently
+/// replaced with probability `mutation_rate` by a random op: a `PartialOp::Limited` of between 1
+/// and the largest limit in the seed pattern, or an `Interrupted` or `WouldBlock` error. Other
+/// ops are kept as they are. Shrinking undoes mutations, so failing sequences shrink back toward
+/// the seed pattern.
+///
+/// # Panics
+///
+/// Panics if `mutation_rate` is not strictly between 0.0 and 1.0.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{proptest_types::biased_strategy, PartialOp};
+/// use proptest::test_runner::TestRunner;
+/// use std::io;
+///
+/// let seed_pattern = vec![
+///     PartialOp::Limited(1),
+///     PartialOp::Err(io::ErrorKind::Interrupted),
+///     PartialOp::Unlimited,
+/// ];
+/// let mut runner = TestRunner::default();
+/// runner
+///     .run(&biased_strategy(seed_pattern, 0.1), |ops| {
+///         assert_eq!(ops.len(), 3);
+///         Ok(())
+///     })
+///     .unwrap();
+/// ```
+pub fn biased_strategy(
+    seed_pattern: Vec<PartialOp>,
+    mutation_rate: f64,
+) -> impl Strategy<Value = Vec<PartialOp>> {
+    let max_limit = seed_pattern
+        .iter()
+        .filter_map(|op| match op {
+            PartialOp::Limited(n) => Some(*n),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let mutations = vec(
+        weighted(
+            mutation_rate,
+            partial_op_strategy(interrupted_would_block_strategy(), max_limit),
+        ),
+        seed_pattern.len(),
+    );
+    mutations.prop_map(move |mutations| {
+        seed_pattern
+            .iter()
+            .zip(mutations)
+            .map(|(op, mutation)| mutation.unwrap_or_else(|| op.clone()))
+            .collect()
+    })
+}
+
+/// Runs a proptest test over sequences of `PartialOp`s, and minimizes any failing sequence further
+/// with [`minimize`](crate::minimize).
+///
+/// `test_fn` is run against sequences generated by `strategy` with a default
+/// [`TestRunner`], just like the [`proptest!`] macro would. If it fails, proptest shrinks the
+/// failing sequence as usual, and the result is then passed through `minimize`, which also tries
+/// removing runs of ops and halving limits. This often finds a shorter reproduction for I/O fault
+/// bugs than proptest's general purpose shrinking. Both the minimized sequence and proptest's
+/// shrunk sequence are then reported in the text format accepted by
+/// [`parse_ops`](crate::parse_ops), ready to be pasted into a regression test.
+///
+/// `test_fn` should be deterministic, since it's called again on each candidate sequence. A
+/// candidate is considered to fail if `test_fn` returns `TestCaseError::Fail`, including through
+/// [`prop_assert!`] and similar macros.
+///
+/// # Panics
+///
+/// Panics if the test fails, with a message including the minimized sequence, or if proptest
+/// aborts the test, for example because too many inputs were rejected.
+///
+/// # Examples
+///
+/// ```rust,should_panic
+/// use partial_io::{
+///     proptest_types::{check_and_minimize, interrupted_strategy, partial_op_strategy},
+///     PartialRead,
+/// };
+/// use proptest::{collection::vec, prelude::*};
+/// use std::io::{self, Read};
+///
+/// // A buggy reader that assumes it gets 8 bytes at a time.
+/// check_and_minimize(
+///     vec(partial_op_strategy(interrupted_strategy(), 16), 0..32),
+///     |ops| {
+///         let mut partial_reader = PartialRead::new(io::repeat(42), ops.to_vec());
+///         let mut buf = [0; 8];
+///         let n = partial_reader.read(&mut buf).unwrap_or(0);
+///         prop_assert_eq!(n, 8);
+///         Ok(())
+///     },
+/// );
+/// ```
+pub fn check_and_minimize<S, F>(strategy: S, test_fn: F)
+where
+    S: Strategy<Value = Vec<PartialOp>>,
+    F: Fn(&[PartialOp]) -> Result<(), TestCaseError>,
+{
+    // There's no source file to persist failures next to, as there would be with proptest!.
+    let config = Config {
+        failure_persistence: None,
+        ..Config::default()
+    };
+    if let Err(failure) = run_and_minimize(&mut TestRunner::new(config), &strategy, &test_fn) {
+        panic!("{}", failure);
+    }
+}
+
+/// A failure found by `run_and_minimize`.
+#[derive(Debug)]
+enum MinimizeFailure {
+    Failed {
+        reason: Reason,
+        minimized: Vec<PartialOp>,
+        shrunk: Vec<PartialOp>,
+    },
+    Aborted(Reason),
+}
+
+impl fmt::Display for MinimizeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_ops = |f: &mut fmt::Formatter<'_>, ops: &[PartialOp]| {
+            for (i, op) in ops.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{}", op)?;
+            }
+            Ok(())
+        };
+        match self {
+            MinimizeFailure::Failed {
+                reason,
+                minimized,
+                shrunk,
+            } => {
+                writeln!(f, "Test failed: {}.", reason)?;
+                f.write_str("minimized failing ops: ")?;
+                write_ops(f, minimized)?;
+                f.write_str("\nops as shrunk by proptest: ")?;
+                write_ops(f, shrunk)
+            }
+            MinimizeFailure::Aborted(reason) => write!(f, "Test aborted: {}", reason),
+        }
+    }
+}
+
+fn run_and_minimize<S, F>(
+    runner: &mut TestRunner,
+    strategy: &S,
+    test_fn: &F,
+) -> Result<(), MinimizeFailure>
+where
+    S: Strategy<Value = Vec<PartialOp>>,
+    F: Fn(&[PartialOp]) -> Result<(), TestCaseError>,
+{
+    match runner.run(strategy, |ops| test_fn(&ops)) {
+        Ok(()) => Ok(()),
+        Err(TestError::Fail(reason, shrunk)) => {
+            let minimized = minimize(shrunk.clone(), |ops| {
+                matches!(test_fn(ops), Err(TestCaseError::Fail(_)))
+            });
+            Err(MinimizeFailure::Failed {
+                reason,
+                minimized,
+                shrunk,
+            })
+        }
+        Err(TestError::Abort(reason)) => Err(MinimizeFailure::Aborted(reason)),
+    }
+}
+
+/// Turns arbitrary `(interrupted, limit)` chunks into a sequence of ops that transfers exactly
+/// `total` bytes.
+///
+/// Chunks past `total` are dropped, the last chunk is trimmed to fit, and any bytes left over
+/// are covered by chunks of `limit_bytes` bytes.
+fn conserving_schedule(
+    chunks: Vec<(bool, usize)>,
+    total: usize,
+    limit_bytes: usize,
+) -> Vec<PartialOp> {
+    let mut ops = Vec::with_capacity(chunks.len());
+    let mut remaining = total;
+    for (interrupted, limit) in chunks {
+        if remaining == 0 {
+            break;
+        }
+        if interrupted {
+            ops.push(PartialOp::Err(io::ErrorKind::Interrupted));
+        }
+        let limit = limit.min(remaining);
+        ops.push(PartialOp::Limited(limit));
+        remaining -= limit;
+    }
+    while remaining > 0 {
+        let limit = limit_bytes.min(remaining);
+        ops.push(PartialOp::Limited(limit));
+        remaining -= limit;
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PartialRead, PartialWrite};
+    use proptest::{
+        strategy::ValueTree,
+        test_runner::{RngAlgorithm, TestRng},
+    };
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_eof_survives_shrinking() {
+        let data = b"Hello, world!";
+        let strategy = vec(
+            prop_oneof![partial_op_strategy(Just(None), 8), Just(PartialOp::Eof)],
+            0..16,
+        );
+        let mut runner = TestRunner::new_with_rng(
+            Config {
+                failure_persistence: None,
+                ..Config::default()
+            },
+            TestRng::deterministic_rng(RngAlgorithm::default()),
+        );
+        let result = runner.run(&strategy, |ops| {
+            let mut partial_read = PartialRead::new(&data[..], ops);
+            let mut out = Vec::new();
+            partial_read.read_to_end(&mut out).unwrap();
+            prop_assert_eq!(&out[..], &data[..]);
+            Ok(())
+        });
+        match result {
+            Err(TestError::Fail(_, ops)) => assert_eq!(ops, [PartialOp::Eof]),
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unlimited_survives_shrinking() {
+        let strategy = vec(
+            partial_op_strategy_with_unlimited(interrupted_strategy(), 8, 0.1),
+            0..32,
+        );
+        let mut runner = TestRunner::new_with_rng(
+            Config {
+                failure_persistence: None,
+                ..Config::default()
+            },
+            TestRng::deterministic_rng(RngAlgorithm::default()),
+        );
+        let result = runner.run(&strategy, |ops| {
+            prop_assert!(!ops.contains(&PartialOp::Unlimited));
+            Ok(())
+        });
+        match result {
+            Err(TestError::Fail(_, ops)) => assert_eq!(ops, [PartialOp::Unlimited]),
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_and_minimize() {
+        // Fails if 2 or more Interrupted errors are followed by a read of at least 10 bytes.
+        let test_fn = |ops: &[PartialOp]| {
+            let mut interrupted = 0;
+            for op in ops {
+                match op {
+                    PartialOp::Err(io::ErrorKind::Interrupted) => interrupted += 1,
+                    PartialOp::Limited(n) => {
+                        prop_assert!(interrupted < 2 || *n < 10, "bad read after {:?}", ops)
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        };
+        let strategy = || vec(partial_op_strategy(interrupted_strategy(), 64), 0..64);
+        let interrupted = PartialOp::Err(io::ErrorKind::Interrupted);
+        let expected = [interrupted.clone(), interrupted, PartialOp::Limited(10)];
+        let mut runner = TestRunner::new_with_rng(
+            Config {
+                failure_persistence: None,
+                ..Config::default()
+            },
+            TestRng::deterministic_rng(RngAlgorithm::default()),
+        );
+
+        // With a naive shrinker that doesn't shrink at all, minimizing does all the work.
+        let failure = run_and_minimize(&mut runner, &strategy().no_shrink(), &test_fn).unwrap_err();
+        match &failure {
+            MinimizeFailure::Failed {
+                minimized, shrunk, ..
+            } => {
+                assert_eq!(minimized, &expected);
+                assert!(minimized.len() < shrunk.len(), "{}", failure);
+            }
+            MinimizeFailure::Aborted(reason) => panic!("aborted: {}", reason),
+        }
+
+        // With proptest's shrinking, minimizing never makes things worse.
+        let failure = run_and_minimize(&mut runner, &strategy(), &test_fn).unwrap_err();
+        match &failure {
+            MinimizeFailure::Failed {
+                minimized, shrunk, ..
+            } => {
+                assert_eq!(minimized, &expected);
+                assert!(minimized.len() <= shrunk.len(), "{}", failure);
+            }
+            MinimizeFailure::Aborted(reason) => panic!("aborted: {}", reason),
+        }
+
+        // The minimized ops are reported in the text format.
+        let message = failure.to_string();
+        let line = message
+            .lines()
+            .find_map(|line| line.strip_prefix("minimized failing ops: "))
+            .unwrap();
+        assert_eq!(line, "INT INT L10");
+
+        // Passing tests don't fail.
+        run_and_minimize(&mut runner, &strategy(), &|_: &[PartialOp]| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn test_buffer_boundary_strategy() {
+        let strategy = buffer_boundary_strategy(8192);
+        let mut runner = TestRunner::deterministic();
+        let mut seen = std::collections::BTreeMap::new();
+        for _ in 0..1000 {
+            let limit = match strategy.new_tree(&mut runner).unwrap().current() {
+                PartialOp::Limited(n) => n,
+                op => panic!("unexpected op {:?}", op),
+            };
+            assert!(
+                (1..=8).contains(&limit) || (8191..=8193).contains(&limit),
+                "limit {} is near the boundary or small",
+                limit
+            );
+            *seen.entry(limit).or_insert(0) += 1;
+        }
+        for boundary in 8191..=8193 {
+            assert!(
+                seen.get(&boundary).copied().unwrap_or(0) > 100,
+                "{} is generated often",
+                boundary
+            );
+        }
+        assert!(seen.range(1..=8).count() > 4, "small limits vary");
+
+        // Shrinking stays on the boundary.
+        let mut tree = strategy.new_tree(&mut runner).unwrap();
+        while tree.simplify() {}
+        assert_eq!(tree.current(), PartialOp::Limited(8191));
+    }
+
+    #[test]
+    fn test_biased_strategy() {
+        let seed_pattern = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Unlimited,
+            PartialOp::Limited(8),
+        ];
+        let strategy = biased_strategy(seed_pattern.clone(), 0.25);
+        let mut runner = TestRunner::deterministic();
+
+        let mut mutated = 0;
+        let samples = 1000;
+        for _ in 0..samples {
+            let ops = strategy.new_tree(&mut runner).unwrap().current();
+            assert_eq!(ops.len(), seed_pattern.len(), "length is preserved");
+            for (op, seed_op) in ops.iter().zip(&seed_pattern) {
+                if op != seed_op {
+                    mutated += 1;
+                    assert!(
+                        matches!(op, PartialOp::Limited(1..=8) | PartialOp::Err(_)),
+                        "mutated op {:?} is within the seed's limits",
+                        op
+                    );
+                }
+            }
+        }
+        // A mutation can coincidentally reproduce the seed op, so slightly fewer than 25% of ops
+        // differ from the seed.
+        let rate = mutated as f64 / (samples * seed_pattern.len()) as f64;
+        assert!(
+            (0.15..=0.3).contains(&rate),
+            "mutation rate {} is close to 0.25",
+            rate
+        );
+
+        // Shrinking undoes mutations.
+        let mut tree = biased_strategy(seed_pattern.clone(), 0.99)
+            .new_tree(&mut runner)
+            .unwrap();
+        while tree.simplify() {}
+        assert_eq!(tree.current(), seed_pattern, "shrinks back to the seed");
+        assert!(biased_strategy(Vec::new(), 0.5)
+            .new_tree(&mut runner)
+            .unwrap()
+            .current()
+            .is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_lossy_strategy(
+            ops in lossy_strategy(0.3, Duration::from_millis(5), 16, 0..32)
+        ) {
+            let mut iter = ops.iter();
+            while let Some(op) = iter.next() {
+                if *op == PartialOp::Eof {
+                    // A loss event must be followed by the retransmission timeout and the chunk.
+                    prop_assert_eq!(
+                        iter.next(),
+                        Some(&PartialOp::Delay(Duration::from_millis(5)))
+                    );
+                    prop_assert!(matches!(iter.next(), Some(PartialOp::Limited(n)) if *n > 0));
+                } else {
+                    prop_assert!(matches!(op, PartialOp::Limited(n) if *n > 0));
+                }
+            }
+        }
+
+        #[test]
+        fn proptest_short_read_strategy(
+            (total_available, request_size, ops) in (0..64usize, 1..64usize)
+                .prop_flat_map(|(total_available, extra)| {
+                    let request_size = total_available + extra;
+                    (
+                        Just(total_available),
+                        Just(request_size),
+                        short_read_strategy(total_available, request_size),
+                    )
+                })
+        ) {
+            let data: Vec<u8> = (0..total_available as u8).collect();
+            let mut partial_read = PartialRead::new(&data[..], ops);
+            let mut out = vec![0; request_size];
+            let err = partial_read.read_exact(&mut out).unwrap_err();
+            prop_assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        #[test]
+        fn proptest_echo_schedules_strategy(
+            (write_ops, read_ops) in echo_schedules_strategy(100, 16)
+        ) {
+            for ops in [&write_ops, &read_ops] {
+                let mut total = 0;
+                for op in ops {
+                    match op {
+                        PartialOp::Limited(n) => {
+                            prop_assert!((1..=16).contains(n));
+                            total += n;
+                        }
+                        PartialOp::Err(kind) => {
+                            prop_assert_eq!(*kind, io::ErrorKind::Interrupted);
+                        }
+                        op => prop_assert!(false, "unexpected op {:?}", op),
+                    }
+                }
+                prop_assert_eq!(total, 100, "each schedule accounts for every byte");
+            }
+
+            let data: Vec<u8> = (0..100).collect();
+            let mut partial_write = PartialWrite::new(Vec::new(), write_ops);
+            partial_write.write_all(&data).unwrap();
+            let echoed = partial_write.into_inner();
+
+            let mut partial_read = PartialRead::new(&echoed[..], read_ops);
+            let mut out = vec![0; 100];
+            partial_read.read_exact(&mut out).unwrap();
+            prop_assert_eq!(out, data);
+        }
+    }
+}
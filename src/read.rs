@@ -6,10 +6,26 @@
 
 use std::{
     cmp, fmt,
-    io::{self, Read, Write},
+    io::{self, BufRead, IoSliceMut, Read, Seek, SeekFrom, Write},
+    iter,
+    time::Instant,
 };
 
-use crate::{make_ops, PartialOp};
+use crate::{
+    ops::{
+        avoid_power_of_two, check_limit, limit_io_slices_mut, trace_op, BoxedOps, Diagnostics,
+        OpEvent, OpHistory, OpObserver, OpSnapshot, Ops, ReplayOps, ResultMap,
+    },
+    rng::SplitMix64,
+    ExhaustedBehavior, PartialOp, PhasePosition, PhasedSchedule,
+};
+
+/// State for replaying the last chunk read, set by `set_duplicate_prob`.
+struct Duplicate {
+    prob: f64,
+    rng: SplitMix64,
+    last_chunk: Vec<u8>,
+}
 
 /// A reader wrapper that breaks inner `Read` instances up according to the
 /// provided iterator.
@@ -30,9 +46,16 @@ use crate::{make_ops, PartialOp};
 /// assert_eq!(size, 1);
 /// assert_eq!(&out[..1], &[1]);
 /// ```
-pub struct PartialRead<R> {
+pub struct PartialRead<R, I = BoxedOps> {
     inner: R,
-    ops: Box<dyn Iterator<Item = PartialOp> + Send>,
+    ops: Ops<I>,
+    captured: Option<Vec<u8>>,
+    avoid_power_of_two: bool,
+    strict_limits: bool,
+    observer: Option<OpObserver>,
+    reorder: Option<SplitMix64>,
+    duplicate: Option<Duplicate>,
+    result_map: Option<ResultMap>,
 }
 
 impl<R> PartialRead<R>
@@ -45,10 +68,57 @@ where
         I: IntoIterator<Item = PartialOp> + 'static,
         I::IntoIter: Send,
     {
-        PartialRead {
-            inner,
-            ops: make_ops(iter),
-        }
+        Self::new_unboxed(inner, Box::new(iter.into_iter()))
+    }
+
+    /// Creates a new `PartialRead` wrapper that delivers at most one byte at a time, without any
+    /// errors.
+    ///
+    /// This is the strictest deterministic chunking possible, and is a good way to shake out
+    /// bugs around read boundaries. It is equivalent to passing in
+    /// `std::iter::repeat(PartialOp::Limited(1))` as the `PartialOp`s.
+    pub fn pinhole(inner: R) -> Self {
+        Self::new(inner, iter::repeat(PartialOp::Limited(1)))
+    }
+
+    /// Creates a new `PartialRead` wrapper that delivers exactly one byte, then fails every read
+    /// after that with an error of the given kind.
+    ///
+    /// This models a connection that dies right after the first byte arrives, which tends to
+    /// expose bugs in how partially built state is cleaned up.
+    pub fn one_then_fail(inner: R, kind: io::ErrorKind) -> Self {
+        let ops = iter::once(PartialOp::Limited(1)).chain(iter::repeat(PartialOp::Err(kind)));
+        Self::new(inner, ops)
+    }
+
+    /// Creates a new `PartialRead` wrapper that delivers the first `header_len` bytes in a single
+    /// read, then applies `body_ops` to the rest of the data.
+    ///
+    /// This models parsers that read a fixed-size header (or a byte order mark) before streaming
+    /// a variable-length body, and keeps the header from triggering boundary bugs that aren't of
+    /// interest. The header is read with a `PartialOp::Limited(header_len)` op, so it arrives
+    /// whole as long as the caller's buffer is large enough and the inner reader returns all of
+    /// it in one call. If `header_len` is 0, only `body_ops` apply.
+    pub fn header_then_chunk<I>(inner: R, header_len: usize, body_ops: I) -> Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        let header = (header_len > 0).then(|| PartialOp::Limited(header_len));
+        Self::new(inner, header.into_iter().chain(body_ops))
+    }
+
+    /// Creates a new `PartialRead` wrapper that returns `interrupts_per_op` `Interrupted` errors
+    /// before each successful, unlimited read.
+    ///
+    /// This deterministically stresses retry loops that must handle `EINTR` without dropping
+    /// data.
+    pub fn interrupt_storm(inner: R, interrupts_per_op: usize) -> Self {
+        let ops = iter::repeat(PartialOp::Err(io::ErrorKind::Interrupted))
+            .take(interrupts_per_op)
+            .chain(iter::once(PartialOp::Unlimited))
+            .cycle();
+        Self::new(inner, ops)
     }
 
     /// Sets the `PartialOp`s for this reader.
@@ -57,7 +127,533 @@ where
         I: IntoIterator<Item = PartialOp> + 'static,
         I::IntoIter: Send,
     {
-        self.ops = make_ops(iter);
+        self.ops.replace(iter);
+        self
+    }
+
+    /// Computes each `PartialOp` for this reader with a callback, replacing any existing ops.
+    ///
+    /// Before each op is applied, `op_fn` is passed an [`OpHistory`] describing the ops applied
+    /// since the callback was set, the number of bytes transferred and the errors seen so far,
+    /// and returns the op to apply next. This makes it possible to express stateful fault models,
+    /// such as failing only after a run of successful transfers, that a fixed sequence of ops
+    /// can't. The callback is called whenever an op is needed, so the ops never run out.
+    ///
+    /// Setting ops any other way removes the callback.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialRead};
+    /// use std::io::{self, Read};
+    ///
+    /// let mut partial_reader = PartialRead::new(&b"Hello, world!"[..], vec![]);
+    /// // Fail with an error only after three reads in a row have succeeded.
+    /// partial_reader.set_op_fn(|history| {
+    ///     let recent = history.recent_ops();
+    ///     let streak = recent
+    ///         .iter()
+    ///         .rev()
+    ///         .take_while(|op| !matches!(op, PartialOp::Err(_)))
+    ///         .count();
+    ///     if streak >= 3 {
+    ///         PartialOp::Err(io::ErrorKind::ConnectionReset)
+    ///     } else {
+    ///         PartialOp::Limited(2)
+    ///     }
+    /// });
+    ///
+    /// let mut buf = [0; 8];
+    /// for _ in 0..3 {
+    ///     assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    /// }
+    /// let err = partial_reader.read(&mut buf).unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    /// ```
+    pub fn set_op_fn<F>(&mut self, op_fn: F) -> &mut Self
+    where
+        F: FnMut(&OpHistory<'_>) -> PartialOp + Send + 'static,
+    {
+        self.ops.replace_with_fn(Box::new(op_fn));
+        self
+    }
+
+    /// Sets the `PartialOp`s for this reader from a [`PhasedSchedule`], and starts tracking
+    /// which phase the last consumed op belongs to.
+    ///
+    /// The current phase is reported by [`current_phase`](Self::current_phase), this reader's
+    /// `Debug` output and [`assert_op_count`](Self::assert_op_count) failure messages. Setting
+    /// ops any other way stops tracking phases.
+    pub fn set_phased_ops(&mut self, schedule: PhasedSchedule) -> &mut Self {
+        self.ops.replace_phased(schedule);
+        self
+    }
+
+    /// Returns a copy of the `PartialOp`s that this reader hasn't consumed yet.
+    ///
+    /// The copy is independent of this reader: passing it to a fresh `PartialRead` produces a
+    /// second reader that continues from the same point in the schedule, for branching tests.
+    /// Unlike [`snapshot`](Self::snapshot), only the ops are copied, not the errors seen so far. A
+    /// latched error isn't part of the schedule either, and isn't carried over.
+    ///
+    /// This collects the remaining `PartialOp`s into a buffer, so they must be finite: with an
+    /// infinite iterator, this method never returns.
+    pub fn fork_schedule(&mut self) -> Vec<PartialOp> {
+        self.ops.fork()
+    }
+
+    /// Returns a snapshot of this reader's position within its `PartialOp`s, along with the
+    /// errors seen so far.
+    ///
+    /// Pass the snapshot to [`restore`](Self::restore) to return to this position, for example to
+    /// explore several branches from the same point. The state of the underlying reader is not
+    /// part of the snapshot.
+    ///
+    /// Taking a snapshot collects the remaining `PartialOp`s into a buffer, so they must be
+    /// finite: with an infinite iterator, this method never returns.
+    pub fn snapshot(&mut self) -> OpSnapshot {
+        self.ops.snapshot()
+    }
+
+    /// Restores the position and errors seen captured by [`snapshot`](Self::snapshot).
+    pub fn restore(&mut self, snapshot: &OpSnapshot) -> &mut Self {
+        self.ops.restore(snapshot);
+        self
+    }
+}
+
+impl<R, I> PartialRead<R, I>
+where
+    R: Read,
+    I: Iterator<Item = PartialOp>,
+{
+    /// Creates a new `PartialRead` wrapper over the reader with the specified `PartialOp`s,
+    /// without boxing the iterator.
+    ///
+    /// This avoids an allocation and dynamic dispatch for each op, and the iterator doesn't need
+    /// to be `'static`, so it can borrow ops from a local `Vec`. In exchange, the ops can't be
+    /// replaced once the wrapper is created: [`set_ops`](PartialRead::set_ops) and the other
+    /// methods that swap out the iterator are only available with the default, boxed iterator.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialRead};
+    /// use std::io::Read;
+    ///
+    /// let ops = vec![PartialOp::Limited(2), PartialOp::Limited(3)];
+    /// let mut partial_reader = PartialRead::new_unboxed(&b"Hello"[..], ops.iter().cloned());
+    /// let mut buf = [0; 8];
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 3);
+    ///
+    /// // The ops were borrowed, not moved.
+    /// assert_eq!(ops.len(), 2);
+    /// ```
+    pub fn new_unboxed(inner: R, iter: I) -> Self {
+        PartialRead {
+            inner,
+            ops: Ops::new_unboxed(iter),
+            captured: None,
+            avoid_power_of_two: false,
+            strict_limits: false,
+            observer: None,
+            reorder: None,
+            duplicate: None,
+            result_map: None,
+        }
+    }
+
+    /// Attaches a callback that is passed an [`OpEvent`] after each `read` call.
+    ///
+    /// The event describes the `PartialOp` that applied, the number of bytes read so far, the
+    /// length of the caller's buffer and the number of bytes read by the call. Replaces
+    /// any previously attached callback, and starts counting the offset from 0 again.
+    pub fn on_op<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+    {
+        self.observer = Some(OpObserver::new(callback));
+        self
+    }
+
+    /// Like [`on_op`](Self::on_op), but timestamps events with `clock` rather than
+    /// `Instant::now()`.
+    ///
+    /// This makes it possible to correlate events with other traces using the same clock, or to
+    /// control time in tests. `clock` is called once per event, and should return quickly
+    /// without blocking, since it's called from within `read`.
+    pub fn on_op_with_clock<F, C>(&mut self, callback: F, clock: C) -> &mut Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+        C: FnMut() -> Instant + Send + 'static,
+    {
+        self.observer = Some(OpObserver::with_clock(callback, clock));
+        self
+    }
+
+    /// Returns the phase of the last consumed `PartialOp`, and its index within that phase.
+    ///
+    /// Returns `None` unless ops were set with [`set_phased_ops`](Self::set_phased_ops), or if no
+    /// ops have been consumed yet. Once the schedule has run out, this keeps returning the
+    /// position of the last op.
+    pub fn current_phase(&self) -> Option<PhasePosition> {
+        self.ops.current_phase()
+    }
+
+    /// Returns the limit the next `PartialOp` will apply, if it is `PartialOp::Limited`.
+    ///
+    /// `PartialOp::Eof` counts as a limit of 0.
+    ///
+    /// Returns `None` if the next op is `PartialOp::Unlimited`, `PartialOp::Err` or
+    /// `PartialOp::Delay`, or if the ops have run out, unless
+    /// [`set_unlimited_cap`](Self::set_unlimited_cap) turns the op into a limit. This peeks at the
+    /// next op, which pulls it out of the underlying iterator early. The op is not consumed: it
+    /// still applies to the next `read` call.
+    pub fn next_limit(&mut self) -> Option<usize> {
+        self.ops.next_limit()
+    }
+
+    /// Asserts that exactly `expected` `PartialOp`s have been consumed since the ops were last
+    /// set.
+    ///
+    /// Each `read` call consumes one op. Calls made after the ops have run out aren't counted. This
+    /// is useful for pinning down golden chunking behavior: with `PartialOp::Limited` ops, a
+    /// regression in how data is chunked changes the number of ops consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics with a message including both counts if they differ.
+    #[track_caller]
+    pub fn assert_op_count(&self, expected: usize) {
+        self.ops.assert_op_count(expected);
+    }
+
+    /// Returns the number of `PartialOp`s consumed since the ops were last set.
+    ///
+    /// This counts ops the same way as [`assert_op_count`](Self::assert_op_count).
+    pub fn consumed_ops(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns the number of `PartialOp`s that haven't been consumed yet, if it is known.
+    ///
+    /// The number is known if the iterator the ops were set from reports an exact size, as
+    /// `ExactSizeIterator`s like `vec::IntoIter` do. Returns `None` for other iterators, including
+    /// infinite ones, and for ops computed by [`set_op_fn`](Self::set_op_fn).
+    pub fn ops_remaining(&self) -> Option<usize> {
+        self.ops.remaining()
+    }
+
+    /// Returns the total number of bytes read through this reader.
+    ///
+    /// This counts the bytes actually transferred by the inner reader, not the amounts requested
+    /// by callers or allowed by `PartialOp`s. Calls that return an error don't count, and bytes
+    /// returned by `fill_buf` only count once they are consumed. Chunks replayed by
+    /// [`set_duplicate_prob`](Self::set_duplicate_prob) don't count either, since the inner
+    /// reader didn't transfer them again. The count is kept when the ops are replaced.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.ops.transferred()
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, in the order they fired.
+    ///
+    /// Errors returned by the underlying reader are not included.
+    pub fn errors_seen(&self) -> &[io::ErrorKind] {
+        self.ops.errors_seen()
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, along with the number of
+    /// bytes transferred through this reader before each one fired.
+    ///
+    /// This covers the same errors as [`errors_seen`](Self::errors_seen), and helps correlate
+    /// errors with positions in the stream.
+    pub fn error_offsets(&self) -> &[(io::ErrorKind, u64)] {
+        self.ops.error_offsets()
+    }
+
+    /// Sets the kind of error that is sticky once it is injected.
+    ///
+    /// Once a `PartialOp::Err` of this kind is applied, every subsequent operation on this
+    /// reader fails with the same kind of error regardless of the remaining `PartialOp`s, which
+    /// are not consumed. This models unrecoverable failures like a connection dying with
+    /// `BrokenPipe`. The latched error is preserved across [`set_ops`](Self::set_ops), and can be
+    /// reset with [`clear_latch`](Self::clear_latch).
+    ///
+    /// Pass in `None` to stop latching errors. This doesn't clear an error that has already
+    /// latched.
+    pub fn set_latch_error(&mut self, kind: Option<io::ErrorKind>) -> &mut Self {
+        self.ops.set_latch_error(kind);
+        self
+    }
+
+    /// Returns the error that has latched, if any.
+    pub fn latched_error(&self) -> Option<io::ErrorKind> {
+        self.ops.latched_error()
+    }
+
+    /// Clears the error that has latched, if any, so that `PartialOp`s apply again.
+    pub fn clear_latch(&mut self) -> &mut Self {
+        self.ops.clear_latch();
+        self
+    }
+
+    /// Randomly merges consecutive `PartialOp::Limited` ops into larger transfers.
+    ///
+    /// With `Some((p, seed))`, whenever a `PartialOp::Limited` op is applied, it is merged with
+    /// the next op with probability `p` if that op is also `PartialOp::Limited`, and so on, so a
+    /// run of `Limited(1)` ops may turn into a single `Limited(3)`. Any other op, including an
+    /// error, stops the merging. The coin flips are derived from `seed`, so they're reproducible.
+    ///
+    /// This adds some larger chunks to a fine-grained schedule without rewriting it, to exercise
+    /// code paths that only small transfers would miss. Merged ops count as consumed.
+    /// [`next_limit`](Self::next_limit) reports the limit before merging.
+    ///
+    /// Pass in `None`, the default, to turn merging off.
+    pub fn set_coalesce_prob(&mut self, prob: Option<(f64, u64)>) -> &mut Self {
+        self.ops.set_coalesce_prob(prob);
+        self
+    }
+
+    /// Caps the number of bytes a `PartialOp::Unlimited` op lets through in a single call.
+    ///
+    /// With a cap of `Some(cap)`, every `PartialOp::Unlimited` op is treated as
+    /// `PartialOp::Limited(cap)`, and so are calls made after the ops have run out. This bounds
+    /// the size of any single transfer, for example to keep fuzz harnesses from making huge
+    /// allocations. Only `PartialOp::Unlimited` is affected: `PartialOp::Limited(n)` is
+    /// applied as is even if `n` is larger than the cap.
+    ///
+    /// Pass in `None`, the default, to remove the cap.
+    pub fn set_unlimited_cap(&mut self, cap: Option<usize>) -> &mut Self {
+        self.ops.set_unlimited_cap(cap);
+        self
+    }
+
+    /// Sets what this reader does once its `PartialOp`s have run out.
+    ///
+    /// By default, calls are forwarded to the inner reader without limits or errors, as with
+    /// `ExhaustedBehavior::Unlimited`. With `ExhaustedBehavior::Panic`, the read after the ops
+    /// run out panics, which catches sequences of ops that are too short for the test. With
+    /// `ExhaustedBehavior::Repeat(op)`, `op` is applied to every call from then on.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{ExhaustedBehavior, PartialOp, PartialRead};
+    /// use std::io::Read;
+    ///
+    /// let mut partial_reader = PartialRead::new(&b"Hello"[..], vec![PartialOp::Limited(2)]);
+    /// partial_reader.set_exhausted_behavior(ExhaustedBehavior::Repeat(PartialOp::Limited(1)));
+    ///
+    /// let mut buf = [0; 8];
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 2);
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 1);
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 1);
+    /// ```
+    pub fn set_exhausted_behavior(&mut self, behavior: ExhaustedBehavior) -> &mut Self {
+        self.ops.set_exhausted_behavior(behavior);
+        self
+    }
+
+    /// Returns a summary of everything this reader has recorded so far.
+    ///
+    /// This bundles the ops consumed, the last op, the bytes transferred and the errors injected
+    /// into a single report, for printing when a test fails. The remaining budget isn't part of
+    /// the report, since working it out consumes the iterator: use
+    /// [`snapshot`](Self::snapshot) for that.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialRead};
+    /// use std::io::{self, Read};
+    ///
+    /// let ops = vec![
+    ///     PartialOp::Limited(2),
+    ///     PartialOp::Err(io::ErrorKind::Interrupted),
+    /// ];
+    /// let mut partial_reader = PartialRead::new(&b"Hello"[..], ops);
+    /// let mut buf = [0; 8];
+    /// partial_reader.read(&mut buf).unwrap();
+    /// partial_reader.read(&mut buf).unwrap_err();
+    ///
+    /// let diagnostics = partial_reader.diagnostics();
+    /// assert_eq!(diagnostics.ops_consumed, 2);
+    /// assert_eq!(diagnostics.last_op, Some(PartialOp::Err(io::ErrorKind::Interrupted)));
+    /// assert_eq!(diagnostics.transferred, 2);
+    /// assert_eq!(diagnostics.flushes, None);
+    /// ```
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.ops.diagnostics()
+    }
+
+    /// Sets whether limits that are powers of two are adjusted down before being applied.
+    ///
+    /// With this set to true, whenever a `PartialOp::Limited` op would pass a buffer whose length
+    /// is a power of two greater than 1 to the inner reader, the length is reduced by one, so
+    /// for example 8 becomes 7. This is a cheap way to shake out code that assumes reads come
+    /// in power-of-two sizes, such as alignment-sensitive parsers. The limit is adjusted after
+    /// it's been truncated to the length of the caller's buffer.
+    ///
+    /// This only affects `PartialOp::Limited` ops (including `PartialOp::Unlimited` ops turned
+    /// into limits by [`set_unlimited_cap`](Self::set_unlimited_cap)). Unlimited reads, and
+    /// limits of 0 or 1, are left alone. The inner reader may still return fewer bytes than
+    /// the limit, including a power of two.
+    pub fn set_avoid_power_of_two(&mut self, avoid: bool) -> &mut Self {
+        self.avoid_power_of_two = avoid;
+        self
+    }
+
+    /// Sets whether to check that the inner reader never transfers more bytes than it was
+    /// allowed to.
+    ///
+    /// With this set to true, every `read` call made under a `PartialOp` panics if the inner
+    /// reader reports more bytes transferred than the length of the buffer it was passed, i.e.
+    /// more than the op's limit. This is an invariant check for the limit calculations in this
+    /// wrapper, and also catches inner readers that violate the `read` contract.
+    ///
+    /// Off by default, in both debug and release builds. Calls forwarded directly to the inner
+    /// reader, for `PartialOp::Unlimited` ops or after the ops have run out, are not checked.
+    pub fn set_strict_limits(&mut self, strict: bool) -> &mut Self {
+        self.strict_limits = strict;
+        self
+    }
+
+    /// Sets a callback that transforms the result of each `read` call.
+    ///
+    /// This is an escape hatch for fault shapes that `PartialOp`s can't express, such as
+    /// reporting fewer bytes than were read, or failing a read based on the data it returned.
+    /// The callback is passed the result of the call along with the bytes that were read into
+    /// the caller's buffer (empty if the call failed), and returns the result to report instead.
+    ///
+    /// The callback runs after the `PartialOp` for the call has been applied and the inner reader
+    /// has been called, and before anything else: the mapped result is what the caller,
+    /// capturing and [`on_op`](Self::on_op) callbacks see, and byte counts such as the offsets
+    /// in [`error_offsets`](Self::error_offsets) are based on it. It is also passed errors
+    /// injected by `PartialOp::Err`, but not chunks replayed by
+    /// [`set_duplicate_prob`](Self::set_duplicate_prob). Replaces any previously set callback.
+    ///
+    /// # Panics
+    ///
+    /// `read` panics if the callback returns a byte count larger than the caller's buffer.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::PartialRead;
+    /// use std::io::{self, Read};
+    ///
+    /// let mut partial_reader = PartialRead::new(&b"Hello, world!"[..], vec![]);
+    /// // Reject any data containing a comma.
+    /// partial_reader.set_result_map(|res: io::Result<usize>, bytes: &[u8]| {
+    ///     if bytes.contains(&b',') {
+    ///         Err(io::Error::new(io::ErrorKind::InvalidData, "found a comma"))
+    ///     } else {
+    ///         res
+    ///     }
+    /// });
+    ///
+    /// let mut buf = [0; 5];
+    /// assert_eq!(partial_reader.read(&mut buf).unwrap(), 5);
+    /// let err = partial_reader.read(&mut buf).unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    /// ```
+    pub fn set_result_map<F>(&mut self, result_map: F) -> &mut Self
+    where
+        F: FnMut(io::Result<usize>, &[u8]) -> io::Result<usize> + Send + 'static,
+    {
+        self.result_map = Some(Box::new(result_map));
+        self
+    }
+
+    /// Sets whether bytes delivered by this reader are captured.
+    ///
+    /// While capturing is enabled, every byte returned to the caller (after any
+    /// `PartialOp::Limited` truncation) is also appended to an internal buffer, which can be
+    /// retrieved with [`take_captured`](Self::take_captured). Disabling capturing discards any
+    /// bytes captured so far.
+    pub fn set_capture(&mut self, capture: bool) -> &mut Self {
+        match (capture, self.captured.is_some()) {
+            (true, false) => self.captured = Some(Vec::new()),
+            (false, true) => self.captured = None,
+            _ => {}
+        }
+        self
+    }
+
+    /// Returns the bytes captured so far, leaving the capture buffer empty.
+    ///
+    /// Returns an empty `Vec` if capturing is disabled.
+    pub fn take_captured(&mut self) -> Vec<u8> {
+        self.captured
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Caps the underlying reader at `limit` bytes, keeping this wrapper's `PartialOp`s and
+    /// settings.
+    ///
+    /// Once `limit` bytes have been read, reads return `Ok(0)` regardless of how much data the
+    /// underlying reader has left, while the `PartialOp`s keep applying up to that point.
+    ///
+    /// This differs from `Read::take`, which wraps this wrapper in an `io::Take` instead.
+    pub fn take_inner(self, limit: u64) -> PartialRead<io::Take<R>, I> {
+        PartialRead {
+            inner: self.inner.take(limit),
+            ops: self.ops,
+            captured: self.captured,
+            avoid_power_of_two: self.avoid_power_of_two,
+            strict_limits: self.strict_limits,
+            observer: self.observer,
+            reorder: self.reorder,
+            duplicate: self.duplicate,
+            result_map: self.result_map,
+        }
+    }
+
+    /// Shuffles the bytes within each chunk returned by `read`, using a deterministic
+    /// pseudo-random permutation derived from `seed`.
+    ///
+    /// **This deliberately corrupts the data**: the bytes of each successful read are delivered
+    /// in a different order than the inner reader returned them in, though chunk boundaries
+    /// and the bytes themselves are preserved. It is meant for robustness testing only, for
+    /// example to check that a parser with integrity checks detects reordered data. Combine it
+    /// with `PartialOp::Limited` ops to control how far bytes can move.
+    ///
+    /// The same seed and the same sequence of reads always produce the same output. Captured
+    /// bytes (see [`set_capture`](Self::set_capture)) are captured after reordering.
+    ///
+    /// Pass in `None` to turn reordering off.
+    pub fn set_intra_chunk_reorder(&mut self, seed: Option<u64>) -> &mut Self {
+        self.reorder = seed.map(SplitMix64::new);
+        self
+    }
+
+    /// Occasionally delivers the previous chunk a second time, to model transports with
+    /// at-least-once delivery.
+    ///
+    /// With `Some((p, seed))`, each `read` call replays the bytes returned by the last successful
+    /// read with probability `p`, instead of reading anything new. A chunk is replayed at most
+    /// once, and if the caller's buffer is too small, only the start of the chunk is replayed.
+    /// The coin flips are derived from `seed`, so they're reproducible.
+    ///
+    /// **This deliberately violates the `Read` contract** by returning the same bytes twice. It
+    /// is a fault injection mode meant for testing consumers that are supposed to be idempotent
+    /// or to detect duplicates. Replays don't consume a `PartialOp` and aren't reported to
+    /// [`on_op`](Self::on_op) callbacks or counted by
+    /// [`bytes_transferred`](Self::bytes_transferred), but are captured if
+    /// [`set_capture`](Self::set_capture) is enabled. To replay chunks, this wrapper keeps a copy
+    /// of the last one.
+    ///
+    /// Pass in `None`, the default, to turn replays off.
+    pub fn set_duplicate_prob(&mut self, prob: Option<(f64, u64)>) -> &mut Self {
+        self.duplicate = prob.map(|(prob, seed)| Duplicate {
+            prob,
+            rng: SplitMix64::new(seed),
+            last_chunk: Vec::new(),
+        });
         self
     }
 
@@ -77,29 +673,295 @@ where
     }
 }
 
-impl<R> Read for PartialRead<R>
+impl<R, V> PartialRead<R, ReplayOps<V>>
+where
+    R: Read,
+    V: IntoIterator<Item = PartialOp> + Clone,
+{
+    /// Creates a new `PartialRead` wrapper over the reader with a repeatable sequence of
+    /// `PartialOp`s, such as a `Vec<PartialOp>`.
+    ///
+    /// Unlike the wrappers created by the other constructors, this one implements `Clone` as long
+    /// as the reader does. See [`ReplayOps`] for more.
+    pub fn new_repeatable(inner: R, ops: V) -> Self {
+        Self::new_unboxed(inner, ReplayOps::new(ops))
+    }
+}
+
+/// The clone starts over from the beginning of the `PartialOp`s, with no ops consumed, no bytes
+/// transferred and no errors seen. Other settings are copied as they currently are, except for
+/// callbacks set with `on_op` and `set_result_map`, which can't be cloned and aren't carried
+/// over. Bytes captured so far aren't carried over either.
+impl<R, V> Clone for PartialRead<R, ReplayOps<V>>
+where
+    R: Clone,
+    V: IntoIterator<Item = PartialOp> + Clone,
+{
+    fn clone(&self) -> Self {
+        PartialRead {
+            inner: self.inner.clone(),
+            ops: self.ops.restarted(),
+            captured: self.captured.as_ref().map(|_| Vec::new()),
+            avoid_power_of_two: self.avoid_power_of_two,
+            strict_limits: self.strict_limits,
+            observer: None,
+            reorder: self.reorder.clone(),
+            duplicate: self.duplicate.as_ref().map(|duplicate| Duplicate {
+                prob: duplicate.prob,
+                rng: duplicate.rng.clone(),
+                last_chunk: Vec::new(),
+            }),
+            result_map: None,
+        }
+    }
+}
+
+impl<R, I> Read for PartialRead<R, I>
 where
     R: Read,
+    I: Iterator<Item = PartialOp>,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.ops.next() {
+        if self.captured.is_none()
+            && self.observer.is_none()
+            && self.reorder.is_none()
+            && self.duplicate.is_none()
+            && self.result_map.is_none()
+            && (self.ops.is_exhausted() || self.ops.take_unlimited())
+        {
+            // Fast path: the ops have run out or the next one is Unlimited, so there's nothing to
+            // do but forward to the inner reader.
+            let res = self.inner.read(buf);
+            return self.ops.track_transfer(res);
+        }
+
+        if let Some(duplicate) = &mut self.duplicate {
+            if !duplicate.last_chunk.is_empty() && duplicate.rng.chance(duplicate.prob) {
+                let n = cmp::min(duplicate.last_chunk.len(), buf.len());
+                buf[..n].copy_from_slice(&duplicate.last_chunk[..n]);
+                duplicate.last_chunk.clear();
+                if let Some(captured) = &mut self.captured {
+                    captured.extend_from_slice(&buf[..n]);
+                }
+                return Ok(n);
+            }
+        }
+
+        let op = self.ops.next();
+        let res = match op {
             Some(PartialOp::Limited(n)) => {
-                let len = cmp::min(n, buf.len());
-                self.inner.read(&mut buf[..len])
+                let mut len = cmp::min(n, buf.len());
+                if self.avoid_power_of_two {
+                    len = avoid_power_of_two(len);
+                }
+                let res = self.inner.read(&mut buf[..len]);
+                if self.strict_limits {
+                    check_limit(&res, len, "reader");
+                }
+                res
             }
             Some(PartialOp::Err(err)) => Err(io::Error::new(
                 err,
                 "error during read, generated by partial-io",
             )),
-            Some(PartialOp::Unlimited) | None => self.inner.read(buf),
+            Some(PartialOp::PendingOnce) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "error during read, generated by partial-io",
+            )),
+            Some(PartialOp::ErrMsg(kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::Unlimited) | None => {
+                let res = self.inner.read(buf);
+                if self.strict_limits {
+                    check_limit(&res, buf.len(), "reader");
+                }
+                res
+            }
+            Some(PartialOp::Delay(_)) => unreachable!("delays are handled by Ops::next"),
+        };
+        let res = match &mut self.result_map {
+            Some(result_map) => {
+                let read = match &res {
+                    Ok(n) => &buf[..*n],
+                    Err(_) => &[],
+                };
+                let res = result_map(res, read);
+                if let Ok(n) = &res {
+                    assert!(
+                        *n <= buf.len(),
+                        "result map returned {} bytes, but the buffer has length {}",
+                        n,
+                        buf.len()
+                    );
+                }
+                res
+            }
+            None => res,
+        };
+        let res = self.ops.track_transfer(res);
+        trace_op(
+            op.as_ref(),
+            Some(buf.len()),
+            res.as_ref().map_err(|err| err.kind()),
+        );
+        if let (Ok(n), Some(reorder)) = (&res, &mut self.reorder) {
+            reorder.shuffle(&mut buf[..*n]);
+        }
+        if let (Ok(n), Some(duplicate)) = (&res, &mut self.duplicate) {
+            duplicate.last_chunk.clear();
+            duplicate.last_chunk.extend_from_slice(&buf[..*n]);
+        }
+        if let (Ok(n), Some(captured)) = (&res, &mut self.captured) {
+            captured.extend_from_slice(&buf[..*n]);
+        }
+        if let Some(observer) = &mut self.observer {
+            let transferred = *res.as_ref().unwrap_or(&0);
+            observer.observe(op.as_ref(), buf.len(), transferred);
+        }
+        res
+    }
+
+    /// Applies a single `PartialOp` to the read as a whole, so a `PartialOp::Limited(n)` op caps
+    /// the total number of bytes read across all of `bufs`.
+    ///
+    /// Reordering, duplication and result maps work on the bytes read as a single slice. If any
+    /// of them is set, only the first nonempty buffer is read into, the same as the default
+    /// implementation of `read_vectored`.
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        if self.reorder.is_some() || self.duplicate.is_some() || self.result_map.is_some() {
+            let buf = bufs
+                .iter_mut()
+                .find(|buf| !buf.is_empty())
+                .map_or(&mut [][..], |buf| &mut **buf);
+            return self.read(buf);
+        }
+        if self.captured.is_none()
+            && self.observer.is_none()
+            && (self.ops.is_exhausted() || self.ops.take_unlimited())
+        {
+            // Fast path: the ops have run out or the next one is Unlimited, so there's nothing to
+            // do but forward to the inner reader.
+            let res = self.inner.read_vectored(bufs);
+            return self.ops.track_transfer(res);
+        }
+
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Limited(n)) => {
+                let mut len = cmp::min(n, total);
+                if self.avoid_power_of_two {
+                    len = avoid_power_of_two(len);
+                }
+                let res = if len == total {
+                    self.inner.read_vectored(bufs)
+                } else {
+                    self.inner
+                        .read_vectored(&mut limit_io_slices_mut(bufs, len))
+                };
+                if self.strict_limits {
+                    check_limit(&res, len, "reader");
+                }
+                res
+            }
+            Some(PartialOp::Err(err)) => Err(io::Error::new(
+                err,
+                "error during read_vectored, generated by partial-io",
+            )),
+            Some(PartialOp::PendingOnce) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "error during read_vectored, generated by partial-io",
+            )),
+            Some(PartialOp::ErrMsg(kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::Unlimited) | None => {
+                let res = self.inner.read_vectored(bufs);
+                if self.strict_limits {
+                    check_limit(&res, total, "reader");
+                }
+                res
+            }
+            Some(PartialOp::Delay(_)) => unreachable!("delays are handled by Ops::next"),
+        };
+        let res = self.ops.track_transfer(res);
+        trace_op(
+            op.as_ref(),
+            Some(total),
+            res.as_ref().map_err(|err| err.kind()),
+        );
+        if let (Ok(n), Some(captured)) = (&res, &mut self.captured) {
+            let mut left = *n;
+            for buf in bufs.iter() {
+                let len = cmp::min(buf.len(), left);
+                captured.extend_from_slice(&buf[..len]);
+                left -= len;
+            }
+        }
+        if let Some(observer) = &mut self.observer {
+            let transferred = *res.as_ref().unwrap_or(&0);
+            observer.observe(op.as_ref(), total, transferred);
         }
+        res
+    }
+}
+
+/// Each call to `fill_buf` consumes a `PartialOp`, and a `PartialOp::Limited(n)` op truncates the
+/// buffer it returns to at most `n` bytes. `consume` is forwarded to the inner reader.
+///
+/// The buffer returned by `fill_buf` is borrowed from the inner reader, so reordering,
+/// duplication, result maps and capturing don't apply to it. Note that an empty buffer signals
+/// end-of-stream to callers, so `PartialOp::Limited(0)` behaves the same as `PartialOp::Eof` here.
+impl<R, I> BufRead for PartialRead<R, I>
+where
+    R: BufRead,
+    I: Iterator<Item = PartialOp>,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.ops.is_exhausted() || self.ops.take_unlimited() {
+            // Fast path: the ops have run out or the next one is Unlimited, so there's nothing to
+            // do but forward to the inner reader.
+            return self.inner.fill_buf();
+        }
+
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Limited(n)) => self
+                .inner
+                .fill_buf()
+                .map(|buf| &buf[..cmp::min(n, buf.len())]),
+            Some(PartialOp::Err(err)) => Err(io::Error::new(
+                err,
+                "error during fill_buf, generated by partial-io",
+            )),
+            Some(PartialOp::PendingOnce) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "error during fill_buf, generated by partial-io",
+            )),
+            Some(PartialOp::ErrMsg(kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
+            Some(PartialOp::Eof) => Ok(&[][..]),
+            Some(PartialOp::Unlimited) | None => self.inner.fill_buf(),
+            Some(PartialOp::Delay(_)) => unreachable!("delays are handled by Ops::next"),
+        };
+        trace_op(
+            op.as_ref(),
+            None,
+            res.as_ref().map(|buf| buf.len()).map_err(|err| err.kind()),
+        );
+        res
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.ops.add_transferred(amt);
+        self.inner.consume(amt)
     }
 }
 
 // Forwarding impl to support duplex structs.
-impl<R> Write for PartialRead<R>
+impl<R, I> Write for PartialRead<R, I>
 where
     R: Read + Write,
+    I: Iterator<Item = PartialOp>,
 {
     #[inline]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
@@ -112,14 +974,31 @@ where
     }
 }
 
-impl<R> fmt::Debug for PartialRead<R>
+/// Seeks are forwarded to the inner reader without consuming a `PartialOp`, so the position
+/// within the ops is unaffected.
+impl<R, I> Seek for PartialRead<R, I>
+where
+    R: Seek,
+    I: Iterator<Item = PartialOp>,
+{
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<R, I> fmt::Debug for PartialRead<R, I>
 where
     R: fmt::Debug,
+    I: Iterator<Item = PartialOp>,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("PartialRead")
-            .field("inner", &self.inner)
-            .finish()
+        let mut f = f.debug_struct("PartialRead");
+        f.field("inner", &self.inner);
+        if let Some(phase) = self.ops.current_phase() {
+            f.field("phase", &format_args!("{}", phase));
+        }
+        f.finish()
     }
 }
 
@@ -135,4 +1014,900 @@ mod tests {
     fn test_sendable() {
         assert_send::<PartialRead<File>>();
     }
+
+    #[test]
+    fn test_duplicate_prob() {
+        use std::io::Cursor;
+
+        let data: Vec<u8> = (0..200).collect();
+        let mut partial_read = PartialRead::new(
+            Cursor::new(data.clone()),
+            iter::repeat(PartialOp::Limited(7)),
+        );
+        partial_read.set_duplicate_prob(Some((0.3, 11)));
+
+        // A consumer that knows each byte is one more than the last, so it can skip duplicates.
+        let mut out = Vec::new();
+        let mut chunks = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            let n = partial_read.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            chunks.push(buf[..n].to_vec());
+            for &byte in &buf[..n] {
+                if out.last().map_or(true, |&last| byte > last) {
+                    out.push(byte);
+                }
+            }
+        }
+        assert_eq!(out, data, "dedup-aware consumer reconstructs the stream");
+
+        let duplicates = chunks.windows(2).filter(|w| w[0] == w[1]).count();
+        assert!(duplicates > 0, "some chunks were delivered twice");
+        assert_eq!(
+            chunks.len() - duplicates,
+            (data.len() + 6) / 7,
+            "every other chunk is new"
+        );
+    }
+
+    #[test]
+    fn test_strict_limits() {
+        use std::io::Cursor;
+
+        // An inner reader that claims to have read more than it was asked to.
+        struct Overreader;
+
+        impl Read for Overreader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                Ok(buf.len() + 1)
+            }
+        }
+
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Unlimited,
+            PartialOp::Limited(100),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(vec![0; 64]), ops);
+        partial_read.set_strict_limits(true);
+        let mut buf = [0; 16];
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 3);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 16);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 16);
+
+        let mut partial_read = PartialRead::new(Overreader, vec![PartialOp::Limited(3)]);
+        partial_read.set_strict_limits(true);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = partial_read.read(&mut buf);
+        }));
+        let message = res.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(
+            *message,
+            "inner reader transferred 4 bytes, exceeding the limit of 3"
+        );
+
+        // Without strict limits, the violation goes unnoticed.
+        let mut partial_read = PartialRead::new(Overreader, vec![PartialOp::Limited(3)]);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_one_then_fail() {
+        use std::io::Cursor;
+
+        let mut partial_read =
+            PartialRead::one_then_fail(Cursor::new(b"Hello".to_vec()), io::ErrorKind::BrokenPipe);
+        let mut buf = [0; 8];
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], b'H');
+        for _ in 0..10 {
+            assert_eq!(
+                partial_read.read(&mut buf).unwrap_err().kind(),
+                io::ErrorKind::BrokenPipe
+            );
+        }
+        assert_eq!(
+            partial_read.get_ref().position(),
+            1,
+            "no further bytes read"
+        );
+    }
+
+    #[test]
+    fn test_header_then_chunk() {
+        use std::io::Cursor;
+
+        let data = b"HDR:0005Hello".to_vec();
+        let mut partial_read = PartialRead::header_then_chunk(
+            Cursor::new(data),
+            8,
+            iter::repeat(PartialOp::Limited(2)),
+        );
+        let mut buf = [0; 64];
+        let n = partial_read.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"HDR:0005", "header arrives whole");
+
+        let mut chunks = Vec::new();
+        loop {
+            let n = partial_read.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            chunks.push(buf[..n].to_vec());
+        }
+        assert_eq!(chunks, vec![b"He".to_vec(), b"ll".to_vec(), b"o".to_vec()]);
+
+        let mut partial_read = PartialRead::header_then_chunk(
+            Cursor::new(b"abc".to_vec()),
+            0,
+            vec![PartialOp::Limited(1)],
+        );
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 1, "no header op");
+    }
+
+    #[test]
+    fn test_avoid_power_of_two() {
+        use std::io::Cursor;
+
+        let limits = [1, 2, 3, 4, 5, 8, 16, 17, 32, 64];
+        let ops: Vec<_> = limits.iter().map(|&n| PartialOp::Limited(n)).collect();
+        let mut partial_read = PartialRead::new(Cursor::new(vec![0; 1024]), ops);
+        partial_read.set_avoid_power_of_two(true);
+
+        let mut buf = [0; 64];
+        let lens: Vec<_> = limits
+            .iter()
+            .map(|_| partial_read.read(&mut buf).unwrap())
+            .collect();
+        assert_eq!(lens, vec![1, 1, 3, 3, 5, 7, 15, 17, 31, 63]);
+        for &len in &lens[1..] {
+            assert!(
+                !len.is_power_of_two() || len == 1,
+                "{} is a power of two",
+                len
+            );
+        }
+
+        // The caller's buffer length is adjusted too.
+        partial_read.set_ops(vec![PartialOp::Limited(100)]);
+        assert_eq!(partial_read.read(&mut buf[..16]).unwrap(), 15);
+        // Unlimited reads are left alone.
+        assert_eq!(partial_read.read(&mut buf[..16]).unwrap(), 16);
+    }
+
+    #[test]
+    fn test_phased_ops() {
+        use std::io::Cursor;
+
+        let schedule = PhasedSchedule::new()
+            .phase(
+                "handshake",
+                vec![PartialOp::Limited(2), PartialOp::Limited(3)],
+            )
+            .phase(
+                "data",
+                vec![
+                    PartialOp::Limited(4),
+                    PartialOp::Err(io::ErrorKind::Interrupted),
+                    PartialOp::Err(io::ErrorKind::BrokenPipe),
+                ],
+            );
+        let mut partial_read = PartialRead::new(Cursor::new(vec![0; 64]), vec![]);
+        partial_read.set_phased_ops(schedule.clone());
+        assert_eq!(partial_read.current_phase(), None, "no ops consumed yet");
+
+        let mut buf = [0; 16];
+        let mut phases = Vec::new();
+        for i in 0..schedule.len() {
+            let _ = partial_read.read(&mut buf);
+            let phase = partial_read.current_phase().unwrap();
+            assert_eq!(Some(&phase), schedule.position(i).as_ref());
+            phases.push(phase.to_string());
+        }
+        assert_eq!(
+            phases,
+            vec![
+                "phase 'handshake' at op 0",
+                "phase 'handshake' at op 1",
+                "phase 'data' at op 0",
+                "phase 'data' at op 1",
+                "phase 'data' at op 2",
+            ]
+        );
+        assert!(format!("{:?}", partial_read).contains("phase: phase 'data' at op 2"));
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            partial_read.assert_op_count(4);
+        }));
+        let message = res.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(
+            *message,
+            "expected 4 PartialOps to be consumed, but 5 were (last consumed: phase 'data' at op 2)"
+        );
+
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 16);
+        assert_eq!(
+            partial_read.current_phase().unwrap().to_string(),
+            "phase 'data' at op 2",
+            "the last op is reported once the schedule has run out"
+        );
+
+        partial_read.set_ops(vec![PartialOp::Unlimited]);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 16);
+        assert_eq!(partial_read.current_phase(), None, "set_ops stops tracking");
+    }
+
+    #[test]
+    fn test_intra_chunk_reorder() {
+        use std::io::Cursor;
+
+        // An order-sensitive "parser": every byte must be one more than the previous one.
+        fn parse(data: &[u8]) -> Result<(), usize> {
+            match data.windows(2).position(|w| w[1] != w[0].wrapping_add(1)) {
+                Some(pos) => Err(pos + 1),
+                None => Ok(()),
+            }
+        }
+
+        let data: Vec<u8> = (0..64).collect();
+        let read_all = |seed| {
+            let mut partial_read = PartialRead::new(
+                Cursor::new(data.clone()),
+                iter::repeat(PartialOp::Limited(8)),
+            );
+            partial_read.set_intra_chunk_reorder(seed);
+            let mut out = Vec::new();
+            let mut buf = [0; 16];
+            loop {
+                let n = partial_read.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                assert_eq!(n, 8, "chunk boundaries are preserved");
+                out.extend_from_slice(&buf[..n]);
+            }
+            out
+        };
+
+        assert_eq!(read_all(None), data);
+        assert_eq!(parse(&read_all(None)), Ok(()));
+
+        let reordered = read_all(Some(1));
+        assert_ne!(reordered, data, "bytes were reordered");
+        assert_eq!(reordered, read_all(Some(1)), "reordering is deterministic");
+        for (chunk, expected) in reordered.chunks(8).zip(data.chunks(8)) {
+            let mut chunk = chunk.to_vec();
+            chunk.sort_unstable();
+            assert_eq!(chunk, expected, "bytes stay within their chunk");
+        }
+        assert!(parse(&reordered).is_err(), "parser detects corruption");
+    }
+
+    #[test]
+    fn test_capture() {
+        let data = b"Hello, world!".to_vec();
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(1),
+            PartialOp::Limited(5),
+        ];
+        let mut partial_read = PartialRead::new(io::Cursor::new(data.clone()), ops);
+        partial_read.set_capture(true);
+
+        let mut out = Vec::new();
+        // read_to_end retries on Interrupted.
+        partial_read.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+        assert_eq!(
+            partial_read.take_captured(),
+            data,
+            "captured bytes match source"
+        );
+        assert!(
+            partial_read.take_captured().is_empty(),
+            "capture buffer was taken"
+        );
+
+        partial_read.set_capture(false);
+        assert!(partial_read.take_captured().is_empty(), "capture disabled");
+    }
+
+    #[test]
+    fn test_pinhole() {
+        let data = b"Hello, world!".to_vec();
+        let mut partial_read = PartialRead::pinhole(io::Cursor::new(data.clone()));
+        let mut out = Vec::new();
+        let mut buf = [0; 8];
+        loop {
+            let n = partial_read.read(&mut buf).unwrap();
+            assert!(n <= 1, "read delivered {} bytes", n);
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_interrupt_storm() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut partial_read = PartialRead::interrupt_storm(io::Cursor::new(data.clone()), 5);
+
+        let mut out = Vec::new();
+        let mut buf = [0; 100];
+        loop {
+            match partial_read.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => out.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }
+        assert_eq!(out, data, "no data was lost");
+        // Three reads with data, plus one returning 0.
+        assert_eq!(partial_read.errors_seen().len(), 4 * 5);
+    }
+
+    #[test]
+    fn test_take_inner() {
+        let data = b"Hello, world!".to_vec();
+        let partial_read =
+            PartialRead::new(io::Cursor::new(data), iter::repeat(PartialOp::Limited(3)));
+        let mut partial_read = partial_read.take_inner(7);
+        let mut buf = [0; 8];
+
+        // Both the chunking and the total cap apply.
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 3);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 3);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 1);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 0);
+        assert_eq!(partial_read.get_ref().limit(), 0);
+    }
+
+    #[test]
+    fn test_result_map() {
+        use std::sync::{Arc, Mutex};
+
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_read = PartialRead::new(&b"Hello, world!"[..], ops);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen2 = seen.clone();
+        partial_read
+            .set_capture(true)
+            .set_result_map(move |res, read: &[u8]| {
+                seen2.lock().unwrap().push(read.to_vec());
+                match res {
+                    Ok(n) if read.starts_with(b"lo") => Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("rejected {} bytes", n),
+                    )),
+                    // Turn injected errors into successful empty reads.
+                    Err(_) => Ok(0),
+                    res => res,
+                }
+            });
+
+        let mut buf = [0; 8];
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 3);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 0);
+        let err = partial_read.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "rejected 8 bytes");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [b"Hel".to_vec(), vec![], b"lo, worl".to_vec()]
+        );
+        assert_eq!(
+            partial_read.take_captured(),
+            b"Hel",
+            "only the mapped results are captured"
+        );
+    }
+
+    #[test]
+    fn test_eof() {
+        let ops = vec![PartialOp::Limited(5), PartialOp::Eof];
+        let mut partial_read = PartialRead::new(&b"Hello, world!"[..], ops);
+
+        // read_to_end stops at the first zero-length read...
+        let mut out = Vec::new();
+        assert_eq!(partial_read.read_to_end(&mut out).unwrap(), 5);
+        assert_eq!(out, b"Hello");
+        // ... but the stream carries on afterwards.
+        out.clear();
+        assert_eq!(partial_read.read_to_end(&mut out).unwrap(), 8);
+        assert_eq!(out, b", world!");
+        assert_eq!(format!("{:?}", PartialOp::Eof), "Eof");
+    }
+
+    #[test]
+    fn test_pending_once() {
+        let ops = vec![PartialOp::Limited(5), PartialOp::PendingOnce];
+        let mut partial_read = PartialRead::new(&b"Hello, world!"[..], ops);
+
+        let mut buf = [0; 16];
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 5);
+        // Synchronous wrappers treat PendingOnce as Interrupted...
+        assert_eq!(
+            partial_read.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        // ... which read_exact retries.
+        partial_read.set_ops(vec![PartialOp::PendingOnce]);
+        partial_read.read_exact(&mut buf[..8]).unwrap();
+        assert_eq!(&buf[..8], b", world!");
+    }
+
+    #[test]
+    fn test_delay() {
+        use std::time::{Duration, Instant};
+
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Delay(Duration::from_millis(50)),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_read = PartialRead::new(&b"Hello, world!"[..], ops);
+        let mut buf = [0; 16];
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 2);
+
+        // The delay blocks, then the op after it applies to the same read.
+        let start = Instant::now();
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(&buf[..3], b"llo");
+        partial_read.assert_op_count(3);
+    }
+
+    #[test]
+    fn test_read_vectored() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(6),
+        ];
+        let mut partial_read = PartialRead::new(&b"Hello, world!"[..], ops);
+        partial_read.set_capture(true);
+        let mut first = [0; 4];
+        let mut second = [0; 4];
+
+        let mut bufs = [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)];
+        assert_eq!(partial_read.read_vectored(&mut bufs).unwrap(), 3);
+        assert_eq!(&first, b"Hel\0");
+        assert_eq!(&second, &[0; 4]);
+        assert_eq!(partial_read.consumed_ops(), 1, "one op per vectored read");
+
+        let mut bufs = [IoSliceMut::new(&mut first), IoSliceMut::new(&mut second)];
+        let err = partial_read.read_vectored(&mut bufs).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(partial_read.read_vectored(&mut bufs).unwrap(), 6);
+        assert_eq!(&first, b"lo, ");
+        assert_eq!(&second, b"wo\0\0");
+        assert_eq!(partial_read.take_captured(), b"Hello, wo");
+    }
+
+    #[test]
+    fn test_buf_read() {
+        let mut partial_read = PartialRead::new(
+            &b"foo\nbar\n"[..],
+            vec![
+                PartialOp::Limited(2),
+                PartialOp::Err(io::ErrorKind::Interrupted),
+                PartialOp::Limited(2),
+                PartialOp::Limited(2),
+            ],
+        );
+        let mut line = String::new();
+        assert_eq!(partial_read.read_line(&mut line).unwrap(), 4);
+        assert_eq!(line, "foo\n");
+        assert_eq!(
+            partial_read.consumed_ops(),
+            3,
+            "the line took two fills and a retry"
+        );
+
+        line.clear();
+        assert_eq!(partial_read.read_line(&mut line).unwrap(), 4);
+        assert_eq!(line, "bar\n");
+        assert_eq!(
+            partial_read.consumed_ops(),
+            4,
+            "the rest of the line was read after the ops ran out"
+        );
+        assert_eq!(partial_read.diagnostics().transferred, 8);
+
+        partial_read.set_ops(vec![PartialOp::Err(io::ErrorKind::BrokenPipe)]);
+        let err = partial_read.fill_buf().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_seek() {
+        use std::io::Cursor;
+
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(1),
+        ];
+        let mut partial_read = PartialRead::new(Cursor::new(b"Hello, world!".to_vec()), ops);
+        let mut buf = [0; 16];
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 2);
+
+        // Seeking doesn't consume or reset the ops: the next read applies the second op.
+        assert_eq!(partial_read.seek(SeekFrom::Start(7)).unwrap(), 7);
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"wor");
+
+        assert_eq!(partial_read.seek(SeekFrom::Start(0)).unwrap(), 0);
+        assert_eq!(
+            partial_read.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"H");
+        partial_read.assert_op_count(4);
+    }
+
+    #[test]
+    fn test_fork_schedule() {
+        let data: Vec<u8> = (0..32).collect();
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(5),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Limited(1),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_read = PartialRead::new(&data[..], ops);
+        let mut buf = [0; 8];
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 3);
+        assert_eq!(partial_read.next_limit(), None, "peeked op is forked too");
+
+        let forked = partial_read.fork_schedule();
+        assert_eq!(forked.len(), 5);
+        let mut forked_read = PartialRead::new(&data[3..], forked);
+
+        let mut outcomes = Vec::new();
+        for partial_read in [&mut partial_read, &mut forked_read] {
+            let mut outcome = Vec::new();
+            loop {
+                match partial_read.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => outcome.push(Ok(buf[..n].to_vec())),
+                    Err(err) => outcome.push(Err(err.kind())),
+                }
+            }
+            outcomes.push(outcome);
+        }
+        assert_eq!(outcomes[0], outcomes[1], "both proceed identically");
+        assert_eq!(outcomes[0].len(), 7);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(1),
+        ];
+        let mut partial_read = PartialRead::new(io::repeat(42), ops);
+        let mut buf = [0; 8];
+
+        assert_eq!(partial_read.read(&mut buf).unwrap(), 2);
+        let snapshot = partial_read.snapshot();
+
+        for _ in 0..2 {
+            assert_eq!(partial_read.read(&mut buf).unwrap(), 3);
+            assert!(partial_read.read(&mut buf).is_err());
+            assert_eq!(partial_read.read(&mut buf).unwrap(), 1);
+            assert_eq!(partial_read.errors_seen().len(), 1);
+
+            // Go back to the position right after the first read.
+            partial_read.restore(&snapshot);
+            assert!(partial_read.errors_seen().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_next_limit() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Unlimited,
+            PartialOp::Limited(5),
+        ];
+        let mut partial_read = PartialRead::new(io::repeat(42), ops);
+        let mut out = [0; 16];
+
+        assert_eq!(partial_read.next_limit(), Some(2));
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+        assert_eq!(partial_read.next_limit(), None);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 16);
+        assert_eq!(partial_read.next_limit(), Some(5));
+        assert_eq!(
+            partial_read.next_limit(),
+            Some(5),
+            "next_limit doesn't consume"
+        );
+        assert_eq!(partial_read.read(&mut out).unwrap(), 5);
+        assert_eq!(partial_read.next_limit(), None);
+    }
+
+    #[test]
+    fn test_unlimited_cap() {
+        let ops = vec![PartialOp::Unlimited, PartialOp::Limited(64)];
+        let mut partial_read = PartialRead::new(io::repeat(42), ops);
+        partial_read.set_unlimited_cap(Some(8));
+        let mut out = [0; 128];
+
+        assert_eq!(
+            partial_read.read(&mut out).unwrap(),
+            8,
+            "Unlimited is capped"
+        );
+        assert_eq!(
+            partial_read.read(&mut out).unwrap(),
+            64,
+            "Limited above the cap is untouched"
+        );
+        assert_eq!(partial_read.read(&mut out).unwrap(), 8);
+
+        partial_read.set_unlimited_cap(None);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 128);
+    }
+
+    #[test]
+    fn test_on_op() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_read = PartialRead::new(&b"Hello, world!"[..], ops);
+        partial_read.on_op(move |event| events2.lock().unwrap().push(event));
+
+        let mut out = [0; 8];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        partial_read.read(&mut out[3..]).unwrap_err();
+        assert_eq!(partial_read.read(&mut out[3..]).unwrap(), 5);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 5);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 0);
+
+        let events = events.lock().unwrap();
+        let fields: Vec<_> = events
+            .iter()
+            .map(|e| (e.op.clone(), e.offset, e.buf_len, e.transferred))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                (PartialOp::Limited(3), 0, 8, 3),
+                (PartialOp::Err(io::ErrorKind::Interrupted), 3, 5, 0),
+                (PartialOp::Unlimited, 3, 5, 5),
+                // The ops have run out.
+                (PartialOp::Unlimited, 8, 8, 5),
+                (PartialOp::Unlimited, 13, 8, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_on_op_with_clock() {
+        use std::{
+            sync::{Arc, Mutex},
+            time::Duration,
+        };
+
+        let start = Instant::now();
+        let now = Arc::new(Mutex::new(start));
+        let now2 = now.clone();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+
+        let mut partial_read = PartialRead::pinhole(&b"Hello"[..]);
+        partial_read.on_op_with_clock(
+            move |event| events2.lock().unwrap().push(event),
+            move || *now2.lock().unwrap(),
+        );
+
+        let mut out = [0; 4];
+        for _ in 0..3 {
+            assert_eq!(partial_read.read(&mut out).unwrap(), 1);
+            *now.lock().unwrap() += Duration::from_millis(10);
+        }
+
+        let timestamps: Vec<_> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| event.timestamp - start)
+            .collect();
+        assert_eq!(
+            timestamps,
+            [0, 10, 20].map(Duration::from_millis),
+            "timestamps come from the clock"
+        );
+    }
+
+    #[test]
+    fn test_new_unboxed() {
+        let ops = [
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(3),
+        ];
+        // The iterator borrows from a local, so it isn't 'static.
+        let mut partial_read = PartialRead::new_unboxed(&b"Hello, world!"[..], ops.iter().cloned());
+        assert_eq!(partial_read.ops_remaining(), Some(3));
+
+        let mut out = [0; 16];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+        assert_eq!(
+            partial_read.read(&mut out).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        // Once the ops run out, reads are unlimited.
+        assert_eq!(partial_read.read(&mut out).unwrap(), 8);
+        assert_eq!(partial_read.consumed_ops(), 3);
+        assert_eq!(partial_read.errors_seen(), [io::ErrorKind::Interrupted]);
+    }
+
+    #[test]
+    fn test_bytes_transferred() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(3),
+            PartialOp::Limited(4),
+        ];
+        let mut partial_read = PartialRead::new(&b"Hello, world!"[..], ops);
+        let mut out = [0; 16];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+        partial_read.read(&mut out).unwrap_err();
+        assert_eq!(partial_read.bytes_transferred(), 2, "errors don't count");
+
+        // Bytes returned by fill_buf only count once they're consumed.
+        assert_eq!(partial_read.fill_buf().unwrap(), b"llo");
+        assert_eq!(partial_read.bytes_transferred(), 2);
+        partial_read.consume(1);
+        assert_eq!(partial_read.bytes_transferred(), 3);
+
+        partial_read.set_ops(vec![PartialOp::Limited(4)]);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 4);
+        assert_eq!(
+            partial_read.bytes_transferred(),
+            7,
+            "kept when ops are replaced"
+        );
+    }
+
+    #[test]
+    fn test_bytes_transferred_duplicates() {
+        let mut partial_read = PartialRead::new(&b"Hello"[..], vec![PartialOp::Limited(2)]);
+        // Always replay the last chunk.
+        partial_read.set_duplicate_prob(Some((1.0, 0)));
+        let mut out = [0; 16];
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+        assert_eq!(partial_read.read(&mut out).unwrap(), 2);
+        assert_eq!(&out[..2], b"He", "the chunk was replayed");
+        assert_eq!(partial_read.bytes_transferred(), 2, "replays don't count");
+        assert_eq!(partial_read.read(&mut out).unwrap(), 3);
+        assert_eq!(partial_read.bytes_transferred(), 5);
+    }
+
+    #[test]
+    fn test_clone() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(3),
+        ];
+        let mut first = PartialRead::new_repeatable(&b"Hello, world!"[..], ops);
+        first.set_unlimited_cap(Some(4));
+        let mut out = [0; 16];
+        assert_eq!(first.read(&mut out).unwrap(), 2);
+        first.read(&mut out).unwrap_err();
+
+        // The clone starts over from the first op, but keeps the settings.
+        let mut second = first.clone();
+        assert_eq!(second.consumed_ops(), 0);
+        assert_eq!(second.bytes_transferred(), 0);
+        assert_eq!(second.errors_seen(), []);
+        assert_eq!(second.ops_remaining(), Some(3));
+
+        let mut replay = |partial_read: &mut PartialRead<_, _>| {
+            let mut reads = Vec::new();
+            loop {
+                match partial_read.read(&mut out) {
+                    Ok(0) => return reads,
+                    Ok(n) => reads.push(n),
+                    Err(err) => assert_eq!(err.kind(), io::ErrorKind::Interrupted),
+                }
+            }
+        };
+        // The reader itself is cloned as it is, so 11 bytes are left.
+        assert_eq!(replay(&mut second), [2, 3, 4, 2]);
+        // Clones can be pointed at a different reader, to compare readers under the same ops.
+        let mut third = second.clone();
+        *third.get_mut() = &b"Goodbye!"[..];
+        assert_eq!(replay(&mut third), [2, 3, 3]);
+        assert_eq!(replay(&mut first), [3, 4, 4]);
+    }
+
+    #[test]
+    fn test_unlimited_fast_path() {
+        let data = b"Hello, world! Goodbye, world!";
+        let ops = vec![
+            PartialOp::Unlimited,
+            PartialOp::Unlimited,
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+            PartialOp::Eof,
+            PartialOp::Unlimited,
+            PartialOp::Unlimited,
+        ];
+        // Runs of Unlimited ops take the fast path.
+        let mut fast = PartialRead::new(&data[..], ops.clone());
+        // A cap of usize::MAX turns Unlimited ops into limits that don't change anything, but
+        // makes them take the general path.
+        let mut general = PartialRead::new(&data[..], ops);
+        general.set_unlimited_cap(Some(usize::MAX));
+
+        for i in 0..12 {
+            if i % 4 == 3 {
+                let fast_res = fast.fill_buf().map(|buf| buf.to_vec());
+                let general_res = general.fill_buf().map(|buf| buf.to_vec());
+                let amt = fast_res.as_ref().map_or(0, |buf| cmp::min(buf.len(), 1));
+                assert_eq!(
+                    fast_res.map_err(|err| err.kind()),
+                    general_res.map_err(|err| err.kind()),
+                    "fill_buf call {}",
+                    i
+                );
+                fast.consume(amt);
+                general.consume(amt);
+            } else {
+                let mut fast_buf = [0; 4];
+                let mut general_buf = [0; 4];
+                let fast_res = fast.read(&mut fast_buf).map_err(|err| err.kind());
+                let general_res = general.read(&mut general_buf).map_err(|err| err.kind());
+                assert_eq!(fast_res, general_res, "read call {}", i);
+                assert_eq!(fast_buf, general_buf, "read call {}", i);
+            }
+            assert_eq!(fast.consumed_ops(), general.consumed_ops(), "call {}", i);
+            assert_eq!(fast.bytes_transferred(), general.bytes_transferred());
+        }
+
+        let mut fast_bufs = [[0; 2]; 2];
+        let mut general_bufs = [[0; 2]; 2];
+        let [fast_a, fast_b] = &mut fast_bufs;
+        let [general_a, general_b] = &mut general_bufs;
+        assert_eq!(
+            fast.read_vectored(&mut [IoSliceMut::new(fast_a), IoSliceMut::new(fast_b)])
+                .unwrap(),
+            general
+                .read_vectored(&mut [IoSliceMut::new(general_a), IoSliceMut::new(general_b)])
+                .unwrap(),
+        );
+        assert_eq!(fast_bufs, general_bufs);
+        assert_eq!(fast.bytes_transferred(), general.bytes_transferred());
+    }
 }
@@ -0,0 +1,103 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! A small, dependency-free pseudo-random number generator.
+//!
+//! This is used where deterministic randomness is needed without the optional `rand` dependency.
+//! It is not suitable for anything but testing.
+
+use std::ops::RangeInclusive;
+
+/// The SplitMix64 generator, as described in "Fast Splittable Pseudorandom Number Generators"
+/// by Steele, Lea and Flood.
+#[derive(Clone, Debug)]
+pub(crate) struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// Creates a new generator from a seed. The same seed always produces the same sequence.
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`. `n` must be non-zero.
+    ///
+    /// This uses a plain modulo, so the result is very slightly biased for large `n`.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Returns a number in `range`.
+    pub(crate) fn in_range(&mut self, range: &RangeInclusive<usize>) -> usize {
+        let start = *range.start();
+        // This can only overflow for 0..=usize::MAX, where every value is fine.
+        match (range.end() - start).checked_add(1) {
+            Some(n) => start + self.below(n),
+            None => self.next_u64() as usize,
+        }
+    }
+
+    /// Returns a number in `0.0..1.0`.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        // 53 bits of randomness, the precision of an f64 mantissa.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns true with probability `p`, which is clamped to `0.0..=1.0`.
+    pub(crate) fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    /// Shuffles `slice` in place with a Fisher-Yates shuffle.
+    pub(crate) fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.below(i + 1);
+            slice.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic() {
+        let mut a = SplitMix64::new(42);
+        let mut b = SplitMix64::new(42);
+        for _ in 0..16 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        // Reference value for seed 0.
+        assert_eq!(SplitMix64::new(0).next_u64(), 0xe220_a839_7b1d_cdaf);
+
+        let mut data: Vec<_> = (0..32).collect();
+        SplitMix64::new(7).shuffle(&mut data);
+        let mut sorted = data.clone();
+        sorted.sort_unstable();
+        assert_eq!(
+            sorted,
+            (0..32).collect::<Vec<_>>(),
+            "shuffle is a permutation"
+        );
+        assert_ne!(data, sorted);
+
+        let mut rng = SplitMix64::new(3);
+        assert!((0..100).all(|_| rng.chance(1.0)));
+        assert!((0..100).all(|_| !rng.chance(0.0)));
+        let hits = (0..10_000).filter(|_| rng.chance(0.25)).count();
+        assert!((2000..3000).contains(&hits), "{} hits", hits);
+
+        assert!((0..100).all(|_| (3..=5).contains(&rng.in_range(&(3..=5)))));
+        assert_eq!(rng.in_range(&(4..=4)), 4);
+    }
+}
@@ -0,0 +1,818 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains helpers for building sequences of `PartialOp`s.
+
+#[cfg(feature = "futures03")]
+use crate::rng::SplitMix64;
+use crate::PartialOp;
+#[cfg(feature = "futures03")]
+use std::io;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::{Bound, RangeBounds},
+};
+
+/// A builder for sequences of `PartialOp`s, expressed in terms of ranges of operation indexes.
+///
+/// Each call to [`range`](Self::range) assigns an op to every operation index within the range.
+/// If ranges overlap, the range added last wins. Indexes not covered by any range are assigned the
+/// default op, which is `PartialOp::Unlimited` unless changed with
+/// [`default_op`](Self::default_op).
+///
+/// If every range has an end bound, the schedule ends after the largest end bound. A range
+/// without an end bound (e.g. `10..`) makes the schedule infinite.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{PartialOp, RangeSchedule};
+/// use std::io;
+///
+/// let schedule = RangeSchedule::new()
+///     .range(0..2, PartialOp::Unlimited)
+///     .range(2..4, PartialOp::Err(io::ErrorKind::WouldBlock))
+///     .range(4.., PartialOp::Limited(1));
+///
+/// let ops: Vec<_> = schedule.into_iter().take(5).collect();
+/// assert_eq!(
+///     ops,
+///     vec![
+///         PartialOp::Unlimited,
+///         PartialOp::Unlimited,
+///         PartialOp::Err(io::ErrorKind::WouldBlock),
+///         PartialOp::Err(io::ErrorKind::WouldBlock),
+///         PartialOp::Limited(1),
+///     ],
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct RangeSchedule {
+    // (start, end, op), with end being exclusive.
+    ranges: Vec<(usize, Option<usize>, PartialOp)>,
+    default: PartialOp,
+}
+
+impl RangeSchedule {
+    /// Creates a new, empty `RangeSchedule`.
+    pub fn new() -> Self {
+        Self {
+            ranges: Vec::new(),
+            default: PartialOp::Unlimited,
+        }
+    }
+
+    /// Assigns `op` to every operation index within `range`.
+    ///
+    /// Later ranges take precedence over earlier ones where they overlap.
+    pub fn range(mut self, range: impl RangeBounds<usize>, op: PartialOp) -> Self {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => Some(end + 1),
+            Bound::Excluded(&end) => Some(end),
+            Bound::Unbounded => None,
+        };
+        self.ranges.push((start, end, op));
+        self
+    }
+
+    /// Sets the op used for indexes not covered by any range.
+    ///
+    /// Defaults to `PartialOp::Unlimited`.
+    pub fn default_op(mut self, op: PartialOp) -> Self {
+        self.default = op;
+        self
+    }
+
+    /// Returns the op assigned to the operation at `index`.
+    pub fn op_at(&self, index: usize) -> PartialOp {
+        self.ranges
+            .iter()
+            .rev()
+            .find(|(start, end, _)| *start <= index && end.map_or(true, |end| index < end))
+            .map_or_else(|| self.default.clone(), |(_, _, op)| op.clone())
+    }
+
+    /// Returns the number of ops in this schedule, or `None` if it is infinite.
+    pub fn len(&self) -> Option<usize> {
+        self.ranges
+            .iter()
+            .try_fold(0, |len, (_, end, _)| end.map(|end| len.max(end)))
+    }
+
+    /// Returns true if this schedule contains no ops.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+impl Default for RangeSchedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IntoIterator for RangeSchedule {
+    type Item = PartialOp;
+    type IntoIter = RangeScheduleIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let len = self.len();
+        RangeScheduleIter {
+            schedule: self,
+            index: 0,
+            len,
+        }
+    }
+}
+
+/// An iterator over the ops in a [`RangeSchedule`].
+#[derive(Clone, Debug)]
+pub struct RangeScheduleIter {
+    schedule: RangeSchedule,
+    index: usize,
+    len: Option<usize>,
+}
+
+impl Iterator for RangeScheduleIter {
+    type Item = PartialOp;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len.map_or(false, |len| self.index >= len) {
+            return None;
+        }
+        let op = self.schedule.op_at(self.index);
+        self.index += 1;
+        Some(op)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.len {
+            Some(len) => {
+                let remaining = len.saturating_sub(self.index);
+                (remaining, Some(remaining))
+            }
+            None => (usize::MAX, None),
+        }
+    }
+}
+
+/// The hard errors injected by [`chaos_ops`].
+#[cfg(feature = "futures03")]
+const CHAOS_ERRORS: &[io::ErrorKind] = &[
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::Other,
+];
+
+/// Returns an infinite, deterministic sequence of mixed `PartialOp`s derived from `seed`.
+#[cfg(feature = "futures03")]
+///
+/// Each op is independently, with probability:
+///
+/// * 40%: `PartialOp::Limited(n)` with `n` in `1..=32`.
+/// * 20%: `PartialOp::Unlimited`.
+/// * 20%: `PartialOp::Err(Interrupted)`.
+/// * 17%: `PartialOp::Err(WouldBlock)`.
+/// * 3%: `PartialOp::Err` with one of `CHAOS_ERRORS`.
+pub(crate) fn chaos_ops(seed: u64) -> impl Iterator<Item = PartialOp> + Send {
+    let mut rng = SplitMix64::new(seed);
+    std::iter::from_fn(move || {
+        let op = match rng.below(100) {
+            0..=39 => PartialOp::Limited(1 + rng.below(32)),
+            40..=59 => PartialOp::Unlimited,
+            60..=79 => PartialOp::Err(io::ErrorKind::Interrupted),
+            80..=96 => PartialOp::Err(io::ErrorKind::WouldBlock),
+            _ => PartialOp::Err(CHAOS_ERRORS[rng.below(CHAOS_ERRORS.len())]),
+        };
+        Some(op)
+    })
+}
+
+/// Returns an iterator that repeats a sequence of `PartialOp`s `times` times.
+///
+/// The iterator is lazy: the ops are not copied up front.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{repeat_schedule, PartialOp};
+/// use std::io;
+///
+/// // (L1, WB) x 3, then U.
+/// let ops: Vec<_> = repeat_schedule(
+///     vec![PartialOp::Limited(1), PartialOp::Err(io::ErrorKind::WouldBlock)],
+///     3,
+/// )
+/// .chain(std::iter::once(PartialOp::Unlimited))
+/// .collect();
+/// assert_eq!(ops.len(), 7);
+/// ```
+pub fn repeat_schedule(
+    ops: Vec<PartialOp>,
+    times: usize,
+) -> impl Iterator<Item = PartialOp> + Clone + Send {
+    let len = ops.len().saturating_mul(times);
+    ops.into_iter().cycle().take(len)
+}
+
+/// A builder for sequences of `PartialOp`s, expressed in terms of individual call indexes.
+///
+/// Each call to [`at`](Self::at) assigns an op to a single call index, and
+/// [`build`](Self::build) produces a schedule of a given length, with every other index assigned
+/// the default op. The default op is `PartialOp::Unlimited` unless changed with
+/// [`default_op`](Self::default_op). For ops that apply to runs of calls, see
+/// [`RangeSchedule`].
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{IndexedSchedule, PartialOp};
+/// use std::io;
+///
+/// let ops = IndexedSchedule::new()
+///     .at(3, PartialOp::Err(io::ErrorKind::Interrupted))
+///     .at(5, PartialOp::Limited(2))
+///     .build(6);
+/// assert_eq!(
+///     ops,
+///     vec![
+///         PartialOp::Unlimited,
+///         PartialOp::Unlimited,
+///         PartialOp::Unlimited,
+///         PartialOp::Err(io::ErrorKind::Interrupted),
+///         PartialOp::Unlimited,
+///         PartialOp::Limited(2),
+///     ],
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct IndexedSchedule {
+    ops: BTreeMap<usize, PartialOp>,
+    default: PartialOp,
+}
+
+impl IndexedSchedule {
+    /// Creates a new, empty `IndexedSchedule`.
+    pub fn new() -> Self {
+        Self {
+            ops: BTreeMap::new(),
+            default: PartialOp::Unlimited,
+        }
+    }
+
+    /// Assigns `op` to the call at `index`, counting from 0.
+    ///
+    /// If an op was already assigned to `index`, it is replaced.
+    pub fn at(mut self, index: usize, op: PartialOp) -> Self {
+        self.ops.insert(index, op);
+        self
+    }
+
+    /// Sets the op for indexes that haven't been assigned one. Defaults to
+    /// `PartialOp::Unlimited`.
+    pub fn default_op(mut self, op: PartialOp) -> Self {
+        self.default = op;
+        self
+    }
+
+    /// Builds a schedule of `len` ops.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an op was assigned to an index that isn't less than `len`, since that op would
+    /// otherwise be silently dropped.
+    pub fn build(&self, len: usize) -> Vec<PartialOp> {
+        if let Some((&index, _)) = self.ops.range(len..).next() {
+            panic!(
+                "op assigned to call index {}, but the schedule has length {}",
+                index, len
+            );
+        }
+        (0..len)
+            .map(|i| self.ops.get(&i).unwrap_or(&self.default).clone())
+            .collect()
+    }
+}
+
+impl Default for IndexedSchedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A sequence of `PartialOp`s grouped into named phases, for more readable failure messages.
+///
+/// Pass a `PhasedSchedule` to the `set_phased_ops` method on a wrapper. The wrapper then keeps
+/// track of which phase the last consumed op belongs to, which is reported by its
+/// `current_phase` method, its `Debug` output and its `assert_op_count` failure messages.
+///
+/// A `PhasedSchedule` can also be used anywhere a sequence of `PartialOp`s is expected, but then
+/// the phase names are lost.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{PartialOp, PartialRead, PhasedSchedule};
+/// use std::io::{self, Cursor, Read};
+///
+/// let schedule = PhasedSchedule::new()
+///     .phase("handshake", vec![PartialOp::Limited(2)])
+///     .phase(
+///         "data",
+///         vec![
+///             PartialOp::Limited(4),
+///             PartialOp::Err(io::ErrorKind::BrokenPipe),
+///         ],
+///     );
+///
+/// let mut partial_read = PartialRead::new(Cursor::new(b"Hello, world!".to_vec()), vec![]);
+/// partial_read.set_phased_ops(schedule);
+/// let mut buf = [0; 16];
+/// assert_eq!(partial_read.read(&mut buf).unwrap(), 2);
+/// assert_eq!(partial_read.read(&mut buf).unwrap(), 4);
+/// partial_read.read(&mut buf).unwrap_err();
+/// assert_eq!(
+///     partial_read.current_phase().unwrap().to_string(),
+///     "phase 'data' at op 1",
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PhasedSchedule {
+    phases: Vec<(String, Vec<PartialOp>)>,
+}
+
+impl PhasedSchedule {
+    /// Creates a new, empty `PhasedSchedule`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a phase named `name`, consisting of `ops`.
+    pub fn phase(
+        mut self,
+        name: impl Into<String>,
+        ops: impl IntoIterator<Item = PartialOp>,
+    ) -> Self {
+        self.phases.push((name.into(), ops.into_iter().collect()));
+        self
+    }
+
+    /// Returns the total number of ops across all phases.
+    pub fn len(&self) -> usize {
+        self.phases.iter().map(|(_, ops)| ops.len()).sum()
+    }
+
+    /// Returns true if there are no ops in any phase.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the phase that the op at `index` (counting from the start of the schedule) belongs
+    /// to, and the op's index within that phase.
+    ///
+    /// Returns `None` if `index` is past the end of the schedule.
+    pub fn position(&self, index: usize) -> Option<PhasePosition> {
+        locate_phase(
+            self.phases
+                .iter()
+                .map(|(name, ops)| (name.as_str(), ops.len())),
+            index,
+        )
+    }
+
+    /// Splits this schedule into the phase names and lengths, and the flattened ops.
+    pub(crate) fn into_parts(self) -> (Vec<(String, usize)>, Vec<PartialOp>) {
+        let mut phases = Vec::with_capacity(self.phases.len());
+        let mut all_ops = Vec::new();
+        for (name, ops) in self.phases {
+            phases.push((name, ops.len()));
+            all_ops.extend(ops);
+        }
+        (phases, all_ops)
+    }
+}
+
+impl IntoIterator for PhasedSchedule {
+    type Item = PartialOp;
+    type IntoIter = std::vec::IntoIter<PartialOp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_parts().1.into_iter()
+    }
+}
+
+/// The position of an op within a [`PhasedSchedule`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct PhasePosition {
+    /// The name of the phase.
+    pub phase: String,
+
+    /// The index of the op within the phase, starting from 0.
+    pub index: usize,
+}
+
+impl fmt::Display for PhasePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "phase '{}' at op {}", self.phase, self.index)
+    }
+}
+
+/// Finds the phase that the op at `index` belongs to, given phase names and lengths in order.
+pub(crate) fn locate_phase<'a>(
+    phases: impl IntoIterator<Item = (&'a str, usize)>,
+    mut index: usize,
+) -> Option<PhasePosition> {
+    for (name, len) in phases {
+        if index < len {
+            return Some(PhasePosition {
+                phase: name.to_owned(),
+                index,
+            });
+        }
+        index -= len;
+    }
+    None
+}
+
+/// The result of [`schedule_covers`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct CoverageReport {
+    /// Whether the schedule lets the whole transfer through before it runs out of ops.
+    pub complete: bool,
+
+    /// The number of bytes left over after the schedule runs out, or 0 if `complete` is true.
+    pub uncovered: usize,
+
+    /// The number of bytes that `PartialOp::Limited` ops allow in excess of the transfer size.
+    ///
+    /// This is 0 if the transfer is completed by a `PartialOp::Unlimited` op.
+    pub surplus: usize,
+}
+
+impl CoverageReport {
+    /// Returns true if the schedule covers the transfer exactly, with no bytes left over either
+    /// way.
+    pub fn is_exact(&self) -> bool {
+        self.complete && self.surplus == 0
+    }
+}
+
+/// Checks whether the `PartialOp::Limited` sizes in `ops` add up to a transfer of `total` bytes.
+///
+/// Each `PartialOp::Limited(n)` op is assumed to transfer up to `n` bytes, and a
+/// `PartialOp::Unlimited` op is assumed to transfer everything that's left. `PartialOp::Err` ops
+/// don't transfer anything. Once the schedule runs out, the wrappers no longer limit calls, so a
+/// report that isn't `complete` usually means the schedule is missing ops, rather than that the
+/// transfer can't finish.
+///
+/// This is meant to catch off-by-one mistakes in hand-written schedules before running a test
+/// with them.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{schedule_covers, PartialOp};
+/// use std::io;
+///
+/// let ops = [
+///     PartialOp::Limited(5),
+///     PartialOp::Err(io::ErrorKind::Interrupted),
+///     PartialOp::Limited(7),
+/// ];
+/// let report = schedule_covers(&ops, 13);
+/// assert!(!report.complete);
+/// assert_eq!(report.uncovered, 1);
+///
+/// assert!(schedule_covers(&ops, 12).is_exact());
+/// ```
+pub fn schedule_covers(ops: &[PartialOp], total: usize) -> CoverageReport {
+    let mut remaining = total;
+    let mut surplus = 0usize;
+    let mut unlimited = false;
+    for op in ops {
+        match op {
+            PartialOp::Limited(n) => {
+                let n = *n;
+                if unlimited {
+                    surplus = surplus.saturating_add(n);
+                } else if n > remaining {
+                    surplus = surplus.saturating_add(n - remaining);
+                    remaining = 0;
+                } else {
+                    remaining -= n;
+                }
+            }
+            PartialOp::Unlimited => {
+                if remaining > 0 {
+                    unlimited = true;
+                    remaining = 0;
+                }
+            }
+            PartialOp::Err(_)
+            | PartialOp::ErrMsg(..)
+            | PartialOp::Delay(_)
+            | PartialOp::Eof
+            | PartialOp::PendingOnce => {}
+        }
+    }
+    CoverageReport {
+        complete: remaining == 0,
+        uncovered: remaining,
+        surplus: if unlimited { 0 } else { surplus },
+    }
+}
+
+impl PartialOp {
+    /// Returns a schedule that splits a transfer of `total_bytes` into exactly `k` operations.
+    ///
+    /// The returned ops are all `PartialOp::Limited`, with sizes that differ by at most one byte,
+    /// larger chunks first. This makes the number of `read` or `write` calls that transfer data
+    /// deterministic, regardless of the buffer sizes used by the code under test (as long as
+    /// those buffers are at least as large as each chunk).
+    ///
+    /// If `k` is 0, an empty schedule is returned, so the transfer isn't limited at all.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is greater than `total_bytes`, since every operation must transfer at least
+    /// one byte. (A `Limited(0)` op would look like EOF to readers and like `WriteZero` to
+    /// writers.)
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::PartialOp;
+    ///
+    /// assert_eq!(
+    ///     PartialOp::force_op_count(10, 3),
+    ///     vec![PartialOp::Limited(4), PartialOp::Limited(3), PartialOp::Limited(3)],
+    /// );
+    /// ```
+    pub fn force_op_count(total_bytes: usize, k: usize) -> Vec<PartialOp> {
+        assert!(
+            k <= total_bytes,
+            "cannot split {} bytes into {} non-empty operations",
+            total_bytes,
+            k
+        );
+        if k == 0 {
+            return Vec::new();
+        }
+        let (chunk, extra) = (total_bytes / k, total_bytes % k);
+        (0..k)
+            .map(|i| PartialOp::Limited(chunk + usize::from(i < extra)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_range_schedule() {
+        let schedule = RangeSchedule::new()
+            .range(0..2, PartialOp::Limited(4))
+            .range(3..=4, PartialOp::Err(io::ErrorKind::WouldBlock))
+            // Overlaps the previous range: last wins.
+            .range(4..6, PartialOp::Limited(1))
+            .default_op(PartialOp::Err(io::ErrorKind::Interrupted));
+        assert_eq!(schedule.len(), Some(6));
+
+        let ops: Vec<_> = schedule.into_iter().collect();
+        assert_eq!(
+            ops,
+            vec![
+                PartialOp::Limited(4),
+                PartialOp::Limited(4),
+                PartialOp::Err(io::ErrorKind::Interrupted),
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+                PartialOp::Limited(1),
+                PartialOp::Limited(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_range_schedule_unbounded() {
+        let schedule = RangeSchedule::new()
+            .range(0..1, PartialOp::Unlimited)
+            .range(2.., PartialOp::Limited(1));
+        assert_eq!(schedule.len(), None);
+
+        let mut iter = schedule.into_iter();
+        assert_eq!(iter.size_hint(), (usize::MAX, None));
+        let ops: Vec<_> = iter.by_ref().take(4).collect();
+        assert_eq!(
+            ops,
+            vec![
+                PartialOp::Unlimited,
+                PartialOp::Unlimited,
+                PartialOp::Limited(1),
+                PartialOp::Limited(1),
+            ]
+        );
+        assert_eq!(iter.next(), Some(PartialOp::Limited(1)));
+
+        assert!(RangeSchedule::new().is_empty());
+        assert_eq!(RangeSchedule::new().into_iter().next(), None);
+    }
+
+    #[test]
+    fn test_repeat_schedule() {
+        let sub = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+        ];
+        let iter = repeat_schedule(sub.clone(), 10);
+        assert_eq!(iter.size_hint(), (20, Some(20)));
+        let ops: Vec<_> = iter.collect();
+        assert_eq!(ops.len(), 20);
+        for chunk in ops.chunks(2) {
+            assert_eq!(chunk, &sub[..]);
+        }
+
+        assert_eq!(repeat_schedule(sub, 0).count(), 0);
+        assert_eq!(repeat_schedule(vec![], 10).count(), 0);
+    }
+
+    #[cfg(feature = "futures03")]
+    #[test]
+    fn test_chaos_ops() {
+        let ops: Vec<_> = chaos_ops(5).take(1000).collect();
+        assert_eq!(
+            ops,
+            chaos_ops(5).take(1000).collect::<Vec<_>>(),
+            "reproducible from the seed"
+        );
+        assert_ne!(ops, chaos_ops(6).take(1000).collect::<Vec<_>>());
+
+        let count = |f: &dyn Fn(&PartialOp) -> bool| ops.iter().filter(|op| f(op)).count();
+        assert!(count(&|op| matches!(op, PartialOp::Limited(1..=32))) > 300);
+        assert!(count(&|op| *op == PartialOp::Unlimited) > 100);
+        assert!(count(&|op| *op == PartialOp::Err(io::ErrorKind::Interrupted)) > 100);
+        assert!(count(&|op| *op == PartialOp::Err(io::ErrorKind::WouldBlock)) > 100);
+        assert!(count(&|op| matches!(op, PartialOp::Err(kind) if CHAOS_ERRORS.contains(kind))) > 5);
+        assert_eq!(
+            count(&|op| matches!(op, PartialOp::Limited(0) | PartialOp::Limited(33..))),
+            0
+        );
+    }
+
+    #[test]
+    fn test_indexed_schedule() {
+        let schedule = IndexedSchedule::new()
+            .at(3, PartialOp::Err(io::ErrorKind::Interrupted))
+            .at(5, PartialOp::Unlimited)
+            .at(0, PartialOp::Limited(1))
+            .at(3, PartialOp::Err(io::ErrorKind::BrokenPipe))
+            .default_op(PartialOp::Limited(2));
+        let ops = schedule.build(7);
+        for (i, op) in ops.iter().enumerate() {
+            let expected = match i {
+                0 => PartialOp::Limited(1),
+                3 => PartialOp::Err(io::ErrorKind::BrokenPipe),
+                5 => PartialOp::Unlimited,
+                _ => PartialOp::Limited(2),
+            };
+            assert_eq!(*op, expected, "op at index {}", i);
+        }
+        assert_eq!(ops.len(), 7);
+        assert_eq!(schedule.build(6).len(), 6);
+
+        assert!(IndexedSchedule::new().build(0).is_empty());
+        assert_eq!(
+            IndexedSchedule::new().build(2),
+            vec![PartialOp::Unlimited; 2]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "op assigned to call index 5, but the schedule has length 5")]
+    fn test_indexed_schedule_out_of_range() {
+        IndexedSchedule::new().at(5, PartialOp::Limited(1)).build(5);
+    }
+
+    #[test]
+    fn test_phased_schedule() {
+        let schedule = PhasedSchedule::new()
+            .phase("handshake", vec![PartialOp::Limited(1); 2])
+            .phase("empty", vec![])
+            .phase("data", vec![PartialOp::Unlimited; 3]);
+        assert_eq!(schedule.len(), 5);
+
+        let phases: Vec<_> = (0..6)
+            .map(|i| schedule.position(i).map(|pos| (pos.phase, pos.index)))
+            .collect();
+        assert_eq!(
+            phases,
+            vec![
+                Some(("handshake".to_owned(), 0)),
+                Some(("handshake".to_owned(), 1)),
+                Some(("data".to_owned(), 0)),
+                Some(("data".to_owned(), 1)),
+                Some(("data".to_owned(), 2)),
+                None,
+            ]
+        );
+
+        let ops: Vec<_> = schedule.into_iter().collect();
+        assert_eq!(ops.len(), 5);
+        assert_eq!(ops[1], PartialOp::Limited(1));
+        assert_eq!(ops[2], PartialOp::Unlimited);
+    }
+
+    #[test]
+    fn test_schedule_covers() {
+        // Exactly covering.
+        let ops = [
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::WouldBlock),
+            PartialOp::Limited(4),
+        ];
+        let report = schedule_covers(&ops, 7);
+        assert_eq!(
+            report,
+            CoverageReport {
+                complete: true,
+                uncovered: 0,
+                surplus: 0,
+            }
+        );
+        assert!(report.is_exact());
+
+        // Under-covering.
+        let report = schedule_covers(&ops, 10);
+        assert!(!report.complete);
+        assert_eq!(report.uncovered, 3);
+        assert!(!report.is_exact());
+
+        // Over-covering.
+        let report = schedule_covers(&ops, 5);
+        assert!(report.complete);
+        assert_eq!(report.surplus, 2);
+        assert!(!report.is_exact());
+
+        // Unlimited-terminated.
+        let ops = [
+            PartialOp::Limited(3),
+            PartialOp::Unlimited,
+            PartialOp::Limited(4),
+        ];
+        assert!(schedule_covers(&ops, 100).is_exact());
+        // The Unlimited op isn't needed here, so the trailing Limited op is surplus.
+        let report = schedule_covers(&ops, 3);
+        assert!(report.complete);
+        assert_eq!(report.surplus, 4);
+
+        assert!(schedule_covers(&[], 0).is_exact());
+        assert_eq!(schedule_covers(&[], 5).uncovered, 5);
+    }
+
+    #[test]
+    fn test_force_op_count() {
+        use crate::PartialRead;
+        use std::io::{Cursor, Read};
+
+        for &(total_bytes, k) in &[(10, 3), (10, 10), (10, 1), (7, 2), (0, 0), (5, 0)] {
+            let ops = PartialOp::force_op_count(total_bytes, k);
+            assert_eq!(ops.len(), k);
+            let sizes: Vec<_> = ops
+                .iter()
+                .map(|op| match op {
+                    PartialOp::Limited(n) => *n,
+                    other => panic!("unexpected op {:?}", other),
+                })
+                .collect();
+            if k > 0 {
+                assert_eq!(sizes.iter().sum::<usize>(), total_bytes);
+                assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1);
+            }
+
+            // Reading with a large buffer takes exactly k calls that return data.
+            let mut partial_read = PartialRead::new(Cursor::new(vec![0u8; total_bytes]), ops);
+            let mut buf = [0u8; 64];
+            let mut calls = 0;
+            while partial_read.read(&mut buf).unwrap() > 0 {
+                calls += 1;
+            }
+            assert_eq!(calls, if k == 0 && total_bytes > 0 { 1 } else { k });
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot split 2 bytes into 3 non-empty operations")]
+    fn test_force_op_count_too_many() {
+        PartialOp::force_op_count(2, 3);
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains a seeker wrapper that injects errors into seeks according to a provided
+//! iterator.
+
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    iter,
+};
+
+use crate::{ops::Ops, PartialOp};
+
+/// A wrapper that injects errors into seeks on inner `Seek` instances according to the provided
+/// iterator.
+///
+/// Each call to `seek` consumes one `PartialOp`. `PartialOp::Err` makes the seek fail with that
+/// kind of error without calling into the inner instance, while `PartialOp::Limited` and
+/// `PartialOp::Unlimited` forward the seek as is, since seeks have no notion of a byte count.
+///
+/// `Read` and `Write` calls are forwarded to the inner instance unchanged, and don't consume any
+/// `PartialOp`s.
+///
+/// # Examples
+///
+/// ```rust
+/// use partial_io::{PartialOp, PartialSeek};
+/// use std::io::{self, Cursor, Seek, SeekFrom};
+///
+/// let ops = vec![PartialOp::Unlimited, PartialOp::Err(io::ErrorKind::Other)];
+/// let mut partial_seek = PartialSeek::new(Cursor::new(vec![0; 8]), ops);
+///
+/// assert_eq!(partial_seek.seek(SeekFrom::Start(4)).unwrap(), 4);
+/// let err = partial_seek.seek(SeekFrom::Start(2)).unwrap_err();
+/// assert_eq!(err.kind(), io::ErrorKind::Other);
+/// assert_eq!(partial_seek.get_ref().position(), 4);
+/// ```
+pub struct PartialSeek<S> {
+    inner: S,
+    ops: Ops,
+}
+
+impl<S> PartialSeek<S>
+where
+    S: Seek,
+{
+    /// Creates a new `PartialSeek` wrapper over the seeker with the specified `PartialOp`s.
+    pub fn new<I>(inner: S, iter: I) -> Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        PartialSeek {
+            inner,
+            ops: Ops::new(iter),
+        }
+    }
+
+    /// Creates a new `PartialSeek` wrapper whose first `seek` fails with an error of kind `kind`,
+    /// with every later `seek` forwarded to the inner instance.
+    ///
+    /// This models a transient failure such as `io::ErrorKind::Interrupted`, and is a direct way
+    /// to test logic that retries seeks. `Read` and `Write` calls are unaffected.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::PartialSeek;
+    /// use std::io::{self, Cursor, Seek, SeekFrom};
+    ///
+    /// let mut partial_seek =
+    ///     PartialSeek::fail_seek_once(Cursor::new(vec![0; 8]), io::ErrorKind::Interrupted);
+    /// let pos = loop {
+    ///     match partial_seek.seek(SeekFrom::End(-2)) {
+    ///         Ok(pos) => break pos,
+    ///         Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+    ///         Err(err) => panic!("unexpected error: {}", err),
+    ///     }
+    /// };
+    /// assert_eq!(pos, 6);
+    /// ```
+    pub fn fail_seek_once(inner: S, kind: io::ErrorKind) -> Self {
+        Self::new(inner, iter::once(PartialOp::Err(kind)))
+    }
+
+    /// Sets the `PartialOp`s for this seeker.
+    pub fn set_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        self.ops.replace(iter);
+        self
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, in the order they fired.
+    ///
+    /// Errors returned by the underlying seeker are not included.
+    pub fn errors_seen(&self) -> &[io::ErrorKind] {
+        self.ops.errors_seen()
+    }
+
+    /// Acquires a reference to the underlying seeker.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Acquires a mutable reference to the underlying seeker.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Consumes this wrapper, returning the underlying seeker.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> Seek for PartialSeek<S>
+where
+    S: Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self.ops.next() {
+            Some(PartialOp::Err(err)) => Err(io::Error::new(
+                err,
+                "error during seek, generated by partial-io",
+            )),
+            Some(PartialOp::ErrMsg(kind, msg)) => Err(io::Error::new(kind, msg)),
+            Some(PartialOp::PendingOnce) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "error during seek, generated by partial-io",
+            )),
+            _ => self.inner.seek(pos),
+        }
+    }
+}
+
+// Forwarding impl to support duplex structs.
+impl<S> Read for PartialSeek<S>
+where
+    S: Read + Seek,
+{
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+// Forwarding impl to support duplex structs.
+impl<S> Write for PartialSeek<S>
+where
+    S: Write + Seek,
+{
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S> fmt::Debug for PartialSeek<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartialSeek")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs::File, io::Cursor};
+
+    use crate::tests::assert_send;
+
+    #[test]
+    fn test_sendable() {
+        assert_send::<PartialSeek<File>>();
+    }
+
+    #[test]
+    fn test_fail_seek_once() {
+        let mut partial_seek = PartialSeek::fail_seek_once(
+            Cursor::new(b"Hello, world!".to_vec()),
+            io::ErrorKind::Interrupted,
+        );
+
+        let err = partial_seek.seek(SeekFrom::Start(7)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(
+            partial_seek.get_ref().position(),
+            0,
+            "failed seek didn't move"
+        );
+        assert_eq!(partial_seek.seek(SeekFrom::Start(7)).unwrap(), 7);
+        assert_eq!(partial_seek.seek(SeekFrom::Current(-2)).unwrap(), 5);
+        assert_eq!(partial_seek.errors_seen(), &[io::ErrorKind::Interrupted]);
+
+        // Reads and writes are unaffected.
+        let mut out = [0; 3];
+        partial_seek.read_exact(&mut out).unwrap();
+        assert_eq!(&out, b", w");
+        partial_seek.write_all(b"W").unwrap();
+        assert_eq!(
+            &partial_seek.into_inner().into_inner()[..],
+            b"Hello, wWrld!"
+        );
+    }
+}
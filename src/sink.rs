@@ -0,0 +1,245 @@
+// Copyright (c) The partial-io Contributors
+// SPDX-License-Identifier: MIT
+
+//! This module contains a `Sink` wrapper that injects errors and backpressure according to
+//! provided iterators.
+
+use crate::{futures_util::FuturesOps, ops::Ops, PartialOp};
+use futures::prelude::*;
+use pin_project::pin_project;
+use std::{
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A wrapper that injects errors and backpressure into inner `Sink` instances according to the
+/// provided iterators.
+///
+/// Readiness, sending and flushing are controlled by independent sequences of `PartialOp`s, so
+/// that, for example, `poll_ready` can return `Poll::Pending` repeatedly while `poll_flush`
+/// succeeds:
+///
+/// * `ready_ops` are consulted by `poll_ready`. `PartialOp::Err(WouldBlock)` returns
+///   `Poll::Pending` and schedules the task to be woken up, `PartialOp::Err(Interrupted)` causes
+///   a retry, and other errors are returned.
+/// * `send_ops` are consulted by `start_send`. Since `start_send` cannot return `Poll::Pending`,
+///   every `PartialOp::Err` is returned as an error, and the item is dropped. For the same reason,
+///   `PartialOp::Delay` blocks the thread.
+/// * `flush_ops` are consulted by both `poll_flush` and `poll_close`, with the same behavior as
+///   `ready_ops`.
+///
+/// Sinks have no notion of a byte count, so `PartialOp::Limited` and `PartialOp::Eof` behave the
+/// same as `PartialOp::Unlimited`.
+///
+/// Injected errors are converted to the inner sink's error type, which must implement
+/// `From<io::Error>`.
+///
+/// Available with the `futures03` feature.
+#[pin_project]
+pub struct PartialSink<S> {
+    #[pin]
+    inner: S,
+    ready_ops: FuturesOps,
+    send_ops: Ops,
+    flush_ops: FuturesOps,
+}
+
+impl<S> PartialSink<S> {
+    /// Creates a new `PartialSink` wrapper over the sink with the specified `PartialOp`s.
+    pub fn new<R, T, F>(inner: S, ready_ops: R, send_ops: T, flush_ops: F) -> Self
+    where
+        R: IntoIterator<Item = PartialOp> + 'static,
+        R::IntoIter: Send,
+        T: IntoIterator<Item = PartialOp> + 'static,
+        T::IntoIter: Send,
+        F: IntoIterator<Item = PartialOp> + 'static,
+        F::IntoIter: Send,
+    {
+        PartialSink {
+            inner,
+            ready_ops: FuturesOps::new(ready_ops),
+            send_ops: Ops::new(send_ops),
+            flush_ops: FuturesOps::new(flush_ops),
+        }
+    }
+
+    /// Sets the `PartialOp`s consulted by `poll_ready`.
+    pub fn set_ready_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        self.ready_ops.replace(iter);
+        self
+    }
+
+    /// Sets the `PartialOp`s consulted by `start_send`.
+    pub fn set_send_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        self.send_ops.replace(iter);
+        self
+    }
+
+    /// Sets the `PartialOp`s consulted by `poll_flush` and `poll_close`.
+    pub fn set_flush_ops<I>(&mut self, iter: I) -> &mut Self
+    where
+        I: IntoIterator<Item = PartialOp> + 'static,
+        I::IntoIter: Send,
+    {
+        self.flush_ops.replace(iter);
+        self
+    }
+
+    /// Returns a shared reference to the underlying sink.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying sink.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns a pinned mutable reference to the underlying sink.
+    pub fn pin_get_mut(self: Pin<&mut Self>) -> Pin<&mut S> {
+        self.project().inner
+    }
+
+    /// Consumes this wrapper, returning the underlying sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, Item> Sink<Item> for PartialSink<S>
+where
+    S: Sink<Item>,
+    S::Error: From<io::Error>,
+{
+    type Error = S::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        let inner = this.inner;
+
+        this.ready_ops
+            .poll_impl_no_limit(
+                cx,
+                |cx| inner.poll_ready(cx).map(Ok),
+                "error during poll_ready, generated by partial-io",
+            )
+            .map(flatten_err)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
+        let this = self.project();
+
+        match this.send_ops.next() {
+            Some(PartialOp::Err(kind)) => {
+                Err(io::Error::new(kind, "error during start_send, generated by partial-io").into())
+            }
+            Some(PartialOp::ErrMsg(kind, msg)) => Err(io::Error::new(kind, msg).into()),
+            _ => this.inner.start_send(item),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        let inner = this.inner;
+
+        this.flush_ops
+            .poll_impl_no_limit(
+                cx,
+                |cx| inner.poll_flush(cx).map(Ok),
+                "error during poll_flush, generated by partial-io",
+            )
+            .map(flatten_err)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        let inner = this.inner;
+
+        this.flush_ops
+            .poll_impl_no_limit(
+                cx,
+                |cx| inner.poll_close(cx).map(Ok),
+                "error during poll_close, generated by partial-io",
+            )
+            .map(flatten_err)
+    }
+}
+
+fn flatten_err<E: From<io::Error>>(res: io::Result<Result<(), E>>) -> Result<(), E> {
+    res.map_err(E::from).and_then(|res| res)
+}
+
+impl<S> fmt::Debug for PartialSink<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartialSink")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::task::noop_waker;
+    use std::convert::Infallible;
+
+    use crate::tests::assert_send;
+
+    #[test]
+    fn test_sendable() {
+        assert_send::<PartialSink<Vec<u8>>>();
+    }
+
+    #[test]
+    fn test_independent_ops() {
+        let inner =
+            Vec::<u8>::new().sink_map_err(|never: Infallible| -> io::Error { match never {} });
+        let mut sink = PartialSink::new(
+            inner,
+            vec![
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+                PartialOp::Err(io::ErrorKind::WouldBlock),
+            ],
+            vec![PartialOp::Err(io::ErrorKind::BrokenPipe)],
+            vec![],
+        );
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        // poll_ready is pending twice while poll_flush succeeds.
+        assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_pending());
+        assert!(matches!(
+            Pin::new(&mut sink).poll_flush(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+        assert!(Pin::new(&mut sink).poll_ready(&mut cx).is_pending());
+        assert!(matches!(
+            Pin::new(&mut sink).poll_ready(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+
+        // start_send fails once, independently of readiness.
+        let err = Pin::new(&mut sink).start_send(1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        Pin::new(&mut sink).start_send(2).unwrap();
+        assert!(matches!(
+            Pin::new(&mut sink).poll_close(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+
+        assert_eq!(sink.get_ref().get_ref(), &[2]);
+    }
+}
@@ -6,14 +6,31 @@
 
 use std::{
     cmp, fmt,
-    io::{self, Read, Write},
+    io::{self, IoSlice, Read, Seek, SeekFrom, Write},
+    iter,
+    sync::mpsc,
+    time::Instant,
 };
 
-use crate::{make_ops, PartialOp};
+use crate::{
+    ops::{
+        check_limit, inspect_write, limit_io_slices, limited_zero_error, trace_op, BoxedOps,
+        Diagnostics, Inspector, OpEvent, OpHistory, OpObserver, OpSnapshot, Ops, ReplayOps,
+        ResultMap,
+    },
+    ExhaustedBehavior, PartialOp, PhasePosition, PhasedSchedule,
+};
+
+/// Returns the number of bytes the inner writer has room for.
+type CapacityProbe<W> = Box<dyn FnMut(&W) -> usize + Send>;
 
 /// A writer wrapper that breaks inner `Write` instances up according to the
 /// provided iterator.
 ///
+/// A `PartialOp::Limited(0)` op fails a nonempty write with an `io::ErrorKind::WriteZero` error
+/// instead of returning `Ok(0)`, since a writer that keeps accepting nothing makes retry loops
+/// spin forever. Use `PartialOp::Eof` to return `Ok(0)` on purpose.
+///
 /// # Examples
 ///
 /// ```rust
@@ -30,9 +47,72 @@ use crate::{make_ops, PartialOp};
 /// assert_eq!(size, 1);
 /// assert_eq!(&partial_writer.get_ref()[..], &[1]);
 /// ```
-pub struct PartialWrite<W> {
+pub struct PartialWrite<W, I = BoxedOps> {
     inner: W,
-    ops: Box<dyn Iterator<Item = PartialOp> + Send>,
+    ops: Ops<I>,
+    inspector: Option<Inspector>,
+    observer: Option<OpObserver>,
+    flush_interrupts: usize,
+    flush_interrupts_set: usize,
+    flushes: usize,
+    black_hole: bool,
+    strict_limits: bool,
+    block_align: usize,
+    split_unaligned_tail: bool,
+    flush_gate: Option<io::ErrorKind>,
+    awaiting_flush: bool,
+    capacity_probe: Option<CapacityProbe<W>>,
+    result_map: Option<ResultMap>,
+    shrinking_capacity: Option<ShrinkingCapacity>,
+    total_limit: Option<TotalLimit>,
+}
+
+/// A cap on the total number of bytes written over the lifetime of the writer.
+#[derive(Clone, Copy, Debug)]
+struct TotalLimit {
+    limit: u64,
+    remaining: u64,
+    exceeded: Option<io::ErrorKind>,
+}
+
+impl TotalLimit {
+    fn new(limit: u64, exceeded: Option<io::ErrorKind>) -> Self {
+        Self {
+            limit,
+            remaining: limit,
+            exceeded,
+        }
+    }
+
+    /// Returns a copy of this limit with none of it used up.
+    fn restarted(&self) -> Self {
+        Self::new(self.limit, self.exceeded)
+    }
+}
+
+/// A cap on the size of writes that shrinks after every successful write.
+#[derive(Clone, Copy, Debug)]
+struct ShrinkingCapacity {
+    start: usize,
+    cap: usize,
+    decay_per_op: usize,
+    floor: usize,
+}
+
+impl ShrinkingCapacity {
+    fn new(start: usize, decay_per_op: usize, floor: usize) -> Self {
+        Self {
+            start,
+            cap: start,
+            decay_per_op,
+            floor,
+        }
+    }
+
+    /// Returns a copy of this capacity with the cap back at its starting value.
+    fn restarted(&self) -> Self {
+        Self::new(self.start, self.decay_per_op, self.floor)
+    }
 }
 
 impl<W> PartialWrite<W>
@@ -45,12 +125,53 @@ where
         I: IntoIterator<Item = PartialOp> + 'static,
         I::IntoIter: Send,
     {
-        PartialWrite {
-            inner,
-            // Use fuse here so that we don't keep calling the inner iterator
-            // once it's returned None.
-            ops: make_ops(iter),
-        }
+        Self::new_unboxed(inner, Box::new(iter.into_iter()))
+    }
+
+    /// Creates a new `PartialWrite` wrapper that receives its `PartialOp`s from a channel.
+    ///
+    /// Each operation tries to receive the next op from `rx` without blocking. If no op is
+    /// pending, the operation behaves as if it were given `PartialOp::Unlimited`. This lets
+    /// another thread inject an op, such as an error, at a precise moment while a transfer is
+    /// ongoing. Once every sender has been dropped and the channel is drained, the ops are
+    /// considered to have run out.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialWrite};
+    /// use std::{
+    ///     io::{self, Write},
+    ///     sync::mpsc,
+    /// };
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let mut partial_writer = PartialWrite::from_receiver(Vec::new(), rx);
+    ///
+    /// // With nothing sent, writes are unlimited.
+    /// assert_eq!(partial_writer.write(b"abc").unwrap(), 3);
+    ///
+    /// tx.send(PartialOp::Err(io::ErrorKind::BrokenPipe)).unwrap();
+    /// let err = partial_writer.write(b"def").unwrap_err();
+    /// assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    /// ```
+    pub fn from_receiver(inner: W, rx: mpsc::Receiver<PartialOp>) -> Self {
+        let iter = iter::from_fn(move || match rx.try_recv() {
+            Ok(op) => Some(op),
+            Err(mpsc::TryRecvError::Empty) => Some(PartialOp::Unlimited),
+            Err(mpsc::TryRecvError::Disconnected) => None,
+        });
+        Self::new(inner, iter)
+    }
+
+    /// Creates a new `PartialWrite` wrapper that accepts at most one byte at a time, without any
+    /// errors.
+    ///
+    /// This is the strictest deterministic chunking possible, and is a good way to shake out
+    /// bugs around write boundaries. It is equivalent to passing in
+    /// `std::iter::repeat(PartialOp::Limited(1))` as the `PartialOp`s.
+    pub fn pinhole(inner: W) -> Self {
+        Self::new(inner, iter::repeat(PartialOp::Limited(1)))
     }
 
     /// Sets the `PartialOp`s for this writer.
@@ -59,10 +180,615 @@ where
         I: IntoIterator<Item = PartialOp> + 'static,
         I::IntoIter: Send,
     {
-        self.ops = make_ops(iter);
+        self.ops.replace(iter);
+        self
+    }
+
+    /// Computes each `PartialOp` for this writer with a callback, replacing any existing ops.
+    ///
+    /// Before each op is applied, `op_fn` is passed an [`OpHistory`] describing the ops applied
+    /// since the callback was set, the number of bytes transferred and the errors seen so far,
+    /// and returns the op to apply next. This makes it possible to express stateful fault models,
+    /// such as failing only after a run of successful transfers, that a fixed sequence of ops
+    /// can't. The callback is called whenever an op is needed, so the ops never run out.
+    ///
+    /// Setting ops any other way removes the callback.
+    pub fn set_op_fn<F>(&mut self, op_fn: F) -> &mut Self
+    where
+        F: FnMut(&OpHistory<'_>) -> PartialOp + Send + 'static,
+    {
+        self.ops.replace_with_fn(Box::new(op_fn));
+        self
+    }
+
+    /// Sets the `PartialOp`s for this writer from a [`PhasedSchedule`], and starts tracking
+    /// which phase the last consumed op belongs to.
+    ///
+    /// The current phase is reported by [`current_phase`](Self::current_phase), this writer's
+    /// `Debug` output and [`assert_op_count`](Self::assert_op_count) failure messages. Setting
+    /// ops any other way stops tracking phases.
+    pub fn set_phased_ops(&mut self, schedule: PhasedSchedule) -> &mut Self {
+        self.ops.replace_phased(schedule);
+        self
+    }
+
+    /// Returns a copy of the `PartialOp`s that this writer hasn't consumed yet.
+    ///
+    /// The copy is independent of this writer: passing it to a fresh `PartialWrite` produces a
+    /// second writer that continues from the same point in the schedule, for branching tests.
+    /// Unlike [`snapshot`](Self::snapshot), only the ops are copied, not the errors seen so far. A
+    /// latched error isn't part of the schedule either, and isn't carried over.
+    ///
+    /// This collects the remaining `PartialOp`s into a buffer, so they must be finite: with an
+    /// infinite iterator, this method never returns.
+    pub fn fork_schedule(&mut self) -> Vec<PartialOp> {
+        self.ops.fork()
+    }
+
+    /// Returns a snapshot of this writer's position within its `PartialOp`s, along with the
+    /// errors seen so far.
+    ///
+    /// Pass the snapshot to [`restore`](Self::restore) to return to this position, for example to
+    /// explore several branches from the same point. The state of the underlying writer is not
+    /// part of the snapshot.
+    ///
+    /// Taking a snapshot collects the remaining `PartialOp`s into a buffer, so they must be
+    /// finite: with an infinite iterator, this method never returns.
+    pub fn snapshot(&mut self) -> OpSnapshot {
+        self.ops.snapshot()
+    }
+
+    /// Restores the position and errors seen captured by [`snapshot`](Self::snapshot).
+    pub fn restore(&mut self, snapshot: &OpSnapshot) -> &mut Self {
+        self.ops.restore(snapshot);
+        self
+    }
+}
+
+impl<W, I> PartialWrite<W, I>
+where
+    W: Write,
+    I: Iterator<Item = PartialOp>,
+{
+    /// Creates a new `PartialWrite` wrapper over the writer with the specified `PartialOp`s,
+    /// without boxing the iterator.
+    ///
+    /// See [`PartialRead::new_unboxed`](crate::PartialRead::new_unboxed) for more.
+    pub fn new_unboxed(inner: W, iter: I) -> Self {
+        PartialWrite {
+            inner,
+            ops: Ops::new_unboxed(iter),
+            inspector: None,
+            observer: None,
+            flush_interrupts: 0,
+            flush_interrupts_set: 0,
+            flushes: 0,
+            black_hole: false,
+            strict_limits: false,
+            block_align: 1,
+            split_unaligned_tail: false,
+            flush_gate: None,
+            awaiting_flush: false,
+            capacity_probe: None,
+            result_map: None,
+            shrinking_capacity: None,
+            total_limit: None,
+        }
+    }
+
+    /// Attaches a callback that observes every `write` call.
+    ///
+    /// After each `write` call, `inspector` is called with the `PartialOp` that applied to it and
+    /// the bytes the underlying writer accepted, which is empty if the call failed. Once the ops
+    /// have run out, writes are reported as `PartialOp::Unlimited`.
+    ///
+    /// This makes it possible to observe the data passing through write-only writers, such as
+    /// sockets, that can't be read back afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialWrite};
+    /// use std::{
+    ///     io::{self, Write},
+    ///     sync::{Arc, Mutex},
+    /// };
+    ///
+    /// let seen = Arc::new(Mutex::new(Vec::new()));
+    /// let seen2 = seen.clone();
+    /// let mut partial_writer = PartialWrite::new(io::sink(), vec![PartialOp::Limited(2)])
+    ///     .with_inspector(move |op, bytes| {
+    ///         seen2.lock().unwrap().push((op.clone(), bytes.to_vec()));
+    ///     });
+    ///
+    /// partial_writer.write(b"abc").unwrap();
+    /// assert_eq!(
+    ///     *seen.lock().unwrap(),
+    ///     vec![(PartialOp::Limited(2), b"ab".to_vec())],
+    /// );
+    /// ```
+    pub fn with_inspector<F>(mut self, inspector: F) -> Self
+    where
+        F: FnMut(&PartialOp, &[u8]) + Send + 'static,
+    {
+        self.inspector = Some(Box::new(inspector));
+        self
+    }
+
+    /// Caps the total number of bytes written over the lifetime of this writer to `limit`,
+    /// simulating a quota.
+    ///
+    /// Until the cap is hit, `PartialOp`s apply as usual, except that a write that would go over
+    /// the cap is truncated so that exactly `limit` bytes get through in total. For example, with
+    /// a limit of 10 and 8 bytes already written, a `PartialOp::Limited(5)` op only lets 2 bytes
+    /// through. `PartialOp::Limited` ops that don't reach the cap are unaffected.
+    ///
+    /// Once `limit` bytes have been written, every later write of a nonempty buffer returns
+    /// `Ok(0)` if `exceeded` is `None`, or fails with an error of kind `exceeded` otherwise. These
+    /// writes don't consume a `PartialOp` or call into the underlying writer. Calls to `flush` are
+    /// unaffected.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialWrite};
+    /// use std::io::{self, Write};
+    ///
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), vec![PartialOp::Limited(4); 3])
+    ///     .with_total_limit(6, Some(io::ErrorKind::Other));
+    ///
+    /// assert_eq!(partial_writer.write(b"Hello, world!").unwrap(), 4);
+    /// // This write straddles the cap, so it is truncated.
+    /// assert_eq!(partial_writer.write(b"o, world!").unwrap(), 2);
+    /// assert_eq!(
+    ///     partial_writer.write(b"world!").unwrap_err().kind(),
+    ///     io::ErrorKind::Other,
+    /// );
+    /// assert_eq!(partial_writer.get_ref(), b"Hello,");
+    /// ```
+    pub fn with_total_limit(mut self, limit: u64, exceeded: Option<io::ErrorKind>) -> Self {
+        self.total_limit = Some(TotalLimit::new(limit, exceeded));
+        self
+    }
+
+    /// Interprets limits relative to the remaining capacity of the underlying writer.
+    ///
+    /// Before each `write` call, `probe` is called with the underlying writer and should return
+    /// the number of bytes it has room for. Each `PartialOp::Limited` op is then capped to that
+    /// remaining space, and so is each `PartialOp::Unlimited` op (as well as calls made after the
+    /// ops have run out). If the writer is full, the write fails with `io::ErrorKind::WriteZero`
+    /// without calling into it. `PartialOp::Err` ops are applied as usual.
+    ///
+    /// This models fixed-capacity writers such as bounded ring buffers, where the schedule should
+    /// adapt to how full the buffer is.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialWrite};
+    /// use std::io::{self, Write};
+    ///
+    /// const CAPACITY: usize = 6;
+    ///
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), vec![PartialOp::Limited(4); 3]);
+    /// partial_writer.set_limit_relative_to_capacity(|buf: &Vec<u8>| CAPACITY - buf.len());
+    ///
+    /// assert_eq!(partial_writer.write(b"abcdefgh").unwrap(), 4);
+    /// assert_eq!(partial_writer.write(b"efgh").unwrap(), 2);
+    /// assert_eq!(
+    ///     partial_writer.write(b"gh").unwrap_err().kind(),
+    ///     io::ErrorKind::WriteZero,
+    /// );
+    /// ```
+    pub fn set_limit_relative_to_capacity<F>(&mut self, probe: F) -> &mut Self
+    where
+        F: FnMut(&W) -> usize + Send + 'static,
+    {
+        self.capacity_probe = Some(Box::new(probe));
+        self
+    }
+
+    /// Attaches a callback that is passed an [`OpEvent`] after each `write` call.
+    ///
+    /// The event describes the `PartialOp` that applied, the number of bytes written so far, the
+    /// length of the caller's buffer and the number of bytes written by the call. Calls to `flush`
+    /// are not reported. Replaces any previously attached callback, and starts counting the offset
+    /// from 0 again.
+    pub fn on_op<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+    {
+        self.observer = Some(OpObserver::new(callback));
         self
     }
 
+    /// Like [`on_op`](Self::on_op), but timestamps events with `clock` rather than
+    /// `Instant::now()`.
+    ///
+    /// This makes it possible to correlate events with other traces using the same clock, or to
+    /// control time in tests. `clock` is called once per event, and should return quickly
+    /// without blocking, since it's called from within `write`.
+    pub fn on_op_with_clock<F, C>(&mut self, callback: F, clock: C) -> &mut Self
+    where
+        F: FnMut(OpEvent) + Send + 'static,
+        C: FnMut() -> Instant + Send + 'static,
+    {
+        self.observer = Some(OpObserver::with_clock(callback, clock));
+        self
+    }
+
+    /// Sets whether to check that the inner writer never transfers more bytes than it was
+    /// allowed to.
+    ///
+    /// With this set to true, every `write` call made under a `PartialOp` panics if the inner
+    /// writer reports more bytes transferred than the length of the buffer it was passed, i.e.
+    /// more than the op's limit. This is an invariant check for the limit calculations in this
+    /// wrapper, and also catches inner writers that violate the `write` contract.
+    ///
+    /// Off by default, in both debug and release builds. Calls forwarded directly to the inner
+    /// writer, for `PartialOp::Unlimited` ops or after the ops have run out, are not checked.
+    pub fn set_strict_limits(&mut self, strict: bool) -> &mut Self {
+        self.strict_limits = strict;
+        self
+    }
+
+    /// Caps the number of bytes accepted by each write, with a cap that shrinks as the transfer
+    /// goes on.
+    ///
+    /// This models backpressure building up over a long transfer, for example a buffer that fills
+    /// up or degrades over time. The cap starts out at `start` bytes, and every successful write
+    /// reduces it by `decay_per_op` bytes, down to `floor` bytes. Each write accepts at most the
+    /// smaller of the limit from its `PartialOp` and the current cap.
+    ///
+    /// With a `floor` of 0, the cap eventually runs out. Nonempty writes made after that fail with
+    /// `io::ErrorKind::WriteZero` rather than returning `Ok(0)`, the same as with
+    /// `PartialOp::Limited(0)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `floor` is greater than `start`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::PartialWrite;
+    /// use std::io::Write;
+    ///
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), vec![]);
+    /// partial_writer.set_shrinking_capacity(8, 3, 1);
+    ///
+    /// let written: Vec<_> = (0..5)
+    ///     .map(|_| partial_writer.write(b"Hello, world!").unwrap())
+    ///     .collect();
+    /// assert_eq!(written, [8, 5, 2, 1, 1]);
+    /// ```
+    pub fn set_shrinking_capacity(
+        &mut self,
+        start: usize,
+        decay_per_op: usize,
+        floor: usize,
+    ) -> &mut Self {
+        assert!(
+            floor <= start,
+            "floor ({}) must not be greater than start ({})",
+            floor,
+            start
+        );
+        self.shrinking_capacity = Some(ShrinkingCapacity::new(start, decay_per_op, floor));
+        self
+    }
+
+    /// Sets a callback that transforms the result of each `write` call.
+    ///
+    /// This is an escape hatch for fault shapes that `PartialOp`s can't express, such as
+    /// reporting fewer bytes than were written, or failing a write based on the data written.
+    /// The callback is passed the result of the call along with the bytes that the inner writer
+    /// accepted (empty if the call failed), and returns the result to report instead.
+    ///
+    /// The callback runs after the `PartialOp` for the call has been applied and the inner writer
+    /// has been called, and before anything else: the mapped result is what the caller,
+    /// inspectors and [`on_op`](Self::on_op) callbacks see, and byte counts such as the offsets
+    /// in [`error_offsets`](Self::error_offsets) are based on it. It is also passed errors
+    /// injected by `PartialOp::Err`. Replaces any previously set callback.
+    ///
+    /// Since the inner writer has already accepted the data, reporting fewer bytes than were
+    /// written makes callers such as `write_all` write the rest of the data again, duplicating it.
+    ///
+    /// # Panics
+    ///
+    /// `write` panics if the callback returns a byte count larger than the caller's buffer.
+    pub fn set_result_map<F>(&mut self, result_map: F) -> &mut Self
+    where
+        F: FnMut(io::Result<usize>, &[u8]) -> io::Result<usize> + Send + 'static,
+    {
+        self.result_map = Some(Box::new(result_map));
+        self
+    }
+
+    /// Aligns the chunks written to the inner writer to multiples of `block_size` bytes.
+    ///
+    /// This is meant for testing code that only handles block-aligned writes correctly, such as
+    /// direct I/O (`O_DIRECT`) code. With a block size greater than 1, the limit of every op that
+    /// doesn't cover the rest of the caller's buffer is rounded down to a multiple of
+    /// `block_size`, and up to a single block if it is smaller than that. A
+    /// `PartialOp::Limited(0)` op is still an error.
+    ///
+    /// The unaligned tail of the caller's buffer, i.e. the bytes left over after the last full
+    /// block, is written along with the blocks before it if the op allows. Use
+    /// [`set_split_unaligned_tail`](Self::set_split_unaligned_tail) to always write the tail on
+    /// its own instead. Either way, all but the last chunk of a buffer passed to `write_all` are
+    /// block-aligned.
+    ///
+    /// A block size of 1, the default, turns alignment off.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block_size` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::{PartialOp, PartialWrite};
+    /// use std::io::Write;
+    ///
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), vec![PartialOp::Limited(10); 3]);
+    /// partial_writer.set_block_align(4);
+    ///
+    /// assert_eq!(partial_writer.write(&[0; 14]).unwrap(), 8);
+    /// // Limits are rounded up to a single block.
+    /// partial_writer.set_ops(vec![PartialOp::Limited(3)]);
+    /// assert_eq!(partial_writer.write(&[0; 6]).unwrap(), 4);
+    /// // The unaligned tail is written as is.
+    /// assert_eq!(partial_writer.write(&[0; 2]).unwrap(), 2);
+    /// ```
+    pub fn set_block_align(&mut self, block_size: usize) -> &mut Self {
+        assert!(block_size > 0, "block size must be greater than 0");
+        self.block_align = block_size;
+        self
+    }
+
+    /// Sets whether the unaligned tail of a buffer is always written on its own when block
+    /// alignment is on.
+    ///
+    /// With this set to true, a `write` call whose op would cover both full blocks and the
+    /// unaligned tail of the caller's buffer only writes the full blocks, forcing the tail into a
+    /// separate, final `write` call. This exercises the code path that handles the tail in
+    /// isolation. Has no effect unless [`set_block_align`](Self::set_block_align) is used.
+    pub fn set_split_unaligned_tail(&mut self, split: bool) -> &mut Self {
+        self.split_unaligned_tail = split;
+        self
+    }
+
+    /// Sets whether this writer silently drops the data written to it.
+    ///
+    /// In black hole mode, `write` calls apply `PartialOp`s as usual and report the number of
+    /// bytes written as if the data had been accepted, but nothing is forwarded to the underlying
+    /// writer. This deliberately loses data, modeling misbehaving sinks that report success
+    /// without persisting anything, so that end-to-end integrity checks can be tested. Calls to
+    /// `flush` are still forwarded.
+    pub fn set_black_hole(&mut self, black_hole: bool) -> &mut Self {
+        self.black_hole = black_hole;
+        self
+    }
+
+    /// Requires a `flush` between consecutive writes, modeling devices that need data to be
+    /// written in flushed batches.
+    ///
+    /// With `Some(kind)`, this writer follows a simple state machine:
+    ///
+    /// * Initially, and after a successful `flush`, the next `write` is allowed.
+    /// * After a successful `write`, the writer waits for a flush. Any `write` made in this state
+    ///   fails with an error of kind `kind`, without consuming a `PartialOp` or calling into the
+    ///   underlying writer.
+    ///
+    /// Failed writes and flushes, including ones injected by `PartialOp::Err`, don't change the
+    /// state. Pass in `None`, the default, to allow writes at any time; this also forgets any
+    /// pending flush.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use partial_io::PartialWrite;
+    /// use std::io::{self, Write};
+    ///
+    /// let mut partial_writer = PartialWrite::new(Vec::new(), vec![]);
+    /// partial_writer.set_require_flush_between_writes(Some(io::ErrorKind::Other));
+    ///
+    /// partial_writer.write(b"foo").unwrap();
+    /// assert!(partial_writer.write(b"bar").is_err());
+    /// partial_writer.flush().unwrap();
+    /// partial_writer.write(b"bar").unwrap();
+    /// ```
+    pub fn set_require_flush_between_writes(&mut self, kind: Option<io::ErrorKind>) -> &mut Self {
+        self.flush_gate = kind;
+        self.awaiting_flush = false;
+        self
+    }
+
+    /// Returns the phase of the last consumed `PartialOp`, and its index within that phase.
+    ///
+    /// Returns `None` unless ops were set with [`set_phased_ops`](Self::set_phased_ops), or if no
+    /// ops have been consumed yet. Once the schedule has run out, this keeps returning the
+    /// position of the last op.
+    pub fn current_phase(&self) -> Option<PhasePosition> {
+        self.ops.current_phase()
+    }
+
+    /// Returns the limit the next `PartialOp` will apply, if it is `PartialOp::Limited`.
+    ///
+    /// `PartialOp::Eof` counts as a limit of 0.
+    ///
+    /// Returns `None` if the next op is `PartialOp::Unlimited`, `PartialOp::Err` or
+    /// `PartialOp::Delay`, or if the ops have run out, unless
+    /// [`set_unlimited_cap`](Self::set_unlimited_cap) turns the op into a limit. This peeks at the
+    /// next op, which pulls it out of the underlying iterator early. The op is not consumed: it
+    /// still applies to the next `write` or `flush` call.
+    pub fn next_limit(&mut self) -> Option<usize> {
+        self.ops.next_limit()
+    }
+
+    /// Asserts that exactly `expected` `PartialOp`s have been consumed since the ops were last
+    /// set.
+    ///
+    /// Each `write` or `flush` call consumes one op. Calls made after the ops have run out aren't
+    /// counted. This is useful for pinning down golden chunking behavior: with `PartialOp::Limited`
+    /// ops, a regression in how data is chunked changes the number of ops consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics with a message including both counts if they differ.
+    #[track_caller]
+    pub fn assert_op_count(&self, expected: usize) {
+        self.ops.assert_op_count(expected);
+    }
+
+    /// Returns the number of `PartialOp`s consumed since the ops were last set.
+    ///
+    /// This counts ops the same way as [`assert_op_count`](Self::assert_op_count).
+    pub fn consumed_ops(&self) -> usize {
+        self.ops.consumed()
+    }
+
+    /// Returns the number of `PartialOp`s that haven't been consumed yet, if it is known.
+    ///
+    /// The number is known if the iterator the ops were set from reports an exact size, as
+    /// `ExactSizeIterator`s like `vec::IntoIter` do. Returns `None` for other iterators, including
+    /// infinite ones, and for ops computed by [`set_op_fn`](Self::set_op_fn).
+    pub fn ops_remaining(&self) -> Option<usize> {
+        self.ops.remaining()
+    }
+
+    /// Returns the total number of bytes written through this writer.
+    ///
+    /// This counts the bytes actually transferred by the inner writer, not the amounts requested
+    /// by callers or allowed by `PartialOp`s. Calls that return an error don't count, and neither
+    /// do flushes. The count is kept when the ops are replaced.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.ops.transferred()
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, in the order they fired.
+    ///
+    /// Errors returned by the underlying writer are not included.
+    pub fn errors_seen(&self) -> &[io::ErrorKind] {
+        self.ops.errors_seen()
+    }
+
+    /// Returns the kinds of errors injected by `PartialOp::Err` so far, along with the number of
+    /// bytes transferred through this writer before each one fired.
+    ///
+    /// This covers the same errors as [`errors_seen`](Self::errors_seen), and helps correlate
+    /// errors with positions in the stream.
+    pub fn error_offsets(&self) -> &[(io::ErrorKind, u64)] {
+        self.ops.error_offsets()
+    }
+
+    /// Sets the kind of error that is sticky once it is injected.
+    ///
+    /// Once a `PartialOp::Err` of this kind is applied, every subsequent operation on this
+    /// writer fails with the same kind of error regardless of the remaining `PartialOp`s, which
+    /// are not consumed. This models unrecoverable failures like a connection dying with
+    /// `BrokenPipe`. The latched error is preserved across [`set_ops`](Self::set_ops), and can be
+    /// reset with [`clear_latch`](Self::clear_latch).
+    ///
+    /// Pass in `None` to stop latching errors. This doesn't clear an error that has already
+    /// latched.
+    pub fn set_latch_error(&mut self, kind: Option<io::ErrorKind>) -> &mut Self {
+        self.ops.set_latch_error(kind);
+        self
+    }
+
+    /// Returns the error that has latched, if any.
+    pub fn latched_error(&self) -> Option<io::ErrorKind> {
+        self.ops.latched_error()
+    }
+
+    /// Clears the error that has latched, if any, so that `PartialOp`s apply again.
+    pub fn clear_latch(&mut self) -> &mut Self {
+        self.ops.clear_latch();
+        self
+    }
+
+    /// Randomly merges consecutive `PartialOp::Limited` ops into larger transfers.
+    ///
+    /// With `Some((p, seed))`, whenever a `PartialOp::Limited` op is applied, it is merged with
+    /// the next op with probability `p` if that op is also `PartialOp::Limited`, and so on, so a
+    /// run of `Limited(1)` ops may turn into a single `Limited(3)`. Any other op, including an
+    /// error, stops the merging. The coin flips are derived from `seed`, so they're reproducible.
+    ///
+    /// This adds some larger chunks to a fine-grained schedule without rewriting it, to exercise
+    /// code paths that only small transfers would miss. Merged ops count as consumed.
+    /// [`next_limit`](Self::next_limit) reports the limit before merging.
+    ///
+    /// Pass in `None`, the default, to turn merging off.
+    pub fn set_coalesce_prob(&mut self, prob: Option<(f64, u64)>) -> &mut Self {
+        self.ops.set_coalesce_prob(prob);
+        self
+    }
+
+    /// Caps the number of bytes a `PartialOp::Unlimited` op lets through in a single call.
+    ///
+    /// With a cap of `Some(cap)`, every `PartialOp::Unlimited` op is treated as
+    /// `PartialOp::Limited(cap)`, and so are calls made after the ops have run out. This bounds
+    /// the size of any single transfer, for example to keep fuzz harnesses from making huge
+    /// allocations. Only `PartialOp::Unlimited` is affected: `PartialOp::Limited(n)` is
+    /// applied as is even if `n` is larger than the cap.
+    ///
+    /// Pass in `None`, the default, to remove the cap.
+    pub fn set_unlimited_cap(&mut self, cap: Option<usize>) -> &mut Self {
+        self.ops.set_unlimited_cap(cap);
+        self
+    }
+
+    /// Sets what this writer does once its `PartialOp`s have run out.
+    ///
+    /// By default, calls are forwarded to the inner writer without limits or errors, as with
+    /// `ExhaustedBehavior::Unlimited`. With `ExhaustedBehavior::Panic`, the write after the ops
+    /// run out panics, which catches sequences of ops that are too short for the test. With
+    /// `ExhaustedBehavior::Repeat(op)`, `op` is applied to every call from then on.
+    pub fn set_exhausted_behavior(&mut self, behavior: ExhaustedBehavior) -> &mut Self {
+        self.ops.set_exhausted_behavior(behavior);
+        self
+    }
+
+    /// Makes the next `n` calls to `flush` fail with `io::ErrorKind::Interrupted`.
+    ///
+    /// These calls don't consume any `PartialOp`s or call into the underlying writer. Once they
+    /// have been used up, `flush` goes back to applying `PartialOp`s as usual. This is a
+    /// targeted way to check that code retries flushes that are interrupted.
+    ///
+    /// Interrupted flushes aren't included in [`errors_seen`](Self::errors_seen).
+    pub fn set_flush_interrupts(&mut self, n: usize) -> &mut Self {
+        self.flush_interrupts = n;
+        self.flush_interrupts_set = n;
+        self
+    }
+
+    /// Returns the number of calls to `flush` that will still fail with
+    /// `io::ErrorKind::Interrupted`.
+    pub fn flush_interrupts_remaining(&self) -> usize {
+        self.flush_interrupts
+    }
+
+    /// Returns a summary of everything this writer has recorded so far.
+    ///
+    /// This bundles the ops consumed, the last op, the bytes transferred, the errors injected,
+    /// the number of successful flushes, and the bytes left under the total limit and the current
+    /// shrinking capacity if those are set, into a single report, for printing when a test fails.
+    /// The remaining budget of `Limited` ops isn't part of the report, since working it out
+    /// consumes the iterator: use [`snapshot`](Self::snapshot) for that.
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            flushes: Some(self.flushes),
+            total_limit_remaining: self.total_limit.as_ref().map(|total| total.remaining),
+            shrinking_capacity: self
+                .shrinking_capacity
+                .as_ref()
+                .map(|capacity| capacity.cap),
+            ..self.ops.diagnostics()
+        }
+    }
+
     /// Acquires a reference to the underlying writer.
     pub fn get_ref(&self) -> &W {
         &self.inner
@@ -79,39 +805,381 @@ where
     }
 }
 
-impl<W> Write for PartialWrite<W>
+impl<W, I> PartialWrite<W, I>
 where
     W: Write,
+    I: Iterator<Item = PartialOp>,
 {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self.ops.next() {
-            Some(PartialOp::Limited(n)) => {
-                let len = cmp::min(n, buf.len());
-                self.inner.write(&buf[..len])
+    /// Adjusts `limit` for a write of `len` bytes according to the block alignment.
+    fn align_limit(&self, len: usize, limit: Option<usize>) -> Option<usize> {
+        let block = self.block_align;
+        let n = limit.map_or(len, |n| cmp::min(n, len));
+        if block == 1 || n == 0 || (n == len && !self.split_unaligned_tail) {
+            return limit;
+        }
+        match n - n % block {
+            // Either the limit is smaller than a block, or all that's left is the tail.
+            0 => Some(cmp::min(block, len)),
+            aligned => Some(aligned),
+        }
+    }
+
+    /// Adjusts `limit` for a write of `len` bytes according to the shrinking capacity, the block
+    /// alignment, the capacity probe and the total limit if any.
+    fn adjust_limit(&mut self, len: usize, limit: Option<usize>) -> io::Result<Option<usize>> {
+        let limit = match &self.shrinking_capacity {
+            Some(capacity) if capacity.cap == 0 && len > 0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "shrinking capacity is exhausted, generated by partial-io",
+                ));
+            }
+            Some(capacity) => Some(limit.map_or(capacity.cap, |n| cmp::min(n, capacity.cap))),
+            None => limit,
+        };
+        let limit = self.align_limit(len, limit);
+        let limit = match &mut self.capacity_probe {
+            Some(probe) => {
+                let remaining = probe(&self.inner);
+                if remaining == 0 && len > 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "inner writer is full, generated by partial-io",
+                    ));
+                }
+                Some(limit.map_or(remaining, |n| cmp::min(n, remaining)))
+            }
+            None => limit,
+        };
+        match &self.total_limit {
+            Some(total) => {
+                // If the rest of the quota doesn't fit in a usize, it can't limit the write.
+                let remaining = usize::try_from(total.remaining).unwrap_or(usize::MAX);
+                Ok(Some(limit.map_or(remaining, |n| cmp::min(n, remaining))))
             }
+            None => Ok(limit),
+        }
+    }
+
+    /// Checks the result of a write of at most `len` bytes to the inner writer, and shrinks the
+    /// capacity and the remaining total limit if it succeeded.
+    fn finish_limited(&mut self, res: &io::Result<usize>, len: usize) {
+        if self.strict_limits {
+            check_limit(res, len, "writer");
+        }
+        if let (Ok(n), Some(total)) = (res, &mut self.total_limit) {
+            total.remaining = total.remaining.saturating_sub(*n as u64);
+        }
+        if let (Ok(_), Some(capacity)) = (res, &mut self.shrinking_capacity) {
+            capacity.cap = cmp::max(
+                capacity.cap.saturating_sub(capacity.decay_per_op),
+                capacity.floor,
+            );
+        }
+    }
+
+    /// Writes at most `limit` bytes from `buf`, further capped by the shrinking capacity and the
+    /// capacity probe if any.
+    fn write_limited(&mut self, buf: &[u8], limit: Option<usize>) -> io::Result<usize> {
+        let buf = match self.adjust_limit(buf.len(), limit)? {
+            Some(n) => &buf[..cmp::min(n, buf.len())],
+            None => buf,
+        };
+        let res = if self.black_hole {
+            // Deliberately drop the data on the floor.
+            Ok(buf.len())
+        } else {
+            self.inner.write(buf)
+        };
+        self.finish_limited(&res, buf.len());
+        res
+    }
+
+    /// Writes at most `limit` bytes in total from `bufs`, further capped by the shrinking
+    /// capacity and the capacity probe if any.
+    fn write_vectored_limited(
+        &mut self,
+        bufs: &[IoSlice<'_>],
+        limit: Option<usize>,
+    ) -> io::Result<usize> {
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let len = match self.adjust_limit(total, limit)? {
+            Some(n) => cmp::min(n, total),
+            None => total,
+        };
+        let res = if self.black_hole {
+            // Deliberately drop the data on the floor.
+            Ok(len)
+        } else if len == total {
+            self.inner.write_vectored(bufs)
+        } else {
+            self.inner.write_vectored(&limit_io_slices(bufs, len))
+        };
+        self.finish_limited(&res, len);
+        res
+    }
+
+    /// Returns an error if a flush must happen before the next write.
+    fn check_flush_gate(&self) -> io::Result<()> {
+        match (self.flush_gate, self.awaiting_flush) {
+            (Some(kind), true) => Err(io::Error::new(
+                kind,
+                "write without an intervening flush, generated by partial-io",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the result of a write of `len` bytes if the total limit has been hit.
+    fn check_total_limit(&self, len: usize) -> Option<io::Result<usize>> {
+        match &self.total_limit {
+            Some(total) if total.remaining == 0 && len > 0 => Some(match total.exceeded {
+                Some(kind) => Err(io::Error::new(
+                    kind,
+                    "total limit exceeded, generated by partial-io",
+                )),
+                None => Ok(0),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the next write can be forwarded directly to the inner writer, because the
+    /// ops have run out or the next one is `Unlimited`. In the latter case, the op is consumed.
+    #[inline]
+    fn take_fast_path(&mut self) -> bool {
+        self.inspector.is_none()
+            && self.observer.is_none()
+            && self.capacity_probe.is_none()
+            && !self.black_hole
+            && self.block_align == 1
+            && self.flush_gate.is_none()
+            && self.result_map.is_none()
+            && self.shrinking_capacity.is_none()
+            && self.total_limit.is_none()
+            && (self.ops.is_exhausted() || self.ops.take_unlimited())
+    }
+}
+
+impl<W, V> PartialWrite<W, ReplayOps<V>>
+where
+    W: Write,
+    V: IntoIterator<Item = PartialOp> + Clone,
+{
+    /// Creates a new `PartialWrite` wrapper over the writer with a repeatable sequence of
+    /// `PartialOp`s, such as a `Vec<PartialOp>`.
+    ///
+    /// Unlike the wrappers created by the other constructors, this one implements `Clone` as long
+    /// as the writer does. See [`ReplayOps`] for more.
+    pub fn new_repeatable(inner: W, ops: V) -> Self {
+        Self::new_unboxed(inner, ReplayOps::new(ops))
+    }
+}
+
+/// The clone starts over from the beginning of the `PartialOp`s, with no ops consumed, no bytes
+/// transferred, no errors seen and no flushes. State that builds up over the transfer starts over
+/// too: the whole total limit is available again, a shrinking capacity is back at its starting
+/// cap, and the number of interrupted flushes is the one last passed to `set_flush_interrupts`.
+/// Other settings are copied as they currently are, except for callbacks set with `with_inspector`, `on_op`, `set_result_map` and
+/// `set_limit_relative_to_capacity`, which can't be cloned and aren't carried over.
+impl<W, V> Clone for PartialWrite<W, ReplayOps<V>>
+where
+    W: Clone,
+    V: IntoIterator<Item = PartialOp> + Clone,
+{
+    fn clone(&self) -> Self {
+        PartialWrite {
+            inner: self.inner.clone(),
+            ops: self.ops.restarted(),
+            inspector: None,
+            observer: None,
+            flush_interrupts: self.flush_interrupts_set,
+            flush_interrupts_set: self.flush_interrupts_set,
+            flushes: 0,
+            black_hole: self.black_hole,
+            strict_limits: self.strict_limits,
+            block_align: self.block_align,
+            split_unaligned_tail: self.split_unaligned_tail,
+            flush_gate: self.flush_gate,
+            awaiting_flush: false,
+            capacity_probe: None,
+            result_map: None,
+            shrinking_capacity: self
+                .shrinking_capacity
+                .as_ref()
+                .map(ShrinkingCapacity::restarted),
+            total_limit: self.total_limit.as_ref().map(TotalLimit::restarted),
+        }
+    }
+}
+
+impl<W, I> Write for PartialWrite<W, I>
+where
+    W: Write,
+    I: Iterator<Item = PartialOp>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_flush_gate()?;
+        if let Some(res) = self.check_total_limit(buf.len()) {
+            return res;
+        }
+        if self.take_fast_path() {
+            // Fast path: the ops have run out or the next one is Unlimited, so there's nothing to
+            // do but forward to the inner writer.
+            let res = self.inner.write(buf);
+            return self.ops.track_transfer(res);
+        }
+
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Limited(0)) if !buf.is_empty() => Err(limited_zero_error()),
+            Some(PartialOp::Limited(n)) => self.write_limited(buf, Some(n)),
             Some(PartialOp::Err(err)) => Err(io::Error::new(
                 err,
                 "error during write, generated by partial-io",
             )),
-            Some(PartialOp::Unlimited) | None => self.inner.write(buf),
+            Some(PartialOp::PendingOnce) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "error during write, generated by partial-io",
+            )),
+            Some(PartialOp::ErrMsg(kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::Unlimited) | None => self.write_limited(buf, None),
+            Some(PartialOp::Delay(_)) => unreachable!("delays are handled by Ops::next"),
+        };
+        let res = match &mut self.result_map {
+            Some(result_map) => {
+                let written = match &res {
+                    Ok(n) => &buf[..*n],
+                    Err(_) => &[],
+                };
+                let res = result_map(res, written);
+                if let Ok(n) = &res {
+                    assert!(
+                        *n <= buf.len(),
+                        "result map returned {} bytes, but the buffer has length {}",
+                        n,
+                        buf.len()
+                    );
+                }
+                res
+            }
+            None => res,
+        };
+        let res = self.ops.track_transfer(res);
+        trace_op(
+            op.as_ref(),
+            Some(buf.len()),
+            res.as_ref().map_err(|err| err.kind()),
+        );
+        if let Some(inspector) = &mut self.inspector {
+            inspect_write(inspector, op.as_ref(), buf, &res);
+        }
+        if let Some(observer) = &mut self.observer {
+            let transferred = *res.as_ref().unwrap_or(&0);
+            observer.observe(op.as_ref(), buf.len(), transferred);
+        }
+        if self.flush_gate.is_some() && res.is_ok() {
+            self.awaiting_flush = true;
+        }
+        res
+    }
+
+    /// Applies a single `PartialOp` to the write as a whole, so a `PartialOp::Limited(n)` op caps
+    /// the total number of bytes written across all of `bufs`.
+    ///
+    /// Inspectors and result maps are passed the written bytes as a single slice. If either of
+    /// them is set, only the first nonempty buffer is written, the same as the default
+    /// implementation of `write_vectored`.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        if self.inspector.is_some() || self.result_map.is_some() {
+            let buf = bufs
+                .iter()
+                .find(|buf| !buf.is_empty())
+                .map_or(&[][..], |buf| &**buf);
+            return self.write(buf);
+        }
+        self.check_flush_gate()?;
+        if let Some(res) = self.check_total_limit(bufs.iter().map(|buf| buf.len()).sum()) {
+            return res;
+        }
+        if self.take_fast_path() {
+            // Fast path: the ops have run out or the next one is Unlimited, so there's nothing to
+            // do but forward to the inner writer.
+            let res = self.inner.write_vectored(bufs);
+            return self.ops.track_transfer(res);
+        }
+
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        let op = self.ops.next();
+        let res = match op {
+            Some(PartialOp::Limited(0)) if len > 0 => Err(limited_zero_error()),
+            Some(PartialOp::Limited(n)) => self.write_vectored_limited(bufs, Some(n)),
+            Some(PartialOp::Err(err)) => Err(io::Error::new(
+                err,
+                "error during write_vectored, generated by partial-io",
+            )),
+            Some(PartialOp::PendingOnce) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "error during write_vectored, generated by partial-io",
+            )),
+            Some(PartialOp::ErrMsg(kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
+            Some(PartialOp::Eof) => Ok(0),
+            Some(PartialOp::Unlimited) | None => self.write_vectored_limited(bufs, None),
+            Some(PartialOp::Delay(_)) => unreachable!("delays are handled by Ops::next"),
+        };
+        let res = self.ops.track_transfer(res);
+        trace_op(
+            op.as_ref(),
+            Some(len),
+            res.as_ref().map_err(|err| err.kind()),
+        );
+        if let Some(observer) = &mut self.observer {
+            let transferred = *res.as_ref().unwrap_or(&0);
+            observer.observe(op.as_ref(), len, transferred);
+        }
+        if self.flush_gate.is_some() && res.is_ok() {
+            self.awaiting_flush = true;
         }
+        res
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        match self.ops.next() {
+        if self.flush_interrupts > 0 {
+            self.flush_interrupts -= 1;
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "flush interrupted, generated by partial-io",
+            ));
+        }
+
+        let op = self.ops.next();
+        let res = match op {
             Some(PartialOp::Err(err)) => Err(io::Error::new(
                 err,
                 "error during flush, generated by partial-io",
             )),
+            Some(PartialOp::PendingOnce) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "error during flush, generated by partial-io",
+            )),
+            Some(PartialOp::ErrMsg(kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
             _ => self.inner.flush(),
+        };
+        trace_op(op.as_ref(), None, res.as_ref().map_err(|err| err.kind()));
+        if res.is_ok() {
+            self.awaiting_flush = false;
+            self.flushes += 1;
         }
+        res
     }
 }
 
 // Forwarding impl to support duplex structs.
-impl<W> Read for PartialWrite<W>
+impl<W, I> Read for PartialWrite<W, I>
 where
     W: Read + Write,
+    I: Iterator<Item = PartialOp>,
 {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -119,14 +1187,31 @@ where
     }
 }
 
-impl<W> fmt::Debug for PartialWrite<W>
+/// Seeks are forwarded to the inner writer without consuming a `PartialOp`, so the position
+/// within the ops is unaffected.
+impl<W, I> Seek for PartialWrite<W, I>
+where
+    W: Seek,
+    I: Iterator<Item = PartialOp>,
+{
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<W, I> fmt::Debug for PartialWrite<W, I>
 where
     W: fmt::Debug,
+    I: Iterator<Item = PartialOp>,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("PartialWrite")
-            .field("inner", &self.inner)
-            .finish()
+        let mut f = f.debug_struct("PartialWrite");
+        f.field("inner", &self.inner);
+        if let Some(phase) = self.ops.current_phase() {
+            f.field("phase", &format_args!("{}", phase));
+        }
+        f.finish()
     }
 }
 
@@ -142,4 +1227,788 @@ mod tests {
     fn test_sendable() {
         assert_send::<PartialWrite<File>>();
     }
+
+    #[test]
+    fn test_pinhole() {
+        let data = b"Hello, world!";
+        let mut partial_write = PartialWrite::pinhole(Vec::new());
+        let mut remaining = &data[..];
+        while !remaining.is_empty() {
+            let n = partial_write.write(remaining).unwrap();
+            assert_eq!(n, 1, "write accepted exactly one byte");
+            remaining = &remaining[n..];
+        }
+        partial_write.flush().unwrap();
+        assert_eq!(&partial_write.get_ref()[..], data);
+    }
+
+    #[test]
+    fn test_latch_error() {
+        let ops = vec![
+            PartialOp::Limited(1),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Limited(2),
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        partial_write.set_latch_error(Some(io::ErrorKind::BrokenPipe));
+
+        assert_eq!(partial_write.write(b"abc").unwrap(), 1);
+        let err = partial_write.write(b"bc").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            io::ErrorKind::Interrupted,
+            "other kinds don't latch"
+        );
+        assert_eq!(partial_write.latched_error(), None);
+
+        // BrokenPipe latches, and every later operation fails with it.
+        for _ in 0..3 {
+            let err = partial_write.write(b"bc").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            let err = partial_write.flush().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        }
+        partial_write.set_ops(vec![]);
+        let err = partial_write.write(b"bc").unwrap_err();
+        assert_eq!(
+            err.kind(),
+            io::ErrorKind::BrokenPipe,
+            "set_ops doesn't clear the latch"
+        );
+        assert_eq!(&partial_write.get_ref()[..], b"a");
+
+        partial_write.clear_latch();
+        assert_eq!(partial_write.latched_error(), None);
+        assert_eq!(partial_write.write(b"bc").unwrap(), 2);
+        assert_eq!(&partial_write.get_ref()[..], b"abc");
+    }
+
+    /// A writer that can't be read back.
+    struct WriteOnly;
+
+    impl Write for WriteOnly {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_error_offsets() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Unlimited,
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::TimedOut),
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        let data = b"Hello, world!";
+        let mut written = 0;
+        let mut errors = 0;
+        while written < data.len() || errors < 3 {
+            match partial_write.write(&data[written % data.len()..]) {
+                Ok(n) => written += n,
+                Err(_) => errors += 1,
+            }
+        }
+        assert_eq!(written, 15);
+        assert_eq!(
+            partial_write.error_offsets(),
+            &[
+                (io::ErrorKind::BrokenPipe, 3),
+                (io::ErrorKind::Interrupted, 13),
+                (io::ErrorKind::TimedOut, 15),
+            ]
+        );
+
+        // Bytes written after the ops run out are counted too.
+        assert_eq!(partial_write.write(data).unwrap(), 13);
+        partial_write.set_ops(vec![PartialOp::Err(io::ErrorKind::WriteZero)]);
+        partial_write.write(data).unwrap_err();
+        assert_eq!(
+            partial_write.error_offsets().last(),
+            Some(&(io::ErrorKind::WriteZero, 28))
+        );
+    }
+
+    #[test]
+    fn test_inspector() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events2 = events.clone();
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write =
+            PartialWrite::new(WriteOnly, ops).with_inspector(move |op, bytes| {
+                events2.lock().unwrap().push((op.clone(), bytes.to_vec()));
+            });
+
+        assert_eq!(partial_write.write(b"abcd").unwrap(), 2);
+        partial_write.write(b"cd").unwrap_err();
+        assert_eq!(partial_write.write(b"cd").unwrap(), 2);
+        // The ops have run out, but the inspector is still called.
+        assert_eq!(partial_write.write(b"ef").unwrap(), 2);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (PartialOp::Limited(2), b"ab".to_vec()),
+                (PartialOp::Err(io::ErrorKind::Interrupted), vec![]),
+                (PartialOp::Unlimited, b"cd".to_vec()),
+                (PartialOp::Unlimited, b"ef".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limit_relative_to_capacity() {
+        const CAPACITY: usize = 10;
+
+        let ops = vec![
+            PartialOp::Limited(4),
+            PartialOp::Limited(4),
+            PartialOp::Limited(4),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        partial_write.set_limit_relative_to_capacity(|buf: &Vec<u8>| CAPACITY - buf.len());
+
+        let data = b"Hello, world!";
+        let mut limits = Vec::new();
+        let mut remaining = &data[..];
+        loop {
+            match partial_write.write(remaining) {
+                Ok(n) => {
+                    limits.push(n);
+                    remaining = &remaining[n..];
+                }
+                Err(err) => {
+                    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(limits, vec![4, 4, 2], "limits shrink as the buffer fills");
+        assert_eq!(&partial_write.get_ref()[..], &data[..CAPACITY]);
+        assert_eq!(
+            partial_write.write(b"").unwrap(),
+            0,
+            "empty writes succeed even when full"
+        );
+    }
+
+    #[test]
+    fn test_flush_interrupts() {
+        let mut partial_write = PartialWrite::new(io::BufWriter::new(Vec::new()), vec![]);
+        partial_write.set_flush_interrupts(3);
+        partial_write.write_all(b"Hello").unwrap();
+
+        let mut interrupts = 0;
+        loop {
+            match partial_write.flush() {
+                Ok(()) => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    assert!(
+                        partial_write.get_ref().get_ref().is_empty(),
+                        "data isn't flushed while interrupted"
+                    );
+                    interrupts += 1;
+                }
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }
+
+        assert_eq!(interrupts, 3);
+        assert_eq!(partial_write.flush_interrupts_remaining(), 0);
+        assert_eq!(&partial_write.get_ref().get_ref()[..], b"Hello");
+    }
+
+    #[test]
+    fn test_from_receiver() {
+        use std::thread;
+
+        let (ops_tx, ops_rx) = mpsc::channel();
+        let (chunk_tx, chunk_rx) = mpsc::channel::<&'static [u8]>();
+        let (result_tx, result_rx) = mpsc::channel();
+
+        // A background writer that writes chunks when told to.
+        let handle = thread::spawn(move || {
+            let mut partial_write = PartialWrite::from_receiver(Vec::new(), ops_rx);
+            for chunk in chunk_rx {
+                let res = partial_write.write(chunk).map_err(|err| err.kind());
+                result_tx.send(res).unwrap();
+            }
+            partial_write.into_inner()
+        });
+
+        let write = |chunk| {
+            chunk_tx.send(chunk).unwrap();
+            result_rx.recv().unwrap()
+        };
+        assert_eq!(write(b"ab"), Ok(2));
+        // Inject an error mid-transfer.
+        ops_tx
+            .send(PartialOp::Err(io::ErrorKind::ConnectionReset))
+            .unwrap();
+        assert_eq!(write(b"cd"), Err(io::ErrorKind::ConnectionReset));
+        assert_eq!(write(b"cd"), Ok(2), "no op pending means unlimited");
+        ops_tx.send(PartialOp::Limited(1)).unwrap();
+        assert_eq!(write(b"ef"), Ok(1));
+
+        drop(chunk_tx);
+        assert_eq!(&handle.join().unwrap()[..], b"abcde");
+    }
+
+    #[test]
+    fn test_assert_op_count() {
+        let mut partial_write = PartialWrite::new(Vec::new(), iter::repeat(PartialOp::Limited(2)));
+        partial_write.write_all(b"Hello, world!").unwrap();
+        // 13 bytes in chunks of at most 2 bytes.
+        partial_write.assert_op_count(7);
+
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            partial_write.assert_op_count(6)
+        }));
+        let payload = res.unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert_eq!(message, "expected 6 PartialOps to be consumed, but 7 were");
+    }
+
+    #[test]
+    fn test_black_hole() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        partial_write.set_black_hole(true);
+
+        assert_eq!(partial_write.write(b"Hello").unwrap(), 3);
+        partial_write.write(b"lo").unwrap_err();
+        // The ops have run out.
+        assert_eq!(partial_write.write(b"lo").unwrap(), 2);
+        partial_write.flush().unwrap();
+        assert!(partial_write.get_ref().is_empty(), "data was dropped");
+
+        partial_write.set_black_hole(false);
+        partial_write.write_all(b"world").unwrap();
+        assert_eq!(&partial_write.get_ref()[..], b"world");
+    }
+
+    #[test]
+    fn test_require_flush_between_writes() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        partial_write.set_require_flush_between_writes(Some(io::ErrorKind::InvalidInput));
+
+        assert_eq!(partial_write.write(b"abc").unwrap(), 2);
+        let err = partial_write.write(b"c").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(
+            partial_write.errors_seen().is_empty(),
+            "gated writes don't consume ops"
+        );
+
+        // A failed flush doesn't count.
+        partial_write.flush().unwrap_err();
+        assert_eq!(
+            partial_write.write(b"c").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        partial_write.flush().unwrap();
+        assert_eq!(partial_write.write(b"c").unwrap(), 1);
+        partial_write.flush().unwrap();
+        assert_eq!(&partial_write.get_ref()[..], b"abc");
+
+        partial_write.set_require_flush_between_writes(None);
+        partial_write.write_all(b"de").unwrap();
+        partial_write.write_all(b"f").unwrap();
+        assert_eq!(&partial_write.get_ref()[..], b"abcdef");
+    }
+
+    #[test]
+    fn test_result_map() {
+        let ops = vec![PartialOp::Limited(4), PartialOp::Unlimited];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        // Fail writes of data containing a zero byte, and report short writes as 1 byte.
+        partial_write.set_result_map(|res, written| match res {
+            Ok(_) if written.contains(&0) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "zero byte written",
+            )),
+            Ok(n) => Ok(n.min(1)),
+            Err(err) => Err(err),
+        });
+
+        assert_eq!(partial_write.write(b"abcdef").unwrap(), 1);
+        let err = partial_write.write(b"gh\0").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // The inner writer saw everything, regardless of what was reported.
+        assert_eq!(&partial_write.get_ref()[..], b"abcdgh\0");
+    }
+
+    #[test]
+    fn test_block_align() {
+        use std::sync::{Arc, Mutex};
+
+        let data: Vec<u8> = (0..100).collect();
+        let limits = [1, 7, 8, 30, 3, 100, 5, 64];
+        for split in [false, true] {
+            let ops: Vec<_> = limits.iter().map(|&n| PartialOp::Limited(n)).collect();
+            let chunks = Arc::new(Mutex::new(Vec::new()));
+            let chunks2 = chunks.clone();
+            let mut partial_write = PartialWrite::new(Vec::new(), ops.into_iter().cycle());
+            partial_write
+                .set_block_align(8)
+                .set_split_unaligned_tail(split)
+                .on_op(move |event| chunks2.lock().unwrap().push(event.transferred));
+            partial_write.write_all(&data).unwrap();
+            assert_eq!(partial_write.get_ref(), &data);
+
+            let chunks = chunks.lock().unwrap();
+            let (last, rest) = chunks.split_last().unwrap();
+            for n in rest {
+                assert_eq!(n % 8, 0, "chunk of {} bytes is block-aligned", n);
+            }
+            assert_eq!(*last, if split { 4 } else { 44 }, "split = {}", split);
+        }
+    }
+
+    #[test]
+    fn test_err_msg() {
+        let ops = vec![
+            PartialOp::ErrMsg(io::ErrorKind::Other, "disk full".into()),
+            PartialOp::Err(io::ErrorKind::Other),
+            PartialOp::ErrMsg(
+                io::ErrorKind::BrokenPipe,
+                format!("closed after {} bytes", 0).into(),
+            ),
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        partial_write.set_latch_error(Some(io::ErrorKind::BrokenPipe));
+
+        let err = partial_write.write(b"Hello").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert!(err.to_string().contains("disk full"), "{}", err);
+        let err = partial_write.write(b"Hello").unwrap_err();
+        assert!(!err.to_string().contains("disk full"), "{}", err);
+        assert_eq!(
+            partial_write.flush().unwrap_err().to_string(),
+            "closed after 0 bytes"
+        );
+        // Custom messages don't carry over to latched errors.
+        let err = partial_write.write(b"Hello").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            err.to_string(),
+            "error during write, generated by partial-io"
+        );
+
+        assert_eq!(
+            partial_write.errors_seen(),
+            &[
+                io::ErrorKind::Other,
+                io::ErrorKind::Other,
+                io::ErrorKind::BrokenPipe,
+                io::ErrorKind::BrokenPipe,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_vectored() {
+        let ops = vec![
+            PartialOp::Limited(4),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(7),
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        let header = b"\x00\x00\x00\x0d";
+        let payload = b"Hello, world!";
+        let bufs = [IoSlice::new(header), IoSlice::new(payload)];
+
+        // A short write that only covers the header.
+        assert_eq!(partial_write.write_vectored(&bufs).unwrap(), 4);
+        assert_eq!(partial_write.consumed_ops(), 1, "one op per vectored write");
+        let err = partial_write.write_vectored(&bufs[1..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+
+        // A limit that spans slices.
+        let bufs = [IoSlice::new(b"Hel"), IoSlice::new(b"lo, world!")];
+        assert_eq!(partial_write.write_vectored(&bufs).unwrap(), 7);
+        assert_eq!(partial_write.consumed_ops(), 3);
+        assert_eq!(&partial_write.get_ref()[4..], b"Hello, ");
+
+        // With block alignment, the limit is aligned across slices too.
+        partial_write.set_ops(vec![PartialOp::Limited(7)]);
+        partial_write.set_block_align(2);
+        assert_eq!(partial_write.write_vectored(&bufs).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_bytes_transferred() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::BrokenPipe),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        assert_eq!(partial_write.write(b"Hello").unwrap(), 2);
+        partial_write.write(b"llo").unwrap_err();
+        assert_eq!(partial_write.bytes_transferred(), 2, "errors don't count");
+        partial_write.flush().unwrap();
+        assert_eq!(partial_write.bytes_transferred(), 2, "flushes don't count");
+
+        partial_write.set_ops(iter::repeat(PartialOp::Limited(1)));
+        partial_write.write_all(b"llo").unwrap();
+        assert_eq!(partial_write.bytes_transferred(), 5);
+    }
+
+    #[test]
+    fn test_ops_remaining() {
+        let ops = vec![
+            PartialOp::Limited(2),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(3),
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        assert_eq!(partial_write.ops_remaining(), Some(3));
+        assert_eq!(partial_write.consumed_ops(), 0);
+
+        assert_eq!(partial_write.write(b"Hello").unwrap(), 2);
+        assert_eq!(partial_write.ops_remaining(), Some(2));
+        // Peeking at the next op doesn't change the count.
+        assert_eq!(partial_write.next_limit(), None);
+        assert_eq!(partial_write.ops_remaining(), Some(2));
+
+        partial_write.write_all(b"llo, world!").unwrap();
+        assert_eq!(partial_write.ops_remaining(), Some(0));
+        assert_eq!(partial_write.consumed_ops(), 3);
+
+        partial_write.set_ops(iter::repeat(PartialOp::Limited(1)));
+        assert_eq!(partial_write.ops_remaining(), None);
+        assert_eq!(partial_write.consumed_ops(), 0);
+        assert_eq!(partial_write.write(b"!").unwrap(), 1);
+        assert_eq!(partial_write.consumed_ops(), 1);
+    }
+
+    #[test]
+    fn test_eof() {
+        let ops = vec![PartialOp::Limited(2), PartialOp::Eof, PartialOp::Eof];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        assert_eq!(partial_write.write(b"Hello").unwrap(), 2);
+        assert_eq!(partial_write.write(b"llo").unwrap(), 0);
+        assert_eq!(
+            partial_write.write_all(b"llo").unwrap_err().kind(),
+            io::ErrorKind::WriteZero
+        );
+        assert_eq!(partial_write.get_ref(), b"He");
+    }
+
+    #[test]
+    fn test_seek() {
+        use std::io::Cursor;
+
+        let ops = vec![PartialOp::Limited(2), PartialOp::Limited(3)];
+        let mut partial_write = PartialWrite::new(Cursor::new(b"Hello, world!".to_vec()), ops);
+        assert_eq!(partial_write.write(b"HE").unwrap(), 2);
+
+        // Seeking doesn't consume or reset the ops: the next write applies the second op.
+        assert_eq!(partial_write.seek(SeekFrom::Start(7)).unwrap(), 7);
+        assert_eq!(partial_write.write(b"WORLD").unwrap(), 3);
+        assert_eq!(partial_write.stream_position().unwrap(), 10);
+        assert_eq!(partial_write.get_ref().get_ref(), b"HEllo, WORld!");
+        partial_write.assert_op_count(2);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let ops = PhasedSchedule::new()
+            .phase("header", vec![PartialOp::Limited(3)])
+            .phase(
+                "body",
+                vec![
+                    PartialOp::Err(io::ErrorKind::Interrupted),
+                    PartialOp::Limited(4),
+                    PartialOp::Unlimited,
+                    PartialOp::Err(io::ErrorKind::BrokenPipe),
+                ],
+            );
+        let mut partial_write = PartialWrite::new(Vec::new(), vec![]);
+        partial_write
+            .set_phased_ops(ops)
+            .set_latch_error(Some(io::ErrorKind::BrokenPipe));
+
+        let diagnostics = partial_write.diagnostics();
+        assert_eq!(diagnostics.ops_consumed, 0);
+        assert_eq!(diagnostics.last_op, None);
+        assert_eq!(diagnostics.phase, None);
+        assert_eq!(diagnostics.flushes, Some(0));
+
+        partial_write.write_all(b"Hello, world!").unwrap();
+        partial_write.flush().unwrap_err();
+
+        let diagnostics = partial_write.diagnostics();
+        assert_eq!(diagnostics.ops_consumed, 5);
+        assert_eq!(
+            diagnostics.last_op,
+            Some(PartialOp::Err(io::ErrorKind::BrokenPipe))
+        );
+        assert_eq!(diagnostics.transferred, 13);
+        assert_eq!(
+            diagnostics.error_offsets,
+            [
+                (io::ErrorKind::Interrupted, 3),
+                (io::ErrorKind::BrokenPipe, 13),
+            ]
+        );
+        assert_eq!(diagnostics.latched_error, Some(io::ErrorKind::BrokenPipe));
+        assert_eq!(
+            diagnostics.phase.unwrap().to_string(),
+            "phase 'body' at op 3"
+        );
+        assert_eq!(diagnostics.flushes, Some(0));
+        assert_eq!(diagnostics.total_limit_remaining, None);
+        assert_eq!(diagnostics.shrinking_capacity, None);
+
+        partial_write.clear_latch();
+        partial_write.flush().unwrap();
+        assert_eq!(partial_write.diagnostics().flushes, Some(1));
+    }
+
+    #[test]
+    fn test_diagnostics_budget() {
+        let mut partial_write = PartialWrite::new(Vec::new(), vec![]).with_total_limit(10, None);
+        partial_write.set_shrinking_capacity(6, 2, 1);
+
+        let diagnostics = partial_write.diagnostics();
+        assert_eq!(diagnostics.total_limit_remaining, Some(10));
+        assert_eq!(diagnostics.shrinking_capacity, Some(6));
+
+        assert_eq!(partial_write.write(b"Hello, world!").unwrap(), 6);
+        let diagnostics = partial_write.diagnostics();
+        assert_eq!(diagnostics.total_limit_remaining, Some(4));
+        assert_eq!(diagnostics.shrinking_capacity, Some(4));
+    }
+
+    #[test]
+    fn test_shrinking_capacity_zero() {
+        let mut partial_write = PartialWrite::new(Vec::new(), vec![]);
+        partial_write.set_shrinking_capacity(4, 3, 0);
+
+        let err = partial_write.write_all(b"Hello, world!").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        // The cap goes 4, 1, then 0.
+        assert_eq!(partial_write.get_ref(), b"Hello");
+        // Empty writes still succeed.
+        assert_eq!(partial_write.write(b"").unwrap(), 0);
+        let err = partial_write
+            .write_vectored(&[io::IoSlice::new(b"o")])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(partial_write.diagnostics().shrinking_capacity, Some(0));
+    }
+
+    #[test]
+    fn test_shrinking_capacity() {
+        let data: Vec<u8> = (0..100).collect();
+        let ops = vec![
+            PartialOp::Unlimited,
+            PartialOp::Limited(4),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops);
+        partial_write.set_shrinking_capacity(20, 6, 3);
+
+        let mut written = Vec::new();
+        let mut remaining = &data[..];
+        while !remaining.is_empty() {
+            match partial_write.write(remaining) {
+                Ok(n) => {
+                    written.push(n);
+                    remaining = &remaining[n..];
+                }
+                Err(err) => assert_eq!(err.kind(), io::ErrorKind::Interrupted),
+            }
+        }
+        assert_eq!(partial_write.get_ref(), &data);
+
+        // The schedule's limit of 4 wins over the cap of 14, errors don't shrink the cap, and the
+        // cap bottoms out at the floor.
+        assert_eq!(&written[..5], &[20, 4, 8, 3, 3]);
+        assert!(
+            written[5..].iter().all(|&n| n <= 3),
+            "written: {:?}",
+            written
+        );
+    }
+
+    #[test]
+    fn test_total_limit() {
+        let ops = vec![
+            PartialOp::Limited(3),
+            PartialOp::Err(io::ErrorKind::Interrupted),
+            PartialOp::Limited(4),
+            PartialOp::Unlimited,
+        ];
+        let mut partial_write = PartialWrite::new(Vec::new(), ops).with_total_limit(10, None);
+
+        assert_eq!(partial_write.write(b"Hello, ").unwrap(), 3);
+        partial_write.write(b"lo, ").unwrap_err();
+        assert_eq!(partial_write.write(b"lo, world!").unwrap(), 4);
+        // This write straddles the cap.
+        assert_eq!(partial_write.write(b"world!").unwrap(), 3);
+        assert_eq!(partial_write.get_ref(), b"Hello, wor");
+        assert_eq!(partial_write.consumed_ops(), 4);
+
+        // Past the cap, writes return Ok(0) without consuming ops.
+        assert_eq!(partial_write.write(b"ld!").unwrap(), 0);
+        assert_eq!(
+            partial_write
+                .write_vectored(&[IoSlice::new(b"ld!")])
+                .unwrap(),
+            0
+        );
+        assert_eq!(partial_write.write(b"").unwrap(), 0);
+        assert_eq!(partial_write.consumed_ops(), 4);
+        partial_write.flush().unwrap();
+        assert_eq!(
+            partial_write.write_all(b"ld!").unwrap_err().kind(),
+            io::ErrorKind::WriteZero
+        );
+
+        let mut partial_write =
+            PartialWrite::new(Vec::new(), vec![]).with_total_limit(5, Some(io::ErrorKind::Other));
+        assert_eq!(
+            partial_write
+                .write_all(b"Hello, world!")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Other
+        );
+        assert_eq!(partial_write.get_ref(), b"Hello");
+    }
+
+    #[test]
+    fn test_limited_zero() {
+        let ops = iter::repeat(PartialOp::Limited(0)).take(3);
+        let mut partial_write =
+            PartialWrite::new(Vec::new(), ops.chain(iter::once(PartialOp::Eof)));
+
+        // Rather than looping forever, write_all fails right away with a clear message.
+        let err = partial_write.write_all(b"Hello").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(
+            err.to_string(),
+            "partial-io: Limited(0) would cause write_all to loop; use PartialOp::Eof to \
+             intentionally return 0"
+        );
+        assert_eq!(
+            partial_write
+                .write_vectored(&[IoSlice::new(b""), IoSlice::new(b"Hello")])
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::WriteZero
+        );
+        // An empty write under Limited(0) is fine.
+        assert_eq!(partial_write.write(b"").unwrap(), 0);
+        // Eof returns Ok(0) on purpose.
+        assert_eq!(partial_write.write(b"Hello").unwrap(), 0);
+        assert_eq!(partial_write.consumed_ops(), 4);
+
+        // Once the ops have run out, writes go through.
+        partial_write.write_all(b"Hello").unwrap();
+        assert_eq!(partial_write.get_ref(), b"Hello");
+    }
+
+    #[test]
+    fn test_clone() {
+        let ops = vec![PartialOp::Limited(2), PartialOp::Limited(3)];
+        let mut first = PartialWrite::new_repeatable(Vec::new(), ops).with_total_limit(6, None);
+        assert_eq!(first.write(b"Hello").unwrap(), 2);
+        first.flush().unwrap();
+
+        // The clone starts over from the first op, but keeps the settings.
+        let mut second = first.clone();
+        second.get_mut().clear();
+        assert_eq!(second.consumed_ops(), 0);
+        assert_eq!(second.diagnostics().flushes, Some(0));
+        assert_eq!(second.write(b"Hello").unwrap(), 2);
+        // The total limit starts over, with all 6 bytes available.
+        assert_eq!(second.write(b"llo").unwrap(), 3);
+        assert_eq!(second.write(b", world").unwrap(), 1);
+        assert_eq!(second.write(b"world").unwrap(), 0);
+        assert_eq!(second.get_ref(), b"Hello,");
+
+        // The original carries on where it left off.
+        assert_eq!(first.consumed_ops(), 2);
+        assert_eq!(first.write(b"llo").unwrap(), 3);
+        assert_eq!(first.get_ref(), b"Hello");
+        assert_eq!(first.write(b", world").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_clone_restarts_state() {
+        let ops = vec![PartialOp::Unlimited; 3];
+        let mut first = PartialWrite::new_repeatable(Vec::new(), ops);
+        first
+            .set_shrinking_capacity(8, 3, 1)
+            .set_flush_interrupts(2);
+        assert_eq!(first.write(b"Hello, world!").unwrap(), 8);
+        assert_eq!(first.write(b"world!").unwrap(), 5);
+        assert_eq!(
+            first.flush().unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+
+        let mut second = first.clone();
+        // The shrinking capacity is back at its starting cap.
+        assert_eq!(second.write(b"Hello, world!").unwrap(), 8);
+        assert_eq!(second.flush_interrupts_remaining(), 2);
+        assert_eq!(first.flush_interrupts_remaining(), 1);
+        assert_eq!(first.write(b"!").unwrap(), 1);
+        assert_eq!(
+            first.write(b"!!").unwrap(),
+            1,
+            "the original keeps shrinking"
+        );
+    }
+
+    #[test]
+    fn test_exhausted_fast_path() {
+        let data = b"Hello, world!";
+
+        // Ops that are all Unlimited, which take the fast path one op at a time.
+        let mut general = PartialWrite::new(Vec::new(), vec![PartialOp::Unlimited; 4]);
+        // The fast path, taken from the start with no ops.
+        let mut fast = PartialWrite::new(Vec::new(), vec![]);
+        assert!(!general.ops.is_exhausted());
+        assert!(fast.ops.is_exhausted());
+
+        for chunk in data.chunks(3) {
+            assert_eq!(general.write(chunk).unwrap(), fast.write(chunk).unwrap());
+        }
+        assert!(
+            general.ops.is_exhausted(),
+            "general path eventually switches over"
+        );
+        assert_eq!(general.get_ref(), fast.get_ref());
+        assert_eq!(&general.get_ref()[..], data);
+    }
 }